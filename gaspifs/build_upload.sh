@@ -1,7 +1,7 @@
 #!/bin/bash
 set -e
 cd ${1}
-echo "{ \"CLI_API\": \"${3}\", \"AWS_REGION\": \"${4}\", \"COGNITO_CLIENT_ID\": \"${5}\" }" > ./gaspifs.json
+echo "{ \"CLI_API\": \"${3}\", \"AWS_REGION\": \"${4}\", \"COGNITO_CLIENT_ID\": \"${5}\", \"COGNITO_IDENTITY_POOL_ID\": \"${6}\", \"DATA_PORTAL_BUCKET\": \"${7}\", \"CLINIC_API\": \"${8}\", \"CLINIC_MODE\": \"${9}\", \"HUB_NAME\": \"${10}\" }" > ./gaspifs.json
 
 docker build --platform=linux/amd64 -t gaspifs-builder .
 
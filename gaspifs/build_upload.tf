@@ -16,6 +16,6 @@ resource "null_resource" "gaspifs-upload" {
   }
 
   provisioner "local-exec" {
-    command = "/bin/bash \"${path.module}/build_upload.sh\" \"${path.module}\" \"${var.gaspifs_binary_destination}\" ${var.cli_backend_api_url} ${var.region} ${var.cognito_user_pool_client_id}"
+    command = "/bin/bash \"${path.module}/build_upload.sh\" \"${path.module}\" \"${var.gaspifs_binary_destination}\" ${var.cli_backend_api_url} ${var.region} ${var.cognito_user_pool_client_id} ${var.cognito_identity_pool_id} ${var.data_portal_bucket} \"${var.clinic_api_url}\" \"${var.clinic_mode}\" \"${var.hub_name}\""
   }
 }
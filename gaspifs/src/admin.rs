@@ -0,0 +1,509 @@
+use crate::networking::api::{self, OutputFormat, format_table};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// The number of projects requested per page when listing projects.
+const PAGE_SIZE: &str = "50";
+
+/// A project as the portal describes it to admins, with its files and ingestion state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminProject {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub pending_files: Vec<String>,
+    #[serde(default)]
+    pub total_samples: u64,
+    #[serde(default)]
+    pub ingested_datasets: Vec<String>,
+    #[serde(default)]
+    pub error_messages: Vec<ProjectError>,
+}
+
+/// An error raised while ingesting a file of a project.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProjectError {
+    pub file: String,
+    pub error: String,
+}
+
+/// Describes a project in a YAML file, so that the projects of an environment can be reproduced.
+/// # Example
+/// ```yaml
+/// name: my-project
+/// description: Whole genomes of the pilot cohort
+/// files: [pilot.vcf.gz, pilot.vcf.gz.tbi]
+/// ```
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSpec {
+    pub name: String,
+    pub description: String,
+    /// The files of the project, uploaded with `upload --project`; kept as they are when not given.
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
+}
+
+/// A YAML file holding a single project spec, or a list of them under `projects`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SpecFile {
+    Many { projects: Vec<ProjectSpec> },
+    One(ProjectSpec),
+}
+
+/// Reads the project specs of a YAML file.
+/// # Errors
+/// Returns an error if the file cannot be read or is not a valid project spec.
+/// # Example
+/// ```
+/// let specs = read_specs("projects.yaml")?;
+/// ```
+pub fn read_specs<P: AsRef<Path>>(path: P) -> Result<Vec<ProjectSpec>> {
+    let content = std::fs::read_to_string(&path)?;
+    let specs = match serde_norway::from_str(&content)
+        .with_context(|| format!("Invalid project spec {}", path.as_ref().display()))?
+    {
+        SpecFile::Many { projects } => projects,
+        SpecFile::One(spec) => vec![spec],
+    };
+    if specs.is_empty() {
+        return Err(anyhow!(
+            "No projects in project spec {}",
+            path.as_ref().display()
+        ));
+    }
+    Ok(specs)
+}
+
+/// Lists the projects of the portal, following all pages.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `search` - Only list projects whose name contains this text.
+/// * `token` - The authentication token of an admin.
+/// # Returns
+/// A `Result` containing the projects.
+/// # Errors
+/// Returns an error if the request fails, for example when the user is not an admin.
+/// # Example
+/// ```
+/// let projects = list_projects(&api, None, &id_token).await?;
+/// ```
+pub async fn list_projects(
+    api: &str,
+    search: Option<&str>,
+    token: &str,
+) -> Result<Vec<AdminProject>> {
+    let mut projects = Vec::new();
+    let mut last_key: Option<String> = None;
+    loop {
+        let mut params = vec![("limit", PAGE_SIZE)];
+        if let Some(key) = &last_key {
+            params.push(("last_evaluated_key", key));
+        }
+        if let Some(search) = search {
+            params.push(("search", search));
+        }
+        let response = api::get(api, "dportal/admin/projects", &params, token).await?;
+        let page: Vec<AdminProject> = serde_json::from_value(response["data"].clone())?;
+        projects.extend(page);
+        match response["last_evaluated_key"].as_str() {
+            Some(key) if !key.is_empty() => last_key = Some(key.to_string()),
+            _ => return Ok(projects),
+        }
+    }
+}
+
+/// Finds a project by its name.
+/// # Returns
+/// A `Result` containing the project, or `None` if it does not exist.
+/// # Errors
+/// Returns an error if the request fails.
+/// # Example
+/// ```
+/// if find_project(&api, "my-project", &id_token).await?.is_none() {
+///     create_project(&api, "my-project", "Whole genomes", &id_token).await?;
+/// }
+/// ```
+pub async fn find_project(api: &str, name: &str, token: &str) -> Result<Option<AdminProject>> {
+    Ok(list_projects(api, Some(name), token)
+        .await?
+        .into_iter()
+        .find(|project| project.name == name))
+}
+
+/// Gets a project by its name.
+/// # Errors
+/// Returns an error if the project does not exist or the request fails.
+/// # Example
+/// ```
+/// let project = get_project(&api, "my-project", &id_token).await?;
+/// ```
+pub async fn get_project(api: &str, name: &str, token: &str) -> Result<AdminProject> {
+    find_project(api, name, token)
+        .await?
+        .ok_or(anyhow!("Project {} was not found.", name))
+}
+
+/// Creates an empty project.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the project.
+/// * `description` - The description of the project.
+/// * `token` - The authentication token of an admin.
+/// # Errors
+/// Returns an error with the message of the API if the project cannot be created.
+/// # Example
+/// ```
+/// create_project(&api, "my-project", "Whole genomes", &id_token).await?;
+/// ```
+pub async fn create_project(api: &str, name: &str, description: &str, token: &str) -> Result<()> {
+    let body = json!({ "name": name, "description": description });
+    api::post(api, "dportal/admin/projects", &body, token).await?;
+    Ok(())
+}
+
+/// Sets the description and files of a project; files left out of `files` are removed from it.
+/// # Errors
+/// Returns an error with the message of the API if the project cannot be updated.
+/// # Example
+/// ```
+/// update_project(&api, "my-project", "Whole genomes", &["pilot.vcf.gz".to_string()], &id_token).await?;
+/// ```
+pub async fn update_project(
+    api: &str,
+    name: &str,
+    description: &str,
+    files: &[String],
+    token: &str,
+) -> Result<()> {
+    let body = json!({ "description": description, "files": files });
+    let endpoint = format!("dportal/admin/projects/{}", name);
+    api::put(api, &endpoint, &body, token).await?;
+    Ok(())
+}
+
+/// Deletes a project and its files.
+/// # Errors
+/// Returns an error if the request fails.
+pub async fn delete_project(api: &str, name: &str, token: &str) -> Result<()> {
+    api::delete(api, &format!("dportal/admin/projects/{}", name), token).await?;
+    Ok(())
+}
+
+/// Clears the ingestion errors of a project.
+/// # Errors
+/// Returns an error if the request fails.
+pub async fn clear_errors(api: &str, name: &str, token: &str) -> Result<()> {
+    let endpoint = format!("dportal/admin/projects/{}/errors", name);
+    api::delete(api, &endpoint, token).await?;
+    Ok(())
+}
+
+/// Creates the projects of specs, then sets the files of those that list them.
+/// # Errors
+/// Returns an error when a project cannot be created or updated; earlier projects are kept.
+pub async fn create_from_specs(api: &str, specs: &[ProjectSpec], token: &str) -> Result<()> {
+    for spec in specs {
+        create_project(api, &spec.name, &spec.description, token).await?;
+        if let Some(files) = spec.files.as_deref().filter(|files| !files.is_empty()) {
+            update_project(api, &spec.name, &spec.description, files, token).await?;
+        }
+        println!("Created project {}", spec.name);
+    }
+    Ok(())
+}
+
+/// Updates existing projects to match specs, keeping the current files of specs without `files`.
+/// # Errors
+/// Returns an error when a project does not exist or cannot be updated; earlier projects are kept.
+pub async fn update_from_specs(api: &str, specs: &[ProjectSpec], token: &str) -> Result<()> {
+    for spec in specs {
+        let files = match &spec.files {
+            Some(files) => files.clone(),
+            None => get_project(api, &spec.name, token).await?.files,
+        };
+        update_project(api, &spec.name, &spec.description, &files, token).await?;
+        println!("Updated project {}", spec.name);
+    }
+    Ok(())
+}
+
+/// Makes projects match specs, creating those that do not exist and updating the others, so that
+/// a spec can be applied again after it changes. Specs without `files` keep the current files.
+/// Projects that already match their spec are left alone.
+/// # Errors
+/// Returns an error when a project cannot be read, created or updated; earlier projects are kept.
+/// # Example
+/// ```
+/// apply_specs(&api, &read_specs("projects.yaml")?, &id_token).await?;
+/// ```
+pub async fn apply_specs(api: &str, specs: &[ProjectSpec], token: &str) -> Result<()> {
+    for spec in specs {
+        let Some(current) = find_project(api, &spec.name, token).await? else {
+            create_from_specs(api, std::slice::from_ref(spec), token).await?;
+            continue;
+        };
+        let files = spec.files.clone().unwrap_or(current.files.clone());
+        if current.description == spec.description && current.files == files {
+            println!("Project {} is up to date", spec.name);
+            continue;
+        }
+        update_project(api, &spec.name, &spec.description, &files, token).await?;
+        println!("Updated project {}", spec.name);
+    }
+    Ok(())
+}
+
+/// Asks the user to confirm that a project and its files are deleted.
+/// The question is skipped with `yes`.
+/// # Errors
+/// Returns an error if confirmation is needed but standard input is not a terminal.
+pub fn confirm_delete(name: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Deleting a project needs confirmation; pass --yes to confirm without a terminal."
+        ));
+    }
+    eprint!("Delete project {} and all its files? [y/N] ", name);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Formats projects for printing, as a table of names, file counts, samples and errors or as JSON.
+pub fn format_projects(projects: &[AdminProject], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(projects)?));
+    }
+    let rows: Vec<Vec<String>> = projects
+        .iter()
+        .map(|project| {
+            vec![
+                project.name.clone(),
+                project.files.len().to_string(),
+                project.pending_files.len().to_string(),
+                project.ingested_datasets.len().to_string(),
+                project.total_samples.to_string(),
+                project.error_messages.len().to_string(),
+                project.description.clone(),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &[
+            "NAME",
+            "FILES",
+            "PENDING",
+            "DATASETS",
+            "SAMPLES",
+            "ERRORS",
+            "DESCRIPTION",
+        ],
+        &rows,
+    ))
+}
+
+/// Formats a project for printing, with its files and ingestion errors, or as JSON.
+pub fn format_project(project: &AdminProject, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(project)?));
+    }
+    let list = |items: &[String]| {
+        if items.is_empty() {
+            "-".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    Ok(format!(
+        "name: {}\ndescription: {}\nfiles: {}\npending files: {}\ningested datasets: {}\nsamples: {}\nerrors: {}\n",
+        project.name,
+        project.description,
+        list(&project.files),
+        list(&project.pending_files),
+        list(&project.ingested_datasets),
+        project.total_samples,
+        project.error_messages.len()
+    ))
+}
+
+/// Formats the ingestion errors of a project for printing, as a table or as JSON.
+pub fn format_errors(errors: &[ProjectError], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(errors)?));
+    }
+    if errors.is_empty() {
+        return Ok("No errors.\n".to_string());
+    }
+    let rows: Vec<Vec<String>> = errors
+        .iter()
+        .map(|error| vec![error.file.clone(), error.error.clone()])
+        .collect();
+    Ok(format_table(&["FILE", "ERROR"], &rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_specs() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "projects:\n  - name: pilot\n    description: Pilot cohort\n    files: [pilot.vcf.gz]\n  - name: main\n    description: Main cohort"
+        )
+        .unwrap();
+        let specs = read_specs(file.path()).unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].files, Some(vec!["pilot.vcf.gz".to_string()]));
+        assert_eq!(specs[1].files, None);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "name: pilot\ndescription: Pilot cohort\nowner: me").unwrap();
+        assert!(read_specs(file.path()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_projects() {
+        let mut server = Server::new_async().await;
+        let first = server
+            .mock("GET", "/dportal/admin/projects")
+            .match_query(Matcher::Exact("limit=50".to_string()))
+            .with_status(200)
+            .with_body(
+                r#"{"data": [{"name": "pilot", "description": "Pilot cohort", "files": ["pilot.vcf.gz"],
+                    "pending_files": [], "total_samples": 12, "ingested_datasets": ["pilot"],
+                    "error_messages": [{"file": "bad.vcf.gz", "error": "Invalid header"}]}],
+                    "last_evaluated_key": "pilot"}"#,
+            )
+            .create();
+        let second = server
+            .mock("GET", "/dportal/admin/projects")
+            .match_query(Matcher::Exact(
+                "limit=50&last_evaluated_key=pilot".to_string(),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": [{"name": "main", "description": "Main cohort"}], "last_evaluated_key": null}"#)
+            .create();
+        let projects = list_projects(&server.url(), None, "token").await.unwrap();
+        first.assert();
+        second.assert();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(
+            format_errors(&projects[0].error_messages, OutputFormat::Table).unwrap(),
+            "FILE        ERROR\nbad.vcf.gz  Invalid header\n"
+        );
+        assert_eq!(
+            format_projects(&projects[1..], OutputFormat::Table).unwrap(),
+            "NAME  FILES  PENDING  DATASETS  SAMPLES  ERRORS  DESCRIPTION\nmain  0      0        0         0        0       Main cohort\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_from_specs() {
+        let mut server = Server::new_async().await;
+        let get = server
+            .mock("GET", "/dportal/admin/projects")
+            .match_query(Matcher::UrlEncoded("search".into(), "pilot".into()))
+            .with_status(200)
+            .with_body(
+                r#"{"data": [{"name": "pilot", "description": "Old", "files": ["pilot.vcf.gz"]}]}"#,
+            )
+            .create();
+        let put = server
+            .mock("PUT", "/dportal/admin/projects/pilot")
+            .match_body(Matcher::Json(
+                json!({"description": "Pilot cohort", "files": ["pilot.vcf.gz"]}),
+            ))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        let specs = vec![ProjectSpec {
+            name: "pilot".to_string(),
+            description: "Pilot cohort".to_string(),
+            files: None,
+        }];
+        update_from_specs(&server.url(), &specs, "token")
+            .await
+            .unwrap();
+        get.assert();
+        put.assert();
+    }
+
+    #[tokio::test]
+    async fn test_apply_specs() {
+        let mut server = Server::new_async().await;
+        let existing = server
+            .mock("GET", "/dportal/admin/projects")
+            .match_query(Matcher::UrlEncoded("search".into(), "pilot".into()))
+            .with_status(200)
+            .with_body(
+                r#"{"data": [{"name": "pilot", "description": "Pilot cohort", "files": ["pilot.vcf.gz"]},
+                    {"name": "pilot-2", "description": "Second pilot"}]}"#,
+            )
+            .create();
+        let missing = server
+            .mock("GET", "/dportal/admin/projects")
+            .match_query(Matcher::UrlEncoded("search".into(), "main".into()))
+            .with_status(200)
+            .with_body(r#"{"data": []}"#)
+            .create();
+        let create = server
+            .mock("POST", "/dportal/admin/projects")
+            .match_body(Matcher::Json(
+                json!({"name": "main", "description": "Main cohort"}),
+            ))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        let update = server.mock("PUT", Matcher::Any).expect(0).create();
+        let specs = vec![
+            ProjectSpec {
+                name: "pilot".to_string(),
+                description: "Pilot cohort".to_string(),
+                files: None,
+            },
+            ProjectSpec {
+                name: "main".to_string(),
+                description: "Main cohort".to_string(),
+                files: None,
+            },
+        ];
+        apply_specs(&server.url(), &specs, "token").await.unwrap();
+        existing.assert();
+        missing.assert();
+        create.assert();
+        update.assert();
+
+        update.remove();
+        let update = server
+            .mock("PUT", "/dportal/admin/projects/pilot")
+            .match_body(Matcher::Json(
+                json!({"description": "Pilot cohort", "files": []}),
+            ))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        let specs = vec![ProjectSpec {
+            name: "pilot".to_string(),
+            description: "Pilot cohort".to_string(),
+            files: Some(Vec::new()),
+        }];
+        apply_specs(&server.url(), &specs, "token").await.unwrap();
+        update.assert();
+    }
+}
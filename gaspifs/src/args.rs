@@ -1,4 +1,15 @@
-use clap::{Parser, Subcommand};
+use crate::beacon::Scope;
+use crate::beacon::lookup::LookupFormat;
+use crate::beacon::query::{Filter, Granularity, QueryRequest, VariantParameters};
+use crate::clinic::Workflow;
+use crate::clinic::jobs::JobStatus;
+use crate::clinic::results::{ColumnFilter, Pipeline, ResultFormat};
+use crate::networking::api::OutputFormat;
+use crate::networking::downloads::ByteRange;
+#[cfg(feature = "uploads")]
+use crate::vcf::index::IndexFormat;
+use clap::{Args, Parser, Subcommand};
+use std::str::FromStr;
 
 #[derive(Parser)]
 pub struct Cli {
@@ -22,11 +33,47 @@ pub enum Command {
         #[clap(short, long, value_parser)]
         project: Option<String>,
         /// The files to download
-        #[clap(short, long, value_parser, num_args(1..), required = true)]
+        #[clap(short, long, value_parser, num_args(1..), required_unless_present = "from_manifest", conflicts_with = "from_manifest")]
         files: Vec<String>,
-        /// Download destination
+        /// Download destination, relative manifest destinations are resolved against it
+        #[clap(short, long, value_parser, required_unless_present = "from_manifest")]
+        destination: Option<String>,
+        /// Read the files to download from a TSV, CSV or JSON manifest
+        #[clap(long, value_parser)]
+        from_manifest: Option<String>,
+        /// Where to write the outcome of each manifest row (default: <manifest>.result.<ext>)
+        #[clap(long, value_parser, requires = "from_manifest")]
+        result_manifest: Option<String>,
+        /// Attempt every file even if some of them fail
+        #[clap(short, long)]
+        keep_going: bool,
+        /// Also download the index (.tbi, .csi, .bai or .crai) of each file when there is one
+        #[clap(long, conflicts_with = "from_manifest")]
+        with_index: bool,
+    },
+    /// Stream a file from user space or a project to standard output
+    Cat {
+        /// The file to stream, as <project>:<file>, or <file> for user space
+        #[clap(value_parser)]
+        target: RemoteFile,
+        /// Only stream this byte range of the file, as START-END or START-
         #[clap(short, long, value_parser)]
-        destination: String,
+        range: Option<ByteRange>,
+        /// Only print the first N lines
+        #[clap(long, value_parser, value_name = "N")]
+        head: Option<usize>,
+        /// Decompress gzip or BGZF data on the fly
+        #[clap(short = 'z', long)]
+        decompress: bool,
+    },
+    /// Compute QC statistics of a local VCF file
+    Stats {
+        /// The plain or BGZF compressed VCF file
+        #[clap(value_parser)]
+        file: String,
+        /// How to print the statistics: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
     },
     #[cfg(feature = "uploads")]
     /// Upload files to user space or a project
@@ -35,11 +82,906 @@ pub enum Command {
         #[clap(short, long, value_parser)]
         project: Option<String>,
         /// The files to upload
-        #[clap(short, long, value_parser, num_args(1..), required = true)]
+        #[clap(short, long, value_parser, num_args(1..), required_unless_present = "from_manifest", conflicts_with = "from_manifest")]
         files: Vec<String>,
+        /// Read the files to upload from a TSV, CSV or JSON manifest
+        #[clap(long, value_parser)]
+        from_manifest: Option<String>,
+        /// Where to write the outcome of each manifest row (default: <manifest>.result.<ext>)
+        #[clap(long, value_parser, requires = "from_manifest")]
+        result_manifest: Option<String>,
+        /// Attempt every file even if some of them fail
+        #[clap(short, long)]
+        keep_going: bool,
+        /// Upload VCF files without validating them first
+        #[clap(long)]
+        skip_validation: bool,
+        /// Build missing or stale tabix/CSI indexes without asking
+        #[clap(long)]
+        build_index: bool,
+        /// The format of the indexes to build, tbi or csi
+        #[clap(long, value_parser, default_value = "tbi")]
+        index_format: IndexFormat,
+        /// Compress plain-text VCF, BED and GFF files with bgzip while uploading them
+        #[clap(long)]
+        bgzip: bool,
+        /// Encrypt the files with age, also to the recipients published in the project, even if no recipients are configured for the destination
+        #[clap(long)]
+        encrypt: bool,
+        /// Upload the files unencrypted, even if recipients are configured for the destination
+        #[clap(long, conflicts_with = "encrypt")]
+        no_encrypt: bool,
+        /// Also upload the QC statistics of each VCF file as <file name>.stats.json
+        #[clap(long, conflicts_with = "from_manifest")]
+        stats: bool,
+    },
+    #[cfg(feature = "uploads")]
+    /// Upload a single file, a named pipe or standard input under a given name
+    Put {
+        /// The file to upload, or - to read from standard input
+        #[clap(value_parser)]
+        source: String,
+        /// The name under which to store the upload (default: the local file name)
+        #[clap(short, long, value_parser, required_if_eq("source", "-"))]
+        name: Option<String>,
+        /// The project to which to upload
+        #[clap(short, long, value_parser)]
+        project: Option<String>,
+        /// Encrypt the upload with age, also to the recipients published in the project, even if no recipients are configured for the destination
+        #[clap(long)]
+        encrypt: bool,
+        /// Upload unencrypted, even if recipients are configured for the destination
+        #[clap(long, conflicts_with = "encrypt")]
+        no_encrypt: bool,
+    },
+    /// Show your storage and query usage against your quota
+    Quota {
+        /// How to print the quota: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Manage the keys used to encrypt uploads and decrypt downloads
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCommand,
+    },
+    /// Query the Beacon of the portal
+    Beacon {
+        #[clap(subcommand)]
+        command: BeaconCommand,
+    },
+    /// Manage and run the queries saved in the portal
+    Queries {
+        #[clap(subcommand)]
+        command: QueriesCommand,
+    },
+    /// Submit and follow clinical workflow jobs
+    Clinic {
+        #[clap(subcommand)]
+        command: ClinicCommand,
+    },
+    /// Create, start, stop and open notebook instances
+    Notebooks {
+        #[clap(subcommand)]
+        command: NotebooksCommand,
+    },
+    /// Define cohorts from YAML specs
+    Cohort {
+        #[clap(subcommand)]
+        command: CohortCommand,
+    },
+    #[cfg(feature = "admin")]
+    /// Manage the portal as an admin
+    Admin {
+        #[clap(subcommand)]
+        command: AdminCommand,
     },
     /// Login to the CLI
     Login {},
     /// Logout from the CLI
     Logout {},
 }
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Generate the identity used to decrypt downloads
+    Generate {
+        /// Replace the existing identity; files encrypted to it will no longer be readable
+        #[clap(long)]
+        force: bool,
+    },
+    /// Print the public key to share with collaborators
+    Show {},
+    /// List the recipients that uploads are encrypted to
+    List {
+        /// The project whose recipients to list (default: user space)
+        #[clap(short, long, value_parser)]
+        project: Option<String>,
+    },
+    /// Encrypt uploads to user space or a project to a recipient
+    Add {
+        /// The age public key of the recipient
+        #[clap(value_parser)]
+        recipient: String,
+        /// The project whose uploads are encrypted to the recipient (default: user space)
+        #[clap(short, long, value_parser)]
+        project: Option<String>,
+    },
+    /// Stop encrypting uploads to user space or a project to a recipient
+    Remove {
+        /// The age public key of the recipient
+        #[clap(value_parser)]
+        recipient: String,
+        /// The project to remove the recipient from (default: user space)
+        #[clap(short, long, value_parser)]
+        project: Option<String>,
+    },
+    /// Publish the recipients of a project and your public key, so that every member encrypts to them
+    #[cfg(feature = "uploads")]
+    Publish {
+        /// The project whose recipients to publish
+        #[clap(short, long, value_parser)]
+        project: String,
+    },
+    /// Encrypt every upload to a project to the recipients it publishes, pinning them
+    #[cfg(feature = "uploads")]
+    Use {
+        /// The project whose published recipients to use
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Accept published recipients that changed since they were pinned without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BeaconCommand {
+    /// Query g_variants, individuals, biosamples, runs or analyses
+    Query {
+        #[clap(flatten)]
+        query: QueryArgs,
+        /// How to print the results: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// List and search the filtering terms of the projects
+    Terms {
+        /// The entry type whose terms to list
+        #[clap(short, long, value_parser, default_value = "individuals")]
+        scope: Scope,
+        /// The projects whose terms to list
+        #[clap(short, long = "project", value_parser, num_args(1..), required = true)]
+        projects: Vec<String>,
+        /// Only list terms whose id or label contains these words
+        #[clap(long, value_parser)]
+        search: Option<String>,
+        /// Fetch the terms again instead of using the local cache
+        #[clap(long)]
+        refresh: bool,
+        /// How to print the terms: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Look up every variant of a local VCF file and annotate it with the Beacon results
+    Lookup {
+        /// The plain or bgzipped VCF file
+        #[clap(value_parser)]
+        vcf: String,
+        /// The projects to look the variants up in
+        #[clap(short, long = "project", value_parser, num_args(1..), required = true)]
+        projects: Vec<String>,
+        /// Where to write the results, - for standard output; .gz outputs are bgzipped
+        #[clap(short, long, value_parser, default_value = "-")]
+        output: String,
+        /// The output format, vcf or tsv (default: from the output name, else vcf)
+        #[clap(long, value_parser)]
+        format: Option<LookupFormat>,
+        /// The assembly of the VCF coordinates
+        #[clap(long, value_parser, default_value = "GRCH38")]
+        assembly: String,
+        /// The number of records read and looked up at a time
+        #[clap(long, value_parser, default_value_t = 100)]
+        batch_size: usize,
+        /// The maximum number of queries in flight
+        #[clap(long, value_parser, default_value_t = 4)]
+        concurrency: usize,
+        /// The maximum number of queries per second
+        #[clap(long, value_parser, default_value_t = 10.0)]
+        rate: f64,
+    },
+}
+
+/// The options describing a Beacon query, shared by `beacon query` and `queries save`.
+#[derive(Args)]
+pub struct QueryArgs {
+    /// The entry type to query
+    #[clap(value_parser)]
+    pub scope: Scope,
+    /// The projects to query
+    #[clap(short, long = "project", value_parser, num_args(1..), required = true)]
+    pub projects: Vec<String>,
+    /// A filter such as NCIT:C16576, age>=30, NCIT:C16576@biosamples or the label of a term; may be repeated
+    #[clap(short, long = "filter", value_parser)]
+    pub filters: Vec<Filter>,
+    /// Do not match the descendants of ontology terms
+    #[clap(long)]
+    pub no_descendants: bool,
+    /// The level of detail of the results: boolean, count or record
+    #[clap(short, long, value_parser, default_value = "count")]
+    pub granularity: Granularity,
+    /// The number of records to skip
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub skip: u64,
+    /// The maximum number of records to return
+    #[clap(long, value_parser, default_value_t = 100)]
+    pub limit: u64,
+    /// Query the entries of type --return related to the record with this id
+    #[clap(long, value_parser, requires = "return_scope")]
+    pub id: Option<String>,
+    /// The entry type to return for the record given by --id
+    #[clap(long = "return", value_parser, requires = "id")]
+    pub return_scope: Option<Scope>,
+    /// The assembly of the variant coordinates
+    #[clap(long, value_parser, default_value = "GRCH38")]
+    pub assembly: String,
+    /// The chromosome of the variants
+    #[clap(short, long, value_parser, requires = "start")]
+    pub chromosome: Option<String>,
+    /// The start position, or two comma-separated bounds
+    #[clap(long, value_parser, value_delimiter = ',', requires = "chromosome")]
+    pub start: Vec<u64>,
+    /// The end position, or two comma-separated bounds (default: the start)
+    #[clap(long, value_parser, value_delimiter = ',', requires = "chromosome")]
+    pub end: Vec<u64>,
+    /// The reference bases, N for any
+    #[clap(long = "ref", value_parser, default_value = "N")]
+    pub reference_bases: String,
+    /// The alternate bases, N for any
+    #[clap(long = "alt", value_parser, default_value = "N")]
+    pub alternate_bases: String,
+}
+
+impl QueryArgs {
+    /// Builds the query described by the options.
+    pub fn into_request(self) -> QueryRequest {
+        let variant = self.chromosome.map(|chromosome| VariantParameters {
+            assembly: self.assembly,
+            chromosome,
+            end: if self.end.is_empty() {
+                self.start.clone()
+            } else {
+                self.end
+            },
+            start: self.start,
+            reference_bases: self.reference_bases,
+            alternate_bases: self.alternate_bases,
+        });
+        QueryRequest {
+            scope: self.scope,
+            projects: self.projects,
+            filters: self.filters,
+            granularity: self.granularity,
+            skip: self.skip,
+            limit: self.limit,
+            variant,
+            related: self.id.zip(self.return_scope),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum QueriesCommand {
+    /// List the saved queries
+    List {
+        /// How to print the queries: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Print a saved query
+    Show {
+        /// The name of the query
+        #[clap(value_parser)]
+        name: String,
+        /// How to print the query: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Save a query under a name, replacing any query with the same name
+    Save {
+        /// The name of the query, 6 to 30 characters
+        #[clap(value_parser)]
+        name: String,
+        /// What the query selects, 10 to 200 characters
+        #[clap(long, value_parser)]
+        description: String,
+        #[clap(flatten)]
+        query: Box<QueryArgs>,
+    },
+    /// Run a saved query
+    Run {
+        /// The name of the query
+        #[clap(value_parser)]
+        name: String,
+        /// Override the level of detail of the results: boolean, count or record
+        #[clap(short, long, value_parser)]
+        granularity: Option<Granularity>,
+        /// Override the number of records to skip
+        #[clap(long, value_parser)]
+        skip: Option<u64>,
+        /// Override the maximum number of records to return
+        #[clap(long, value_parser)]
+        limit: Option<u64>,
+        /// How to print the results: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Delete a saved query
+    Delete {
+        /// The name of the query
+        #[clap(value_parser)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CohortCommand {
+    /// Validate and submit a cohort spec, then download the cohort files
+    Create {
+        /// The YAML file describing the cohort
+        #[clap(short, long, value_parser)]
+        spec: String,
+        /// The directory where to save the cohort files
+        #[clap(short, long, value_parser, default_value = ".")]
+        destination: String,
+        /// Submit the cohort without waiting for its files
+        #[clap(long)]
+        no_wait: bool,
+        /// Seconds between checks for the cohort files
+        #[clap(long, value_parser, default_value_t = 15)]
+        interval: u64,
+        /// Seconds to wait for the cohort files before giving up
+        #[clap(long, value_parser, default_value_t = 1800)]
+        timeout: u64,
+    },
+}
+
+#[cfg(feature = "admin")]
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Create, update and delete projects
+    Projects {
+        #[clap(subcommand)]
+        command: AdminProjectsCommand,
+    },
+}
+
+#[cfg(feature = "admin")]
+#[derive(Subcommand)]
+pub enum AdminProjectsCommand {
+    /// Create a project, or the projects of a YAML spec
+    Create {
+        /// The name of the project
+        #[clap(
+            value_parser,
+            required_unless_present = "spec",
+            conflicts_with = "spec"
+        )]
+        name: Option<String>,
+        /// The description of the project
+        #[clap(short, long, value_parser, required_unless_present = "spec")]
+        description: Option<String>,
+        /// The YAML file describing the project, or a list of them under `projects`
+        #[clap(short, long, value_parser, conflicts_with = "description")]
+        spec: Option<String>,
+    },
+    /// Change the description or files of a project, or apply a YAML spec to existing projects
+    Update {
+        /// The name of the project
+        #[clap(
+            value_parser,
+            required_unless_present = "spec",
+            conflicts_with = "spec"
+        )]
+        name: Option<String>,
+        /// The new description of the project
+        #[clap(short, long, value_parser)]
+        description: Option<String>,
+        /// The files the project keeps; others are removed from it
+        #[clap(short, long, value_parser, num_args(0..))]
+        files: Option<Vec<String>>,
+        /// The YAML file describing the projects
+        #[clap(short, long, value_parser, conflicts_with_all = ["description", "files"])]
+        spec: Option<String>,
+    },
+    /// Create or update the projects of a YAML spec so that they match it; safe to run again
+    Apply {
+        /// The YAML file describing the project, or a list of them under `projects`
+        #[clap(short, long, value_parser)]
+        spec: String,
+    },
+    /// Delete a project and its files
+    Delete {
+        /// The name of the project
+        #[clap(value_parser)]
+        name: String,
+        /// Delete without asking for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Show a project, or list all projects
+    Show {
+        /// The name of the project (default: list all projects)
+        #[clap(value_parser)]
+        name: Option<String>,
+        /// Only list projects whose name contains this text
+        #[clap(long, value_parser, conflicts_with = "name")]
+        search: Option<String>,
+        /// How to print the projects: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// List the ingestion errors of a project
+    Errors {
+        /// The name of the project
+        #[clap(value_parser)]
+        name: String,
+        /// Clear the errors after listing them
+        #[clap(long)]
+        clear: bool,
+        /// How to print the errors: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NotebooksCommand {
+    /// List your notebooks with their statuses, instance types and volume sizes
+    List {
+        /// How to print the notebooks: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Create a notebook, which starts once it is created
+    Create {
+        /// The name of the notebook, 5 to 30 letters, digits or '-'
+        #[clap(value_parser)]
+        name: String,
+        /// The instance type, such as ml.t3.medium or ml.g5.xlarge
+        #[clap(long, value_parser, default_value = "ml.t3.medium")]
+        instance_type: String,
+        /// The size of the volume in GB
+        #[clap(long, value_parser, default_value_t = 5)]
+        volume_size: u64,
+        #[clap(flatten)]
+        wait: WaitArgs,
+    },
+    /// Start a stopped notebook, optionally changing its instance type or volume size first
+    Start {
+        /// The name of the notebook
+        #[clap(value_parser)]
+        name: String,
+        /// Change the instance type before starting
+        #[clap(long, value_parser)]
+        instance_type: Option<String>,
+        /// Change the size of the volume in GB before starting
+        #[clap(long, value_parser)]
+        volume_size: Option<u64>,
+        #[clap(flatten)]
+        wait: WaitArgs,
+    },
+    /// Stop a running notebook, keeping its volume
+    Stop {
+        /// The name of the notebook
+        #[clap(value_parser)]
+        name: String,
+        #[clap(flatten)]
+        wait: WaitArgs,
+    },
+    /// Delete a stopped notebook and its volume
+    Delete {
+        /// The name of the notebook
+        #[clap(value_parser)]
+        name: String,
+    },
+    /// Print a signed URL that opens a running notebook
+    Url {
+        /// The name of the notebook
+        #[clap(value_parser)]
+        name: String,
+    },
+}
+
+#[derive(Args)]
+pub struct WaitArgs {
+    /// Wait until the notebook is in service, or stopped when stopping it
+    #[clap(long)]
+    pub wait: bool,
+    /// Seconds between checks of the notebook status
+    #[clap(long, value_parser, default_value_t = 15, requires = "wait")]
+    pub interval: u64,
+    /// Seconds to wait for the notebook before giving up
+    #[clap(long, value_parser, default_value_t = 900, requires = "wait")]
+    pub timeout: u64,
+}
+
+#[derive(Subcommand)]
+pub enum ClinicCommand {
+    /// Submit a clinical workflow job for a VCF file and print its job ID
+    Submit {
+        /// The project the job runs in
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// A local VCF file, which is uploaded to the project first, or the name of a project file
+        #[clap(long, value_parser)]
+        vcf: String,
+        /// The name of the job, 3 to 20 letters, digits or spaces (default: from the file name)
+        #[clap(short = 'n', long, value_parser)]
+        job_name: Option<String>,
+        /// The workflow the job is expected to run: svep, pgxflow or hybrid
+        #[clap(short, long, value_parser)]
+        workflow: Option<Workflow>,
+        /// Treat positions missing from the VCF as reference calls (PGxFlow)
+        #[clap(long)]
+        missing_to_ref: bool,
+    },
+    /// List and show clinical workflow jobs
+    Jobs {
+        #[clap(subcommand)]
+        command: ClinicJobsCommand,
+    },
+    /// Wait for a job to finish; exits with 0 if it completed, 2 if it failed, 4 if it expired and 5 on timeout
+    Watch {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Seconds before the first check, growing up to --max-interval while nothing changes
+        #[clap(long, value_parser, default_value_t = 10)]
+        interval: u64,
+        /// The longest number of seconds between checks
+        #[clap(long, value_parser, default_value_t = 120)]
+        max_interval: u64,
+        /// Seconds to wait before giving up (default: wait until the job finishes)
+        #[clap(long, value_parser)]
+        timeout: Option<u64>,
+    },
+    /// Export every page of the results of a job to a TSV, Parquet or JSON lines file
+    Results {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// The file format: tsv, parquet or jsonl
+        #[clap(short, long, value_parser, default_value = "tsv")]
+        format: ResultFormat,
+        /// The file to write (default: standard output; required for parquet)
+        #[clap(short, long, value_parser)]
+        output: Option<String>,
+        /// Only keep rows of these genes, comma-separated
+        #[clap(long, value_parser)]
+        gene: Option<String>,
+        /// Only keep rows whose consequence contains one of these terms, comma-separated
+        #[clap(long, value_parser)]
+        consequence: Option<String>,
+        /// Only keep rows whose global allele frequency is below this value or unknown
+        #[clap(long, value_parser)]
+        max_af: Option<f64>,
+        /// Only keep rows passing a column filter such as "SIFT (max)>=0.5"; may be repeated
+        #[clap(long = "where", value_parser)]
+        filters: Vec<ColumnFilter>,
+        /// The results of a PGxFlow job: pharmcat or lookup (default: lookup on PGxFlow portals)
+        #[clap(long, value_parser)]
+        pipeline: Option<Pipeline>,
+    },
+    /// Annotate variants of a job, selected from a TSV file such as the output of results
+    Annotate {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// The text of the annotation
+        #[clap(short, long, value_parser)]
+        annotation: String,
+        /// A TSV, CSV, JSON or JSON lines file with a row per variant, or - to read TSV from standard input
+        #[clap(long, value_parser)]
+        variants: String,
+        /// The columns of the variants to keep, comma-separated (default: every column)
+        #[clap(long, value_parser, value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    /// Save, list, show and delete the variants saved for reporting from a job
+    Variants {
+        #[clap(subcommand)]
+        command: ClinicVariantsCommand,
+    },
+    /// Sign off saved variants and negative reports, and list pending and past validations
+    Validate {
+        #[clap(subcommand)]
+        command: ClinicValidateCommand,
+    },
+    /// Generate and download the clinical reports of jobs
+    Report {
+        /// The jobs whose reports to download
+        #[clap(value_parser, num_args(1..), required_unless_present = "jobs")]
+        job_ids: Vec<String>,
+        /// The project of the jobs, and of the lines of --jobs that do not name one
+        #[clap(short, long, value_parser, required_unless_present = "jobs")]
+        project: Option<String>,
+        /// Read the jobs from the output of batch-submit, a job ID and project per line
+        #[clap(long, value_parser, conflicts_with = "job_ids")]
+        jobs: Option<String>,
+        /// Where to save the report of a single job
+        #[clap(short, long, value_parser, conflicts_with = "jobs")]
+        output: Option<String>,
+        /// The directory reports are saved in as <project>_<job id>_report.pdf
+        #[clap(short, long, value_parser, default_value = ".")]
+        destination: String,
+        /// The language of the report on hubs that offer several: en or id
+        #[clap(long, value_parser)]
+        lang: Option<String>,
+        /// The template of the report on hubs that offer several: crd, md or generic
+        #[clap(long, value_parser)]
+        template: Option<String>,
+        /// Attempt every report even if some of them fail
+        #[clap(short, long)]
+        keep_going: bool,
+    },
+    /// Fetch the QC plots and notes of the inputs of jobs into one table
+    Qc {
+        /// The jobs whose inputs to check
+        #[clap(value_parser, num_args(1..), required_unless_present = "jobs")]
+        job_ids: Vec<String>,
+        /// The project of the jobs, and of the lines of --jobs that do not name one
+        #[clap(short, long, value_parser, required_unless_present = "jobs")]
+        project: Option<String>,
+        /// Read the jobs from the output of batch-submit, a job ID and project per line
+        #[clap(long, value_parser, conflicts_with = "job_ids")]
+        jobs: Option<String>,
+        /// How to print the QC: table or json, which includes the URLs of the plots
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Add and list the QC notes of the input of a job
+    QcNote {
+        #[clap(subcommand)]
+        command: ClinicQcNoteCommand,
+    },
+    /// Submit a job for each row of a sample sheet and print their job IDs
+    BatchSubmit {
+        /// A TSV, CSV or JSON sample sheet with vcf and optional job_name and project columns
+        #[clap(value_parser)]
+        sample_sheet: String,
+        /// The project of the rows that do not name one
+        #[clap(short, long, value_parser)]
+        project: Option<String>,
+        /// The workflow the jobs are expected to run: svep, pgxflow or hybrid
+        #[clap(short, long, value_parser)]
+        workflow: Option<Workflow>,
+        /// Treat positions missing from the VCFs as reference calls (PGxFlow)
+        #[clap(long)]
+        missing_to_ref: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClinicJobsCommand {
+    /// List the clinical workflow jobs of a project, most recent first
+    List {
+        /// The project of the jobs
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Only list jobs with this status: pending, completed, failed or expired
+        #[clap(short, long, value_parser)]
+        status: Option<JobStatus>,
+        /// Only list jobs whose name or input contains this text
+        #[clap(long, value_parser)]
+        search: Option<String>,
+        /// The maximum number of jobs to list
+        #[clap(long, value_parser, default_value_t = 50)]
+        limit: usize,
+        /// How to print the jobs: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Print a clinical workflow job
+    Show {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// How to print the job: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClinicVariantsCommand {
+    /// Save variants of a job for reporting, selected from a TSV file such as the output of results
+    Save {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Why the variants are reported
+        #[clap(short, long, value_parser)]
+        comment: String,
+        /// A TSV, CSV, JSON or JSON lines file with a row per variant, or - to read TSV from standard input
+        #[clap(long, value_parser)]
+        variants: String,
+        /// The columns of the variants to keep, comma-separated (default: every column)
+        #[clap(long, value_parser, value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    /// List the variant collections saved for reporting from a job, most recent first
+    List {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// How to print the collections: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Print the variants of a saved collection as TSV, which save accepts back
+    Show {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The name of the collection
+        #[clap(value_parser)]
+        name: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// How to print the collection: table (TSV) or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Delete a saved variant collection
+    Delete {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The name of the collection
+        #[clap(value_parser)]
+        name: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClinicQcNoteCommand {
+    /// Add a note to the QC of the input of a job
+    Add {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// The title of the note
+        #[clap(short, long, value_parser)]
+        title: String,
+        /// The text of the note, or - to read it from standard input
+        #[clap(short, long, value_parser, default_value = "")]
+        description: String,
+    },
+    /// List the QC notes of the input of a job, oldest first
+    List {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// How to print the notes: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClinicValidateCommand {
+    /// List the saved variants, and the jobs without any, awaiting validation
+    Pending {
+        /// The project of the jobs
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Only list what awaits validation in this job (default: every completed job)
+        #[clap(value_parser)]
+        job_id: Option<String>,
+        /// The maximum number of completed jobs to check
+        #[clap(long, value_parser, default_value_t = 50)]
+        limit: usize,
+        /// How to print the validations: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Validate saved variants, or the negative report of a job when no collection is given
+    Approve {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The name of the saved variant collection
+        #[clap(value_parser)]
+        name: Option<String>,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// The validation comment
+        #[clap(short, long, value_parser)]
+        comment: String,
+        /// Sign without asking for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Revoke the validation of saved variants, or of the negative report of a job
+    Revoke {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The name of the saved variant collection
+        #[clap(value_parser)]
+        name: Option<String>,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// Sign without asking for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// List the validations recorded for a job, oldest first
+    History {
+        /// The ID of the job
+        #[clap(value_parser)]
+        job_id: String,
+        /// The project of the job
+        #[clap(short, long, value_parser)]
+        project: String,
+        /// How to print the validations: table or json
+        #[clap(short, long, value_parser, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+/// Represents a file in user space or a project, written as `<project>:<file>` or `<file>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteFile {
+    pub project: Option<String>,
+    pub file: String,
+}
+
+impl FromStr for RemoteFile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (project, file) = match value.split_once(':') {
+            Some((project, file)) => (Some(project.to_string()), file.to_string()),
+            None => (None, value.to_string()),
+        };
+        if file.is_empty() || project.as_deref() == Some("") {
+            return Err(format!(
+                "Invalid file {:?}, expected <project>:<file> or <file>",
+                value
+            ));
+        }
+        Ok(RemoteFile { project, file })
+    }
+}
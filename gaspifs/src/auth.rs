@@ -4,10 +4,11 @@ use aws_sdk_cognitoidentityprovider::{
     Client,
     types::{AuthFlowType, ChallengeNameType},
 };
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use std::{
     env,
     fs::File,
-    io::{self, Write},
+    io::{self, BufRead, Write},
     path::Path,
 };
 
@@ -37,6 +38,9 @@ pub async fn logout() -> Result<()> {
 /// If a refresh token is found in the home directory, it attempts to bypass the login process.
 /// If the refresh token is invalid or not found, it prompts the user for their username and password.
 /// The tokens are then saved to a file in the home directory for future use.
+/// Prompts and messages are written to standard error, so that they never mix with the output
+/// of a command, such as a file streamed by `cat`, and credentials are read from the terminal
+/// rather than standard input, which may carry the data of a command such as `put -`.
 /// # Returns
 /// A `Result` containing a tuple of access token, ID token, and refresh token if successful,
 /// or an error if the login process fails.
@@ -51,7 +55,7 @@ pub async fn login() -> Result<(String, String, String)> {
     let mut path = home::home_dir().ok_or(anyhow!("Could not determine home directory."))?;
     path.push(".refresh_token.txt");
     let path = Path::new(&path);
-    let mut username: String = String::new();
+    let username: String;
     let password: String;
 
     if path.exists() {
@@ -59,12 +63,9 @@ pub async fn login() -> Result<(String, String, String)> {
         let Ok((access_token, id_token, refresh_token)) = bypass_login(refresh_token).await else {
             eprintln!("Failed to bypass login. Please log in again.");
             // login flow
-            print!("Please enter your username: ");
-            io::stdout().flush()?;
-            std::io::stdin().read_line(&mut username)?;
-            username = username.trim().to_lowercase();
-            print!("Please enter your password: ");
-            io::stdout().flush()?;
+            username = read_username()?;
+            eprint!("Please enter your password: ");
+            io::stderr().flush()?;
             password = rpassword::read_password()?;
             let (access_token, id_token, refresh_token) = perform_login(username, password).await?;
             // end login flow
@@ -75,21 +76,102 @@ pub async fn login() -> Result<(String, String, String)> {
         return Ok((access_token, id_token, refresh_token));
     }
     // login flow
-    print!("Please enter your username: ");
-    io::stdout().flush()?;
-    std::io::stdin().read_line(&mut username)?;
-    username = username.trim().to_lowercase();
-    print!("Please enter your password: ");
-    io::stdout().flush()?;
+    username = read_username()?;
+    eprint!("Please enter your password: ");
+    io::stderr().flush()?;
     password = rpassword::read_password()?;
     let (access_token, id_token, refresh_token) = perform_login(username, password).await?;
-    println!("Login successful!");
+    eprintln!("Login successful!");
     // end login flow
     let mut file = File::create(path)?;
     file.write_all(refresh_token.as_bytes())?;
     Ok((access_token, id_token, refresh_token))
 }
 
+/// Prompts for the username and reads it from the terminal, like the password.
+/// Standard input is only used on platforms without `/dev/tty`.
+fn read_username() -> Result<String> {
+    eprint!("Please enter your username: ");
+    io::stderr().flush()?;
+    let mut username = String::new();
+    #[cfg(unix)]
+    io::BufReader::new(File::open("/dev/tty")?).read_line(&mut username)?;
+    #[cfg(not(unix))]
+    io::stdin().read_line(&mut username)?;
+    Ok(username.trim().to_lowercase())
+}
+
+/// Decodes the claims of a JSON Web Token, such as the ID token returned by `login`.
+/// The signature is not verified; the claims are only used to describe the user to the API.
+/// # Arguments
+/// * `token` - The token to decode.
+/// # Returns
+/// A `Result` containing the claims as a JSON object.
+/// # Example
+/// ```
+/// let claims = token_claims(&id_token)?;
+/// println!("Logged in as {}", claims["email"]);
+/// ```
+pub fn token_claims(token: &str) -> Result<serde_json::Value> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or(anyhow!("The token is not a JSON Web Token"))?;
+    let decoded = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('='))?;
+    Ok(serde_json::from_slice(&decoded)?)
+}
+
+/// Returns the Cognito identity ID of the user, under which the portal stores the user's files
+/// and which the clinic API expects as the `userId` of a job.
+/// # Arguments
+/// * `id_token` - The ID token returned by `login`.
+/// # Returns
+/// A `Result` containing the identity ID.
+/// # Errors
+/// Returns an error if `AWS_REGION` or `COGNITO_IDENTITY_POOL_ID` is not set, or if Cognito rejects the token.
+/// # Example
+/// ```
+/// let (_access_token, id_token, _refresh_token) = login().await?;
+/// let user_id = identity_id(&id_token).await?;
+/// ```
+pub async fn identity_id(id_token: &str) -> Result<String> {
+    let region = env::var("AWS_REGION")?;
+    let pool = env::var("COGNITO_IDENTITY_POOL_ID")
+        .map_err(|_| anyhow!("COGNITO_IDENTITY_POOL_ID is not set."))?;
+    let endpoint = format!("https://cognito-identity.{}.amazonaws.com", region);
+    get_identity_id(&endpoint, &pool, id_token).await
+}
+
+async fn get_identity_id(endpoint: &str, pool: &str, id_token: &str) -> Result<String> {
+    let claims = token_claims(id_token)?;
+    let issuer = claims["iss"]
+        .as_str()
+        .ok_or(anyhow!("The ID token has no issuer"))?;
+    let provider = issuer.trim_start_matches("https://");
+    // GetId is not signed, the ID token authenticates the request
+    let response = reqwest::Client::new()
+        .post(endpoint)
+        .header("X-Amz-Target", "AWSCognitoIdentityService.GetId")
+        .header(reqwest::header::CONTENT_TYPE, "application/x-amz-json-1.1")
+        .json(&serde_json::json!({
+            "IdentityPoolId": pool,
+            "Logins": {provider: id_token},
+        }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to get the identity of the user: {}",
+            response.text().await?
+        ));
+    }
+    let body: serde_json::Value = response.json().await?;
+    body["IdentityId"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or(anyhow!("Cognito returned no identity ID"))
+}
+
 async fn perform_login(username: String, password: String) -> Result<(String, String, String)> {
     let config = aws_config::defaults(BehaviorVersion::latest())
         .region(Region::new(env::var("AWS_REGION")?))
@@ -140,8 +222,8 @@ async fn perform_login(username: String, password: String) -> Result<(String, St
         ))
     } else if let Some(challenge_name) = auth_response.challenge_name() {
         if *challenge_name == ChallengeNameType::SoftwareTokenMfa {
-            print!("MFA detected. Please enter your OTP code: ");
-            io::stdout().flush()?;
+            eprint!("MFA detected. Please enter your OTP code: ");
+            io::stderr().flush()?;
             let totp_code = rpassword::read_password()?;
             let challenge_response = client
                 .respond_to_auth_challenge()
@@ -242,6 +324,42 @@ async fn bypass_login(refresh_token: String) -> Result<(String, String, String)>
 mod tests {
     use super::*;
 
+    fn token(claims: serde_json::Value) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        format!("eyJhbGciOiJSUzI1NiJ9.{}.signature", payload)
+    }
+
+    #[test]
+    fn test_token_claims() {
+        let claims = serde_json::json!({"email": "user@example.org", "given_name": "Ana"});
+        assert_eq!(token_claims(&token(claims.clone())).unwrap(), claims);
+        assert!(token_claims("not a token").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_identity_id() {
+        let mut server = mockito::Server::new_async().await;
+        let id_token = token(serde_json::json!({
+            "iss": "https://cognito-idp.ap-southeast-2.amazonaws.com/ap-southeast-2_abc"
+        }));
+        let mock = server
+            .mock("POST", "/")
+            .match_header("x-amz-target", "AWSCognitoIdentityService.GetId")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "IdentityPoolId": "pool",
+                "Logins": {"cognito-idp.ap-southeast-2.amazonaws.com/ap-southeast-2_abc": id_token},
+            })))
+            .with_status(200)
+            .with_body(r#"{"IdentityId": "ap-southeast-2:1234"}"#)
+            .create();
+
+        let identity = get_identity_id(&server.url(), "pool", &id_token)
+            .await
+            .unwrap();
+        assert_eq!(identity, "ap-southeast-2:1234");
+        mock.assert();
+    }
+
     #[tokio::test]
     async fn test_perform_login() {
         let username =
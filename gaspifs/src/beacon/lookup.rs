@@ -0,0 +1,484 @@
+use super::Scope;
+use super::query::{Granularity, QueryRequest, VariantParameters};
+use crate::networking::api::Session;
+use crate::vcf::{self, bgzf};
+use anyhow::{Result, anyhow};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// INFO fields added to the records of an annotated VCF, one value per alternate allele.
+const INFO_HEADERS: [&str; 2] = [
+    "##INFO=<ID=BEACON_EXISTS,Number=A,Type=Integer,Description=\"1 if the allele is in the queried projects, 0 if not\">",
+    "##INFO=<ID=BEACON_COUNT,Number=A,Type=Integer,Description=\"Number of matching variants in the queried projects\">",
+];
+
+/// How the results of a lookup are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupFormat {
+    /// The input VCF with `BEACON_EXISTS` and `BEACON_COUNT` added to the INFO column.
+    Vcf,
+    /// One row per alternate allele.
+    Tsv,
+}
+
+impl FromStr for LookupFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "vcf" => Ok(LookupFormat::Vcf),
+            "tsv" => Ok(LookupFormat::Tsv),
+            _ => Err(anyhow!("Unknown format {:?}, expected vcf or tsv", value)),
+        }
+    }
+}
+
+impl LookupFormat {
+    /// Guesses the format from the output path, defaulting to VCF.
+    pub fn for_path(path: &str) -> Self {
+        let path = path.to_lowercase();
+        if path.ends_with(".tsv") || path.ends_with(".tsv.gz") || path.ends_with(".txt") {
+            LookupFormat::Tsv
+        } else {
+            LookupFormat::Vcf
+        }
+    }
+}
+
+/// Controls which projects are queried and how fast.
+#[derive(Debug, Clone)]
+pub struct LookupOptions {
+    pub projects: Vec<String>,
+    pub assembly: String,
+    /// Number of records read and queried at a time.
+    pub batch_size: usize,
+    /// Maximum number of queries in flight.
+    pub concurrency: usize,
+    /// Maximum number of queries started per second.
+    pub rate: f64,
+}
+
+/// Counts of what a lookup did, printed once it is done.
+#[derive(Debug, Default, PartialEq)]
+pub struct LookupSummary {
+    pub records: u64,
+    pub queried: u64,
+    pub found: u64,
+}
+
+/// The Beacon answer for one alternate allele.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hit {
+    exists: bool,
+    count: u64,
+}
+
+/// A VCF record and the variant to query for each of its alternate alleles.
+/// Alleles that cannot be queried, such as symbolic alleles, have no variant.
+struct Record {
+    line: String,
+    variants: Vec<Option<VariantParameters>>,
+}
+
+/// Queries the Beacon for every alternate allele of a VCF file and writes the annotated records.
+/// Records are read and queried in batches of `options.batch_size`, so memory use does not grow
+/// with the size of the file; queries are started at most `options.rate` times per second.
+/// # Arguments
+/// * `session` - The Beacon session.
+/// * `input` - The path to the plain or BGZF compressed VCF file.
+/// * `output` - The path of the output, `-` for standard output; `.gz` outputs are BGZF compressed.
+/// * `format` - Whether to write an annotated VCF or a TSV.
+/// * `options` - The projects to query and the query rate.
+/// # Returns
+/// A `Result` containing a `LookupSummary`.
+/// # Errors
+/// Returns an error if the VCF file is malformed, if a query fails after its retries, or if the output cannot be written.
+/// # Example
+/// ```
+/// let summary = lookup_vcf(&session, "sample.vcf.gz", "annotated.vcf.gz", LookupFormat::Vcf, &options).await?;
+/// println!("{} of {} alleles found", summary.found, summary.queried);
+/// ```
+pub async fn lookup_vcf(
+    session: &Session,
+    input: &str,
+    output: &str,
+    format: LookupFormat,
+    options: &LookupOptions,
+) -> Result<LookupSummary> {
+    let reader = vcf::open(input)?;
+    let lower = output.to_lowercase();
+    if output == "-" {
+        let (summary, _) = annotate(session, reader, io::stdout().lock(), format, options).await?;
+        Ok(summary)
+    } else if lower.ends_with(".gz") || lower.ends_with(".bgz") {
+        let writer = bgzf::Writer::new(BufWriter::new(File::create(output)?));
+        let (summary, writer) = annotate(session, reader, writer, format, options).await?;
+        writer.finish()?;
+        Ok(summary)
+    } else {
+        let writer = BufWriter::new(File::create(output)?);
+        let (summary, mut writer) = annotate(session, reader, writer, format, options).await?;
+        writer.flush()?;
+        Ok(summary)
+    }
+}
+
+/// Reads records in batches, queries them and writes them in their original order.
+async fn annotate<W: Write>(
+    session: &Session,
+    mut reader: impl BufRead,
+    mut writer: W,
+    format: LookupFormat,
+    options: &LookupOptions,
+) -> Result<(LookupSummary, W)> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {pos} records looked up {msg}")
+            .unwrap(),
+    );
+    let limiter = rate_limiter(options.rate)?;
+    let mut summary = LookupSummary::default();
+    let mut line = String::new();
+    let mut number = 0;
+
+    // header
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("The VCF file has no #CHROM header line"));
+        }
+        number += 1;
+        let header = line.trim_end_matches(['\n', '\r']);
+        if header.starts_with("#CHROM") {
+            match format {
+                LookupFormat::Vcf => {
+                    for info in INFO_HEADERS {
+                        writeln!(writer, "{}", info)?;
+                    }
+                    writeln!(writer, "{}", header)?;
+                }
+                LookupFormat::Tsv => writeln!(writer, "CHROM\tPOS\tID\tREF\tALT\tEXISTS\tCOUNT")?,
+            }
+            break;
+        }
+        if format == LookupFormat::Vcf {
+            writeln!(writer, "{}", header)?;
+        }
+    }
+
+    // records
+    let mut batch = Vec::with_capacity(options.batch_size);
+    loop {
+        line.clear();
+        let done = reader.read_line(&mut line)? == 0;
+        if !done {
+            number += 1;
+            let record = line.trim_end_matches(['\n', '\r']);
+            if !record.is_empty() {
+                batch.push(
+                    parse_record(record, &options.assembly)
+                        .map_err(|err| anyhow!("line {}: {}", number, err))?,
+                );
+            }
+        }
+        if batch.len() >= options.batch_size.max(1) || (done && !batch.is_empty()) {
+            let hits = query_batch(session, &batch, options, &limiter).await?;
+            for (record, hits) in batch.drain(..).zip(hits) {
+                summary.records += 1;
+                summary.queried += hits.iter().flatten().count() as u64;
+                summary.found += hits.iter().flatten().filter(|hit| hit.exists).count() as u64;
+                write_record(&mut writer, &record, &hits, format)?;
+            }
+            pb.set_position(summary.records);
+            pb.set_message(format!("({} alleles found)", summary.found));
+        }
+        if done {
+            break;
+        }
+    }
+    pb.finish_and_clear();
+    Ok((summary, writer))
+}
+
+/// Creates the interval that paces queries, allowing `rate` queries per second.
+fn rate_limiter(rate: f64) -> Result<Mutex<Interval>> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(anyhow!("The query rate must be a positive number"));
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Ok(Mutex::new(interval))
+}
+
+/// Queries every allele of a batch of records, at most `options.concurrency` at a time.
+/// Returns the hits of each record in the same order as the records.
+async fn query_batch(
+    session: &Session,
+    batch: &[Record],
+    options: &LookupOptions,
+    limiter: &Mutex<Interval>,
+) -> Result<Vec<Vec<Option<Hit>>>> {
+    let variants =
+        batch
+            .iter()
+            .flat_map(|record| record.variants.iter())
+            .map(|variant| async move {
+                let Some(variant) = variant else {
+                    return Ok(None);
+                };
+                limiter.lock().await.tick().await;
+                query_variant(session, variant, &options.projects)
+                    .await
+                    .map(Some)
+            });
+    let mut hits: Vec<Option<Hit>> = stream::iter(variants)
+        .buffered(options.concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    let mut hits = hits.drain(..);
+    Ok(batch
+        .iter()
+        .map(|record| hits.by_ref().take(record.variants.len()).collect())
+        .collect())
+}
+
+/// Asks the Beacon whether a single variant exists in the projects and how often.
+async fn query_variant(
+    session: &Session,
+    variant: &VariantParameters,
+    projects: &[String],
+) -> Result<Hit> {
+    let request = QueryRequest {
+        scope: Scope::GenomicVariants,
+        projects: projects.to_vec(),
+        filters: Vec::new(),
+        granularity: Granularity::Count,
+        skip: 0,
+        limit: 1,
+        variant: Some(variant.clone()),
+        related: None,
+    };
+    let response = session.post(&request.endpoint(), &request.body()?).await?;
+    let summary = &response["responseSummary"];
+    let count = summary["numTotalResults"].as_u64().unwrap_or(0);
+    Ok(Hit {
+        exists: summary["exists"].as_bool().unwrap_or(count > 0),
+        count,
+    })
+}
+
+/// Parses a VCF record into the variants to query, one per alternate allele.
+/// Beacon coordinates are 0-based, so the VCF position is shifted by one.
+fn parse_record(line: &str, assembly: &str) -> Result<Record> {
+    let fields: Vec<&str> = line.splitn(9, '\t').collect();
+    if fields.len() < 8 {
+        return Err(anyhow!(
+            "expected at least 8 columns, found {}",
+            fields.len()
+        ));
+    }
+    let position: u64 = fields[1]
+        .parse()
+        .ok()
+        .filter(|position| *position > 0)
+        .ok_or(anyhow!("invalid position {:?}", fields[1]))?;
+    let reference = fields[3];
+    let variants = fields[4]
+        .split(',')
+        .map(|alternate| {
+            (is_bases(reference) && is_bases(alternate)).then(|| VariantParameters {
+                assembly: assembly.to_string(),
+                chromosome: fields[0].to_string(),
+                start: vec![position - 1],
+                end: vec![position - 1 + reference.len() as u64],
+                reference_bases: reference.to_uppercase(),
+                alternate_bases: alternate.to_uppercase(),
+            })
+        })
+        .collect();
+    Ok(Record {
+        line: line.to_string(),
+        variants,
+    })
+}
+
+/// Returns `true` if the allele is made of plain bases, unlike `.`, `*` or `<DEL>`.
+fn is_bases(allele: &str) -> bool {
+    !allele.is_empty()
+        && allele
+            .bytes()
+            .all(|base| matches!(base.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'N'))
+}
+
+/// Writes a record with its hits, using `.` for alleles that were not queried.
+fn write_record<W: Write>(
+    writer: &mut W,
+    record: &Record,
+    hits: &[Option<Hit>],
+    format: LookupFormat,
+) -> Result<()> {
+    let exists =
+        |hit: &Option<Hit>| hit.map_or(".".to_string(), |hit| (hit.exists as u8).to_string());
+    let count = |hit: &Option<Hit>| hit.map_or(".".to_string(), |hit| hit.count.to_string());
+    let mut fields: Vec<&str> = record.line.split('\t').collect();
+    match format {
+        LookupFormat::Vcf => {
+            let annotation = format!(
+                "BEACON_EXISTS={};BEACON_COUNT={}",
+                hits.iter().map(exists).collect::<Vec<_>>().join(","),
+                hits.iter().map(count).collect::<Vec<_>>().join(","),
+            );
+            let info = match fields[7] {
+                "" | "." => annotation,
+                info => format!("{};{}", info, annotation),
+            };
+            fields[7] = &info;
+            writeln!(writer, "{}", fields.join("\t"))?;
+        }
+        LookupFormat::Tsv => {
+            for (alternate, hit) in fields[4].split(',').zip(hits) {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    fields[0],
+                    fields[1],
+                    fields[2],
+                    fields[3],
+                    alternate,
+                    exists(hit),
+                    count(hit)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    const VCF: &str = "##fileformat=VCFv4.2\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        1\t100\trs1\tA\tG,<DEL>\t.\tPASS\tDP=10\n\
+        1\t200\t.\tCT\tC\t.\tPASS\t.\n";
+
+    fn options() -> LookupOptions {
+        LookupOptions {
+            projects: vec!["project1".to_string()],
+            assembly: "GRCH38".to_string(),
+            batch_size: 1,
+            concurrency: 2,
+            rate: 1000.0,
+        }
+    }
+
+    async fn mock_variant(
+        server: &mut Server,
+        start: &str,
+        alternate: &str,
+        count: u64,
+    ) -> mockito::Mock {
+        server
+            .mock("POST", "/g_variants")
+            .match_body(Matcher::PartialJson(json!({
+                "query": {"requestParameters": {"start": [start], "alternateBases": alternate}}
+            })))
+            .with_status(200)
+            .with_body(
+                json!({"responseSummary": {"exists": count > 0, "numTotalResults": count}})
+                    .to_string(),
+            )
+            .create_async()
+            .await
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let record = parse_record("chr2\t10\t.\tAC\tA,*\t.\t.\t.", "GRCH38").unwrap();
+        let variant = record.variants[0].as_ref().unwrap();
+        assert_eq!(variant.start, [9]);
+        assert_eq!(variant.end, [11]);
+        assert_eq!(variant.chromosome, "chr2");
+        assert!(record.variants[1].is_none());
+        assert!(parse_record("chr2\t0\t.\tA\tG\t.\t.\t.", "GRCH38").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_annotate_vcf() {
+        let mut server = Server::new_async().await;
+        let first = mock_variant(&mut server, "99", "G", 3).await;
+        let second = mock_variant(&mut server, "199", "C", 0).await;
+        let session = Session::new(&server.url(), "token".to_string());
+
+        let (summary, output) = annotate(
+            &session,
+            VCF.as_bytes(),
+            Vec::new(),
+            LookupFormat::Vcf,
+            &options(),
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[1], INFO_HEADERS[0]);
+        assert_eq!(
+            lines[4],
+            "1\t100\trs1\tA\tG,<DEL>\t.\tPASS\tDP=10;BEACON_EXISTS=1,.;BEACON_COUNT=3,."
+        );
+        assert_eq!(
+            lines[5],
+            "1\t200\t.\tCT\tC\t.\tPASS\tBEACON_EXISTS=0;BEACON_COUNT=0"
+        );
+        assert_eq!(
+            summary,
+            LookupSummary {
+                records: 2,
+                queried: 2,
+                found: 1
+            }
+        );
+        first.assert();
+        second.assert();
+    }
+
+    #[tokio::test]
+    async fn test_annotate_tsv() {
+        let mut server = Server::new_async().await;
+        mock_variant(&mut server, "99", "G", 3).await;
+        mock_variant(&mut server, "199", "C", 0).await;
+        let session = Session::new(&server.url(), "token".to_string());
+        let options = LookupOptions {
+            batch_size: 10,
+            ..options()
+        };
+
+        let (_, output) = annotate(
+            &session,
+            VCF.as_bytes(),
+            Vec::new(),
+            LookupFormat::Tsv,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "CHROM\tPOS\tID\tREF\tALT\tEXISTS\tCOUNT\n\
+             1\t100\trs1\tA\tG\t1\t3\n\
+             1\t100\trs1\tA\t<DEL>\t.\t.\n\
+             1\t200\t.\tCT\tC\t0\t0\n"
+        );
+    }
+}
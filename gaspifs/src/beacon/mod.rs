@@ -0,0 +1,76 @@
+pub mod lookup;
+pub mod query;
+pub mod saved;
+pub mod terms;
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::str::FromStr;
+
+/// The Beacon v2 entry types that can be queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    GenomicVariants,
+    Individuals,
+    Biosamples,
+    Runs,
+    Analyses,
+    Datasets,
+    Cohorts,
+}
+
+impl Scope {
+    /// Returns the path segment of the scope, as used by the Beacon API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::GenomicVariants => "g_variants",
+            Scope::Individuals => "individuals",
+            Scope::Biosamples => "biosamples",
+            Scope::Runs => "runs",
+            Scope::Analyses => "analyses",
+            Scope::Datasets => "datasets",
+            Scope::Cohorts => "cohorts",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "g_variants" | "variants" => Ok(Scope::GenomicVariants),
+            "individuals" => Ok(Scope::Individuals),
+            "biosamples" => Ok(Scope::Biosamples),
+            "runs" => Ok(Scope::Runs),
+            "analyses" => Ok(Scope::Analyses),
+            "datasets" => Ok(Scope::Datasets),
+            "cohorts" => Ok(Scope::Cohorts),
+            _ => Err(anyhow!(
+                "Unknown scope {:?}, expected g_variants, individuals, biosamples, runs, analyses, datasets or cohorts",
+                value
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scope() {
+        assert_eq!(
+            "g_variants".parse::<Scope>().unwrap(),
+            Scope::GenomicVariants
+        );
+        assert_eq!(Scope::Biosamples.to_string(), "biosamples");
+        assert!("samples".parse::<Scope>().is_err());
+    }
+}
@@ -0,0 +1,610 @@
+use super::Scope;
+use crate::networking::api::OutputFormat;
+use anyhow::{Result, anyhow};
+use serde_json::{Value, json};
+use std::fmt;
+use std::str::FromStr;
+
+/// The level of detail requested from a Beacon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Boolean,
+    Count,
+    Record,
+}
+
+impl fmt::Display for Granularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Granularity::Boolean => "boolean",
+            Granularity::Count => "count",
+            Granularity::Record => "record",
+        })
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "boolean" => Ok(Granularity::Boolean),
+            "count" => Ok(Granularity::Count),
+            "record" => Ok(Granularity::Record),
+            _ => Err(anyhow!(
+                "Unknown granularity {:?}, expected boolean, count or record",
+                value
+            )),
+        }
+    }
+}
+
+/// The kinds of Beacon v2 filters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterKind {
+    /// An ontology term such as `NCIT:C16576`.
+    Ontology { include_descendants: bool },
+    /// A comparison of a field with a value, such as `age>=30`.
+    Alphanumeric { operator: String, value: String },
+    /// A term defined by the Beacon itself.
+    Custom,
+}
+
+/// Represents a Beacon v2 filter, written on the command line as `<id>`, `<id><operator><value>`
+/// or either of them followed by `@<scope>` to filter on another entry type.
+/// The id may also be the label of a filtering term, which `terms::resolve_filters` replaces with the term id.
+/// Ids of the form `prefix:suffix` are ontology terms; the operators are `=`, `!=`, `<`, `<=`, `>` and `>=`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub id: String,
+    pub kind: FilterKind,
+    pub scope: Option<Scope>,
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (term, scope) = match value.rsplit_once('@') {
+            Some((term, scope)) => match scope.parse::<Scope>() {
+                Ok(scope) => (term, Some(scope)),
+                Err(_) => (value, None),
+            },
+            None => (value, None),
+        };
+        let (id, kind) = match term.find(['=', '!', '<', '>']) {
+            Some(at) => {
+                let (id, rest) = term.split_at(at);
+                let length = if rest[1..].starts_with('=') { 2 } else { 1 };
+                let (operator, value) = rest.split_at(length);
+                // the Beacon specification spells "not equal" as a single `!`
+                let operator = match operator {
+                    "!=" | "!" => "!",
+                    "==" => "=",
+                    operator => operator,
+                };
+                let kind = FilterKind::Alphanumeric {
+                    operator: operator.to_string(),
+                    value: value.to_string(),
+                };
+                (id, kind)
+            }
+            None if is_curie(term) => (
+                term,
+                FilterKind::Ontology {
+                    include_descendants: true,
+                },
+            ),
+            None => (term, FilterKind::Custom),
+        };
+        if id.is_empty() {
+            return Err(anyhow!("Invalid filter {:?}, the id is missing", value));
+        }
+        Ok(Filter {
+            id: id.to_string(),
+            kind,
+            scope,
+        })
+    }
+}
+
+impl Filter {
+    /// Serialises the filter as in a Beacon v2 request, defaulting its scope to that of the query.
+    pub fn to_json(&self, default_scope: Scope) -> Value {
+        let scope = self.scope.unwrap_or(default_scope).as_str();
+        match &self.kind {
+            FilterKind::Ontology {
+                include_descendants,
+            } => json!({
+                "scope": scope,
+                "id": self.id,
+                "includeDescendantTerms": include_descendants,
+            }),
+            FilterKind::Alphanumeric { operator, value } => json!({
+                "scope": scope,
+                "id": self.id,
+                "operator": operator,
+                "value": value,
+            }),
+            FilterKind::Custom => json!({"scope": scope, "id": self.id}),
+        }
+    }
+}
+
+impl Filter {
+    /// Reads a filter serialised as in a Beacon v2 request, such as those of saved queries.
+    /// The scope is left unset when it is `default_scope`.
+    /// # Errors
+    /// Returns an error if the filter has no id or an unknown scope.
+    pub fn from_json(value: &Value, default_scope: Scope) -> Result<Self> {
+        let id = value["id"]
+            .as_str()
+            .filter(|id| !id.is_empty())
+            .ok_or(anyhow!("Invalid filter {}, the id is missing", value))?;
+        let scope = match value["scope"].as_str() {
+            Some(scope) => Some(scope.parse::<Scope>()?),
+            None => None,
+        }
+        .filter(|scope| *scope != default_scope);
+        let operator = value["operator"].as_str().unwrap_or_default();
+        let text = match &value["value"] {
+            Value::Null => String::new(),
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        // as in the portal, ontology terms take precedence over comparisons
+        let kind = if is_curie(id) {
+            FilterKind::Ontology {
+                include_descendants: value["includeDescendantTerms"].as_bool().unwrap_or(true),
+            }
+        } else if operator.is_empty() && text.is_empty() {
+            FilterKind::Custom
+        } else {
+            FilterKind::Alphanumeric {
+                operator: operator.to_string(),
+                value: text,
+            }
+        };
+        Ok(Filter {
+            id: id.to_string(),
+            kind,
+            scope,
+        })
+    }
+}
+
+impl fmt::Display for Filter {
+    /// Writes the filter as it is given to `--filter`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id)?;
+        if let FilterKind::Alphanumeric { operator, value } = &self.kind {
+            let operator = if operator == "!" { "!=" } else { operator };
+            write!(f, "{}{}", operator, value)?;
+        }
+        if let Some(scope) = self.scope {
+            write!(f, "@{}", scope)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if the id looks like an ontology term, `prefix:suffix`.
+pub(super) fn is_curie(id: &str) -> bool {
+    match id.split_once(':') {
+        Some((prefix, suffix)) => {
+            prefix
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+                && !suffix.is_empty()
+        }
+        None => false,
+    }
+}
+
+/// The genomic region and alleles of a g_variants query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantParameters {
+    pub assembly: String,
+    pub chromosome: String,
+    /// One position, or two for the bounds of a bracket query.
+    pub start: Vec<u64>,
+    pub end: Vec<u64>,
+    pub reference_bases: String,
+    pub alternate_bases: String,
+}
+
+impl VariantParameters {
+    /// Serialises the parameters as the `requestParameters` of a Beacon v2 request.
+    /// # Errors
+    /// Returns an error if `start` or `end` do not hold one or two positions, or if a range ends before it starts.
+    pub fn to_json(&self) -> Result<Value> {
+        for (name, positions) in [("start", &self.start), ("end", &self.end)] {
+            if !(1..=2).contains(&positions.len()) {
+                return Err(anyhow!("--{} takes one or two positions", name));
+            }
+            if positions.windows(2).any(|pair| pair[1] < pair[0]) {
+                return Err(anyhow!("--{} positions must be in increasing order", name));
+            }
+        }
+        if self.end.last() < self.start.first() {
+            return Err(anyhow!("The variant range ends before it starts"));
+        }
+        // positions are sent as strings, as the portal does
+        let positions =
+            |positions: &[u64]| -> Vec<String> { positions.iter().map(u64::to_string).collect() };
+        Ok(json!({
+            "assemblyId": self.assembly,
+            "referenceName": self.chromosome,
+            "start": positions(&self.start),
+            "end": positions(&self.end),
+            "referenceBases": self.reference_bases,
+            "alternateBases": self.alternate_bases,
+        }))
+    }
+}
+
+impl VariantParameters {
+    /// Reads the `requestParameters` of a Beacon v2 request, whose positions may be numbers or strings.
+    /// # Errors
+    /// Returns an error if the chromosome or start is missing, or if a position is not a number.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let text = |name: &str, default: &str| -> String {
+            value[name]
+                .as_str()
+                .filter(|text| !text.is_empty())
+                .unwrap_or(default)
+                .to_string()
+        };
+        let positions = |name: &str| -> Result<Vec<u64>> {
+            let values = match &value[name] {
+                Value::Array(values) => values.clone(),
+                Value::Null => Vec::new(),
+                single => vec![single.clone()],
+            };
+            values
+                .iter()
+                .map(|position| match position {
+                    Value::Number(number) => number.as_u64(),
+                    Value::String(text) => text.trim().parse().ok(),
+                    _ => None,
+                })
+                .map(|position| position.ok_or(anyhow!("Invalid {} position in {}", name, value)))
+                .collect()
+        };
+        let chromosome = text("referenceName", "");
+        let start = positions("start")?;
+        if chromosome.is_empty() || start.is_empty() {
+            return Err(anyhow!(
+                "The variant parameters need a referenceName and a start"
+            ));
+        }
+        let end = positions("end")?;
+        Ok(VariantParameters {
+            assembly: text("assemblyId", "GRCH38"),
+            chromosome,
+            end: if end.is_empty() { start.clone() } else { end },
+            start,
+            reference_bases: text("referenceBases", "N"),
+            alternate_bases: text("alternateBases", "N"),
+        })
+    }
+}
+
+/// Describes a Beacon v2 query against an entry type, or against the entries related to a single record.
+#[derive(Debug, Clone)]
+pub struct QueryRequest {
+    pub scope: Scope,
+    pub projects: Vec<String>,
+    pub filters: Vec<Filter>,
+    pub granularity: Granularity,
+    pub skip: u64,
+    pub limit: u64,
+    pub variant: Option<VariantParameters>,
+    /// Query the entries of this type related to a record of `scope`, given by its id.
+    pub related: Option<(String, Scope)>,
+}
+
+impl QueryRequest {
+    /// Returns the entry type of the results.
+    pub fn returned_scope(&self) -> Scope {
+        self.related
+            .as_ref()
+            .map_or(self.scope, |(_, scope)| *scope)
+    }
+
+    /// Returns the endpoint the query is posted to, relative to the API.
+    pub fn endpoint(&self) -> String {
+        match &self.related {
+            Some((id, returned)) => format!("{}/{}/{}", self.scope, id, returned),
+            None => self.scope.to_string(),
+        }
+    }
+
+    /// Stops the ontology filters of the query from matching the descendants of their terms.
+    pub fn exclude_descendants(&mut self) {
+        for filter in &mut self.filters {
+            if let FilterKind::Ontology {
+                include_descendants,
+            } = &mut filter.kind
+            {
+                *include_descendants = false;
+            }
+        }
+    }
+
+    /// Builds the Beacon v2 request body.
+    /// # Errors
+    /// Returns an error if no project is given, or if a query returning variants has no region.
+    pub fn body(&self) -> Result<Value> {
+        if self.projects.is_empty() {
+            return Err(anyhow!("At least one project is required"));
+        }
+        let mut body = json!({
+            "projects": self.projects,
+            "query": {
+                "filters": self.filters.iter().map(|filter| filter.to_json(self.scope)).collect::<Vec<_>>(),
+                "requestedGranularity": self.granularity.to_string(),
+                "pagination": {"skip": self.skip, "limit": self.limit},
+            },
+            "meta": {"apiVersion": "v2.0"},
+        });
+        if self.returned_scope() == Scope::GenomicVariants {
+            let variant = self.variant.as_ref().ok_or(anyhow!(
+                "Variant queries need --chromosome and --start; use --id and --return to list the variants of a record"
+            ))?;
+            body["query"]["requestParameters"] = variant.to_json()?;
+        }
+        Ok(body)
+    }
+}
+
+/// Formats a Beacon v2 response for printing.
+/// Records are shown one per row with a column per field; summaries show whether anything
+/// matched and how many results there are.
+/// # Example
+/// ```
+/// let response = post(&api, &request.endpoint(), &request.body()?, &token).await?;
+/// print!("{}", format_response(&response, OutputFormat::Table)?);
+/// ```
+pub fn format_response(response: &Value, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(response)?));
+    }
+
+    let summary = &response["responseSummary"];
+    let mut output = format!("exists: {}\n", summary["exists"].as_bool().unwrap_or(false));
+    if let Some(count) = summary["numTotalResults"].as_u64() {
+        output.push_str(&format!("results: {}\n", count));
+    }
+
+    let records: Vec<&serde_json::Map<String, Value>> = response["response"]["resultSets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|set| set["results"].as_array().into_iter().flatten())
+        .filter_map(Value::as_object)
+        .collect();
+    if records.is_empty() {
+        return Ok(output);
+    }
+
+    let mut columns: Vec<&str> = Vec::new();
+    for record in &records {
+        for key in record.keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            columns
+                .iter()
+                .map(|column| record.get(*column).map(cell).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([column.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    output.push('\n');
+    let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        output.push_str(line.join("  ").trim_end());
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Formats a field of a record as a table cell: ontology terms show their label, lists are comma separated.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        Value::Array(values) => values.iter().map(cell).collect::<Vec<_>>().join(", "),
+        Value::Object(object) => match object.get("label").or(object.get("id")) {
+            Some(value) => cell(value),
+            None if object.len() == 1 => object.values().map(cell).collect(),
+            None => value.to_string(),
+        },
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(scope: Scope) -> QueryRequest {
+        QueryRequest {
+            scope,
+            projects: vec!["project1".to_string()],
+            filters: Vec::new(),
+            granularity: Granularity::Count,
+            skip: 0,
+            limit: 100,
+            variant: None,
+            related: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        let filter: Filter = "NCIT:C16576".parse().unwrap();
+        assert_eq!(
+            filter.kind,
+            FilterKind::Ontology {
+                include_descendants: true
+            }
+        );
+        let filter: Filter = "age>=30@individuals".parse().unwrap();
+        assert_eq!(filter.id, "age");
+        assert_eq!(filter.scope, Some(Scope::Individuals));
+        assert_eq!(
+            filter.to_json(Scope::Biosamples),
+            json!({"scope": "individuals", "id": "age", "operator": ">=", "value": "30"})
+        );
+        let filter: Filter = "karyotypicSex!=XX".parse().unwrap();
+        assert_eq!(
+            filter.kind,
+            FilterKind::Alphanumeric {
+                operator: "!".to_string(),
+                value: "XX".to_string()
+            }
+        );
+        let filter: Filter = "email@example.org".parse().unwrap();
+        assert_eq!(filter.kind, FilterKind::Custom);
+        assert_eq!(filter.id, "email@example.org");
+        assert!("=30".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn test_filters_from_json() {
+        let filters = [
+            json!({"scope": "individuals", "id": "age", "operator": ">=", "value": "30"}),
+            json!({"scope": "biosamples", "id": "UBERON:0000178", "includeDescendantTerms": false}),
+            json!({"scope": "individuals", "id": "NCIT:C16576", "operator": "", "value": ""}),
+            json!({"scope": "individuals", "id": "karyotypicSex", "operator": "!", "value": "XX"}),
+        ];
+        let parsed: Vec<Filter> = filters
+            .iter()
+            .map(|filter| Filter::from_json(filter, Scope::Individuals).unwrap())
+            .collect();
+        let text: Vec<String> = parsed.iter().map(Filter::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "age>=30",
+                "UBERON:0000178@biosamples",
+                "NCIT:C16576",
+                "karyotypicSex!=XX"
+            ]
+        );
+        assert_eq!(parsed[0].to_json(Scope::Individuals), filters[0]);
+        assert_eq!(parsed[1].to_json(Scope::Individuals), filters[1]);
+        assert_eq!(parsed[3].to_json(Scope::Individuals), filters[3]);
+        assert!(Filter::from_json(&json!({"scope": "individuals"}), Scope::Individuals).is_err());
+    }
+
+    #[test]
+    fn test_variant_from_json() {
+        let variant = VariantParameters::from_json(&json!({
+            "assemblyId": "GRCH37",
+            "referenceName": "1",
+            "start": ["100", 200],
+            "referenceBases": "A",
+        }))
+        .unwrap();
+        assert_eq!(variant.start, [100, 200]);
+        assert_eq!(variant.end, [100, 200]);
+        assert_eq!(variant.assembly, "GRCH37");
+        assert_eq!(variant.alternate_bases, "N");
+        assert!(VariantParameters::from_json(&json!({"referenceName": "1"})).is_err());
+    }
+
+    #[test]
+    fn test_body() {
+        let mut request = request(Scope::Individuals);
+        request.filters = vec!["NCIT:C16576@biosamples".parse().unwrap()];
+        assert_eq!(request.endpoint(), "individuals");
+        assert_eq!(
+            request.body().unwrap(),
+            json!({
+                "projects": ["project1"],
+                "query": {
+                    "filters": [{"scope": "biosamples", "id": "NCIT:C16576", "includeDescendantTerms": true}],
+                    "requestedGranularity": "count",
+                    "pagination": {"skip": 0, "limit": 100},
+                },
+                "meta": {"apiVersion": "v2.0"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_variant_body() {
+        let mut request = request(Scope::Individuals);
+        request.related = Some(("I1".to_string(), Scope::GenomicVariants));
+        assert_eq!(request.endpoint(), "individuals/I1/g_variants");
+        assert!(request.body().is_err());
+
+        request.variant = Some(VariantParameters {
+            assembly: "GRCH38".to_string(),
+            chromosome: "1".to_string(),
+            start: vec![100, 200],
+            end: vec![300],
+            reference_bases: "N".to_string(),
+            alternate_bases: "A".to_string(),
+        });
+        let body = request.body().unwrap();
+        assert_eq!(
+            body["query"]["requestParameters"],
+            json!({
+                "assemblyId": "GRCH38",
+                "referenceName": "1",
+                "start": ["100", "200"],
+                "end": ["300"],
+                "referenceBases": "N",
+                "alternateBases": "A",
+            })
+        );
+
+        request.variant.as_mut().unwrap().end = vec![50];
+        assert!(request.body().is_err());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let response = json!({
+            "responseSummary": {"exists": true, "numTotalResults": 2},
+            "response": {"resultSets": [{"results": [
+                {"id": "I1", "sex": {"id": "NCIT:C16576", "label": "female"}},
+                {"id": "I2", "karyotypicSex": "XY", "diseases": [{"diseaseCode": {"id": "ICD10:E11"}}]},
+            ]}]},
+        });
+        let table = format_response(&response, OutputFormat::Table).unwrap();
+        assert_eq!(
+            table,
+            "exists: true\nresults: 2\n\n\
+             id  sex     diseases   karyotypicSex\n\
+             I1  female\n\
+             I2          ICD10:E11  XY\n"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use super::Scope;
+use super::query::{Filter, Granularity, QueryRequest, VariantParameters};
+use crate::networking::api::{OutputFormat, delete, get, post};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// Represents a query saved in the portal, with the query stored as the portal's query form.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub query: Value,
+}
+
+impl SavedQuery {
+    /// Converts the stored query form into a query that can be run.
+    /// # Errors
+    /// Returns an error if the stored scope, granularity, filters or variant parameters are invalid.
+    pub fn to_request(&self) -> Result<QueryRequest> {
+        let entry = &self.query;
+        let scope: Scope = entry["scope"]
+            .as_str()
+            .ok_or(anyhow!("Saved query {:?} has no scope", self.name))?
+            .parse()?;
+        let projects = entry["projects"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+        let query = &entry["body"]["query"];
+        let filters = query["filters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|filter| Filter::from_json(filter, scope))
+            .collect::<Result<Vec<_>>>()?;
+        let granularity: Granularity = query["requestedGranularity"]
+            .as_str()
+            .unwrap_or("count")
+            .parse()?;
+        let related = if entry["customReturn"].as_bool().unwrap_or(false) {
+            let id = entry["id"]
+                .as_str()
+                .ok_or(anyhow!("Saved query {:?} has no record id", self.name))?;
+            let returned: Scope = entry["return"]
+                .as_str()
+                .ok_or(anyhow!("Saved query {:?} has no return scope", self.name))?
+                .parse()?;
+            Some((id.to_string(), returned))
+        } else {
+            None
+        };
+        let variant = match &query["requestParameters"] {
+            Value::Null => None,
+            parameters => Some(VariantParameters::from_json(parameters)?),
+        };
+        Ok(QueryRequest {
+            scope,
+            projects,
+            filters,
+            granularity,
+            skip: query["pagination"]["skip"].as_u64().unwrap_or(0),
+            limit: query["pagination"]["limit"].as_u64().unwrap_or(100),
+            variant,
+            related,
+        })
+    }
+}
+
+/// Serialises a query as the portal's query form, so that it can be loaded in the portal.
+/// # Errors
+/// Returns an error if the request body is invalid.
+pub fn to_entry(request: &QueryRequest) -> Result<Value> {
+    let (id, returned) = match &request.related {
+        Some((id, returned)) => (id.as_str(), returned.as_str()),
+        None => ("", request.scope.as_str()),
+    };
+    Ok(json!({
+        "projects": request.projects,
+        "scope": request.scope.as_str(),
+        "return": returned,
+        "id": id,
+        "customReturn": request.related.is_some(),
+        "body": request.body()?,
+    }))
+}
+
+/// Lists the queries saved by the user.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the saved queries.
+/// # Example
+/// ```
+/// let queries = list_queries("https://api.example.com", "my_token").await?;
+/// ```
+pub async fn list_queries(api: &str, token: &str) -> Result<Vec<SavedQuery>> {
+    let response = get(api, "dportal/queries", &[], token).await?;
+    Ok(serde_json::from_value(response)?)
+}
+
+/// Returns the saved query with the given name.
+/// # Errors
+/// Returns an error if listing the queries fails or no query has this name.
+/// # Example
+/// ```
+/// let query = find_query(&api, "t2d_females", &token).await?;
+/// ```
+pub async fn find_query(api: &str, name: &str, token: &str) -> Result<SavedQuery> {
+    list_queries(api, token)
+        .await?
+        .into_iter()
+        .find(|query| query.name == name)
+        .ok_or(anyhow!("No saved query named {:?}", name))
+}
+
+/// Saves a query under a name, replacing any query saved under the same name.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the query, 6 to 30 characters.
+/// * `description` - What the query selects, 10 to 200 characters.
+/// * `request` - The query to save.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the name or description has the wrong length, the query is invalid or the request fails.
+/// # Example
+/// ```
+/// save_query(&api, "t2d_females", "Females with type 2 diabetes", &request, &token).await?;
+/// ```
+pub async fn save_query(
+    api: &str,
+    name: &str,
+    description: &str,
+    request: &QueryRequest,
+    token: &str,
+) -> Result<()> {
+    // the portal enforces the same limits
+    if !(6..=30).contains(&name.chars().count()) || name.contains('/') {
+        return Err(anyhow!(
+            "Invalid query name {:?}, expected 6 to 30 characters other than /",
+            name
+        ));
+    }
+    if !(10..=200).contains(&description.chars().count()) {
+        return Err(anyhow!("The description must have 10 to 200 characters"));
+    }
+    let body = json!({
+        "name": name,
+        "description": description,
+        "query": to_entry(request)?,
+    });
+    post(api, "dportal/queries", &body, token).await?;
+    Ok(())
+}
+
+/// Deletes a saved query.
+/// # Example
+/// ```
+/// delete_query(&api, "t2d_females", &token).await?;
+/// ```
+pub async fn delete_query(api: &str, name: &str, token: &str) -> Result<()> {
+    delete(api, &format!("dportal/queries/{}", name), token).await?;
+    Ok(())
+}
+
+/// Formats saved queries for printing, as a table of names and descriptions or as JSON.
+pub fn format_queries(queries: &[SavedQuery], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(queries)?));
+    }
+    let width = queries
+        .iter()
+        .map(|query| query.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut output = String::new();
+    for query in queries {
+        let line = format!(
+            "{:width$}  {}",
+            query.name,
+            query.description,
+            width = width
+        );
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Formats a saved query for printing, describing it with the options of `beacon query` or as JSON.
+pub fn format_query(query: &SavedQuery, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(query)?));
+    }
+    let request = query.to_request()?;
+    let mut output = format!(
+        "name: {}\ndescription: {}\nscope: {}\nprojects: {}\ngranularity: {}\n",
+        query.name,
+        query.description,
+        request.scope,
+        request.projects.join(", "),
+        request.granularity
+    );
+    for filter in &request.filters {
+        output.push_str(&format!("filter: {}\n", filter));
+    }
+    if let Some((id, returned)) = &request.related {
+        output.push_str(&format!("id: {}\nreturn: {}\n", id, returned));
+    }
+    if let Some(variant) = &request.variant {
+        let positions = |positions: &[u64]| -> String {
+            positions
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        output.push_str(&format!(
+            "variant: {} {}:{}-{} {}>{}\n",
+            variant.assembly,
+            variant.chromosome,
+            positions(&variant.start),
+            positions(&variant.end),
+            variant.reference_bases,
+            variant.alternate_bases
+        ));
+    }
+    output.push_str(&format!(
+        "skip: {}\nlimit: {}\n",
+        request.skip, request.limit
+    ));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    fn request() -> QueryRequest {
+        QueryRequest {
+            scope: Scope::Individuals,
+            projects: vec!["project1".to_string()],
+            filters: vec![
+                "NCIT:C16576".parse().unwrap(),
+                "age>=30".parse().unwrap(),
+                "UBERON:0000178@biosamples".parse().unwrap(),
+            ],
+            granularity: Granularity::Record,
+            skip: 10,
+            limit: 50,
+            variant: None,
+            related: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let request = request();
+        let saved = SavedQuery {
+            name: "t2d_females".to_string(),
+            description: "Females over thirty".to_string(),
+            query: to_entry(&request).unwrap(),
+        };
+        assert_eq!(saved.query["customReturn"], false);
+        let loaded = saved.to_request().unwrap();
+        assert_eq!(loaded.filters, request.filters);
+        assert_eq!(loaded.granularity, Granularity::Record);
+        assert_eq!((loaded.skip, loaded.limit), (10, 50));
+        assert_eq!(loaded.body().unwrap(), request.body().unwrap());
+    }
+
+    #[test]
+    fn test_portal_entry() {
+        // as saved by the portal for the variants of an individual
+        let saved: SavedQuery = serde_json::from_value(json!({
+            "name": "variants_of_i1",
+            "description": "Variants of I1",
+            "query": {
+                "projects": ["project1"],
+                "scope": "individuals",
+                "return": "g_variants",
+                "id": "I1",
+                "customReturn": true,
+                "body": {
+                    "query": {
+                        "filters": [],
+                        "requestedGranularity": "record",
+                        "pagination": {"skip": 0, "limit": 100},
+                        "requestParameters": {
+                            "assemblyId": "GRCH38",
+                            "referenceName": "1",
+                            "start": ["100"],
+                            "end": ["200"],
+                            "referenceBases": "N",
+                            "alternateBases": "N",
+                        },
+                    },
+                    "meta": {"apiVersion": "v2.0"},
+                },
+            },
+        }))
+        .unwrap();
+        let request = saved.to_request().unwrap();
+        assert_eq!(request.endpoint(), "individuals/I1/g_variants");
+        assert_eq!(request.variant.as_ref().unwrap().end, [200]);
+        let text = format_query(&saved, OutputFormat::Table).unwrap();
+        assert!(text.contains("variant: GRCH38 1:100-200 N>N\n"));
+        assert!(text.contains("return: g_variants\n"));
+    }
+
+    #[tokio::test]
+    async fn test_save_and_delete() {
+        let mut server = Server::new_async().await;
+        let save = server
+            .mock("POST", "/dportal/queries")
+            .match_body(Matcher::PartialJson(json!({
+                "name": "t2d_females",
+                "query": {"scope": "individuals", "customReturn": false},
+            })))
+            .with_status(200)
+            .with_body(r#"{"success": true}"#)
+            .create();
+        let remove = server
+            .mock("DELETE", "/dportal/queries/t2d_females")
+            .with_status(200)
+            .create();
+
+        save_query(
+            &server.url(),
+            "t2d_females",
+            "Females over thirty",
+            &request(),
+            "token",
+        )
+        .await
+        .unwrap();
+        assert!(
+            save_query(&server.url(), "t2d", "Too short", &request(), "token")
+                .await
+                .is_err()
+        );
+        delete_query(&server.url(), "t2d_females", "token")
+            .await
+            .unwrap();
+        save.assert();
+        remove.assert();
+    }
+
+    #[tokio::test]
+    async fn test_find_query() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/dportal/queries")
+            .with_status(200)
+            .with_body(r#"[{"name": "t2d_females", "description": "Females", "query": {}}]"#)
+            .expect(2)
+            .create();
+
+        let query = find_query(&server.url(), "t2d_females", "token")
+            .await
+            .unwrap();
+        assert_eq!(query.description, "Females");
+        assert!(find_query(&server.url(), "unknown", "token").await.is_err());
+        mock.assert();
+    }
+}
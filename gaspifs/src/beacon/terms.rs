@@ -0,0 +1,371 @@
+use super::Scope;
+use super::query::{Filter, FilterKind, QueryRequest, is_curie};
+use crate::networking::api::{OutputFormat, get};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Cached terms are fetched again after a day, since newly ingested datasets may add terms.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of terms requested per page when filling the cache.
+const PAGE_SIZE: usize = 1000;
+
+/// Represents a filtering term, as listed by the `filtering_terms` endpoints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilteringTerm {
+    pub id: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default, rename = "type")]
+    pub kind: Option<String>,
+}
+
+/// The filtering terms of a scope and a set of projects, as stored in the cache.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedTerms {
+    fetched_at: u64,
+    terms: Vec<FilteringTerm>,
+}
+
+/// Caches filtering terms in `~/.gaspifs/terms`, one file per scope and set of projects.
+pub struct TermCache {
+    dir: PathBuf,
+}
+
+impl TermCache {
+    /// Opens the cache in the home directory.
+    pub fn open() -> Result<Self> {
+        let mut dir = home::home_dir().ok_or(anyhow!("Could not determine home directory."))?;
+        dir.push(".gaspifs");
+        dir.push("terms");
+        Ok(TermCache { dir })
+    }
+
+    fn path(&self, scope: Scope, projects: &[String]) -> PathBuf {
+        let mut projects = projects.to_vec();
+        projects.sort();
+        projects.dedup();
+        let digest = Sha256::digest(projects.join("\n"));
+        let key: String = digest[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.dir.join(format!("{}-{}.json", scope, key))
+    }
+
+    /// Returns the cached terms, or `None` if there are none or they are older than `CACHE_TTL`.
+    fn load(&self, scope: Scope, projects: &[String]) -> Result<Option<Vec<FilteringTerm>>> {
+        let content = match fs::read_to_string(self.path(scope, projects)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        // a cache written by another version is simply fetched again
+        let Ok(cached) = serde_json::from_str::<CachedTerms>(&content) else {
+            return Ok(None);
+        };
+        let age = now().saturating_sub(cached.fetched_at);
+        Ok((age < CACHE_TTL.as_secs()).then_some(cached.terms))
+    }
+
+    fn save(&self, scope: Scope, projects: &[String], terms: &[FilteringTerm]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let cached = CachedTerms {
+            fetched_at: now(),
+            terms: terms.to_vec(),
+        };
+        fs::write(self.path(scope, projects), serde_json::to_string(&cached)?)?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Fetches every filtering term of a scope in the given projects, page by page.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The authentication token.
+/// * `scope` - The entry type whose terms to list.
+/// * `projects` - The projects whose terms to list.
+/// # Returns
+/// A `Result` containing the terms.
+/// # Errors
+/// Returns an error if a request fails or a response does not list filtering terms.
+/// # Example
+/// ```
+/// let terms = fetch_terms("https://api.example.com", "my_token", Scope::Individuals, &projects).await?;
+/// ```
+pub async fn fetch_terms(
+    api: &str,
+    token: &str,
+    scope: Scope,
+    projects: &[String],
+) -> Result<Vec<FilteringTerm>> {
+    let endpoint = format!("{}/filtering_terms", scope);
+    let projects = projects.join(",");
+    let limit = PAGE_SIZE.to_string();
+    let mut terms = Vec::new();
+    loop {
+        let skip = terms.len().to_string();
+        let params = [
+            ("skip", skip.as_str()),
+            ("limit", limit.as_str()),
+            ("projects", projects.as_str()),
+        ];
+        let response = get(api, &endpoint, &params, token).await?;
+        let page: Vec<FilteringTerm> = serde_json::from_value(
+            response["response"]["filteringTerms"].clone(),
+        )
+        .map_err(|err| {
+            anyhow!(
+                "Unexpected response from {}: {} ({})",
+                endpoint,
+                err,
+                response
+            )
+        })?;
+        let last = page.len() < PAGE_SIZE;
+        terms.extend(page);
+        if last {
+            return Ok(terms);
+        }
+    }
+}
+
+/// Returns the filtering terms of a scope from the cache, fetching them if the cache is missing,
+/// stale or `refresh` is set. The cache is only written once every page was fetched.
+/// # Example
+/// ```
+/// let terms = load_terms(&api, &token, Scope::Individuals, &projects, false).await?;
+/// ```
+pub async fn load_terms(
+    api: &str,
+    token: &str,
+    scope: Scope,
+    projects: &[String],
+    refresh: bool,
+) -> Result<Vec<FilteringTerm>> {
+    let cache = TermCache::open()?;
+    if !refresh && let Some(terms) = cache.load(scope, projects)? {
+        return Ok(terms);
+    }
+    let terms = fetch_terms(api, token, scope, projects).await?;
+    cache.save(scope, projects, &terms)?;
+    Ok(terms)
+}
+
+/// Returns the terms whose id or label contains every word of the search, ignoring case.
+pub fn search<'a>(terms: &'a [FilteringTerm], query: &str) -> Vec<&'a FilteringTerm> {
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    terms
+        .iter()
+        .filter(|term| {
+            let text =
+                format!("{} {}", term.id, term.label.as_deref().unwrap_or_default()).to_lowercase();
+            words.iter().all(|word| text.contains(word))
+        })
+        .collect()
+}
+
+/// Replaces a filter given by the label of a term with the id of that term.
+/// Filters that already name a term, or that match no label, are left unchanged.
+/// # Errors
+/// Returns an error if the label is shared by several terms.
+pub fn resolve_label(filter: &mut Filter, terms: &[FilteringTerm]) -> Result<()> {
+    if filter.kind != FilterKind::Custom || terms.iter().any(|term| term.id == filter.id) {
+        return Ok(());
+    }
+    let mut ids: Vec<&str> = terms
+        .iter()
+        .filter(|term| {
+            term.label
+                .as_deref()
+                .is_some_and(|label| label.eq_ignore_ascii_case(&filter.id))
+        })
+        .map(|term| term.id.as_str())
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    match ids[..] {
+        [] => Ok(()),
+        [id] => {
+            filter.kind = if is_curie(id) {
+                FilterKind::Ontology {
+                    include_descendants: true,
+                }
+            } else {
+                FilterKind::Custom
+            };
+            filter.id = id.to_string();
+            Ok(())
+        }
+        _ => Err(anyhow!(
+            "{:?} is the label of several terms ({}); use one of their ids instead",
+            filter.id,
+            ids.join(", ")
+        )),
+    }
+}
+
+/// Resolves the labels used as filters in a query, loading the terms of each scope involved.
+/// # Example
+/// ```
+/// resolve_filters(&api, &token, &mut request).await?;
+/// ```
+pub async fn resolve_filters(api: &str, token: &str, request: &mut QueryRequest) -> Result<()> {
+    let mut terms: HashMap<Scope, Vec<FilteringTerm>> = HashMap::new();
+    for filter in &mut request.filters {
+        if filter.kind != FilterKind::Custom {
+            continue;
+        }
+        let scope = filter.scope.unwrap_or(request.scope);
+        if let Entry::Vacant(entry) = terms.entry(scope) {
+            entry.insert(load_terms(api, token, scope, &request.projects, false).await?);
+        }
+        resolve_label(filter, &terms[&scope])?;
+    }
+    Ok(())
+}
+
+/// Formats filtering terms for printing, as a table of ids, labels and types or as JSON.
+pub fn format_terms(terms: &[&FilteringTerm], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(terms)?));
+    }
+    let width = terms
+        .iter()
+        .map(|term| term.id.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut output = String::new();
+    for term in terms {
+        let line = format!(
+            "{:width$}  {}  {}",
+            term.id,
+            term.label.as_deref().unwrap_or("-"),
+            term.kind
+                .as_deref()
+                .map(|kind| format!("({})", kind))
+                .unwrap_or_default(),
+            width = width
+        );
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    fn term(id: &str, label: &str) -> FilteringTerm {
+        FilteringTerm {
+            id: id.to_string(),
+            label: Some(label.to_string()),
+            kind: Some("ontology".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = TermCache {
+            dir: dir.path().join("terms"),
+        };
+        let projects = ["p2".to_string(), "p1".to_string()];
+        assert!(cache.load(Scope::Individuals, &projects).unwrap().is_none());
+        cache
+            .save(
+                Scope::Individuals,
+                &projects,
+                &[term("NCIT:C16576", "female")],
+            )
+            .unwrap();
+        let reordered = ["p1".to_string(), "p2".to_string()];
+        assert_eq!(
+            cache.load(Scope::Individuals, &reordered).unwrap().unwrap(),
+            [term("NCIT:C16576", "female")]
+        );
+        assert!(cache.load(Scope::Biosamples, &projects).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_search() {
+        let terms = [
+            term("ICD10:E11", "Type 2 diabetes mellitus"),
+            term("ICD10:E10", "Type 1 diabetes mellitus"),
+            term("NCIT:C16576", "female"),
+        ];
+        let found = search(&terms, "DIABETES type 2");
+        assert_eq!(found, [&terms[0]]);
+        assert_eq!(search(&terms, "icd10").len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_label() {
+        let terms = [
+            term("NCIT:C16576", "female"),
+            term("SNOMED:248152002", "Female"),
+            term("ICD10:E11", "Type 2 diabetes mellitus"),
+        ];
+        let mut filter: Filter = "type 2 diabetes mellitus".parse().unwrap();
+        resolve_label(&mut filter, &terms).unwrap();
+        assert_eq!(filter.id, "ICD10:E11");
+        assert!(matches!(filter.kind, FilterKind::Ontology { .. }));
+
+        let mut filter: Filter = "female".parse().unwrap();
+        assert!(resolve_label(&mut filter, &terms).is_err());
+
+        let mut filter: Filter = "unknown".parse().unwrap();
+        resolve_label(&mut filter, &terms).unwrap();
+        assert_eq!(filter.kind, FilterKind::Custom);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_terms() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/individuals/filtering_terms")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("projects".into(), "p1,p2".into()),
+                Matcher::UrlEncoded("skip".into(), "0".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                r#"{"response": {"filteringTerms": [{"id": "NCIT:C16576", "label": "female", "type": "ontology"}]}}"#,
+            )
+            .create();
+
+        let projects = ["p1".to_string(), "p2".to_string()];
+        let terms = fetch_terms(&server.url(), "token", Scope::Individuals, &projects)
+            .await
+            .unwrap();
+        assert_eq!(terms, [term("NCIT:C16576", "female")]);
+        mock.assert();
+
+        mock.remove();
+        server
+            .mock("GET", "/individuals/filtering_terms")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"response": {"message": "timed out"}}"#)
+            .create();
+        let err = fetch_terms(&server.url(), "token", Scope::Individuals, &projects)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Unexpected response"));
+    }
+}
@@ -0,0 +1,515 @@
+use crate::manifest::ManifestFormat;
+use crate::networking::api::{self, OutputFormat, format_table};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Number of entries requested per page when listing saved variants.
+const PAGE_SIZE: usize = 50;
+
+/// A variant as stored in annotations and saved variant collections: the values of the result
+/// columns the curator selected, such as `Gene Name` and `Variant Name`.
+pub type Variant = Map<String, Value>;
+
+/// Represents a collection of variants saved for reporting, with the validation of the medical
+/// director, if any, kept in `fields`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedVariants {
+    pub name: String,
+    #[serde(default)]
+    pub comment: String,
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+    #[serde(default)]
+    pub user: Value,
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(default)]
+    pub validated_by_medical_director: bool,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+/// Reads a selection of variants from a TSV or CSV file with a header row, such as the output of
+/// `clinic results`, or from a JSON array or JSON lines file. `-` reads TSV from standard input.
+/// Empty values are left out, as in the portal.
+/// # Arguments
+/// * `path` - The file to read, or `-`.
+/// * `columns` - The columns to keep, in order; all columns if empty.
+/// # Returns
+/// A `Result` containing the variants.
+/// # Errors
+/// Returns an error if the file cannot be read, has no rows, or lacks one of the columns.
+/// # Example
+/// ```
+/// let variants = read_variants("rare_missense.tsv", &["Gene Name".to_string(), "Variant Name".to_string()])?;
+/// ```
+pub fn read_variants(path: &str, columns: &[String]) -> Result<Vec<Variant>> {
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    let rows: Vec<Variant> = if path.to_lowercase().ends_with(".jsonl") {
+        BufReader::new(reader)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<Result<_>>()?
+    } else {
+        let format = match path {
+            "-" => ManifestFormat::Tsv,
+            _ => ManifestFormat::from_path(path).unwrap_or(ManifestFormat::Tsv),
+        };
+        match format {
+            ManifestFormat::Json => serde_json::from_reader(reader)?,
+            _ => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .delimiter(format.delimiter())
+                    .flexible(true)
+                    .from_reader(reader);
+                let header = reader.headers()?.clone();
+                reader
+                    .records()
+                    .map(|record| {
+                        let record = record?;
+                        Ok(header
+                            .iter()
+                            .zip(record.iter())
+                            .map(|(name, value)| (name.to_string(), Value::from(value)))
+                            .collect())
+                    })
+                    .collect::<Result<_>>()?
+            }
+        }
+    };
+    if rows.is_empty() {
+        return Err(anyhow!("{} has no variants.", path));
+    }
+    if let Some(column) = columns
+        .iter()
+        .find(|column| rows.iter().all(|row| !row.contains_key(*column)))
+    {
+        return Err(anyhow!("{} has no column {:?}.", path, column));
+    }
+    let is_empty = |value: &Value| value.is_null() || value.as_str() == Some("");
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            if columns.is_empty() {
+                row.into_iter()
+                    .filter(|(_, value)| !is_empty(value))
+                    .collect()
+            } else {
+                columns
+                    .iter()
+                    .filter_map(|column| {
+                        let value = row.get(column).filter(|value| !is_empty(value))?;
+                        Some((column.clone(), value.clone()))
+                    })
+                    .collect()
+            }
+        })
+        .collect())
+}
+
+fn collection_endpoint(project: &str, job_id: &str, collection: &str) -> String {
+    format!(
+        "dportal/projects/{}/clinical-workflows/{}/{}",
+        project, job_id, collection
+    )
+}
+
+/// Fetches every page of a collection of a job, whose entries the response holds under `key`.
+async fn list_collection(api: &str, endpoint: &str, key: &str, token: &str) -> Result<Vec<Value>> {
+    let page_size = PAGE_SIZE.to_string();
+    let mut entries = Vec::new();
+    let mut next: Option<String> = None;
+    loop {
+        let mut params = vec![("limit", page_size.as_str())];
+        if let Some(key) = &next {
+            params.push(("last_evaluated_key", key));
+        }
+        let response = api::get(api, endpoint, &params, token).await?;
+        let page = response[key].as_array().cloned().unwrap_or_default();
+        let empty = page.is_empty();
+        entries.extend(page);
+        next = match &response["last_evaluated_key"] {
+            Value::Null => None,
+            Value::String(key) => Some(key.clone()),
+            key => Some(key.to_string()),
+        };
+        if next.is_none() || empty {
+            return Ok(entries);
+        }
+    }
+}
+
+/// Checks the response of a request that saves an entry, which reports failures with `success: false`.
+fn check_saved(response: &Value, what: &str) -> Result<()> {
+    if response["success"] == false {
+        return Err(anyhow!(
+            "Failed to save the {}: {}",
+            what,
+            response["message"].as_str().unwrap_or("unknown error")
+        ));
+    }
+    Ok(())
+}
+
+/// Annotates variants of a job, as the Add annotation dialog of the results page does.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `annotation` - The text of the annotation.
+/// * `variants` - The annotated variants.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the annotation is empty or the request fails.
+/// # Example
+/// ```
+/// save_annotation(&api, "my_project", "job-id", "Likely benign in this cohort", &variants, &id_token).await?;
+/// ```
+pub async fn save_annotation(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    annotation: &str,
+    variants: &[Variant],
+    token: &str,
+) -> Result<()> {
+    if annotation.trim().is_empty() {
+        return Err(anyhow!("The annotation must not be empty"));
+    }
+    let body = json!({"annotation": annotation, "variants": variants});
+    let endpoint = collection_endpoint(project, job_id, "annotations");
+    check_saved(
+        &api::post(api, &endpoint, &body, token).await?,
+        "annotation",
+    )
+}
+
+/// Saves variants of a job for reporting, as the Save for reporting dialog of the results page does.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `comment` - Why the variants are reported.
+/// * `variants` - The variants to report.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the comment is empty or the request fails.
+/// # Example
+/// ```
+/// save_variants(&api, "my_project", "job-id", "Pathogenic BRCA variants", &variants, &id_token).await?;
+/// ```
+pub async fn save_variants(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    comment: &str,
+    variants: &[Variant],
+    token: &str,
+) -> Result<()> {
+    if comment.trim().is_empty() {
+        return Err(anyhow!("The comment must not be empty"));
+    }
+    let body = json!({"comment": comment, "variants": variants});
+    let endpoint = collection_endpoint(project, job_id, "variants");
+    check_saved(&api::post(api, &endpoint, &body, token).await?, "variants")
+}
+
+/// Lists the variant collections saved for reporting from a job, most recent first.
+/// # Example
+/// ```
+/// let collections = list_saved_variants(&api, "my_project", "job-id", &id_token).await?;
+/// ```
+pub async fn list_saved_variants(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    token: &str,
+) -> Result<Vec<SavedVariants>> {
+    let endpoint = collection_endpoint(project, job_id, "variants");
+    let entries = list_collection(api, &endpoint, "variants", token).await?;
+    let mut collections: Vec<SavedVariants> = serde_json::from_value(Value::Array(entries))?;
+    collections.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(collections)
+}
+
+/// Returns the saved variant collection with the given name.
+/// # Errors
+/// Returns an error if listing the collections fails or no collection has this name.
+pub async fn find_saved_variants(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    name: &str,
+    token: &str,
+) -> Result<SavedVariants> {
+    list_saved_variants(api, project, job_id, token)
+        .await?
+        .into_iter()
+        .find(|collection| collection.name == name)
+        .ok_or(anyhow!(
+            "Job {} has no saved variants named {:?}",
+            job_id,
+            name
+        ))
+}
+
+/// Deletes a saved variant collection.
+/// # Example
+/// ```
+/// delete_saved_variants(&api, "my_project", "job-id", "collection-name", &id_token).await?;
+/// ```
+pub async fn delete_saved_variants(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    name: &str,
+    token: &str,
+) -> Result<()> {
+    let endpoint = collection_endpoint(project, job_id, &format!("variants/{}", name));
+    api::delete(api, &endpoint, token).await?;
+    Ok(())
+}
+
+/// Describes the user who saved or validated an entry, as `First Last <email>`.
+pub(crate) fn user_label(user: &Value) -> String {
+    let field = |name: &str| user[name].as_str().unwrap_or_default();
+    let name = format!("{} {}", field("firstName"), field("lastName"));
+    match (name.trim(), field("email")) {
+        ("", email) => email.to_string(),
+        (name, "") => name.to_string(),
+        (name, email) => format!("{} <{}>", name, email),
+    }
+}
+
+/// Formats saved variant collections for printing, as a table of names and comments or as JSON.
+pub fn format_saved_variants(
+    collections: &[SavedVariants],
+    format: OutputFormat,
+) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(collections)?));
+    }
+    let rows: Vec<Vec<String>> = collections
+        .iter()
+        .map(|collection| {
+            vec![
+                collection.name.clone(),
+                collection.variants.len().to_string(),
+                if collection.validated_by_medical_director {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+                user_label(&collection.user),
+                collection.created_at.clone(),
+                collection.comment.clone(),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &[
+            "NAME",
+            "VARIANTS",
+            "VALIDATED",
+            "SAVED BY",
+            "CREATED",
+            "COMMENT",
+        ],
+        &rows,
+    ))
+}
+
+/// Formats variants as TSV with a header row holding every column, in the order they first appear.
+/// The output can be read back by `read_variants`.
+pub fn format_variants(variants: &[Variant]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    for variant in variants {
+        for column in variant.keys() {
+            if !columns.contains(&column.as_str()) {
+                columns.push(column);
+            }
+        }
+    }
+    let mut output = format!("{}\n", columns.join("\t"));
+    for variant in variants {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|column| match variant.get(*column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(value)) => value.replace(['\t', '\n'], " "),
+                Some(value) => value.to_string(),
+            })
+            .collect();
+        output.push_str(&values.join("\t"));
+        output.push('\n');
+    }
+    output
+}
+
+/// Formats a saved variant collection for printing, as the TSV of its variants or as JSON.
+pub fn format_collection(collection: &SavedVariants, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(collection)?));
+    }
+    Ok(format_variants(&collection.variants))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use std::io::Write;
+
+    #[test]
+    fn test_read_variants() {
+        let mut file = tempfile::Builder::new().suffix(".tsv").tempfile().unwrap();
+        write!(
+            file,
+            "Gene Name\tVariant Name\tclinSig\nBRCA1\tNM_007294.4:c.68_69del\tPathogenic\nTP53\tNM_000546.6:c.743G>A\t\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let variants = read_variants(path, &[]).unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0]["clinSig"], "Pathogenic");
+        assert!(!variants[1].contains_key("clinSig"));
+
+        let columns = ["Variant Name".to_string(), "Gene Name".to_string()];
+        let variants = read_variants(path, &columns).unwrap();
+        assert_eq!(variants[1].len(), 2);
+        assert!(read_variants(path, &["Drugs".to_string()]).is_err());
+
+        // what `variants show` prints can be saved again
+        let again = tempfile::Builder::new().suffix(".tsv").tempfile().unwrap();
+        std::fs::write(again.path(), format_variants(&variants)).unwrap();
+        assert_eq!(
+            read_variants(again.path().to_str().unwrap(), &[]).unwrap(),
+            variants
+        );
+    }
+
+    #[test]
+    fn test_format_saved_variants() {
+        let collection: SavedVariants = serde_json::from_value(json!({
+            "name": "c1",
+            "comment": "Pathogenic BRCA variants",
+            "variants": [{"Gene Name": "BRCA1"}],
+            "user": {"firstName": "Ana", "lastName": "Lee", "email": "ana@example.com"},
+            "createdAt": "2025-01-02T03:04:05Z",
+            "validatedByMedicalDirector": true,
+            "validationComment": "Confirmed",
+        }))
+        .unwrap();
+        assert_eq!(collection.fields["validationComment"], "Confirmed");
+        let text = format_saved_variants(&[collection], OutputFormat::Table).unwrap();
+        let row = text.lines().nth(1).unwrap();
+        assert!(row.starts_with("c1    1         yes"));
+        assert!(row.contains("Ana Lee <ana@example.com>"));
+        assert!(row.ends_with("Pathogenic BRCA variants"));
+    }
+
+    #[tokio::test]
+    async fn test_saved_variants_round_trip() {
+        let mut server = Server::new_async().await;
+        let endpoint = "/dportal/projects/project1/clinical-workflows/job1/variants";
+        let save = server
+            .mock("POST", endpoint)
+            .match_body(Matcher::PartialJson(json!({
+                "comment": "Pathogenic BRCA variants",
+                "variants": [{"Gene Name": "BRCA1"}],
+            })))
+            .with_status(200)
+            .with_body(r#"{"success": true}"#)
+            .create();
+        let first = server
+            .mock("GET", endpoint)
+            .match_query(Matcher::Regex("^limit=50$".to_string()))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "variants": [{"name": "c1", "comment": "Old", "createdAt": "2025-01-01"}],
+                    "last_evaluated_key": "k1",
+                })
+                .to_string(),
+            )
+            .create();
+        let second = server
+            .mock("GET", endpoint)
+            .match_query(Matcher::Regex("last_evaluated_key=k1".to_string()))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "variants": [{"name": "c2", "comment": "New", "createdAt": "2025-02-01"}],
+                    "last_evaluated_key": null,
+                })
+                .to_string(),
+            )
+            .create();
+        let remove = server
+            .mock("DELETE", format!("{}/c1", endpoint).as_str())
+            .with_status(200)
+            .create();
+
+        let variants = vec![Variant::from_iter([(
+            "Gene Name".to_string(),
+            Value::from("BRCA1"),
+        )])];
+        save_variants(
+            &server.url(),
+            "project1",
+            "job1",
+            "Pathogenic BRCA variants",
+            &variants,
+            "token",
+        )
+        .await
+        .unwrap();
+        assert!(
+            save_variants(&server.url(), "project1", "job1", " ", &variants, "token")
+                .await
+                .is_err()
+        );
+        let collections = list_saved_variants(&server.url(), "project1", "job1", "token")
+            .await
+            .unwrap();
+        let names: Vec<&str> = collections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["c2", "c1"]);
+        delete_saved_variants(&server.url(), "project1", "job1", "c1", "token")
+            .await
+            .unwrap();
+        save.assert();
+        first.assert();
+        second.assert();
+        remove.assert();
+    }
+
+    #[tokio::test]
+    async fn test_save_annotation_failure() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock(
+                "POST",
+                "/dportal/projects/project1/clinical-workflows/job1/annotations",
+            )
+            .with_status(200)
+            .with_body(r#"{"success": false, "message": "Job not found"}"#)
+            .create();
+
+        let err = save_annotation(&server.url(), "project1", "job1", "Benign", &[], "token")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("Job not found"));
+        mock.assert();
+    }
+}
@@ -0,0 +1,528 @@
+use crate::networking::api::{self, OutputFormat, format_table};
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Number of jobs requested per page when listing jobs.
+const PAGE_SIZE: usize = 50;
+
+/// The status of a step of a clinical workflow job, as stored by the portal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Completed,
+    Failed,
+    Expired,
+}
+
+impl JobStatus {
+    /// Returns the status as stored by the portal.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Expired => "expired",
+        }
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "pending" => Ok(JobStatus::Pending),
+            "completed" => Ok(JobStatus::Completed),
+            "failed" => Ok(JobStatus::Failed),
+            "expired" => Ok(JobStatus::Expired),
+            _ => Err(anyhow!(
+                "Unknown job status {:?}, expected pending, completed, failed or expired",
+                value
+            )),
+        }
+    }
+}
+
+/// Represents a clinical workflow job, as listed by `dportal/projects/<project>/clinical-workflows`.
+/// Each workflow step of the hub has its own `<step>_status`, `<step>_failed_step` and
+/// `<step>_error_message` fields, such as `svep_status` or `pharmcat_status`, which are kept in `fields`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: String,
+    #[serde(default)]
+    pub job_name: String,
+    #[serde(default)]
+    pub input_vcf: String,
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl Job {
+    /// Returns the status of each step of the job, such as `("svep", Completed)`.
+    pub fn steps(&self) -> Vec<(&str, JobStatus)> {
+        self.fields
+            .iter()
+            .filter_map(|(key, value)| {
+                let step = key.strip_suffix("_status")?;
+                let status = value.as_str()?.parse().ok()?;
+                Some((step, status))
+            })
+            .collect()
+    }
+
+    /// Returns the overall status of the job. The job is pending until every step has finished;
+    /// it has then failed or expired if any step did, and completed otherwise.
+    pub fn status(&self) -> JobStatus {
+        let steps: Vec<JobStatus> = self.steps().into_iter().map(|(_, status)| status).collect();
+        let any = |status: JobStatus| steps.contains(&status);
+        if steps.is_empty() || any(JobStatus::Pending) {
+            JobStatus::Pending
+        } else if any(JobStatus::Failed) {
+            JobStatus::Failed
+        } else if any(JobStatus::Expired) {
+            JobStatus::Expired
+        } else {
+            JobStatus::Completed
+        }
+    }
+
+    /// Returns a description of the failed steps of the job, with the step that failed and the error message.
+    pub fn failure(&self) -> Option<String> {
+        let failures: Vec<String> = self
+            .steps()
+            .into_iter()
+            .filter(|(_, status)| *status == JobStatus::Failed)
+            .map(|(step, _)| {
+                let field = |name: &str| {
+                    self.fields
+                        .get(&format!("{}_{}", step, name))
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown")
+                        .to_string()
+                };
+                format!(
+                    "{} failed at {}: {}",
+                    step,
+                    field("failed_step"),
+                    field("error_message")
+                )
+            })
+            .collect();
+        (!failures.is_empty()).then(|| failures.join("; "))
+    }
+
+    fn step_summary(&self) -> String {
+        self.steps()
+            .iter()
+            .map(|(step, status)| format!("{}:{}", step, status))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Criteria on the jobs returned by `list_jobs`.
+#[derive(Debug, Default)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+    pub search: Option<String>,
+}
+
+/// Lists the clinical workflow jobs of a project, most recent first, page by page.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the jobs.
+/// * `filter` - The status and the text the jobs must match.
+/// * `limit` - The maximum number of jobs to return.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the jobs.
+/// # Example
+/// ```
+/// let filter = JobFilter { status: Some(JobStatus::Failed), ..Default::default() };
+/// let jobs = list_jobs("https://api.example.com", "my_project", &filter, 100, "my_token").await?;
+/// ```
+pub async fn list_jobs(
+    api: &str,
+    project: &str,
+    filter: &JobFilter,
+    limit: usize,
+    token: &str,
+) -> Result<Vec<Job>> {
+    let endpoint = format!("dportal/projects/{}/clinical-workflows", project);
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut next: Option<String> = None;
+    while jobs.len() < limit {
+        let page_size = PAGE_SIZE.min(limit - jobs.len()).to_string();
+        let mut params = vec![("limit", page_size.as_str())];
+        if let Some(key) = &next {
+            params.push(("last_evaluated_key", key));
+        }
+        if let Some(search) = &filter.search {
+            params.push(("search", search));
+        }
+        if let Some(status) = filter.status {
+            params.push(("job_status", status.as_str()));
+        }
+        let response = api::get(api, &endpoint, &params, token).await?;
+        if response["success"] == false {
+            return Err(anyhow!(
+                "Failed to list the jobs of {}: {}",
+                project,
+                response["message"].as_str().unwrap_or("unknown error")
+            ));
+        }
+        let page: Vec<Job> = serde_json::from_value(response["jobs"].clone()).unwrap_or_default();
+        let empty = page.is_empty();
+        jobs.extend(page);
+        next = match &response["last_evaluated_key"] {
+            Value::Null => None,
+            Value::String(key) => Some(key.clone()),
+            key => Some(key.to_string()),
+        };
+        if next.is_none() || empty {
+            break;
+        }
+    }
+    jobs.truncate(limit);
+    Ok(jobs)
+}
+
+/// Gets a clinical workflow job of a project.
+/// # Errors
+/// Returns an error if the job does not exist or the request fails.
+/// # Example
+/// ```
+/// let job = get_job(&api, "my_project", "job-id", &id_token).await?;
+/// println!("{}", job.status());
+/// ```
+pub async fn get_job(api: &str, project: &str, job_id: &str, token: &str) -> Result<Job> {
+    let response = api::get(api, &job_endpoint(project, job_id), &[], token).await?;
+    parse_job(response, project, job_id)
+}
+
+fn job_endpoint(project: &str, job_id: &str) -> String {
+    format!("dportal/projects/{}/clinical-workflows/{}", project, job_id)
+}
+
+fn parse_job(response: Value, project: &str, job_id: &str) -> Result<Job> {
+    if response["success"] == false || response["job"].is_null() {
+        return Err(anyhow!(
+            "Job {} not found in {}: {}",
+            job_id,
+            project,
+            response["message"].as_str().unwrap_or("no such job")
+        ));
+    }
+    Ok(serde_json::from_value(response["job"].clone())?)
+}
+
+/// Waits for a job to finish, polling with exponential backoff and showing its status on a live line.
+/// The interval grows from `interval` to `max_interval`, and starts again from `interval` when a step changes.
+/// Polls go through a `Session`, so that a long wait survives expired tokens and throttling.
+/// # Arguments
+/// * `session` - The session to poll with.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `interval` - The first interval between polls.
+/// * `max_interval` - The longest interval between polls.
+/// * `timeout` - How long to wait in total, if limited.
+/// # Returns
+/// A `Result` containing the job once it has finished, or its last state when the timeout is reached.
+/// # Example
+/// ```
+/// let session = Session::new(&api, id_token);
+/// let job = watch_job(&session, "my_project", "job-id", Duration::from_secs(10), Duration::from_secs(120), None).await?;
+/// ```
+pub async fn watch_job(
+    session: &api::Session,
+    project: &str,
+    job_id: &str,
+    interval: Duration,
+    max_interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<Job> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    pb.enable_steady_tick(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let mut delay = interval;
+    let mut previous = String::new();
+    let endpoint = job_endpoint(project, job_id);
+    loop {
+        let job = parse_job(session.get(&endpoint, &[]).await?, project, job_id)?;
+        let summary = job.step_summary();
+        pb.set_message(format!("{} ({}) {}", job.job_name, job_id, summary));
+        let status = job.status();
+        if status != JobStatus::Pending {
+            let mark = if status == JobStatus::Completed {
+                "✓"
+            } else {
+                "✗"
+            };
+            pb.finish_with_message(format!(
+                "{} {} ({}) {}",
+                mark, job.job_name, job_id, summary
+            ));
+            return Ok(job);
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            pb.finish_with_message(format!("… {} ({}) {}", job.job_name, job_id, summary));
+            return Ok(job);
+        }
+        if summary != previous {
+            delay = interval;
+            previous = summary;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 3 / 2).min(max_interval);
+    }
+}
+
+/// Reads the jobs listed by `clinic batch-submit`, a job ID and project per line separated by tabs.
+/// Lines without a project use `project`; lines whose job ID is `-` were never assigned one and are skipped.
+/// # Errors
+/// Returns an error if the file cannot be read or a line names no project.
+/// # Example
+/// ```
+/// let jobs = read_jobs_file("jobs.tsv", Some("my_project"))?;
+/// ```
+pub fn read_jobs_file<P: AsRef<Path>>(
+    path: P,
+    project: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+    let text = std::fs::read_to_string(path.as_ref())?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|(index, line)| {
+            let mut fields = line.split('\t').map(str::trim);
+            let job_id = fields.next().filter(|job_id| *job_id != "-")?;
+            let row_project = fields.next().filter(|value| !value.is_empty()).or(project);
+            Some(match row_project {
+                Some(row_project) => Ok((row_project.to_string(), job_id.to_string())),
+                None => Err(anyhow!(
+                    "Line {} of {} has no project; pass --project.",
+                    index + 1,
+                    path.as_ref().display()
+                )),
+            })
+        })
+        .collect()
+}
+
+/// Returns the project and ID of the jobs named on the command line, or listed in a file by `clinic batch-submit`.
+/// # Errors
+/// Returns an error if the file cannot be read or a job has no project.
+/// # Example
+/// ```
+/// let jobs = select_jobs(vec!["job-id".to_string()], Some("my_project"), None)?;
+/// ```
+pub fn select_jobs(
+    job_ids: Vec<String>,
+    project: Option<&str>,
+    jobs_file: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+    if let Some(path) = jobs_file {
+        return read_jobs_file(path, project);
+    }
+    let project = project.ok_or(anyhow!("The project of the jobs is required."))?;
+    Ok(job_ids
+        .into_iter()
+        .map(|job_id| (project.to_string(), job_id))
+        .collect())
+}
+
+/// Formats jobs for printing, as a table of ids, names, statuses and inputs or as JSON.
+pub fn format_jobs(jobs: &[Job], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(jobs)?));
+    }
+    let rows: Vec<Vec<String>> = jobs
+        .iter()
+        .map(|job| {
+            vec![
+                job.job_id.clone(),
+                job.job_name.clone(),
+                job.status().to_string(),
+                job.step_summary(),
+                job.created_at.clone(),
+                job.input_vcf.clone(),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &["JOB ID", "NAME", "STATUS", "STEPS", "CREATED", "INPUT"],
+        &rows,
+    ))
+}
+
+/// Formats a job for printing, with every field of the job, or as JSON.
+pub fn format_job(job: &Job, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(job)?));
+    }
+    let mut output = format!(
+        "job_id: {}\njob_name: {}\nstatus: {}\ncreated_at: {}\ninput_vcf: {}\n",
+        job.job_id,
+        job.job_name,
+        job.status(),
+        job.created_at,
+        job.input_vcf
+    );
+    for (key, value) in &job.fields {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        output.push_str(&format!("{}: {}\n", key, value));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    fn job(fields: Value) -> Job {
+        let mut value = json!({"job_id": "j1", "job_name": "sample", "input_vcf": "sample.vcf.gz"});
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_status() {
+        let hybrid = job(json!({"pharmcat_status": "completed", "lookup_status": "pending"}));
+        assert_eq!(hybrid.status(), JobStatus::Pending);
+        let hybrid = job(
+            json!({"pharmcat_status": "completed", "lookup_status": "failed",
+            "lookup_failed_step": "annotate", "lookup_error_message": "out of memory"}),
+        );
+        assert_eq!(hybrid.status(), JobStatus::Failed);
+        assert_eq!(
+            hybrid.failure().unwrap(),
+            "lookup failed at annotate: out of memory"
+        );
+        let svep = job(json!({"svep_status": "completed"}));
+        assert_eq!(svep.status(), JobStatus::Completed);
+        assert!(svep.failure().is_none());
+        assert_eq!(job(json!({})).status(), JobStatus::Pending);
+    }
+
+    #[test]
+    fn test_read_jobs_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            "j1\tcohort1\tsample one\ts1.vcf.gz\n-\tcohort1\tsample two\ts2.vcf.gz\nj3\n",
+        )
+        .unwrap();
+        let jobs = read_jobs_file(file.path(), Some("cohort2")).unwrap();
+        assert_eq!(
+            jobs,
+            [
+                ("cohort1".to_string(), "j1".to_string()),
+                ("cohort2".to_string(), "j3".to_string())
+            ]
+        );
+        assert!(read_jobs_file(file.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_format_jobs() {
+        let jobs = [job(
+            json!({"svep_status": "completed", "created_at": "2025-01-01 10:00:00"}),
+        )];
+        let table = format_jobs(&jobs, OutputFormat::Table).unwrap();
+        assert_eq!(
+            table,
+            "JOB ID  NAME    STATUS     STEPS           CREATED              INPUT\n\
+             j1      sample  completed  svep:completed  2025-01-01 10:00:00  sample.vcf.gz\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_jobs_pages() {
+        let mut server = Server::new_async().await;
+        let first = server
+            .mock("GET", "/dportal/projects/p1/clinical-workflows")
+            // the first page is requested without a key
+            .match_query(Matcher::Regex("^limit=10&job_status=failed$".into()))
+            .with_status(200)
+            .with_body(r#"{"success": true, "jobs": [{"job_id": "j1", "svep_status": "failed"}], "last_evaluated_key": "k1"}"#)
+            .create();
+        let second = server
+            .mock("GET", "/dportal/projects/p1/clinical-workflows")
+            .match_query(Matcher::UrlEncoded("last_evaluated_key".into(), "k1".into()))
+            .with_status(200)
+            .with_body(r#"{"success": true, "jobs": [{"job_id": "j2", "svep_status": "failed"}], "last_evaluated_key": null}"#)
+            .create();
+
+        let filter = JobFilter {
+            status: Some(JobStatus::Failed),
+            search: None,
+        };
+        let jobs = list_jobs(&server.url(), "p1", &filter, 10, "token")
+            .await
+            .unwrap();
+        let ids: Vec<&str> = jobs.iter().map(|job| job.job_id.as_str()).collect();
+        assert_eq!(ids, ["j1", "j2"]);
+        first.assert();
+        second.assert();
+    }
+
+    #[tokio::test]
+    async fn test_watch_job() {
+        let mut server = Server::new_async().await;
+        // the first poll is throttled and retried by the session
+        let throttled = server
+            .mock("GET", "/dportal/projects/p1/clinical-workflows/j1")
+            .with_status(503)
+            .expect(1)
+            .create();
+        let session = api::Session::new(&server.url(), "token".to_string());
+        let watch = watch_job(
+            &session,
+            "p1",
+            "j1",
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+            None,
+        );
+        let failed = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            throttled.remove();
+            server
+                .mock("GET", "/dportal/projects/p1/clinical-workflows/j1")
+                .with_status(200)
+                .with_body(r#"{"success": true, "job": {"job_id": "j1", "job_name": "sample", "svep_status": "failed"}}"#)
+                .create()
+        };
+        let (job, failed) = tokio::join!(watch, failed);
+        assert_eq!(job.unwrap().status(), JobStatus::Failed);
+        failed.assert();
+    }
+}
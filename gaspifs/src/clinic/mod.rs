@@ -0,0 +1,238 @@
+pub mod curation;
+pub mod jobs;
+pub mod qc;
+pub mod report;
+pub mod results;
+pub mod submit;
+pub mod validation;
+
+use anyhow::{Result, anyhow};
+use reqwest::{Client as URLClient, RequestBuilder, header};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// The clinical workflows a portal can run.
+/// A portal is deployed with either sVEP or PGxFlow; hybrid jobs run PharmCAT and the
+/// allele lookup of a PGxFlow deployment together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workflow {
+    Svep,
+    Pgxflow,
+    Hybrid,
+}
+
+impl Workflow {
+    /// Returns the clinic mode of the deployments that run the workflow.
+    pub fn mode(&self) -> &'static str {
+        match self {
+            Workflow::Svep => "svep",
+            Workflow::Pgxflow | Workflow::Hybrid => "pgxflow",
+        }
+    }
+
+    /// Checks that the workflow runs on a portal deployed in the given clinic mode.
+    /// # Errors
+    /// Returns an error naming the mode of the portal if it cannot run the workflow.
+    pub fn check(&self, mode: &str) -> Result<()> {
+        if mode.is_empty() || mode == self.mode() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "This portal runs {} workflows and cannot run {} jobs.",
+                mode,
+                self
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Workflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Workflow::Svep => "svep",
+            Workflow::Pgxflow => "pgxflow",
+            Workflow::Hybrid => "hybrid",
+        })
+    }
+}
+
+impl FromStr for Workflow {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "svep" => Ok(Workflow::Svep),
+            "pgxflow" => Ok(Workflow::Pgxflow),
+            "hybrid" => Ok(Workflow::Hybrid),
+            _ => Err(anyhow!(
+                "Unknown workflow {:?}, expected svep, pgxflow or hybrid",
+                value
+            )),
+        }
+    }
+}
+
+/// Returns the base URL of the clinic API, which is only deployed on portals that run clinical workflows.
+/// # Errors
+/// Returns an error if `CLINIC_API` is not set or empty.
+pub fn clinic_api() -> Result<String> {
+    env::var("CLINIC_API")
+        .ok()
+        .filter(|api| !api.is_empty())
+        .ok_or(anyhow!(
+            "CLINIC_API is not set; this portal does not run clinical workflows."
+        ))
+}
+
+/// Returns the clinic mode of the portal, `svep` or `pgxflow`, or an empty string if it is unknown.
+pub fn clinic_mode() -> String {
+    env::var("CLINIC_MODE").unwrap_or_default()
+}
+
+/// Gets a clinic endpoint, such as `results`.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `endpoint` - The path of the endpoint, relative to the API.
+/// * `params` - The query string parameters.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the JSON response, or `null` if it has no body.
+/// # Errors
+/// Returns an error like `post`.
+/// # Example
+/// ```
+/// let results = get(&clinic_api()?, "results", &[("request_id", "my_job"), ("project_name", "my_project")], &id_token).await?;
+/// ```
+pub async fn get(
+    api: &str,
+    endpoint: &str,
+    params: &[(&str, &str)],
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.get(format!("{}/{}", api, endpoint)).query(params);
+    send(request, endpoint, token).await
+}
+
+/// Posts a request body to a clinic endpoint, such as `submit`.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `endpoint` - The path of the endpoint, relative to the API.
+/// * `body` - The request body.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the JSON response, or `null` if it has no body.
+/// # Errors
+/// Returns an error with the message of the API if the request fails.
+/// # Example
+/// ```
+/// let response = post(&clinic_api()?, "submit", &body, &id_token).await?;
+/// ```
+pub async fn post(
+    api: &str,
+    endpoint: &str,
+    body: &serde_json::Value,
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.post(format!("{}/{}", api, endpoint)).json(body);
+    send(request, endpoint, token).await
+}
+
+/// Posts a request body to a clinic endpoint that also takes query string parameters, such as `qcnotes`.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `endpoint` - The path of the endpoint, relative to the API.
+/// * `params` - The query string parameters.
+/// * `body` - The request body.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the JSON response, or `null` if it has no body.
+/// # Errors
+/// Returns an error like `post`.
+/// # Example
+/// ```
+/// post_with_query(&clinic_api()?, "qcnotes", &[("projectName", "my_project"), ("fileName", "sample.vcf.gz")], &notes, &id_token).await?;
+/// ```
+pub async fn post_with_query(
+    api: &str,
+    endpoint: &str,
+    params: &[(&str, &str)],
+    body: &serde_json::Value,
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client
+        .post(format!("{}/{}", api, endpoint))
+        .query(params)
+        .json(body);
+    send(request, endpoint, token).await
+}
+
+/// Sends a clinic request, turning error statuses into errors with the message of the API.
+async fn send(request: RequestBuilder, endpoint: &str, token: &str) -> Result<serde_json::Value> {
+    let response = request
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, "*/*")
+        .header(header::USER_AGENT, "gaspifs/1.0")
+        .send()
+        .await?;
+
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        let message = serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|error| {
+                error["error"]["errorMessage"]
+                    .as_str()
+                    .or(error["message"].as_str())
+                    .map(str::to_string)
+            })
+            .unwrap_or(text);
+        return Err(anyhow!(
+            "Clinic request to {} failed ({}): {}",
+            endpoint,
+            status,
+            message
+        ));
+    }
+    if text.trim().is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+
+    #[test]
+    fn test_workflow() {
+        let workflow: Workflow = "Hybrid".parse().unwrap();
+        assert_eq!(workflow, Workflow::Hybrid);
+        assert!(workflow.check("pgxflow").is_ok());
+        assert!(workflow.check("").is_ok());
+        assert!(workflow.check("svep").is_err());
+        assert!("vep".parse::<Workflow>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_post_error_message() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/submit")
+            .with_status(400)
+            .with_body(r#"{"error": {"errorMessage": "Job name already exists"}}"#)
+            .create();
+
+        let err = post(&server.url(), "submit", &serde_json::json!({}), "token")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("Job name already exists"));
+        mock.assert();
+    }
+}
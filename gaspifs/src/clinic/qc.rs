@@ -0,0 +1,490 @@
+use super::jobs::get_job;
+use super::{get, post, post_with_query};
+use crate::auth::token_claims;
+use crate::networking::api::{OutputFormat, format_table};
+use anyhow::{Result, anyhow};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The QC plots `vcfstats` generates for a VCF file, by key and column title.
+pub const QC_PLOTS: [(&str, &str); 5] = [
+    ("qc_hc", "QUALITY"),
+    ("low_var", "LOW VARIANTS"),
+    ("gq", "GQ"),
+    ("alle_freq", "AF"),
+    ("snp_pass", "SNP PASS"),
+];
+
+/// Number of times a plot that is still being generated is requested again.
+const PLOT_RETRIES: usize = 2;
+
+/// Number of jobs whose QC is fetched at the same time.
+const CONCURRENT_JOBS: usize = 4;
+
+/// A QC plot of a VCF file, or the reason it could not be generated.
+#[derive(Debug, Clone, Serialize)]
+pub struct QcPlot {
+    pub key: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A note left on the QC of a VCF file, as stored by the portal.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QcNote {
+    pub id: String,
+    pub user: String,
+    #[serde(default)]
+    pub email: String,
+    pub created_at: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl QcNote {
+    /// Creates a note signed with the username and email in an ID token.
+    /// # Example
+    /// ```
+    /// let note = QcNote::from_token(&id_token, "Low depth", "Mean depth is below 20x")?;
+    /// ```
+    pub fn from_token(token: &str, title: &str, description: &str) -> Result<Self> {
+        let claims = token_claims(token)?;
+        let user = claims["cognito:username"]
+            .as_str()
+            .ok_or(anyhow!("The ID token does not name the user"))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        Ok(QcNote {
+            id: format!("{}_{}", user, now.as_secs()),
+            user: user.to_string(),
+            email: claims["email"].as_str().unwrap_or_default().to_string(),
+            created_at: iso_timestamp(now),
+            title: title.to_string(),
+            description: description.to_string(),
+        })
+    }
+}
+
+/// The QC plots and notes of the input of a job.
+#[derive(Debug, Clone, Serialize)]
+pub struct QcSummary {
+    pub job_id: String,
+    pub project: String,
+    pub file: String,
+    pub plots: Vec<QcPlot>,
+    pub notes: Vec<QcNote>,
+}
+
+/// Generates a QC plot of a VCF file, asking again while the plot is not ready.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `project` - The project of the file.
+/// * `file` - The name of the VCF file in the project.
+/// * `key` - The key of the plot, one of `QC_PLOTS`.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `QcPlot` with the URL of the plot, or the reason it could not be generated.
+/// # Example
+/// ```
+/// let plot = generate_plot(&clinic_api()?, "my_project", "sample.vcf.gz", "gq", &id_token).await;
+/// ```
+pub async fn generate_plot(api: &str, project: &str, file: &str, key: &str, token: &str) -> QcPlot {
+    let body = json!({"projectName": project, "fileName": file, "key": key});
+    let mut plot = QcPlot {
+        key: key.to_string(),
+        title: String::new(),
+        url: None,
+        error: None,
+    };
+    for attempt in 0..=PLOT_RETRIES {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let image = match post(api, "vcfstats", &body, token).await {
+            Ok(response) => response["images"][key].clone(),
+            Err(error) => {
+                plot.error = Some(error.to_string());
+                continue;
+            }
+        };
+        if let Some(description) = image["description"].as_str() {
+            // The plot cannot be generated from this file, asking again will not help
+            plot.error = Some(description.to_string());
+            return plot;
+        }
+        if let Some(url) = image["url"].as_str() {
+            plot.title = image["title"].as_str().unwrap_or_default().to_string();
+            plot.url = Some(url.to_string());
+            plot.error = None;
+            return plot;
+        }
+        plot.error = Some("The plot is not ready".to_string());
+    }
+    plot
+}
+
+/// Gets the QC notes of a VCF file, in the order they were added.
+/// The portal stores the notes as a JSON array encoded in a string; notes saved as plain text
+/// by older portals are returned as a single note.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `project` - The project of the file.
+/// * `file` - The name of the VCF file in the project.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the notes.
+/// # Errors
+/// Returns an error if the request fails or the notes are neither text nor missing.
+/// # Example
+/// ```
+/// let notes = get_notes(&clinic_api()?, "my_project", "sample.vcf.gz", &id_token).await?;
+/// ```
+pub async fn get_notes(api: &str, project: &str, file: &str, token: &str) -> Result<Vec<QcNote>> {
+    let response = get(
+        api,
+        "qcnotes",
+        &[("projectName", project), ("fileName", file)],
+        token,
+    )
+    .await?;
+    let text = match &response["notes"] {
+        Value::String(text) => text.as_str(),
+        Value::Null => "",
+        other => {
+            return Err(anyhow!(
+                "Unexpected QC notes of {} in {}: expected text, got {}",
+                file,
+                project,
+                other
+            ));
+        }
+    };
+    let notes = match serde_json::from_str::<Vec<QcNote>>(text) {
+        Ok(notes) => notes,
+        Err(_) if text.trim().is_empty() => Vec::new(),
+        Err(_) => vec![QcNote {
+            id: "legacy".to_string(),
+            user: "System".to_string(),
+            email: String::new(),
+            created_at: String::new(),
+            title: "Legacy Note".to_string(),
+            description: text.to_string(),
+        }],
+    };
+    Ok(notes)
+}
+
+/// Appends a note to the QC notes of a VCF file.
+/// The portal stores the notes of a file together, so they are read and written back with the new note,
+/// encoded in a string as the portal does.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `project` - The project of the file.
+/// * `file` - The name of the VCF file in the project.
+/// * `note` - The note to add.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the notes cannot be read or saved.
+/// # Example
+/// ```
+/// add_note(&clinic_api()?, "my_project", "sample.vcf.gz", &note, &id_token).await?;
+/// ```
+pub async fn add_note(
+    api: &str,
+    project: &str,
+    file: &str,
+    note: &QcNote,
+    token: &str,
+) -> Result<()> {
+    let mut notes = get_notes(api, project, file, token).await?;
+    notes.push(note.clone());
+    post_with_query(
+        api,
+        "qcnotes",
+        &[("projectName", project), ("fileName", file)],
+        &Value::String(serde_json::to_string(&notes)?),
+        token,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Fetches the QC plots and notes of the input of a job.
+/// # Example
+/// ```
+/// let summary = fetch_summary(&clinic_api()?, "my_project", "job-id", "sample.vcf.gz", &id_token).await?;
+/// ```
+pub async fn fetch_summary(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    file: &str,
+    token: &str,
+) -> Result<QcSummary> {
+    let plots = futures::future::join_all(
+        QC_PLOTS
+            .iter()
+            .map(|(key, _)| generate_plot(api, project, file, key, token)),
+    )
+    .await;
+    let notes = get_notes(api, project, file, token).await?;
+    Ok(QcSummary {
+        job_id: job_id.to_string(),
+        project: project.to_string(),
+        file: file.to_string(),
+        plots,
+        notes,
+    })
+}
+
+/// Fetches the QC plots and notes of the inputs of several jobs, a few jobs at a time.
+/// # Arguments
+/// * `api` - The base URL of the API, which knows the inputs of the jobs.
+/// * `clinic_api` - The base URL of the clinic API.
+/// * `jobs` - The project and ID of each job.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing a `QcSummary` per job, in the order of `jobs`.
+/// # Errors
+/// Returns an error if a job cannot be found or its notes cannot be read.
+/// # Example
+/// ```
+/// let summaries = fetch_summaries(&api, &clinic_api()?, &jobs, &id_token).await?;
+/// ```
+pub async fn fetch_summaries(
+    api: &str,
+    clinic_api: &str,
+    jobs: &[(String, String)],
+    token: &str,
+) -> Result<Vec<QcSummary>> {
+    stream::iter(jobs)
+        .map(|(project, job_id)| async move {
+            let job = get_job(api, project, job_id, token).await?;
+            fetch_summary(clinic_api, project, job_id, &job.input_vcf, token).await
+        })
+        .buffered(CONCURRENT_JOBS)
+        .try_collect()
+        .await
+}
+
+/// Formats QC summaries for printing, as a table with the state of each plot and the latest note, or as JSON.
+pub fn format_summaries(summaries: &[QcSummary], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(summaries)?));
+    }
+    let mut header = vec!["JOB ID", "FILE"];
+    header.extend(QC_PLOTS.iter().map(|(_, title)| *title));
+    header.extend(["NOTES", "LATEST NOTE"]);
+    let rows: Vec<Vec<String>> = summaries
+        .iter()
+        .map(|summary| {
+            let mut row = vec![summary.job_id.clone(), summary.file.clone()];
+            row.extend(summary.plots.iter().map(|plot| {
+                if plot.url.is_some() {
+                    "ready"
+                } else {
+                    "failed"
+                }
+                .to_string()
+            }));
+            row.push(summary.notes.len().to_string());
+            row.push(
+                summary
+                    .notes
+                    .iter()
+                    .max_by(|a, b| a.created_at.cmp(&b.created_at))
+                    .map(|note| format!("{}: {}", note.user, note.title))
+                    .unwrap_or_default(),
+            );
+            row
+        })
+        .collect();
+    Ok(format_table(&header, &rows))
+}
+
+/// Formats QC notes for printing, as a table or as JSON.
+pub fn format_notes(notes: &[QcNote], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(notes)?));
+    }
+    let rows: Vec<Vec<String>> = notes
+        .iter()
+        .map(|note| {
+            vec![
+                note.created_at.clone(),
+                note.user.clone(),
+                note.title.clone(),
+                note.description.replace('\n', " "),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &["CREATED", "USER", "TITLE", "DESCRIPTION"],
+        &rows,
+    ))
+}
+
+/// Formats a time since the Unix epoch as an ISO 8601 UTC timestamp with milliseconds,
+/// like the notes written by the portal.
+fn iso_timestamp(time: Duration) -> String {
+    let seconds = time.as_secs();
+    let (days, seconds_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+    // civil date from days since the epoch, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        time.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    #[test]
+    fn test_iso_timestamp() {
+        assert_eq!(
+            iso_timestamp(Duration::from_millis(0)),
+            "1970-01-01T00:00:00.000Z"
+        );
+        assert_eq!(
+            iso_timestamp(Duration::from_millis(1_709_210_096_789)),
+            "2024-02-29T12:34:56.789Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_note() {
+        let mut server = Server::new_async().await;
+        let query = Matcher::AllOf(vec![
+            Matcher::UrlEncoded("projectName".into(), "p1".into()),
+            Matcher::UrlEncoded("fileName".into(), "s1.vcf.gz".into()),
+        ]);
+        let get = server
+            .mock("GET", "/qcnotes")
+            .match_query(query.clone())
+            .with_status(200)
+            .with_body(json!({"notes": "Low depth on chr21"}).to_string())
+            .create();
+        let note = QcNote {
+            id: "ana_1700000000".to_string(),
+            user: "ana".to_string(),
+            email: "ana@example.com".to_string(),
+            created_at: "2023-11-14T22:13:20.000Z".to_string(),
+            title: "Rerun".to_string(),
+            description: "Resequenced".to_string(),
+        };
+        let legacy = QcNote {
+            id: "legacy".to_string(),
+            user: "System".to_string(),
+            email: String::new(),
+            created_at: String::new(),
+            title: "Legacy Note".to_string(),
+            description: "Low depth on chr21".to_string(),
+        };
+        let notes = serde_json::to_string(&[legacy, note.clone()]).unwrap();
+        assert!(notes.contains(r#""createdAt":"2023-11-14T22:13:20.000Z""#));
+        let post = server
+            .mock("POST", "/qcnotes")
+            .match_query(query)
+            .match_body(Matcher::Json(Value::String(notes)))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        add_note(&server.url(), "p1", "s1.vcf.gz", &note, "token")
+            .await
+            .unwrap();
+        get.assert();
+        post.assert();
+
+        get.remove();
+        server
+            .mock("GET", "/qcnotes")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({"notes": [{"title": "Rerun"}]}).to_string())
+            .create();
+        assert!(
+            get_notes(&server.url(), "p1", "s1.vcf.gz", "token")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_summary() {
+        let mut server = Server::new_async().await;
+        for (key, _) in QC_PLOTS {
+            let image = if key == "gq" {
+                json!({"description": "Report failed to generate, missing field on file."})
+            } else {
+                json!({"title": key, "url": format!("https://plots/{}.png", key)})
+            };
+            server
+                .mock("POST", "/vcfstats")
+                .match_body(Matcher::PartialJson(
+                    json!({"fileName": "s1.vcf.gz", "key": key}),
+                ))
+                .with_status(200)
+                .with_body(json!({"images": {key: image}}).to_string())
+                .create();
+        }
+        let notes = json!([
+            {"id": "b_2", "user": "bo", "createdAt": "2024-02-01T00:00:00.000Z", "title": "Checked"},
+            {"id": "a_1", "user": "ana", "createdAt": "2024-01-01T00:00:00.000Z", "title": "Low depth"}
+        ]);
+        server
+            .mock("GET", "/qcnotes")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({"notes": notes.to_string()}).to_string())
+            .create();
+
+        let summary = fetch_summary(&server.url(), "p1", "j1", "s1.vcf.gz", "token")
+            .await
+            .unwrap();
+        assert_eq!(
+            summary.plots[0].url.as_deref(),
+            Some("https://plots/qc_hc.png")
+        );
+        assert!(summary.plots[2].url.is_none());
+        assert!(
+            summary.plots[2]
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("missing field")
+        );
+        assert_eq!(summary.notes.len(), 2);
+
+        let table = format_summaries(&[summary], OutputFormat::Table).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("JOB ID  FILE"));
+        assert!(lines[1].contains("ready    ready         failed"));
+        assert!(lines[1].ends_with("2      bo: Checked"));
+    }
+}
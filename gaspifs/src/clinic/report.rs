@@ -0,0 +1,215 @@
+use crate::networking::api;
+use crate::networking::report::{TransferReport, run_transfers};
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde_json::{Map, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The language and template of a report. Hubs with a single template ignore them.
+#[derive(Debug, Clone, Default)]
+pub struct ReportOptions {
+    /// The language of the report, such as `en` or `id`.
+    pub lang: Option<String>,
+    /// The template of the report, such as `crd`, `md` or `generic`.
+    pub template: Option<String>,
+}
+
+/// Returns the name of the hub, which selects the lab whose report layout is used.
+/// # Errors
+/// Returns an error if `HUB_NAME` is not set or empty.
+pub fn hub_name() -> Result<String> {
+    env::var("HUB_NAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .ok_or(anyhow!(
+            "HUB_NAME is not set; the report layout is unknown."
+        ))
+}
+
+/// Generates the clinical report of a job from its validated variants, or its negative report.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `lab` - The hub whose report layout is used.
+/// * `options` - The language and template of the report.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the report document.
+/// # Errors
+/// Returns an error with the message of the API if the report cannot be generated.
+/// # Example
+/// ```
+/// let document = generate_report(&api, "my_project", "job-id", &hub_name()?, &ReportOptions::default(), &id_token).await?;
+/// ```
+pub async fn generate_report(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    lab: &str,
+    options: &ReportOptions,
+    token: &str,
+) -> Result<Vec<u8>> {
+    let mut body = Map::new();
+    body.insert("lab".to_string(), Value::from(lab));
+    if let Some(lang) = &options.lang {
+        body.insert("lang".to_string(), Value::from(lang.as_str()));
+    }
+    if let Some(template) = &options.template {
+        body.insert("mode".to_string(), Value::from(template.as_str()));
+    }
+    let endpoint = format!(
+        "dportal/projects/{}/clinical-workflows/{}/report",
+        project, job_id
+    );
+    let response = api::post(api, &endpoint, &Value::Object(body), token).await?;
+    if response["success"] != true {
+        return Err(anyhow!(
+            "Failed to generate the report of job {}: {}",
+            job_id,
+            response["message"].as_str().unwrap_or("unknown error")
+        ));
+    }
+    let content = response["content"]
+        .as_str()
+        .ok_or(anyhow!("The report of job {} has no content", job_id))?;
+    Ok(STANDARD.decode(content)?)
+}
+
+/// Returns the file extension of a report document: `pdf`, `docx`, or `bin` if it is neither.
+pub fn report_extension(document: &[u8]) -> &'static str {
+    if document.starts_with(b"%PDF") {
+        "pdf"
+    } else if document.starts_with(b"PK\x03\x04") {
+        // DOCX documents are ZIP archives
+        "docx"
+    } else {
+        "bin"
+    }
+}
+
+/// Generates and saves the reports of several jobs at once, like a batch download.
+/// A report saved to `output` keeps that name; otherwise it is saved in `destination` as
+/// `<project>_<job id>_report.<pdf|docx>`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `jobs` - The project and ID of each job.
+/// * `output` - Where to save the report of a single job, if given.
+/// * `destination` - The directory the reports are saved in otherwise.
+/// * `lab` - The hub whose report layout is used.
+/// * `options` - The language and template of the reports.
+/// * `token` - The authentication token.
+/// * `keep_going` - Whether to attempt every report even if some of them fail.
+/// # Returns
+/// A `TransferReport` with one entry per job.
+/// # Example
+/// ```
+/// let jobs = vec![("my_project".to_string(), "job-id".to_string())];
+/// let report = download_reports(&api, &jobs, None, Path::new("."), &hub_name()?, &ReportOptions::default(), &id_token, false).await;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn download_reports(
+    api: &str,
+    jobs: &[(String, String)],
+    output: Option<&Path>,
+    destination: &Path,
+    lab: &str,
+    options: &ReportOptions,
+    token: &str,
+    keep_going: bool,
+) -> TransferReport {
+    let transfers = jobs
+        .iter()
+        .map(|(project, job_id)| {
+            let (api, project, job_id) = (api.to_string(), project.clone(), job_id.clone());
+            let (lab, options, token) = (lab.to_string(), options.clone(), token.to_string());
+            let path = output.map(Path::to_path_buf);
+            let destination = destination.to_path_buf();
+            let name = format!("{}:{}", project, job_id);
+            let transfer = async move {
+                let document =
+                    generate_report(&api, &project, &job_id, &lab, &options, &token).await?;
+                let path = path.unwrap_or_else(|| {
+                    destination.join(report_file_name(&project, &job_id, &document))
+                });
+                tokio::fs::write(&path, &document).await?;
+                eprintln!("✓ {} saved to {}", job_id, path.display());
+                Ok(())
+            };
+            (name, Ok(transfer))
+        })
+        .collect();
+    run_transfers(transfers, keep_going).await
+}
+
+fn report_file_name(project: &str, job_id: &str, document: &[u8]) -> PathBuf {
+    PathBuf::from(format!(
+        "{}_{}_report.{}",
+        project,
+        job_id,
+        report_extension(document)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    #[test]
+    fn test_report_extension() {
+        assert_eq!(report_extension(b"%PDF-1.7"), "pdf");
+        assert_eq!(report_extension(b"PK\x03\x04word/"), "docx");
+        assert_eq!(report_extension(b"<html>"), "bin");
+    }
+
+    #[tokio::test]
+    async fn test_download_reports() {
+        let mut server = Server::new_async().await;
+        let good = server
+            .mock("POST", "/dportal/projects/p1/clinical-workflows/j1/report")
+            .match_body(Matcher::Json(
+                json!({"lab": "RSSARDJITO", "lang": "id", "mode": "crd"}),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({"success": true, "content": STANDARD.encode(b"%PDF-1.7 report")})
+                    .to_string(),
+            )
+            .create();
+        let bad = server
+            .mock("POST", "/dportal/projects/p1/clinical-workflows/j2/report")
+            .with_status(200)
+            .with_body(r#"{"success": false, "message": "No validated variants"}"#)
+            .create();
+
+        let destination = tempfile::tempdir().unwrap();
+        let options = ReportOptions {
+            lang: Some("id".to_string()),
+            template: Some("crd".to_string()),
+        };
+        let jobs = [
+            ("p1".to_string(), "j1".to_string()),
+            ("p1".to_string(), "j2".to_string()),
+        ];
+        let report = download_reports(
+            &server.url(),
+            &jobs,
+            None,
+            destination.path(),
+            "RSSARDJITO",
+            &options,
+            "token",
+            true,
+        )
+        .await;
+        assert_eq!((report.succeeded(), report.failed()), (1, 1));
+        assert!(report.exit_code() != std::process::ExitCode::SUCCESS);
+        let saved = std::fs::read(destination.path().join("p1_j1_report.pdf")).unwrap();
+        assert_eq!(saved, b"%PDF-1.7 report");
+        good.assert();
+        bad.assert();
+    }
+}
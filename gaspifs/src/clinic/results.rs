@@ -0,0 +1,1099 @@
+use super::get;
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use parquet::basic::{Compression, LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The columns of sVEP results, which are returned as tab-separated lines without a header.
+pub const SVEP_COLUMNS: &[&str] = &[
+    "Rank",
+    "Region",
+    "Alt Allele",
+    "Consequence",
+    "Variant Name",
+    "Gene Name",
+    "Gene ID",
+    "Feature",
+    "Transcript ID & Version",
+    "Transcript Biotype",
+    "Exon Number",
+    "Amino Acid Change",
+    "Codon Change",
+    "Strand",
+    "Transcript Support Level",
+    "ref",
+    "gt",
+    "qual",
+    "filter",
+    "variationId",
+    "rsId",
+    "omimId",
+    "classification",
+    "conditions",
+    "clinSig",
+    "reviewStatus",
+    "lastEvaluated",
+    "accession",
+    "pubmed",
+    "Allele Frequency (African)",
+    "Allele Frequency (East Asian)",
+    "Allele Frequency (Finnish)",
+    "Allele Frequency (Non-Finnish European)",
+    "Allele Frequency (South Asian)",
+    "Allele Frequency (Admixed American)",
+    "Allele Frequency (Global)",
+    "Allele Count",
+    "Allele Number",
+    "SIFT (max)",
+    "Global Allele Frequency",
+    "KHV",
+    "ac1KG",
+    "an1KG",
+    "Mis Z",
+    "Mis o/e",
+    "Mis o/e lower CI",
+    "Mis o/e upper CI",
+    "pLI",
+    "pLOF o/e",
+    "pLOF o/e upper CI",
+    "pLOF o/e lower CI",
+    "dp",
+    "gq",
+    "mq",
+    "qd",
+];
+
+/// The gene columns of sVEP and lookup results.
+const GENE_COLUMNS: &[&str] = &["Gene Name", "Gene"];
+/// The consequence column of sVEP results.
+const CONSEQUENCE_COLUMNS: &[&str] = &["Consequence"];
+/// The global allele frequency columns of sVEP and lookup results.
+const ALLELE_FREQUENCY_COLUMNS: &[&str] = &["Allele Frequency (Global)", "AF"];
+
+/// Number of rows written to each Parquet row group.
+const ROW_GROUP_SIZE: usize = 100_000;
+
+/// How exported results are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Tab-separated values with a header row.
+    Tsv,
+    /// A Parquet file with a column per result column.
+    Parquet,
+    /// A JSON object per line.
+    Jsonl,
+}
+
+impl FromStr for ResultFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "tsv" => Ok(ResultFormat::Tsv),
+            "parquet" => Ok(ResultFormat::Parquet),
+            "jsonl" => Ok(ResultFormat::Jsonl),
+            _ => Err(anyhow!(
+                "Unknown format {:?}, expected tsv, parquet or jsonl",
+                value
+            )),
+        }
+    }
+}
+
+/// The results of a PGxFlow job: the PharmCAT report or the allele lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pipeline {
+    Pharmcat,
+    Lookup,
+}
+
+impl Pipeline {
+    /// Returns the name of the pipeline in the clinic API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Pipeline::Pharmcat => "pharmcat",
+            Pipeline::Lookup => "lookup",
+        }
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "pharmcat" => Ok(Pipeline::Pharmcat),
+            "lookup" => Ok(Pipeline::Lookup),
+            _ => Err(anyhow!(
+                "Unknown pipeline {:?}, expected pharmcat or lookup",
+                value
+            )),
+        }
+    }
+}
+
+/// The comparisons of a column filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Represents a filter on the rows of the results, written on the command line as
+/// `<column><operator><value>`, such as `Gene Name=BRCA1,BRCA2` or `Allele Frequency (Global)<0.01`.
+/// The operators are `=`, `!=` and `~` (contains), which compare text case-insensitively and accept
+/// comma-separated alternatives, and `<`, `<=`, `>` and `>=`, which compare numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFilter {
+    /// The names the column may have; the first one present in the results is used.
+    pub columns: Vec<String>,
+    pub comparison: Comparison,
+    pub value: String,
+    /// Whether rows without a value in the column are kept.
+    pub keep_missing: bool,
+}
+
+impl FromStr for ColumnFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        // two-character operators first, so that `<=` is not read as `<`
+        let operators = [
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("=", Comparison::Equal),
+            ("~", Comparison::Contains),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ];
+        let (column, comparison, operand) = operators
+            .iter()
+            .filter_map(|(operator, comparison)| {
+                let (column, operand) = value.split_once(operator)?;
+                Some((column, *comparison, operand))
+            })
+            .min_by_key(|(column, _, _)| column.len())
+            .ok_or(anyhow!(
+                "Invalid column filter {:?}, expected <column><operator><value>",
+                value
+            ))?;
+        let filter = ColumnFilter {
+            columns: vec![column.trim().to_string()],
+            comparison,
+            value: operand.trim().to_string(),
+            keep_missing: false,
+        };
+        if filter.columns[0].is_empty() {
+            return Err(anyhow!(
+                "Invalid column filter {:?}, no column given",
+                value
+            ));
+        }
+        if filter.is_numeric() && filter.value.parse::<f64>().is_err() {
+            return Err(anyhow!(
+                "Invalid column filter {:?}, expected a number after the operator",
+                value
+            ));
+        }
+        Ok(filter)
+    }
+}
+
+impl ColumnFilter {
+    /// Creates a filter on the first of the named columns present in the results.
+    pub fn new(columns: &[&str], comparison: Comparison, value: &str) -> Self {
+        ColumnFilter {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            comparison,
+            value: value.to_string(),
+            keep_missing: false,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        !matches!(
+            self.comparison,
+            Comparison::Equal | Comparison::NotEqual | Comparison::Contains
+        )
+    }
+
+    /// Returns whether a value of the column passes the filter.
+    pub fn matches(&self, value: &str) -> bool {
+        if is_missing(value) {
+            return self.keep_missing || self.comparison == Comparison::NotEqual;
+        }
+        let value = value.to_lowercase();
+        let mut alternatives = self
+            .value
+            .split(',')
+            .map(|alternative| alternative.trim().to_lowercase());
+        let number = || value.parse::<f64>().ok();
+        let threshold = self.value.parse::<f64>().unwrap_or(f64::NAN);
+        match self.comparison {
+            Comparison::Equal => alternatives.any(|alternative| value == alternative),
+            Comparison::NotEqual => alternatives.all(|alternative| value != alternative),
+            Comparison::Contains => alternatives.any(|alternative| value.contains(&alternative)),
+            Comparison::Less => number().is_some_and(|number| number < threshold),
+            Comparison::LessOrEqual => number().is_some_and(|number| number <= threshold),
+            Comparison::Greater => number().is_some_and(|number| number > threshold),
+            Comparison::GreaterOrEqual => number().is_some_and(|number| number >= threshold),
+        }
+    }
+}
+
+/// Returns whether a result value is empty; the workflows write `-` or `.` for missing values.
+fn is_missing(value: &str) -> bool {
+    matches!(value.trim(), "" | "-" | ".")
+}
+
+/// A JSON object with its fields in the order they were written, which `Map` does not keep.
+/// Lookup rows are named by their keys, and the portal shows them in this order.
+struct OrderedObject(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for OrderedObject {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> Visitor<'de> for ObjectVisitor {
+            type Value = OrderedObject;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<OrderedObject, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(OrderedObject(fields))
+            }
+        }
+
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+/// The type a result column is exported as, inferred from its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Text,
+}
+
+/// The rows of the results of a job, with the values of each row in the order of the columns.
+/// `rows` holds the rows that have not been written yet; see `ResultWriter`.
+#[derive(Debug, Default)]
+pub struct ResultTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// The index of the column of each filter, resolved once the columns are known.
+    resolved: Vec<usize>,
+}
+
+impl ResultTable {
+    /// Adds the rows of a chunk of results that pass every filter.
+    /// sVEP chunks are tab-separated lines named by `SVEP_COLUMNS`; lookup chunks are a JSON object
+    /// per line, named by their keys.
+    /// # Errors
+    /// Returns an error if a line cannot be parsed or a filter names a column the results do not have.
+    pub fn add_content(&mut self, content: &str, filters: &[ColumnFilter]) -> Result<()> {
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let row = if line.trim_start().starts_with('{') {
+                let OrderedObject(object) = serde_json::from_str(line)?;
+                let mut row = vec![String::new(); self.columns.len()];
+                for (key, value) in object {
+                    let index = match self.columns.iter().position(|column| *column == key) {
+                        Some(index) => index,
+                        None => {
+                            self.columns.push(key);
+                            row.push(String::new());
+                            self.columns.len() - 1
+                        }
+                    };
+                    row[index] = match value {
+                        Value::String(text) => text,
+                        Value::Null => String::new(),
+                        value => value.to_string(),
+                    };
+                }
+                row
+            } else {
+                if self.columns.is_empty() {
+                    self.columns = SVEP_COLUMNS
+                        .iter()
+                        .map(|column| column.to_string())
+                        .collect();
+                }
+                let row: Vec<String> = line
+                    .trim_end_matches(['\r', '\n'])
+                    .split('\t')
+                    .map(str::to_string)
+                    .collect();
+                while self.columns.len() < row.len() {
+                    self.columns
+                        .push(format!("column_{}", self.columns.len() + 1));
+                }
+                row
+            };
+            if self.resolved.len() < filters.len() {
+                self.resolved = filters
+                    .iter()
+                    .map(|filter| self.resolve(filter))
+                    .collect::<Result<_>>()?;
+            }
+            let keep = filters.iter().zip(&self.resolved).all(|(filter, index)| {
+                filter.matches(row.get(*index).map(String::as_str).unwrap_or(""))
+            });
+            if keep {
+                self.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, filter: &ColumnFilter) -> Result<usize> {
+        filter
+            .columns
+            .iter()
+            .find_map(|name| {
+                self.columns
+                    .iter()
+                    .position(|column| column.eq_ignore_ascii_case(name))
+            })
+            .ok_or(anyhow!(
+                "The results have no column {}",
+                filter.columns.join(" or ")
+            ))
+    }
+
+    /// Infers the type of each column from the pending rows: integer or float if every value
+    /// present is one, and text otherwise.
+    pub fn column_types(&self) -> Vec<ColumnType> {
+        (0..self.columns.len())
+            .map(|column| {
+                let values = self
+                    .rows
+                    .iter()
+                    .map(|row| value(row, column))
+                    .filter(|value| !is_missing(value));
+                let mut column_type = None;
+                for value in values {
+                    let value_type = if value.parse::<i64>().is_ok() {
+                        ColumnType::Integer
+                    } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+                        ColumnType::Float
+                    } else {
+                        return ColumnType::Text;
+                    };
+                    column_type = Some(match (column_type, value_type) {
+                        (Some(ColumnType::Float), _) | (_, ColumnType::Float) => ColumnType::Float,
+                        _ => ColumnType::Integer,
+                    });
+                }
+                column_type.unwrap_or(ColumnType::Text)
+            })
+            .collect()
+    }
+}
+
+/// Returns a value of a row, which is empty if the row is shorter than the columns.
+fn value(row: &[String], column: usize) -> &str {
+    row.get(column).map(String::as_str).unwrap_or("")
+}
+
+/// Where a `ResultWriter` writes to.
+enum Sink {
+    Text(Box<dyn Write + Send>),
+    Parquet {
+        file: Option<File>,
+        writer: Option<SerializedFileWriter<File>>,
+        row_groups: usize,
+    },
+}
+
+/// Writes the rows of a `ResultTable` as they are fetched, so that large results are never held
+/// in memory. The columns and their types are fixed by the first rows: the first page with rows
+/// for TSV and JSON lines, and the first `ROW_GROUP_SIZE` rows for Parquet, which is then written
+/// a row group of `ROW_GROUP_SIZE` rows at a time.
+pub struct ResultWriter {
+    format: ResultFormat,
+    sink: Sink,
+    /// The columns written and their types, once the first rows are written.
+    schema: Option<(Vec<String>, Vec<ColumnType>)>,
+    written: u64,
+}
+
+impl ResultWriter {
+    /// Creates a writer to a file, or to standard output if no file is given.
+    /// # Errors
+    /// Returns an error if the file cannot be created, or if Parquet is asked for without a file.
+    pub fn create(format: ResultFormat, output: Option<&str>) -> Result<Self> {
+        let sink = match (format, output) {
+            (ResultFormat::Parquet, None) => {
+                return Err(anyhow!("Parquet results can only be written to a file"));
+            }
+            (ResultFormat::Parquet, Some(path)) => Sink::Parquet {
+                file: Some(File::create(path)?),
+                writer: None,
+                row_groups: 0,
+            },
+            (_, Some(path)) => Sink::Text(Box::new(BufWriter::new(File::create(path)?))),
+            (_, None) => Sink::Text(Box::new(BufWriter::new(io::stdout()))),
+        };
+        Ok(ResultWriter {
+            format,
+            sink,
+            schema: None,
+            written: 0,
+        })
+    }
+
+    /// Writes the pending rows of a table and removes them from it. Until the columns are fixed,
+    /// rows are only written once there are enough of them to infer the column types from.
+    /// # Errors
+    /// Returns an error if writing fails, or if the rows do not fit the columns fixed earlier.
+    pub fn write(&mut self, table: &mut ResultTable) -> Result<()> {
+        if self.schema.is_none() {
+            let needed = match self.format {
+                ResultFormat::Parquet => ROW_GROUP_SIZE,
+                _ => 1,
+            };
+            if table.rows.len() < needed {
+                return Ok(());
+            }
+            self.start(table)?;
+        }
+        self.write_rows(table, false)
+    }
+
+    /// Writes the rows left in a table and completes the output.
+    /// # Returns
+    /// A `Result` containing the number of rows written.
+    /// # Errors
+    /// Returns an error like `write`.
+    pub fn finish(mut self, table: &mut ResultTable) -> Result<u64> {
+        if self.schema.is_none() {
+            self.start(table)?;
+        }
+        self.write_rows(table, true)?;
+        match self.sink {
+            Sink::Text(mut writer) => writer.flush()?,
+            Sink::Parquet { writer, .. } => {
+                if let Some(writer) = writer {
+                    writer.close()?;
+                }
+            }
+        }
+        Ok(self.written)
+    }
+
+    /// Fixes the columns and their types from the pending rows and writes the header, if any.
+    fn start(&mut self, table: &ResultTable) -> Result<()> {
+        let columns = table.columns.clone();
+        let types = table.column_types();
+        match &mut self.sink {
+            Sink::Text(writer) => {
+                if self.format == ResultFormat::Tsv {
+                    writeln!(writer, "{}", columns.join("\t"))?;
+                }
+            }
+            Sink::Parquet { file, writer, .. } => {
+                let file = file
+                    .take()
+                    .ok_or(anyhow!("The Parquet file is already open"))?;
+                let properties = Arc::new(
+                    WriterProperties::builder()
+                        .set_compression(Compression::SNAPPY)
+                        .build(),
+                );
+                *writer = Some(SerializedFileWriter::new(
+                    file,
+                    parquet_schema(&columns, &types)?,
+                    properties,
+                )?);
+            }
+        }
+        self.schema = Some((columns, types));
+        Ok(())
+    }
+
+    /// Writes the pending rows; Parquet rows are only written by full row groups unless `last` is set.
+    fn write_rows(&mut self, table: &mut ResultTable, last: bool) -> Result<()> {
+        let Some((columns, types)) = self.schema.as_mut() else {
+            return Ok(());
+        };
+        if let Some(column) = table.columns.get(columns.len()) {
+            // JSON lines have no header, so columns that appear later are simply written as text
+            if self.format != ResultFormat::Jsonl {
+                return Err(anyhow!(
+                    "The results have a column {:?} that their first rows did not have; export them with --format jsonl instead",
+                    column
+                ));
+            }
+            columns.extend_from_slice(&table.columns[columns.len()..]);
+            types.resize(columns.len(), ColumnType::Text);
+        }
+        match &mut self.sink {
+            Sink::Text(writer) => {
+                for row in table.rows.drain(..) {
+                    if self.format == ResultFormat::Tsv {
+                        let values: Vec<&str> = (0..columns.len())
+                            .map(|column| value(&row, column))
+                            .collect();
+                        writeln!(writer, "{}", values.join("\t"))?;
+                    } else {
+                        let object: Map<String, Value> = columns
+                            .iter()
+                            .zip(types.iter())
+                            .enumerate()
+                            .map(|(column, (name, column_type))| {
+                                (name.clone(), json_value(value(&row, column), *column_type))
+                            })
+                            .collect();
+                        writeln!(writer, "{}", Value::Object(object))?;
+                    }
+                    self.written += 1;
+                }
+            }
+            Sink::Parquet {
+                writer, row_groups, ..
+            } => {
+                let writer = writer
+                    .as_mut()
+                    .ok_or(anyhow!("The Parquet file is not open"))?;
+                // an empty table still gets a row group, so that readers see its columns
+                while table.rows.len() >= ROW_GROUP_SIZE
+                    || (last && (!table.rows.is_empty() || *row_groups == 0))
+                {
+                    let count = table.rows.len().min(ROW_GROUP_SIZE);
+                    let rows: Vec<Vec<String>> = table.rows.drain(..count).collect();
+                    write_row_group(writer, columns, types, &rows)?;
+                    *row_groups += 1;
+                    self.written += count as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts a value to JSON, as a number in numeric columns and `null` when missing.
+/// Values that do not fit the type of their column are kept as text.
+fn json_value(value: &str, column_type: ColumnType) -> Value {
+    if is_missing(value) && column_type != ColumnType::Text {
+        return Value::Null;
+    }
+    match column_type {
+        ColumnType::Integer => value
+            .parse::<i64>()
+            .map_or_else(|_| value.into(), Value::from),
+        ColumnType::Float => value
+            .parse::<f64>()
+            .map_or_else(|_| value.into(), Value::from),
+        ColumnType::Text => value.into(),
+    }
+}
+
+/// Builds the Parquet schema of the results, with a nullable column per result column.
+fn parquet_schema(columns: &[String], types: &[ColumnType]) -> Result<Arc<Type>> {
+    let fields = columns
+        .iter()
+        .zip(types)
+        .map(|(name, column_type)| {
+            let field = match column_type {
+                ColumnType::Integer => Type::primitive_type_builder(name, PhysicalType::INT64),
+                ColumnType::Float => Type::primitive_type_builder(name, PhysicalType::DOUBLE),
+                ColumnType::Text => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                    .with_logical_type(Some(LogicalType::String)),
+            };
+            Ok(Arc::new(
+                field.with_repetition(Repetition::OPTIONAL).build()?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(
+        Type::group_type_builder("results")
+            .with_fields(fields)
+            .build()?,
+    ))
+}
+
+/// Writes rows as a Snappy-compressed Parquet row group.
+/// # Errors
+/// Returns an error if a value does not fit the type its column was given from the first rows.
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    columns: &[String],
+    types: &[ColumnType],
+    rows: &[Vec<String>],
+) -> Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut column_writer) = row_group.next_column()? {
+        let values: Vec<&str> = rows.iter().map(|row| value(row, column)).collect();
+        let levels: Vec<i16> = values
+            .iter()
+            .map(|value| i16::from(!(is_missing(value) && types[column] != ColumnType::Text)))
+            .collect();
+        let present = values
+            .iter()
+            .zip(&levels)
+            .filter(|(_, level)| **level == 1)
+            .map(|(value, _)| *value);
+        let mismatch = |value: &str| {
+            anyhow!(
+                "Column {:?} holds {:?}, but its type was inferred as {:?} from the first {} rows; export the results with --format jsonl or tsv instead",
+                columns[column],
+                value,
+                types[column],
+                ROW_GROUP_SIZE
+            )
+        };
+        match types[column] {
+            ColumnType::Integer => {
+                let present = present
+                    .map(|value| value.parse().map_err(|_| mismatch(value)))
+                    .collect::<Result<Vec<i64>>>()?;
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&present, Some(&levels), None)?;
+            }
+            ColumnType::Float => {
+                let present = present
+                    .map(|value| value.parse().map_err(|_| mismatch(value)))
+                    .collect::<Result<Vec<f64>>>()?;
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&present, Some(&levels), None)?;
+            }
+            ColumnType::Text => {
+                let present: Vec<ByteArray> = present.map(ByteArray::from).collect();
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &present,
+                    Some(&levels),
+                    None,
+                )?;
+            }
+        }
+        column_writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+/// Builds the filters of the `--gene`, `--consequence` and `--max-af` options.
+/// Genes are matched exactly and consequences by substring, both against comma-separated alternatives.
+/// Rows without an allele frequency pass `max_af`, since variants missing from the population
+/// databases are the rarest of all.
+pub fn preset_filters(
+    gene: Option<&str>,
+    consequence: Option<&str>,
+    max_af: Option<f64>,
+) -> Vec<ColumnFilter> {
+    let mut filters = Vec::new();
+    if let Some(gene) = gene {
+        filters.push(ColumnFilter::new(GENE_COLUMNS, Comparison::Equal, gene));
+    }
+    if let Some(consequence) = consequence {
+        filters.push(ColumnFilter::new(
+            CONSEQUENCE_COLUMNS,
+            Comparison::Contains,
+            consequence,
+        ));
+    }
+    if let Some(max_af) = max_af {
+        let mut filter = ColumnFilter::new(
+            ALLELE_FREQUENCY_COLUMNS,
+            Comparison::Less,
+            &max_af.to_string(),
+        );
+        filter.keep_missing = true;
+        filters.push(filter);
+    }
+    filters
+}
+
+/// Orders chromosomes as 1 to 22, then X, Y and M, then any others by name.
+fn chromosome_key(chromosome: &str) -> (u32, String) {
+    let name = chromosome.trim_start_matches("chr");
+    match (name.parse::<u32>(), name) {
+        (Ok(number), _) => (number, String::new()),
+        (_, "X") => (23, String::new()),
+        (_, "Y") => (24, String::new()),
+        (_, "M" | "MT") => (25, String::new()),
+        _ => (u32::MAX, name.to_string()),
+    }
+}
+
+/// Fetches every page of the results of a job and writes the rows that pass the filters as each
+/// page arrives.
+/// The first response either links to the whole result file or holds the first page of the
+/// first chromosome, with the number of pages of every chromosome.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `pipeline` - The results to fetch on PGxFlow portals; `None` for sVEP.
+/// * `filters` - The filters every kept row passes.
+/// * `token` - The authentication token.
+/// * `writer` - Where to write the kept rows.
+/// # Returns
+/// A `Result` containing the number of rows written.
+/// # Errors
+/// Returns an error if a request fails, a page cannot be parsed or written, or a filter names an unknown column.
+/// # Example
+/// ```
+/// let writer = ResultWriter::create(ResultFormat::Tsv, None)?;
+/// let rows = fetch_results(&clinic_api()?, "my_project", "job_id", None, &filters, &id_token, writer).await?;
+/// ```
+pub async fn fetch_results(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    pipeline: Option<Pipeline>,
+    filters: &[ColumnFilter],
+    token: &str,
+    mut writer: ResultWriter,
+) -> Result<u64> {
+    let mut params = vec![("request_id", job_id), ("project_name", project)];
+    if let Some(pipeline) = pipeline {
+        params.push(("pipeline", pipeline.as_str()));
+    }
+    let mut table = ResultTable::default();
+    let first = get(api, "results", &params, token).await?;
+    if let Some(url) = first["url"].as_str() {
+        let content = reqwest::get(url).await?.error_for_status()?.text().await?;
+        table.add_content(&content, filters)?;
+        return writer.finish(&mut table);
+    }
+    if let Some(message) = first["noResultsMessage"].as_str() {
+        return Err(anyhow!("No results for job {}: {}", job_id, message));
+    }
+
+    let mut pages: Vec<(String, u64)> = first["pages"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(chromosome, count)| (chromosome.clone(), count.as_u64().unwrap_or(0)))
+        .collect();
+    pages.sort_by_key(|(chromosome, _)| chromosome_key(chromosome));
+    let first_page = (
+        first["chromosome"].as_str().unwrap_or_default(),
+        first["page"].as_u64().unwrap_or(1),
+    );
+
+    let pb = ProgressBar::new(pages.iter().map(|(_, count)| count).sum());
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] {pos}/{len} pages {msg}")
+            .unwrap(),
+    );
+    table.add_content(first["content"].as_str().unwrap_or_default(), filters)?;
+    writer.write(&mut table)?;
+    pb.inc(1);
+    for (chromosome, count) in &pages {
+        for page in 1..=*count {
+            if (chromosome.as_str(), page) == first_page {
+                continue;
+            }
+            pb.set_message(format!("chromosome {}", chromosome));
+            let page = page.to_string();
+            let mut page_params = params.clone();
+            page_params.extend([("chromosome", chromosome.as_str()), ("page", page.as_str())]);
+            let response = get(api, "results", &page_params, token).await?;
+            table.add_content(response["content"].as_str().unwrap_or_default(), filters)?;
+            writer.write(&mut table)?;
+            pb.inc(1);
+        }
+    }
+    pb.finish_and_clear();
+    writer.finish(&mut table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn svep_line(gene: &str, consequence: &str, frequency: &str) -> String {
+        let mut values = vec!["1"; SVEP_COLUMNS.len()];
+        values[1] = "chr1:100-100";
+        values[3] = consequence;
+        values[5] = gene;
+        values[35] = frequency;
+        format!("{}\n", values.join("\t"))
+    }
+
+    fn write_table(table: &mut ResultTable, format: ResultFormat) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = ResultWriter::create(format, file.path().to_str()).unwrap();
+        writer.write(table).unwrap();
+        writer.finish(table).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_column_filter() {
+        let filter: ColumnFilter = "Gene Name=BRCA1, brca2".parse().unwrap();
+        assert_eq!(filter.columns, ["Gene Name"]);
+        assert_eq!(filter.comparison, Comparison::Equal);
+        assert!(filter.matches("BRCA2"));
+        assert!(!filter.matches("BRCA"));
+
+        let filter: ColumnFilter = "AF<=0.01".parse().unwrap();
+        assert_eq!(filter.comparison, Comparison::LessOrEqual);
+        assert!(filter.matches("0.01"));
+        assert!(!filter.matches("0.5"));
+        assert!(!filter.matches("-"));
+
+        let filter: ColumnFilter = "Consequence~missense".parse().unwrap();
+        assert!(filter.matches("splice_region_variant&missense_variant"));
+        assert!("AF<rare".parse::<ColumnFilter>().is_err());
+        assert!("=BRCA1".parse::<ColumnFilter>().is_err());
+        assert!("BRCA1".parse::<ColumnFilter>().is_err());
+    }
+
+    #[test]
+    fn test_pipeline() {
+        assert_eq!("lookup".parse::<Pipeline>().unwrap(), Pipeline::Lookup);
+        assert_eq!("pharmcat".parse::<Pipeline>().unwrap().as_str(), "pharmcat");
+        assert!("svep".parse::<Pipeline>().is_err());
+    }
+
+    #[test]
+    fn test_add_content() {
+        let filters = preset_filters(None, Some("missense"), Some(0.01));
+        let mut table = ResultTable::default();
+        let content = [
+            svep_line("BRCA1", "missense_variant", "0.001"),
+            svep_line("BRCA2", "missense_variant", "0.2"),
+            svep_line("TP53", "synonymous_variant", "0.001"),
+            svep_line("ATM", "missense_variant", "-"),
+        ]
+        .concat();
+        table.add_content(&content, &filters).unwrap();
+        let genes: Vec<&str> = table.rows.iter().map(|row| row[5].as_str()).collect();
+        assert_eq!(genes, ["BRCA1", "ATM"]);
+        assert_eq!(table.columns.len(), SVEP_COLUMNS.len());
+
+        let unknown = [ColumnFilter::new(&["Drugs"], Comparison::Equal, "warfarin")];
+        assert!(
+            ResultTable::default()
+                .add_content(&content, &unknown)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_lookup_content() {
+        let mut table = ResultTable::default();
+        let content = concat!(
+            r#"{"PharmGKB ID": "PA1", "Gene": "CYP2C9", "AF": 0.05, "Level": "1A"}"#,
+            "\n",
+            r#"{"PharmGKB ID": "PA2", "Gene": "VKORC1", "AF": null, "Level": "1A"}"#,
+            "\n"
+        );
+        let filters = [ColumnFilter::new(GENE_COLUMNS, Comparison::Equal, "cyp2c9")];
+        table.add_content(content, &filters).unwrap();
+        assert_eq!(table.columns, ["PharmGKB ID", "Gene", "AF", "Level"]);
+        assert_eq!(table.rows, [["PA1", "CYP2C9", "0.05", "1A"]]);
+
+        let output = write_table(&mut table, ResultFormat::Jsonl);
+        let row: Value = serde_json::from_slice(&std::fs::read(output.path()).unwrap()).unwrap();
+        assert_eq!(row["AF"], 0.05);
+        assert_eq!(row["Gene"], "CYP2C9");
+    }
+
+    fn sample_table() -> ResultTable {
+        ResultTable {
+            columns: vec!["Rank".to_string(), "AF".to_string(), "Gene".to_string()],
+            rows: vec![
+                vec!["1".to_string(), "0.5".to_string(), "BRCA1".to_string()],
+                vec!["2".to_string(), "-".to_string(), "-".to_string()],
+            ],
+            resolved: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_column_types_and_writers() {
+        assert_eq!(
+            sample_table().column_types(),
+            [ColumnType::Integer, ColumnType::Float, ColumnType::Text]
+        );
+
+        let output = write_table(&mut sample_table(), ResultFormat::Tsv);
+        assert_eq!(
+            std::fs::read_to_string(output.path()).unwrap(),
+            "Rank\tAF\tGene\n1\t0.5\tBRCA1\n2\t-\t-\n"
+        );
+
+        let output = write_table(&mut sample_table(), ResultFormat::Jsonl);
+        let output = std::fs::read_to_string(output.path()).unwrap();
+        let second: Value = serde_json::from_str(output.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(
+            second,
+            serde_json::json!({"Rank": 2, "AF": null, "Gene": "-"})
+        );
+
+        let file = write_table(&mut sample_table(), ResultFormat::Parquet);
+        let reader = SerializedFileReader::new(file.reopen().unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert_eq!(rows[0], r#"{Rank: 1, AF: 0.5, Gene: "BRCA1"}"#);
+        assert_eq!(rows[1], r#"{Rank: 2, AF: null, Gene: "-"}"#);
+
+        let file = write_table(&mut ResultTable::default(), ResultFormat::Parquet);
+        let reader = SerializedFileReader::new(file.reopen().unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 1);
+    }
+
+    #[test]
+    fn test_writer_streams_rows() {
+        // Text rows are written as soon as their page is added
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = ResultWriter::create(ResultFormat::Jsonl, file.path().to_str()).unwrap();
+        let mut table = sample_table();
+        writer.write(&mut table).unwrap();
+        assert!(table.rows.is_empty());
+        // JSON lines keep the types of the first rows, and write values that do not fit them as text
+        table.columns.push("Drug".to_string());
+        table.rows.push(vec![
+            "third".to_string(),
+            "0.1".to_string(),
+            "TP53".to_string(),
+            "warfarin".to_string(),
+        ]);
+        assert_eq!(writer.finish(&mut table).unwrap(), 3);
+        let output = std::fs::read_to_string(file.path()).unwrap();
+        let third: Value = serde_json::from_str(output.lines().nth(2).unwrap()).unwrap();
+        assert_eq!(
+            third,
+            serde_json::json!({"Rank": "third", "AF": 0.1, "Gene": "TP53", "Drug": "warfarin"})
+        );
+
+        // TSV files cannot gain columns once the header is written
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = ResultWriter::create(ResultFormat::Tsv, file.path().to_str()).unwrap();
+        let mut table = sample_table();
+        writer.write(&mut table).unwrap();
+        table.columns.push("Drug".to_string());
+        assert!(writer.finish(&mut table).is_err());
+
+        // Parquet rows are kept until a row group is full, then written a row group at a time
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = ResultWriter::create(ResultFormat::Parquet, file.path().to_str()).unwrap();
+        let mut table = ResultTable {
+            columns: vec!["Rank".to_string()],
+            ..Default::default()
+        };
+        for page in 0..3 {
+            table.rows.extend(
+                (0..ROW_GROUP_SIZE).map(|row| vec![(page * ROW_GROUP_SIZE + row).to_string()]),
+            );
+            writer.write(&mut table).unwrap();
+            assert!(table.rows.is_empty());
+        }
+        table.rows.push(vec!["-".to_string()]);
+        writer.write(&mut table).unwrap();
+        assert_eq!(table.rows.len(), 1);
+        assert_eq!(
+            writer.finish(&mut table).unwrap(),
+            3 * ROW_GROUP_SIZE as u64 + 1
+        );
+        let reader = SerializedFileReader::new(file.reopen().unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 4);
+        assert_eq!(
+            reader.metadata().file_metadata().num_rows(),
+            3 * ROW_GROUP_SIZE as i64 + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_results() {
+        let mut server = Server::new_async().await;
+        let first = server
+            .mock("GET", "/results")
+            .match_query(Matcher::Regex(
+                "^request_id=job1&project_name=project1$".to_string(),
+            ))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "pages": {"2": 1, "1": 2},
+                    "chromosome": "1",
+                    "page": 1,
+                    "content": svep_line("BRCA1", "missense_variant", "0.001"),
+                })
+                .to_string(),
+            )
+            .create();
+        let second = server
+            .mock("GET", "/results")
+            .match_query(Matcher::Regex("chromosome=1&page=2$".to_string()))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({"content": svep_line("BRCA2", "stop_gained", "0.001")})
+                    .to_string(),
+            )
+            .create();
+        let third = server
+            .mock("GET", "/results")
+            .match_query(Matcher::Regex("chromosome=2&page=1$".to_string()))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({"content": svep_line("TP53", "missense_variant", "0.3")})
+                    .to_string(),
+            )
+            .create();
+
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let writer = ResultWriter::create(ResultFormat::Tsv, output.path().to_str()).unwrap();
+        let written = fetch_results(
+            &server.url(),
+            "project1",
+            "job1",
+            None,
+            &[],
+            "token",
+            writer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 3);
+        let output = std::fs::read_to_string(output.path()).unwrap();
+        let genes: Vec<&str> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(5).unwrap())
+            .collect();
+        assert_eq!(genes, ["BRCA1", "BRCA2", "TP53"]);
+        first.assert();
+        second.assert();
+        third.assert();
+    }
+}
@@ -0,0 +1,430 @@
+use super::jobs::{JobFilter, list_jobs};
+use super::post;
+use crate::manifest::{empty_as_none, read_rows};
+#[cfg(feature = "uploads")]
+use crate::networking::uploads;
+use crate::networking::util::get_file_name_from_path;
+#[cfg(feature = "uploads")]
+use crate::vcf;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::env;
+use std::path::Path;
+
+/// Represents a row of the sample sheet of `clinic batch-submit`.
+/// `vcf` is a local file, which is uploaded first, or the name of a file of the project.
+/// Columns other than these are ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SampleSheetRow {
+    pub vcf: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub job_name: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub project: Option<String>,
+}
+
+/// Reads a sample sheet, a TSV, CSV or JSON file like a transfer manifest.
+/// # Errors
+/// Returns an error if the sample sheet cannot be read, is not valid, or has no rows.
+/// # Example
+/// ```
+/// let rows = read_sample_sheet("samples.tsv")?;
+/// ```
+pub fn read_sample_sheet<P: AsRef<Path>>(path: P) -> Result<Vec<SampleSheetRow>> {
+    let (_, rows): (_, Vec<SampleSheetRow>) = read_rows(path, "Sample sheet")?;
+    if let Some(index) = rows.iter().position(|row| row.vcf.is_empty()) {
+        return Err(anyhow!("Sample sheet row {} has no vcf.", index + 1));
+    }
+    Ok(rows)
+}
+
+/// Checks a job name as the clinic page does: 3 to 20 letters, digits or spaces.
+/// # Errors
+/// Returns an error describing the expected names.
+pub fn validate_job_name(name: &str) -> Result<()> {
+    let valid = (3..=20).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid job name {:?}, expected 3 to 20 letters, digits or spaces",
+            name
+        ))
+    }
+}
+
+/// Derives a job name from a VCF file name, as the clinic page does: the extension is removed
+/// and other characters than letters and digits become spaces. Long names are cut to 20 characters.
+/// # Example
+/// ```
+/// assert_eq!(default_job_name("data/sample_01.vcf.gz"), "sample 01");
+/// ```
+pub fn default_job_name(file: &str) -> String {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let lower = name.to_lowercase();
+    let stem = [".vcf.gz", ".vcf.bgz", ".bcf.gz", ".vcf", ".bcf"]
+        .iter()
+        .find(|extension| lower.ends_with(*extension))
+        .map_or(name, |extension| &name[..name.len() - extension.len()]);
+    let words: Vec<&str> = stem
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut name = words.join(" ");
+    name.truncate(20);
+    name.trim_end().to_string()
+}
+
+/// Returns the name of the S3 bucket holding the files of the portal.
+/// # Errors
+/// Returns an error if `DATA_PORTAL_BUCKET` is not set.
+pub fn data_portal_bucket() -> Result<String> {
+    env::var("DATA_PORTAL_BUCKET")
+        .ok()
+        .filter(|bucket| !bucket.is_empty())
+        .ok_or(anyhow!("DATA_PORTAL_BUCKET is not set."))
+}
+
+/// Returns the S3 URI of a file of a project, the location expected by the `submit` endpoint.
+/// # Example
+/// ```
+/// let location = project_file_location("portal-bucket", "my_project", "sample.vcf.gz");
+/// assert_eq!(location, "s3://portal-bucket/projects/my_project/project-files/sample.vcf.gz");
+/// ```
+pub fn project_file_location(bucket: &str, project: &str, file: &str) -> String {
+    format!(
+        "s3://{}/projects/{}/project-files/{}",
+        bucket, project, file
+    )
+}
+
+/// Returns the project file names of the VCF files of a submission, uploading the local files first.
+/// Local VCF files are validated and uploaded with their index, which is built if it is missing.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project the jobs run in.
+/// * `vcfs` - Local paths or names of files of the project.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the name of each file in the project, in the same order as `vcfs`.
+/// # Errors
+/// Returns an error if a local file is invalid or fails to upload, or if the CLI was built without uploads.
+/// # Example
+/// ```
+/// let names = prepare_inputs(&api, "my_project", &["data/sample.vcf.gz".to_string()], &id_token).await?;
+/// assert_eq!(names, ["sample.vcf.gz"]);
+/// ```
+pub async fn prepare_inputs(
+    api: &str,
+    project: &str,
+    vcfs: &[String],
+    token: &str,
+) -> Result<Vec<String>> {
+    let local: Vec<String> = vcfs
+        .iter()
+        .filter(|vcf| Path::new(vcf).is_file())
+        .cloned()
+        .collect();
+    if !local.is_empty() {
+        upload_inputs(api, project, &local, token).await?;
+    }
+    vcfs.iter()
+        .map(|vcf| {
+            if local.contains(vcf) {
+                get_file_name_from_path(vcf)
+            } else {
+                Ok(vcf.clone())
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "uploads")]
+async fn upload_inputs(api: &str, project: &str, files: &[String], token: &str) -> Result<()> {
+    vcf::validate::validate_files(files, false).await?;
+    // the workflows read the VCF through its index
+    let files = vcf::index::pair_indexes(files, true, vcf::index::IndexFormat::Tbi)?;
+    let urls = uploads::get_upload_urls(api, Some(project), &files, token).await?;
+    let report = uploads::upload_files(urls, &files, false).await?;
+    if !report.is_success() {
+        report.print_summary();
+        return Err(anyhow!(
+            "Failed to upload the VCF files, no job was submitted."
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "uploads"))]
+async fn upload_inputs(_api: &str, _project: &str, files: &[String], _token: &str) -> Result<()> {
+    Err(anyhow!(
+        "{} is a local file, but this build cannot upload files. Upload it first or give the name of a project file.",
+        files[0]
+    ))
+}
+
+/// Submits a clinical workflow job for a VCF file of a project.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `location` - The S3 URI of the VCF file, see `project_file_location`.
+/// * `project` - The project the job runs in.
+/// * `user_id` - The Cognito identity ID of the user, see `auth::identity_id`.
+/// * `job_name` - The name of the job.
+/// * `missing_to_ref` - Whether PharmCAT treats missing positions as reference calls.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the job ID, if the API returns it.
+/// # Errors
+/// Returns an error if the job is rejected.
+/// # Example
+/// ```
+/// let job_id = submit_job(&clinic_api, &location, "my_project", &user_id, "sample 01", false, &id_token).await?;
+/// ```
+pub async fn submit_job(
+    api: &str,
+    location: &str,
+    project: &str,
+    user_id: &str,
+    job_name: &str,
+    missing_to_ref: bool,
+    token: &str,
+) -> Result<Option<String>> {
+    let body = json!({
+        "location": location,
+        "projectName": project,
+        "userId": user_id,
+        "jobName": job_name,
+        "missingToRef": missing_to_ref,
+    });
+    let response = post(api, "submit", &body, token).await?;
+    Ok(job_ids(&response)?.into_iter().next().flatten())
+}
+
+/// Submits a clinical workflow job for each of several VCF files of a project in one request.
+/// # Arguments
+/// * `api` - The base URL of the clinic API.
+/// * `project` - The project the jobs run in.
+/// * `jobs` - The file name and job name of each job.
+/// * `user_id` - The Cognito identity ID of the user.
+/// * `missing_to_ref` - Whether PharmCAT treats missing positions as reference calls.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the job ID of each job, if the API returns them, in the same order as `jobs`.
+/// # Example
+/// ```
+/// let jobs = [("sample.vcf.gz".to_string(), "sample".to_string())];
+/// let job_ids = batch_submit(&clinic_api, "my_project", &jobs, &user_id, false, &id_token).await?;
+/// ```
+pub async fn batch_submit(
+    api: &str,
+    project: &str,
+    jobs: &[(String, String)],
+    user_id: &str,
+    missing_to_ref: bool,
+    token: &str,
+) -> Result<Vec<Option<String>>> {
+    let body = json!({
+        "projectName": project,
+        "jobs": jobs
+            .iter()
+            .map(|(filename, job_name)| json!({"filename": filename, "jobName": job_name}))
+            .collect::<Vec<_>>(),
+        "userId": user_id,
+        "missingToRef": missing_to_ref,
+    });
+    let response = post(api, "batch-submit", &body, token).await?;
+    let mut ids = job_ids(&response)?;
+    ids.resize(jobs.len(), None);
+    Ok(ids)
+}
+
+/// Reads the job IDs of a submission response, a result or a list of results.
+/// # Errors
+/// Returns the message of the first result that did not succeed.
+fn job_ids(response: &Value) -> Result<Vec<Option<String>>> {
+    let results = match response {
+        Value::Array(results) => results.iter().collect(),
+        result => vec![result],
+    };
+    results
+        .into_iter()
+        .map(|result| {
+            if result["Success"] == false {
+                return Err(anyhow!(
+                    "Job submission failed: {}",
+                    result["Response"]
+                        .as_str()
+                        .unwrap_or("the clinic API gave no reason")
+                ));
+            }
+            let id = [result, &result["Response"]].into_iter().find_map(|value| {
+                ["jobId", "job_id", "RequestId", "requestId"]
+                    .iter()
+                    .find_map(|key| value[key].as_str())
+            });
+            Ok(id.map(str::to_string))
+        })
+        .collect()
+}
+
+/// Finds the IDs of the most recent jobs of a project with the given names, for submissions whose
+/// response does not include them. The jobs of the project are listed once for all the names.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the jobs.
+/// * `job_names` - The names of the jobs.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the ID of each job, in the order of `job_names`, or `None` if no job has that name.
+/// # Example
+/// ```
+/// let job_ids = find_job_ids(&api, "my_project", &["sample 01".to_string()], &id_token).await?;
+/// ```
+pub async fn find_job_ids(
+    api: &str,
+    project: &str,
+    job_names: &[String],
+    token: &str,
+) -> Result<Vec<Option<String>>> {
+    if job_names.is_empty() {
+        return Ok(Vec::new());
+    }
+    // A single name narrows the listing down, several names need every job of the project
+    let filter = JobFilter {
+        status: None,
+        search: (job_names.len() == 1).then(|| job_names[0].clone()),
+    };
+    let jobs = list_jobs(api, project, &filter, usize::MAX, token).await?;
+    Ok(job_names
+        .iter()
+        .map(|job_name| {
+            jobs.iter()
+                .filter(|job| job.job_name == *job_name)
+                .max_by(|a, b| a.created_at.cmp(&b.created_at))
+                .map(|job| job.job_id.clone())
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+    use std::io::Write;
+
+    #[test]
+    fn test_job_names() {
+        assert_eq!(default_job_name("data/sample_01.vcf.gz"), "sample 01");
+        assert_eq!(default_job_name("NA12878-filtered.VCF"), "NA12878 filtered");
+        assert_eq!(
+            default_job_name("a_very_long_sample_name_from_the_sequencer.vcf.gz"),
+            "a very long sample n"
+        );
+        assert!(validate_job_name("sample 01").is_ok());
+        assert!(validate_job_name("ab").is_err());
+        assert!(validate_job_name("sample_01").is_err());
+    }
+
+    #[test]
+    fn test_read_sample_sheet() {
+        let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+        writeln!(
+            file,
+            "vcf,job_name,sex\nsample1.vcf.gz,first,F\nsample2.vcf.gz,,M"
+        )
+        .unwrap();
+        let rows = read_sample_sheet(file.path()).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].job_name.as_deref(), Some("first"));
+        assert_eq!(rows[1].job_name, None);
+        assert_eq!(rows[1].project, None);
+    }
+
+    #[test]
+    fn test_job_ids() {
+        let response = json!({"Success": true, "Response": {"jobId": "abc"}});
+        assert_eq!(job_ids(&response).unwrap(), [Some("abc".to_string())]);
+        let response = json!([{"Success": true, "Response": "Job started"}]);
+        assert_eq!(job_ids(&response).unwrap(), [None]);
+        let response = json!({"Success": false, "Response": "Invalid VCF"});
+        assert!(
+            job_ids(&response)
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid VCF")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_submit_job() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/submit")
+            .match_body(Matcher::Json(json!({
+                "location": "s3://bucket/projects/p1/project-files/sample.vcf.gz",
+                "projectName": "p1",
+                "userId": "region:user",
+                "jobName": "sample",
+                "missingToRef": false,
+            })))
+            .with_status(200)
+            .with_body(r#"{"Success": true, "Response": {"RequestId": "job-1"}}"#)
+            .create();
+
+        let location = project_file_location("bucket", "p1", "sample.vcf.gz");
+        let job_id = submit_job(
+            &server.url(),
+            &location,
+            "p1",
+            "region:user",
+            "sample",
+            false,
+            "token",
+        )
+        .await
+        .unwrap();
+        assert_eq!(job_id.as_deref(), Some("job-1"));
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_find_job_ids() {
+        let mut server = Server::new_async().await;
+        let body = r#"{"success": true, "jobs": [
+            {"job_id": "old", "job_name": "sample", "created_at": "2025-01-01T00:00:00"},
+            {"job_id": "new", "job_name": "sample", "created_at": "2025-02-01T00:00:00"},
+            {"job_id": "other", "job_name": "sample 2", "created_at": "2025-03-01T00:00:00"}
+        ]}"#;
+        let mock = server
+            .mock("GET", "/dportal/projects/p1/clinical-workflows")
+            .match_query(Matcher::UrlEncoded("search".into(), "sample".into()))
+            .with_status(200)
+            .with_body(body)
+            .create();
+        let job_ids = find_job_ids(&server.url(), "p1", &["sample".to_string()], "token")
+            .await
+            .unwrap();
+        assert_eq!(job_ids, [Some("new".to_string())]);
+        mock.assert();
+
+        mock.remove();
+        let mock = server
+            .mock("GET", "/dportal/projects/p1/clinical-workflows")
+            .match_query(Matcher::Exact("limit=50".into()))
+            .with_status(200)
+            .with_body(body)
+            .expect(1)
+            .create();
+        let names = ["sample 2", "sample", "missing"].map(String::from);
+        let job_ids = find_job_ids(&server.url(), "p1", &names, "token")
+            .await
+            .unwrap();
+        assert_eq!(
+            job_ids,
+            [Some("other".to_string()), Some("new".to_string()), None]
+        );
+        mock.assert();
+    }
+}
@@ -0,0 +1,460 @@
+use super::curation::{SavedVariants, list_saved_variants, user_label};
+use super::jobs::Job;
+use crate::auth::token_claims;
+use crate::networking::api::{self, OutputFormat, format_table};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::io::{IsTerminal, Write};
+
+/// The user signing validations, as described by the claims of their ID token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signer {
+    pub name: String,
+    pub email: String,
+    /// Whether the user is a medical director, if the token says.
+    pub medical_director: Option<bool>,
+}
+
+impl Signer {
+    /// Describes the user of an ID token.
+    /// # Errors
+    /// Returns an error if the token cannot be decoded or names no user.
+    /// # Example
+    /// ```
+    /// let signer = Signer::from_token(&id_token)?;
+    /// ```
+    pub fn from_token(token: &str) -> Result<Self> {
+        let claims = token_claims(token)?;
+        let claim = |name: &str| claims[name].as_str().unwrap_or_default().to_string();
+        let name = format!("{} {}", claim("given_name"), claim("family_name"))
+            .trim()
+            .to_string();
+        let email = claim("email");
+        if name.is_empty() && email.is_empty() {
+            return Err(anyhow!("The ID token does not name the user"));
+        }
+        let medical_director = match &claims["custom:is_medical_director"] {
+            Value::String(value) => Some(value == "true"),
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        };
+        Ok(Signer {
+            name,
+            email,
+            medical_director,
+        })
+    }
+
+    /// Checks that the user may sign validations, which only medical directors do.
+    /// Tokens that do not say are left for the API to check.
+    /// # Errors
+    /// Returns an error if the token says the user is not a medical director.
+    pub fn check(&self) -> Result<()> {
+        if self.medical_director == Some(false) {
+            return Err(anyhow!(
+                "Only medical directors can sign validations, and {} is not one.",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.name.as_str(), self.email.as_str()) {
+            ("", email) => f.write_str(email),
+            (name, "") => f.write_str(name),
+            (name, email) => write!(f, "{} <{}>", name, email),
+        }
+    }
+}
+
+/// Asks the user to confirm that they sign a validation decision, naming who signs.
+/// The question is skipped with `yes`, but the signer is still printed.
+/// # Arguments
+/// * `signer` - The user signing.
+/// * `action` - What is being signed, such as `Validation of saved variants c1 of job j1`.
+/// * `yes` - Whether the user confirmed on the command line.
+/// # Returns
+/// A `Result` containing whether the user confirmed.
+/// # Errors
+/// Returns an error if confirmation is needed but standard input is not a terminal.
+pub fn confirm_signature(signer: &Signer, action: &str, yes: bool) -> Result<bool> {
+    eprintln!("{} signed by {}", action, signer);
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Signing needs confirmation; pass --yes to confirm without a terminal."
+        ));
+    }
+    eprint!("Sign as {}? [y/N] ", signer);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Returns the validation endpoint of a saved variant collection, or of the job itself when no
+/// collection is given; validating a job without saved variants signs off a negative report.
+fn validation_endpoint(project: &str, job_id: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!(
+            "dportal/projects/{}/clinical-workflows/{}/variants/{}/validation",
+            project, job_id, name
+        ),
+        None => format!(
+            "dportal/projects/{}/clinical-workflows/{}/validation",
+            project, job_id
+        ),
+    }
+}
+
+/// Records the validation of a saved variant collection, or of a negative report for the job
+/// when no collection is given.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The project of the job.
+/// * `job_id` - The ID of the job.
+/// * `name` - The name of the saved variant collection, if any.
+/// * `comment` - The validation comment.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the comment is empty or the request fails.
+/// # Example
+/// ```
+/// sign_validation(&api, "my_project", "job-id", Some("c1"), "Confirmed by Sanger sequencing", &id_token).await?;
+/// ```
+pub async fn sign_validation(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    name: Option<&str>,
+    comment: &str,
+    token: &str,
+) -> Result<()> {
+    if comment.trim().is_empty() {
+        return Err(anyhow!("The validation comment must not be empty"));
+    }
+    let endpoint = validation_endpoint(project, job_id, name);
+    let response = api::post(api, &endpoint, &json!({"comment": comment}), token).await?;
+    if response["success"] == false {
+        return Err(anyhow!(
+            "Failed to record the validation: {}",
+            response["message"].as_str().unwrap_or("unknown error")
+        ));
+    }
+    Ok(())
+}
+
+/// Revokes the validation of a saved variant collection, or of the job's negative report.
+/// # Example
+/// ```
+/// revoke_validation(&api, "my_project", "job-id", Some("c1"), &id_token).await?;
+/// ```
+pub async fn revoke_validation(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    name: Option<&str>,
+    token: &str,
+) -> Result<()> {
+    api::delete(api, &validation_endpoint(project, job_id, name), token).await?;
+    Ok(())
+}
+
+/// Represents a validation recorded for a saved variant collection or a job's negative report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Validation {
+    pub job_id: String,
+    /// The saved variant collection, or `None` for a negative report.
+    pub collection: Option<String>,
+    pub comment: String,
+    pub validator: Value,
+    pub validated_at: String,
+}
+
+impl Validation {
+    fn from_fields(job_id: &str, collection: Option<&str>, fields: &Map<String, Value>) -> Self {
+        let field = |name: &str| fields.get(name).and_then(Value::as_str).unwrap_or_default();
+        Validation {
+            job_id: job_id.to_string(),
+            collection: collection.map(str::to_string),
+            comment: field("validationComment").to_string(),
+            validator: fields.get("validator").cloned().unwrap_or_default(),
+            validated_at: field("validatedAt").to_string(),
+        }
+    }
+}
+
+/// Represents a saved variant collection, or a job without any, awaiting validation.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingValidation {
+    pub job_id: String,
+    pub job_name: String,
+    /// The saved variant collection, or `None` for a job awaiting a negative report.
+    pub collection: Option<String>,
+    pub variants: usize,
+    pub saved_by: Value,
+    pub comment: String,
+}
+
+fn job_validated(job: &Job) -> bool {
+    job.fields
+        .get("validatedByMedicalDirector")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Lists what awaits validation in a job: its saved variant collections that are not validated,
+/// or the job itself when it has no saved variants and its negative report is not validated.
+pub fn pending_validations(job: &Job, collections: &[SavedVariants]) -> Vec<PendingValidation> {
+    if collections.is_empty() {
+        if job_validated(job) {
+            return Vec::new();
+        }
+        return vec![PendingValidation {
+            job_id: job.job_id.clone(),
+            job_name: job.job_name.clone(),
+            collection: None,
+            variants: 0,
+            saved_by: Value::Null,
+            comment: String::new(),
+        }];
+    }
+    collections
+        .iter()
+        .filter(|collection| !collection.validated_by_medical_director)
+        .map(|collection| PendingValidation {
+            job_id: job.job_id.clone(),
+            job_name: job.job_name.clone(),
+            collection: Some(collection.name.clone()),
+            variants: collection.variants.len(),
+            saved_by: collection.user.clone(),
+            comment: collection.comment.clone(),
+        })
+        .collect()
+}
+
+/// Lists the validations recorded for a job and its saved variant collections, oldest first.
+pub fn validation_history(job: &Job, collections: &[SavedVariants]) -> Vec<Validation> {
+    let mut validations: Vec<Validation> = collections
+        .iter()
+        .filter(|collection| collection.validated_by_medical_director)
+        .map(|collection| {
+            Validation::from_fields(&job.job_id, Some(&collection.name), &collection.fields)
+        })
+        .collect();
+    if job_validated(job) {
+        validations.push(Validation::from_fields(&job.job_id, None, &job.fields));
+    }
+    validations.sort_by(|a, b| a.validated_at.cmp(&b.validated_at));
+    validations
+}
+
+/// Fetches a job with its saved variant collections, from which validations are read.
+/// # Example
+/// ```
+/// let (job, collections) = job_with_collections(&api, "my_project", "job-id", &id_token).await?;
+/// ```
+pub async fn job_with_collections(
+    api: &str,
+    project: &str,
+    job_id: &str,
+    token: &str,
+) -> Result<(Job, Vec<SavedVariants>)> {
+    let job = super::jobs::get_job(api, project, job_id, token).await?;
+    let collections = list_saved_variants(api, project, job_id, token).await?;
+    Ok((job, collections))
+}
+
+/// Formats pending validations for printing, as a table or as JSON.
+pub fn format_pending(pending: &[PendingValidation], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(pending)?));
+    }
+    let rows: Vec<Vec<String>> = pending
+        .iter()
+        .map(|entry| match &entry.collection {
+            Some(collection) => vec![
+                entry.job_id.clone(),
+                entry.job_name.clone(),
+                collection.clone(),
+                entry.variants.to_string(),
+                user_label(&entry.saved_by),
+                entry.comment.clone(),
+            ],
+            None => vec![
+                entry.job_id.clone(),
+                entry.job_name.clone(),
+                "-".to_string(),
+                "0".to_string(),
+                "-".to_string(),
+                "negative report".to_string(),
+            ],
+        })
+        .collect();
+    Ok(format_table(
+        &[
+            "JOB ID",
+            "NAME",
+            "COLLECTION",
+            "VARIANTS",
+            "SAVED BY",
+            "COMMENT",
+        ],
+        &rows,
+    ))
+}
+
+/// Formats validations for printing, as a table or as JSON.
+pub fn format_history(validations: &[Validation], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(validations)?));
+    }
+    let rows: Vec<Vec<String>> = validations
+        .iter()
+        .map(|validation| {
+            vec![
+                validation.validated_at.clone(),
+                validation.job_id.clone(),
+                validation
+                    .collection
+                    .clone()
+                    .unwrap_or_else(|| "negative report".to_string()),
+                user_label(&validation.validator),
+                validation.comment.clone(),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &["VALIDATED", "JOB ID", "COLLECTION", "VALIDATOR", "COMMENT"],
+        &rows,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    use mockito::{Matcher, Server};
+
+    fn token(claims: Value) -> String {
+        format!(
+            "header.{}.signature",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    fn collection(name: &str, validated: bool) -> SavedVariants {
+        serde_json::from_value(json!({
+            "name": name,
+            "comment": "Pathogenic BRCA variants",
+            "variants": [{"Gene Name": "BRCA1"}],
+            "validatedByMedicalDirector": validated,
+            "validationComment": "Confirmed",
+            "validatedAt": "2025-03-01T00:00:00Z",
+            "validator": {"firstName": "Ana", "lastName": "Lee", "email": "ana@example.com"},
+        }))
+        .unwrap()
+    }
+
+    fn job(validated: bool) -> Job {
+        serde_json::from_value(json!({
+            "job_id": "j1",
+            "job_name": "sample",
+            "svep_status": "completed",
+            "validatedByMedicalDirector": validated,
+            "validationComment": "No findings",
+            "validatedAt": "2025-02-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_signer() {
+        let signer = Signer::from_token(&token(json!({
+            "given_name": "Ana",
+            "family_name": "Lee",
+            "email": "ana@example.com",
+            "custom:is_medical_director": "true",
+        })))
+        .unwrap();
+        assert_eq!(signer.to_string(), "Ana Lee <ana@example.com>");
+        assert!(signer.check().is_ok());
+
+        let signer = Signer::from_token(&token(json!({
+            "email": "bob@example.com",
+            "custom:is_medical_director": "false",
+        })))
+        .unwrap();
+        assert_eq!(signer.to_string(), "bob@example.com");
+        assert!(signer.check().is_err());
+        assert!(Signer::from_token(&token(json!({"sub": "u1"}))).is_err());
+    }
+
+    #[test]
+    fn test_pending_and_history() {
+        let collections = [collection("c1", true), collection("c2", false)];
+        let pending = pending_validations(&job(false), &collections);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].collection.as_deref(), Some("c2"));
+
+        let pending = pending_validations(&job(false), &[]);
+        assert_eq!(pending[0].collection, None);
+        assert!(pending_validations(&job(true), &[]).is_empty());
+
+        let history = validation_history(&job(true), &collections);
+        let entries: Vec<Option<&str>> = history
+            .iter()
+            .map(|validation| validation.collection.as_deref())
+            .collect();
+        assert_eq!(entries, [None, Some("c1")]);
+        let text = format_history(&history, OutputFormat::Table).unwrap();
+        assert!(text.contains("Ana Lee <ana@example.com>  Confirmed"));
+    }
+
+    #[tokio::test]
+    async fn test_sign_and_revoke() {
+        let mut server = Server::new_async().await;
+        let endpoint = "/dportal/projects/project1/clinical-workflows/j1/variants/c1/validation";
+        let sign = server
+            .mock("POST", endpoint)
+            .match_body(Matcher::Json(json!({"comment": "Confirmed"})))
+            .with_status(200)
+            .with_body(r#"{"success": true}"#)
+            .create();
+        let revoke = server
+            .mock(
+                "DELETE",
+                "/dportal/projects/project1/clinical-workflows/j1/validation",
+            )
+            .with_status(200)
+            .create();
+
+        sign_validation(
+            &server.url(),
+            "project1",
+            "j1",
+            Some("c1"),
+            "Confirmed",
+            "token",
+        )
+        .await
+        .unwrap();
+        assert!(
+            sign_validation(&server.url(), "project1", "j1", Some("c1"), "", "token")
+                .await
+                .is_err()
+        );
+        revoke_validation(&server.url(), "project1", "j1", None, "token")
+            .await
+            .unwrap();
+        sign.assert();
+        revoke.assert();
+    }
+}
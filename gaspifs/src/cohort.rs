@@ -0,0 +1,548 @@
+use crate::beacon::Scope;
+use crate::beacon::query::{Filter, FilterKind, Granularity, QueryRequest, VariantParameters};
+use crate::networking::api;
+use crate::networking::files::list_project_files;
+use crate::networking::quota::get_quota;
+use anyhow::{Context, Result, anyhow};
+use indicatif::{BinaryBytes, ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Describes a cohort in a YAML file, so that cohort definitions can be kept in version control.
+/// # Example
+/// ```yaml
+/// name: t2d-female-2025
+/// projects: [project1, project2]
+/// scope: individuals
+/// filters:
+///   - NCIT:C16576
+///   - Type 2 diabetes mellitus
+///   - id: age
+///     operator: ">="
+///     value: "40"
+/// samples:
+///   - UBERON:0000178
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CohortSpec {
+    /// The job id of the cohort, 10 to 20 letters, digits, `-` or `_`.
+    pub name: String,
+    pub projects: Vec<String>,
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    #[serde(default = "default_granularity")]
+    pub granularity: String,
+    /// Criteria on the entries of `scope`, written like `--filter` or as a mapping.
+    #[serde(default)]
+    pub filters: Vec<SpecFilter>,
+    /// Criteria on the biosamples of the entries.
+    #[serde(default)]
+    pub samples: Vec<SpecFilter>,
+    /// The variants a g_variants cohort is built from.
+    #[serde(default)]
+    pub variant: Option<SpecVariant>,
+}
+
+fn default_scope() -> String {
+    "individuals".to_string()
+}
+
+fn default_granularity() -> String {
+    "record".to_string()
+}
+
+/// A filter of a cohort spec, either as on the command line or as a mapping of its fields.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SpecFilter {
+    Text(String),
+    Fields {
+        id: String,
+        #[serde(default)]
+        operator: Option<String>,
+        #[serde(default)]
+        value: Option<String>,
+        #[serde(default)]
+        scope: Option<String>,
+        #[serde(default, rename = "includeDescendantTerms")]
+        include_descendants: Option<bool>,
+    },
+}
+
+impl SpecFilter {
+    /// Converts the filter, applying `default_scope` when it does not name one.
+    fn to_filter(&self, default_scope: Option<Scope>) -> Result<Filter> {
+        let mut filter = match self {
+            SpecFilter::Text(text) => text.parse::<Filter>()?,
+            SpecFilter::Fields {
+                id,
+                operator,
+                value,
+                scope,
+                include_descendants,
+            } => {
+                let mut filter = match (operator, value) {
+                    (Some(operator), Some(value)) => format!("{}{}{}", id, operator, value),
+                    (None, None) => id.clone(),
+                    _ => {
+                        return Err(anyhow!(
+                            "filter {:?} needs both an operator and a value",
+                            id
+                        ));
+                    }
+                }
+                .parse::<Filter>()?;
+                filter.scope = scope.as_deref().map(str::parse).transpose()?;
+                if let (
+                    FilterKind::Ontology {
+                        include_descendants: include,
+                    },
+                    Some(value),
+                ) = (&mut filter.kind, include_descendants)
+                {
+                    *include = *value;
+                }
+                filter
+            }
+        };
+        filter.scope = filter.scope.or(default_scope);
+        Ok(filter)
+    }
+}
+
+/// A single position or the two bounds of a bracket query.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Positions {
+    One(u64),
+    Many(Vec<u64>),
+}
+
+impl Positions {
+    fn to_vec(&self) -> Vec<u64> {
+        match self {
+            Positions::One(position) => vec![*position],
+            Positions::Many(positions) => positions.clone(),
+        }
+    }
+}
+
+/// The variant region of a cohort spec.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpecVariant {
+    pub chromosome: String,
+    pub start: Positions,
+    #[serde(default)]
+    pub end: Option<Positions>,
+    #[serde(default = "any_bases", rename = "ref")]
+    pub reference_bases: String,
+    #[serde(default = "any_bases", rename = "alt")]
+    pub alternate_bases: String,
+    #[serde(default = "default_assembly")]
+    pub assembly: String,
+}
+
+fn any_bases() -> String {
+    "N".to_string()
+}
+
+fn default_assembly() -> String {
+    "GRCH38".to_string()
+}
+
+/// Reads a cohort spec from a YAML file.
+/// # Errors
+/// Returns an error if the file cannot be read or is not a valid spec.
+pub fn read_spec<P: AsRef<Path>>(path: P) -> Result<CohortSpec> {
+    let content = std::fs::read_to_string(&path)?;
+    serde_norway::from_str(&content)
+        .with_context(|| format!("Invalid cohort spec {}", path.as_ref().display()))
+}
+
+impl CohortSpec {
+    /// Validates the spec and builds the query that selects the cohort.
+    /// # Errors
+    /// Returns an error if the name, scope, granularity, a filter or the variant region is invalid.
+    pub fn to_request(&self) -> Result<QueryRequest> {
+        let valid_name = (10..=20).contains(&self.name.len())
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(anyhow!(
+                "Invalid cohort name {:?}, expected 10 to 20 letters, digits, - or _",
+                self.name
+            ));
+        }
+        let scope: Scope = self.scope.parse()?;
+        let granularity: Granularity = self.granularity.parse()?;
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| filter.to_filter(None))
+            .chain(
+                self.samples
+                    .iter()
+                    .map(|filter| filter.to_filter(Some(Scope::Biosamples))),
+            )
+            .collect::<Result<Vec<_>>>()?;
+        let variant = self.variant.as_ref().map(|variant| VariantParameters {
+            assembly: variant.assembly.clone(),
+            chromosome: variant.chromosome.clone(),
+            start: variant.start.to_vec(),
+            end: variant.end.as_ref().unwrap_or(&variant.start).to_vec(),
+            reference_bases: variant.reference_bases.clone(),
+            alternate_bases: variant.alternate_bases.clone(),
+        });
+        let request = QueryRequest {
+            scope,
+            projects: self.projects.clone(),
+            filters,
+            granularity,
+            skip: 0,
+            limit: 0,
+            variant,
+            related: None,
+        };
+        request.body()?;
+        Ok(request)
+    }
+}
+
+/// Checks that every project of a spec is one the user can access.
+/// # Errors
+/// Returns an error listing the unknown projects.
+pub fn check_projects(projects: &[String], available: &[String]) -> Result<()> {
+    let unknown: Vec<&str> = projects
+        .iter()
+        .filter(|project| !available.contains(project))
+        .map(String::as_str)
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Unknown or inaccessible projects: {}",
+            unknown.join(", ")
+        ))
+    }
+}
+
+/// Returns the file that the backend writes a cohort to, relative to the user's space.
+/// Other files of the cohort are written to the same `<job id>/` folder.
+pub fn cohort_file(job_id: &str, scope: Scope) -> String {
+    format!("{}/{}.json", job_id, scope.as_str())
+}
+
+/// Builds the body posted to `dportal/cohort`, which is the query with the job ID and scope added.
+fn cohort_body(job_id: &str, request: &QueryRequest) -> Result<serde_json::Value> {
+    let mut body = request.body()?;
+    body["jobId"] = job_id.into();
+    body["scope"] = request.scope.as_str().into();
+    if let Some(query) = body["query"].as_object_mut() {
+        query.remove("pagination");
+    }
+    Ok(body)
+}
+
+/// Checks that the storage quota of the user leaves room for a cohort, as the web app does before
+/// submitting one. Users without a quota are not limited.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `job_id` - The name of the cohort.
+/// * `request` - The query that selects the cohort.
+/// * `token` - The ID token, which identifies the user.
+/// # Errors
+/// Returns an error if the quota cannot be read or is already used up.
+/// # Example
+/// ```
+/// check_cohort_quota(&api, &spec.name, &request, &id_token).await?;
+/// ```
+pub async fn check_cohort_quota(
+    api: &str,
+    job_id: &str,
+    request: &QueryRequest,
+    token: &str,
+) -> Result<()> {
+    let Some(quota) = get_quota(api, token).await? else {
+        return Ok(());
+    };
+    let size = serde_json::to_vec(&cohort_body(job_id, request)?)?.len() as u64;
+    if quota.usage_size + size >= quota.quota_size {
+        return Err(anyhow!(
+            "Cannot create cohort {} because your storage quota is reached: {} of {} used. \
+            Delete files or ask an administrator to increase your quota.",
+            job_id,
+            BinaryBytes(quota.usage_size),
+            BinaryBytes(quota.quota_size)
+        ));
+    }
+    Ok(())
+}
+
+/// Submits a cohort job, whose files are written to the user's space once it completes.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `job_id` - The name of the cohort.
+/// * `request` - The query that selects the cohort.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` indicating whether the job was accepted.
+/// # Example
+/// ```
+/// let spec = read_spec("cohort.yaml")?;
+/// submit_cohort("https://api.example.com", &spec.name, &spec.to_request()?, "my_token").await?;
+/// ```
+pub async fn submit_cohort(
+    api: &str,
+    job_id: &str,
+    request: &QueryRequest,
+    token: &str,
+) -> Result<()> {
+    let body = cohort_body(job_id, request)?;
+    api::post(api, "dportal/cohort", &body, token)
+        .await
+        .with_context(|| format!("Failed to submit cohort '{}'", job_id))?;
+    Ok(())
+}
+
+/// Checks that no files of an earlier cohort with the same name are in the user's space.
+/// Cohort files are written to the `<job id>/` folder, so a new run under the same name could not
+/// be told apart from the earlier one.
+/// # Arguments
+/// * `job_id` - The name of the cohort.
+/// * `existing` - The files of the user's space.
+/// # Errors
+/// Returns an error if the folder of the cohort already holds files.
+pub fn check_cohort_name(job_id: &str, existing: &[String]) -> Result<()> {
+    let folder = format!("{}/", job_id);
+    if existing.iter().any(|file| file.starts_with(&folder)) {
+        return Err(anyhow!(
+            "Cohort {} already exists in your user space. Choose another name in the spec, or delete the files in {} first.",
+            job_id,
+            folder
+        ));
+    }
+    Ok(())
+}
+
+/// Waits for the files of a cohort job to appear in the user's space.
+/// Only files in the `<job id>/` folder count, which `check_cohort_name` ensures were not there
+/// before the job was submitted. The job is considered complete once its `cohort_file` is listed
+/// and the files are unchanged between two polls, so that files still being written are not missed.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `job_id` - The name of the cohort.
+/// * `scope` - The scope of the cohort query.
+/// * `token` - The authentication token.
+/// * `interval` - How long to wait between polls.
+/// * `timeout` - How long to wait in total.
+/// # Returns
+/// A `Result` containing the names of the cohort files.
+/// # Errors
+/// Returns an error if listing the files fails or if no complete set of files appears before the timeout.
+/// # Example
+/// ```
+/// check_cohort_name("t2d-female-2025", &list_project_files(&api, None, &token).await?)?;
+/// submit_cohort(&api, "t2d-female-2025", &request, &token).await?;
+/// let files = wait_for_cohort(&api, "t2d-female-2025", request.scope, &token, Duration::from_secs(15), Duration::from_secs(1800)).await?;
+/// ```
+pub async fn wait_for_cohort(
+    api: &str,
+    job_id: &str,
+    scope: Scope,
+    token: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<Vec<String>> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    pb.set_message(format!("Waiting for cohort {}", job_id));
+    pb.enable_steady_tick(Duration::from_millis(200));
+
+    let folder = format!("{}/", job_id);
+    let main_file = cohort_file(job_id, scope);
+    let started = Instant::now();
+    let mut previous: Vec<String> = Vec::new();
+    loop {
+        let mut files: Vec<String> = list_project_files(api, None, token)
+            .await?
+            .into_iter()
+            .filter(|file| file.starts_with(&folder))
+            .collect();
+        files.sort();
+        if files.contains(&main_file) && files == previous {
+            pb.finish_with_message(format!("✓ cohort {} ({} files)", job_id, files.len()));
+            return Ok(files);
+        }
+        if started.elapsed() >= timeout {
+            pb.finish_with_message(format!("✗ cohort {}", job_id));
+            return Err(anyhow!(
+                "Cohort {} is not ready after {} seconds. Its files will appear in your user space; list them later with `gaspifs files`.",
+                job_id,
+                timeout.as_secs()
+            ));
+        }
+        previous = files;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    use mockito::{Matcher, Server};
+    use serde_json::json;
+
+    const SPEC: &str = r#"
+name: t2d-female-2025
+projects: [project1]
+filters:
+  - NCIT:C16576
+  - id: age
+    operator: ">="
+    value: "40"
+  - id: "SNOMED:73211009"
+    includeDescendantTerms: false
+samples:
+  - UBERON:0000178
+"#;
+
+    #[test]
+    fn test_spec_to_request() {
+        let spec: CohortSpec = serde_norway::from_str(SPEC).unwrap();
+        let request = spec.to_request().unwrap();
+        assert_eq!(request.scope, Scope::Individuals);
+        assert_eq!(
+            request.body().unwrap()["query"]["filters"],
+            json!([
+                {"scope": "individuals", "id": "NCIT:C16576", "includeDescendantTerms": true},
+                {"scope": "individuals", "id": "age", "operator": ">=", "value": "40"},
+                {"scope": "individuals", "id": "SNOMED:73211009", "includeDescendantTerms": false},
+                {"scope": "biosamples", "id": "UBERON:0000178", "includeDescendantTerms": true},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        let spec: CohortSpec = serde_norway::from_str("name: short\nprojects: [p1]").unwrap();
+        assert!(spec.to_request().is_err());
+        let spec: CohortSpec =
+            serde_norway::from_str("name: variants-cohort\nprojects: [p1]\nscope: g_variants")
+                .unwrap();
+        assert!(spec.to_request().is_err());
+        assert!(
+            serde_norway::from_str::<CohortSpec>("name: a-cohort-name\nprojects: [p1]\nfilter: []")
+                .is_err()
+        );
+        assert!(check_projects(&["p1".to_string()], &["p2".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_check_cohort_name() {
+        let existing = [
+            "t2d-female-2025-old/individuals.json".to_string(),
+            "other.vcf.gz".to_string(),
+        ];
+        assert!(check_cohort_name("t2d-female-2025", &existing).is_ok());
+        let existing = ["t2d-female-2025/individuals.json".to_string()];
+        let err = check_cohort_name("t2d-female-2025", &existing).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+    }
+
+    #[tokio::test]
+    async fn test_submit_and_wait() {
+        let spec: CohortSpec = serde_norway::from_str(SPEC).unwrap();
+        let request = spec.to_request().unwrap();
+        let mut server = Server::new_async().await;
+        let submit = server
+            .mock("POST", "/dportal/cohort")
+            .match_body(Matcher::PartialJson(json!({
+                "jobId": "t2d-female-2025",
+                "scope": "individuals",
+                "projects": ["project1"],
+                "query": {"requestedGranularity": "record"},
+            })))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        let list = server
+            .mock("POST", "/dportal/cli")
+            .with_status(200)
+            .with_body(
+                r#"["t2d-female-2025/individuals.json", "t2d-female-2025/summary.json",
+                    "t2d-female-2025-old/individuals.json", "other.vcf.gz"]"#,
+            )
+            .expect(2)
+            .create();
+
+        submit_cohort(&server.url(), &spec.name, &request, "token")
+            .await
+            .unwrap();
+        let files = wait_for_cohort(
+            &server.url(),
+            &spec.name,
+            request.scope,
+            "token",
+            Duration::ZERO,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            files,
+            [
+                cohort_file(&spec.name, Scope::Individuals),
+                "t2d-female-2025/summary.json".to_string()
+            ]
+        );
+        submit.assert();
+        list.assert();
+    }
+
+    #[tokio::test]
+    async fn test_check_cohort_quota() {
+        let token = format!(
+            "header.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"sub": "user-1"}"#)
+        );
+        let spec: CohortSpec = serde_norway::from_str(SPEC).unwrap();
+        let request = spec.to_request().unwrap();
+        let mut server = Server::new_async().await;
+        let quota = |usage: u64| {
+            format!(
+                r#"{{"success": true, "data": {{"Usage": {{"quotaSize": 1000000, "usageSize": {}}}}}}}"#,
+                usage
+            )
+        };
+        let mock = server
+            .mock("GET", "/dportal/quota/user-1")
+            .with_status(200)
+            .with_body(quota(1000))
+            .create();
+        check_cohort_quota(&server.url(), &spec.name, &request, &token)
+            .await
+            .unwrap();
+
+        mock.remove();
+        server
+            .mock("GET", "/dportal/quota/user-1")
+            .with_status(200)
+            .with_body(quota(999999))
+            .create();
+        let err = check_cohort_quota(&server.url(), &spec.name, &request, &token)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("storage quota is reached"));
+    }
+}
@@ -0,0 +1,665 @@
+#[cfg(feature = "uploads")]
+use crate::networking::{
+    downloads::{get_download_urls, stream_file},
+    files::list_project_files,
+};
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+#[cfg(feature = "uploads")]
+use anyhow::Context;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+#[cfg(feature = "uploads")]
+use std::io::IsTerminal;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+/// Extension added to the name of encrypted files.
+pub const ENCRYPTED_EXTENSION: &str = ".age";
+
+/// Name of the file in a project that lists the recipients its members can opt in to encrypting uploads to.
+#[cfg(feature = "uploads")]
+pub const PROJECT_RECIPIENTS_FILE: &str = "gaspifs-recipients.txt";
+
+/// Recipients that uploads are encrypted to, for user space and for each project.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Recipients {
+    #[serde(default)]
+    pub user: Vec<String>,
+    #[serde(default)]
+    pub projects: BTreeMap<String, Vec<String>>,
+    /// Recipients published in projects, as accepted with `keys use` or on the first `--encrypt` upload.
+    #[serde(default)]
+    pub pinned: BTreeMap<String, Vec<String>>,
+}
+
+impl Recipients {
+    /// Returns the recipients configured for user space or a project.
+    pub fn get(&self, project: Option<&str>) -> &[String] {
+        match project {
+            Some(project) => self.projects.get(project).map_or(&[], Vec::as_slice),
+            None => &self.user,
+        }
+    }
+
+    /// Adds a recipient to user space or a project, returning `false` if it was already there.
+    /// # Errors
+    /// Returns an error if the recipient is not a valid age public key.
+    pub fn add(&mut self, project: Option<&str>, recipient: &str) -> Result<bool> {
+        recipient
+            .parse::<Recipient>()
+            .map_err(|err| anyhow!("Invalid recipient {:?}: {}", recipient, err))?;
+        let recipients = match project {
+            Some(project) => self.projects.entry(project.to_string()).or_default(),
+            None => &mut self.user,
+        };
+        if recipients.iter().any(|existing| existing == recipient) {
+            return Ok(false);
+        }
+        recipients.push(recipient.to_string());
+        Ok(true)
+    }
+
+    /// Removes a recipient from user space or a project, returning `false` if it was not there.
+    pub fn remove(&mut self, project: Option<&str>, recipient: &str) -> bool {
+        let recipients = match project {
+            Some(project) => match self.projects.get_mut(project) {
+                Some(recipients) => recipients,
+                None => return false,
+            },
+            None => &mut self.user,
+        };
+        let before = recipients.len();
+        recipients.retain(|existing| existing != recipient);
+        let removed = recipients.len() != before;
+        self.projects.retain(|_, recipients| !recipients.is_empty());
+        removed
+    }
+
+    /// Compares the recipients published in a project with those pinned for it.
+    /// # Returns
+    /// The published recipients that are not pinned, and the pinned recipients that are no longer published.
+    #[cfg(feature = "uploads")]
+    pub fn pinned_changes<'a>(
+        &'a self,
+        project: &str,
+        published: &'a [String],
+    ) -> (Vec<&'a String>, Vec<&'a String>) {
+        let pinned = self.pinned.get(project).map_or(&[][..], Vec::as_slice);
+        let added = published
+            .iter()
+            .filter(|recipient| !pinned.contains(recipient))
+            .collect();
+        let removed = pinned
+            .iter()
+            .filter(|recipient| !published.contains(recipient))
+            .collect();
+        (added, removed)
+    }
+}
+
+/// Stores the identity used to decrypt downloads and the recipients that uploads are encrypted to,
+/// in `~/.gaspifs/identity.txt` and `~/.gaspifs/recipients.json`.
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    /// Opens the key store in the home directory.
+    pub fn open() -> Result<Self> {
+        let mut dir = home::home_dir().ok_or(anyhow!("Could not determine home directory."))?;
+        dir.push(".gaspifs");
+        Ok(KeyStore { dir })
+    }
+
+    fn identity_path(&self) -> PathBuf {
+        self.dir.join("identity.txt")
+    }
+
+    fn recipients_path(&self) -> PathBuf {
+        self.dir.join("recipients.json")
+    }
+
+    /// Returns `true` if an identity has been generated.
+    pub fn has_identity(&self) -> bool {
+        self.identity_path().exists()
+    }
+
+    /// Generates a new identity, readable only by the current user, and returns its public key.
+    /// # Errors
+    /// Returns an error if an identity already exists and `force` is not set, since replacing it
+    /// makes every file encrypted to the old public key unreadable.
+    pub fn generate(&self, force: bool) -> Result<Recipient> {
+        let path = self.identity_path();
+        if path.exists() && !force {
+            return Err(anyhow!(
+                "An identity already exists at {}. Pass --force to replace it; files encrypted to it will no longer be readable.",
+                path.display()
+            ));
+        }
+        fs::create_dir_all(&self.dir)?;
+
+        let identity = Identity::generate();
+        let recipient = identity.to_public();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path)?;
+        writeln!(file, "# public key: {}", recipient)?;
+        writeln!(file, "{}", identity.to_string().expose_secret())?;
+        Ok(recipient)
+    }
+
+    /// Reads the identities from the identity file, ignoring comments.
+    /// # Errors
+    /// Returns an error if no identity has been generated or if the file is invalid.
+    pub fn identities(&self) -> Result<Vec<Identity>> {
+        let path = self.identity_path();
+        let content = fs::read_to_string(&path).map_err(|_| {
+            anyhow!(
+                "No identity found at {}. Run `gaspifs keys generate` first.",
+                path.display()
+            )
+        })?;
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.parse::<Identity>()
+                    .map_err(|err| anyhow!("Invalid identity in {}: {}", path.display(), err))
+            })
+            .collect()
+    }
+
+    /// Returns the public key of the identity.
+    pub fn public_key(&self) -> Result<Recipient> {
+        self.identities()?
+            .first()
+            .map(Identity::to_public)
+            .ok_or(anyhow!("The identity file is empty."))
+    }
+
+    /// Reads the configured recipients, which are empty if none were configured yet.
+    pub fn recipients(&self) -> Result<Recipients> {
+        match fs::read_to_string(self.recipients_path()) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Recipients::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the configured recipients.
+    pub fn save_recipients(&self, recipients: &Recipients) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(
+            self.recipients_path(),
+            serde_json::to_string_pretty(recipients)?,
+        )?;
+        Ok(())
+    }
+
+    /// Pins the recipients published in a project, so that later uploads notice when they change.
+    /// # Arguments
+    /// * `project` - The project that publishes the recipients.
+    /// * `published` - The recipients published in the project, see `project_recipients`.
+    /// * `accept` - Whether to replace recipients pinned earlier that differ from `published`.
+    /// # Returns
+    /// A `Result` containing `true` if the pinned recipients were saved, or `false` if they were up to date.
+    /// # Errors
+    /// Returns an error if other recipients were pinned for the project and `accept` is not set.
+    #[cfg(feature = "uploads")]
+    pub fn pin_published(&self, project: &str, published: &[String], accept: bool) -> Result<bool> {
+        let mut recipients = self.recipients()?;
+        let (added, removed) = recipients.pinned_changes(project, published);
+        if added.is_empty() && removed.is_empty() && recipients.pinned.contains_key(project) {
+            return Ok(false);
+        }
+        if recipients.pinned.contains_key(project) && !accept {
+            return Err(anyhow!(
+                "The recipients published in project {} changed since they were pinned ({} added, {} removed). Review them with `gaspifs keys use --project {}`, or pass --no-encrypt.",
+                project,
+                added.len(),
+                removed.len(),
+                project
+            ));
+        }
+        recipients
+            .pinned
+            .insert(project.to_string(), published.to_vec());
+        self.save_recipients(&recipients)?;
+        Ok(true)
+    }
+
+    /// Returns the recipients an upload to user space or a project must be encrypted to.
+    /// Uploads are encrypted whenever recipients are configured locally for their destination,
+    /// when the project's published recipients are used, or when `force` is set; the user's own
+    /// public key is always added so that they can read their uploads.
+    /// # Arguments
+    /// * `project` - The destination project, if None, user space.
+    /// * `published` - The recipients published in the project, see `published_recipients`.
+    /// * `force` - Whether to encrypt even if no recipients are configured.
+    /// # Returns
+    /// A `Result` containing the recipients, which is empty if the upload is not encrypted.
+    /// # Errors
+    /// Returns an error if encryption is forced but there is neither a recipient nor an identity.
+    #[cfg(feature = "uploads")]
+    pub fn upload_recipients(
+        &self,
+        project: Option<&str>,
+        published: &[String],
+        force: bool,
+    ) -> Result<Vec<Recipient>> {
+        let local = self.recipients()?;
+        let mut configured: Vec<&String> = local.get(project).iter().collect();
+        for recipient in published {
+            if !configured.contains(&recipient) {
+                configured.push(recipient);
+            }
+        }
+        if configured.is_empty() && !force {
+            return Ok(Vec::new());
+        }
+
+        let mut recipients = configured
+            .iter()
+            .map(|recipient| {
+                recipient
+                    .parse::<Recipient>()
+                    .map_err(|err| anyhow!("Invalid recipient {:?}: {}", recipient, err))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if self.has_identity() {
+            let own = self.public_key()?;
+            if !configured.contains(&&own.to_string()) {
+                recipients.push(own);
+            }
+        }
+        if recipients.is_empty() {
+            return Err(anyhow!(
+                "No recipients to encrypt to. Run `gaspifs keys generate` or `gaspifs keys add` first."
+            ));
+        }
+        Ok(recipients)
+    }
+}
+
+/// Parses a recipients file, which holds one age public key per line.
+/// Blank lines and lines starting with `#` are ignored.
+/// # Errors
+/// Returns an error if a line is not a valid age public key.
+#[cfg(feature = "uploads")]
+pub fn parse_recipients(text: &str) -> Result<Vec<String>> {
+    let mut recipients: Vec<String> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        line.parse::<Recipient>()
+            .map_err(|err| anyhow!("Invalid recipient {:?}: {}", line, err))?;
+        if !recipients.iter().any(|existing| existing == line) {
+            recipients.push(line.to_string());
+        }
+    }
+    Ok(recipients)
+}
+
+/// Gets the recipients published in a project, so that every member encrypts uploads to the same keys.
+/// They are read from the `PROJECT_RECIPIENTS_FILE` of the project.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, user space, which has no published recipients.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the recipients, which is empty if the project does not publish any.
+/// # Errors
+/// Returns an error if the project files cannot be listed or read, or if the file holds an invalid key.
+/// # Example
+/// ```
+/// let published = project_recipients(&api, Some("my_project"), &id_token).await?;
+/// println!("{} recipient(s) published", published.len());
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn project_recipients(
+    api: &str,
+    project: Option<&str>,
+    token: &str,
+) -> Result<Vec<String>> {
+    let Some(project) = project else {
+        return Ok(Vec::new());
+    };
+    let files = list_project_files(api, Some(project), token).await?;
+    if !files.iter().any(|file| file == PROJECT_RECIPIENTS_FILE) {
+        return Ok(Vec::new());
+    }
+    let url = get_download_urls(
+        api,
+        Some(project),
+        &[PROJECT_RECIPIENTS_FILE.to_string()],
+        token,
+    )
+    .await?
+    .into_iter()
+    .next()
+    .ok_or(anyhow!(
+        "No download URL returned for {}.",
+        PROJECT_RECIPIENTS_FILE
+    ))?;
+    let mut data = Vec::new();
+    stream_file(&url, None, None, false, &mut data).await?;
+    parse_recipients(&String::from_utf8_lossy(&data))
+        .with_context(|| format!("Failed to read the recipients of project {}", project))
+}
+
+/// Gets the recipients published in a project that an upload to it is encrypted to.
+/// Published recipients are opt-in: they are only read when the project was accepted with
+/// `keys use` or when `encrypt` is set. They are pinned on first use, and the upload is refused
+/// if they no longer match the pinned ones.
+/// # Arguments
+/// * `store` - The key store holding the pinned recipients.
+/// * `api` - The base URL of the API.
+/// * `project` - The destination project, if None, user space, which has no published recipients.
+/// * `token` - The authentication token.
+/// * `encrypt` - Whether the user asked for the upload to be encrypted.
+/// # Returns
+/// A `Result` containing the recipients, which is empty if they are not used.
+/// # Errors
+/// Returns an error if the recipients cannot be read, or if they changed since they were pinned.
+/// # Example
+/// ```
+/// let store = KeyStore::open()?;
+/// let published = published_recipients(&store, &api, Some("my_project"), &id_token, true).await?;
+/// let recipients = store.upload_recipients(Some("my_project"), &published, true)?;
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn published_recipients(
+    store: &KeyStore,
+    api: &str,
+    project: Option<&str>,
+    token: &str,
+    encrypt: bool,
+) -> Result<Vec<String>> {
+    let Some(project) = project else {
+        return Ok(Vec::new());
+    };
+    if !encrypt && !store.recipients()?.pinned.contains_key(project) {
+        return Ok(Vec::new());
+    }
+    let published = project_recipients(api, Some(project), token).await?;
+    if !published.is_empty() && store.pin_published(project, &published, false)? {
+        eprintln!(
+            "Pinned the {} recipient(s) published in project {}.",
+            published.len(),
+            project
+        );
+    }
+    Ok(published)
+}
+
+/// Asks the user to confirm that the recipients published in a project replace those pinned for it.
+/// The question is skipped with `yes`.
+/// # Errors
+/// Returns an error if confirmation is needed but standard input is not a terminal.
+#[cfg(feature = "uploads")]
+pub fn confirm_pin(project: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Replacing pinned recipients needs confirmation; pass --yes to confirm without a terminal."
+        ));
+    }
+    eprint!(
+        "Encrypt uploads to project {} to these recipients? [y/N] ",
+        project
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Returns the name of a file once decrypted, or `None` if the name is not that of an encrypted file.
+pub fn decrypted_name(name: &str) -> Option<&str> {
+    name.strip_suffix(ENCRYPTED_EXTENSION)
+        .filter(|name| !name.is_empty())
+}
+
+/// Returns the name under which a downloaded file is saved once decrypted, or `None` if the file
+/// is not encrypted or there is no identity to decrypt it with, in which case it is saved as is.
+pub fn decrypted_download_name(name: &str) -> Option<&str> {
+    decrypted_name(name).filter(|_| KeyStore::open().is_ok_and(|store| store.has_identity()))
+}
+
+/// Starts encrypting to a list of recipients; the returned writer must be finished with `finish`.
+/// # Example
+/// ```
+/// let mut writer = encrypt(&recipients, File::create("data.txt.age")?)?;
+/// writer.write_all(b"secret")?;
+/// writer.finish()?;
+/// ```
+#[cfg(feature = "uploads")]
+pub fn encrypt<W: Write>(
+    recipients: &[Recipient],
+    output: W,
+) -> Result<age::stream::StreamWriter<W>> {
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )?;
+    Ok(encryptor.wrap_output(output)?)
+}
+
+/// Decrypts data encrypted to one of the identities.
+/// # Arguments
+/// * `identities` - The identities that may decrypt the data.
+/// * `input` - The encrypted data.
+/// * `output` - Where to write the decrypted data.
+/// # Returns
+/// A `Result` containing the number of decrypted bytes.
+/// # Errors
+/// Returns an error if the data was not encrypted to any of the identities or was tampered with.
+/// # Example
+/// ```
+/// let identities = KeyStore::open()?.identities()?;
+/// decrypt(&identities, File::open("data.txt.age")?, File::create("data.txt")?)?;
+/// ```
+pub fn decrypt<R: Read, W: Write>(identities: &[Identity], input: R, mut output: W) -> Result<u64> {
+    let decryptor = age::Decryptor::new(input)?;
+    let mut reader = decryptor.decrypt(
+        identities
+            .iter()
+            .map(|identity| identity as &dyn age::Identity),
+    )?;
+    let decrypted = io::copy(&mut reader, &mut output)?;
+    output.flush()?;
+    Ok(decrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_store() -> (tempfile::TempDir, KeyStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyStore {
+            dir: dir.path().join(".gaspifs"),
+        };
+        (dir, store)
+    }
+
+    #[test]
+    fn test_generate_identity() {
+        let (_dir, store) = key_store();
+        assert!(!store.has_identity());
+        let recipient = store.generate(false).unwrap();
+        assert_eq!(
+            store.public_key().unwrap().to_string(),
+            recipient.to_string()
+        );
+        assert!(store.generate(false).is_err());
+        assert!(store.generate(true).is_ok());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let (_dir, store) = key_store();
+        let recipient = Identity::generate().to_public().to_string();
+        let mut recipients = store.recipients().unwrap();
+        assert!(recipients.add(Some("p1"), &recipient).unwrap());
+        assert!(!recipients.add(Some("p1"), &recipient).unwrap());
+        assert!(recipients.add(None, "not-a-key").is_err());
+        store.save_recipients(&recipients).unwrap();
+
+        let mut recipients = store.recipients().unwrap();
+        assert_eq!(recipients.get(Some("p1")), std::slice::from_ref(&recipient));
+        assert!(recipients.get(None).is_empty());
+        assert!(recipients.remove(Some("p1"), &recipient));
+        assert!(recipients.projects.is_empty());
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let (_dir, store) = key_store();
+        assert!(
+            store
+                .upload_recipients(None, &[], false)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(store.upload_recipients(None, &[], true).is_err());
+        store.generate(false).unwrap();
+        let recipients = store.upload_recipients(None, &[], true).unwrap();
+
+        let mut writer = encrypt(&recipients, Vec::new()).unwrap();
+        writer.write_all(b"patient data").unwrap();
+        let encrypted = writer.finish().unwrap();
+
+        let mut decrypted = Vec::new();
+        decrypt(&store.identities().unwrap(), &encrypted[..], &mut decrypted).unwrap();
+        assert_eq!(decrypted, b"patient data");
+        let other = [Identity::generate()];
+        assert!(decrypt(&other, &encrypted[..], Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_parse_recipients() {
+        let recipient = Identity::generate().to_public().to_string();
+        let text = format!("# lab members\n{}\n\n  {}  \n", recipient, recipient);
+        assert_eq!(parse_recipients(&text).unwrap(), [recipient]);
+        assert!(parse_recipients("not-a-key\n").is_err());
+    }
+
+    #[cfg(feature = "uploads")]
+    #[tokio::test]
+    async fn test_project_recipients() {
+        let (_dir, store) = key_store();
+        let published = Identity::generate().to_public().to_string();
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/dportal/cli")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "mode": "files",
+                "project": "p1"
+            })))
+            .with_status(200)
+            .with_body(format!(
+                r#"["sample.vcf.gz", "{}"]"#,
+                PROJECT_RECIPIENTS_FILE
+            ))
+            .create();
+        server
+            .mock("POST", "/dportal/cli")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "mode": "download",
+                "project": "p1",
+                "files": [PROJECT_RECIPIENTS_FILE]
+            })))
+            .with_status(200)
+            .with_body(format!(r#"["{}/recipients"]"#, server.url()))
+            .create();
+        let file = server
+            .mock("GET", "/recipients")
+            .with_status(200)
+            .with_body(format!("# shared keys\n{}\n", published))
+            .create();
+
+        let found = project_recipients(&server.url(), Some("p1"), "token")
+            .await
+            .unwrap();
+        assert_eq!(found, std::slice::from_ref(&published));
+        assert!(
+            project_recipients(&server.url(), None, "token")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let recipients = store.upload_recipients(Some("p1"), &found, false).unwrap();
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].to_string(), published);
+
+        // Published recipients are only used once the project is opted in, and are then pinned
+        let api = server.url();
+        assert!(
+            published_recipients(&store, &api, Some("p1"), "token", false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let used = published_recipients(&store, &api, Some("p1"), "token", true)
+            .await
+            .unwrap();
+        assert_eq!(used, found);
+        assert_eq!(store.recipients().unwrap().pinned["p1"], found);
+        let used = published_recipients(&store, &api, Some("p1"), "token", false)
+            .await
+            .unwrap();
+        assert_eq!(used, found);
+
+        // A changed set of recipients is refused until it is accepted
+        file.remove();
+        let other = Identity::generate().to_public().to_string();
+        server
+            .mock("GET", "/recipients")
+            .with_status(200)
+            .with_body(format!("{}\n", other))
+            .create();
+        let err = published_recipients(&store, &api, Some("p1"), "token", false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("1 added, 1 removed"), "{}", err);
+        assert!(
+            store
+                .pin_published("p1", std::slice::from_ref(&other), true)
+                .unwrap()
+        );
+        assert!(!store.pin_published("p1", &[other], false).unwrap());
+    }
+
+    #[cfg(feature = "uploads")]
+    #[tokio::test]
+    async fn test_published_recipients_not_used() {
+        let (_dir, store) = key_store();
+        // The project is neither opted in nor asked to encrypt, so nothing is requested
+        let published =
+            published_recipients(&store, "http://localhost:1", Some("p1"), "token", false)
+                .await
+                .unwrap();
+        assert!(published.is_empty());
+    }
+
+    #[test]
+    fn test_decrypted_name() {
+        assert_eq!(decrypted_name("a.vcf.gz.age"), Some("a.vcf.gz"));
+        assert_eq!(decrypted_name("a.vcf.gz"), None);
+        assert_eq!(decrypted_name(".age"), None);
+    }
+}
@@ -1,12 +1,23 @@
 use crate::auth::login;
-use crate::networking::{downloads, files, projects};
+use crate::networking::{api, downloads, files, projects};
 use anyhow::{Result, anyhow};
 use clap::Parser;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::{env, path::Path};
+use std::time::Duration;
+#[cfg(feature = "admin")]
+mod admin;
 mod args;
 mod auth;
+mod beacon;
+mod clinic;
+mod cohort;
+mod encryption;
+mod manifest;
 mod networking;
+mod notebooks;
+mod vcf;
 
 #[cfg(feature = "uploads")]
 use crate::networking::uploads;
@@ -30,7 +41,15 @@ fn load_env() -> Result<()> {
     Ok(())
 }
 
-async fn run() -> Result<()> {
+/// Logs in and returns the ID token, failing the command if the login does not succeed.
+async fn login_or_fail() -> Result<String> {
+    let (_access_token, id_token, _refresh_token) = login()
+        .await
+        .map_err(|_| anyhow!("Login failed. Please check your credentials."))?;
+    Ok(id_token)
+}
+
+async fn run() -> Result<ExitCode> {
     let args = args::Cli::parse();
     // we call this function but ignore its errors and seek environment variables
     // this is useful for testing with environment properly set using export VAR=value
@@ -39,10 +58,7 @@ async fn run() -> Result<()> {
 
     match args.command {
         args::Command::Projects {} => {
-            let Ok((_access_token, id_token, _refresh_token)) = login().await else {
-                eprintln!("Login failed. Please check your credentials.");
-                return Ok(());
-            };
+            let id_token = login_or_fail().await?;
             // Logic to list project
             let projects = projects::list_projects(&api, &id_token).await?;
             for (i, project) in projects.iter().enumerate() {
@@ -54,11 +70,16 @@ async fn run() -> Result<()> {
                 );
             }
         }
+        args::Command::Quota { output } => {
+            let id_token = login_or_fail().await?;
+            let quota = networking::quota::get_quota(&api, &id_token).await?;
+            print!(
+                "{}",
+                networking::quota::format_quota(quota.as_ref(), output)?
+            );
+        }
         args::Command::Files { project } => {
-            let Ok((_access_token, id_token, _refresh_token)) = login().await else {
-                eprintln!("Login failed. Please check your credentials.");
-                return Ok(());
-            };
+            let id_token = login_or_fail().await?;
             let files = files::list_project_files(&api, project.as_deref(), &id_token).await?;
             for (i, file) in files.iter().enumerate() {
                 println!("File {:>4}: \"{}\"", i + 1, file);
@@ -68,11 +89,36 @@ async fn run() -> Result<()> {
             project,
             files,
             destination,
+            from_manifest,
+            result_manifest,
+            keep_going,
+            with_index,
         } => {
-            let Ok((_access_token, id_token, _refresh_token)) = login().await else {
-                eprintln!("Login failed. Please check your credentials.");
-                return Ok(());
-            };
+            let manifest = from_manifest
+                .as_deref()
+                .map(manifest::read_manifest)
+                .transpose()?;
+            let id_token = login_or_fail().await?;
+            let destination = destination.unwrap_or_else(|| ".".to_string());
+            if let (Some(path), Some((format, rows))) = (from_manifest, manifest) {
+                // Logic to download files listed in a manifest
+                let report = manifest::download_manifest(
+                    &api,
+                    &id_token,
+                    &rows,
+                    project.as_deref(),
+                    Path::new(&destination),
+                    keep_going,
+                )
+                .await?;
+                report.print_summary();
+                let result_path = result_manifest
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| manifest::result_manifest_path(&path, format));
+                manifest::write_results(&result_path, format, &rows, &report)?;
+                println!("Results written to {}", result_path.display());
+                return Ok(report.exit_code());
+            }
             // Logic to download files from a project
             let destination = Path::new(&destination);
             if destination.exists() && !destination.is_dir() {
@@ -82,30 +128,1294 @@ async fn run() -> Result<()> {
                 std::fs::create_dir_all(destination)
                     .map_err(|_| anyhow!("Failed to create destination directory."))?;
             }
+            let files = if with_index {
+                let available =
+                    files::list_project_files(&api, project.as_deref(), &id_token).await?;
+                networking::paths::with_indexes(&files, &available)
+            } else {
+                files
+            };
             let download_urls =
                 downloads::get_download_urls(&api, project.as_deref(), &files, &id_token).await?;
-            downloads::download_files(&download_urls, destination.to_str().unwrap()).await?;
+            let report = downloads::download_files(
+                &download_urls,
+                destination.to_str().unwrap(),
+                keep_going,
+            )
+            .await?;
+            report.print_summary();
+            return Ok(report.exit_code());
+        }
+        args::Command::Cat {
+            target,
+            range,
+            head,
+            decompress,
+        } => {
+            let id_token = login_or_fail().await?;
+            // Logic to stream a file to standard output
+            let download_urls = downloads::get_download_urls(
+                &api,
+                target.project.as_deref(),
+                std::slice::from_ref(&target.file),
+                &id_token,
+            )
+            .await?;
+            let url = download_urls
+                .first()
+                .ok_or(anyhow!("No download URL returned for {}.", target.file))?;
+            let mut stdout = tokio::io::stdout();
+            downloads::stream_file(url, range.as_ref(), head, decompress, &mut stdout).await?;
+        }
+        args::Command::Stats { file, output } => {
+            let stats = tokio::task::spawn_blocking(move || vcf::stats::vcf_stats(file)).await??;
+            print!("{}", vcf::stats::format_stats(&stats, output)?);
         }
         #[cfg(feature = "uploads")]
-        args::Command::Upload { project, files } => {
-            let Ok((_access_token, id_token, _refresh_token)) = login().await else {
-                eprintln!("Login failed. Please check your credentials.");
-                return Ok(());
-            };
+        args::Command::Upload {
+            project,
+            files,
+            from_manifest,
+            result_manifest,
+            keep_going,
+            skip_validation,
+            build_index,
+            index_format,
+            bgzip,
+            encrypt,
+            no_encrypt,
+            stats,
+        } => {
+            let manifest = from_manifest
+                .as_deref()
+                .map(manifest::read_manifest)
+                .transpose()?;
+            let mut invalid = std::collections::BTreeMap::new();
+            if !skip_validation {
+                // Logic to validate VCF files before requesting upload URLs
+                let sources: Vec<String> = match &manifest {
+                    Some((_, rows)) => rows.iter().map(|row| row.source.clone()).collect(),
+                    None => files.clone(),
+                };
+                invalid = vcf::validate::validate_files(&sources, keep_going).await?;
+            }
+            let store = encryption::KeyStore::open()?;
+            let id_token = login_or_fail().await?;
+            if let Some((_, rows)) = &manifest
+                && !no_encrypt
+            {
+                // Manifest rows are uploaded as they are, so they cannot be encrypted
+                let mut published = std::collections::BTreeMap::new();
+                for row in rows {
+                    let project = row.project.as_deref().or(project.as_deref());
+                    if let std::collections::btree_map::Entry::Vacant(entry) =
+                        published.entry(project)
+                    {
+                        entry.insert(
+                            encryption::published_recipients(
+                                &store, &api, project, &id_token, encrypt,
+                            )
+                            .await?,
+                        );
+                    }
+                    if !store
+                        .upload_recipients(project, &published[&project], encrypt)?
+                        .is_empty()
+                    {
+                        return Err(anyhow!(
+                            "Encrypted uploads are not supported with --from-manifest; upload {} with --files instead, or pass --no-encrypt to upload it unencrypted.",
+                            row.source
+                        ));
+                    }
+                }
+            }
+            if let (Some(path), Some((format, rows))) = (from_manifest, manifest) {
+                // Logic to check that the rows uploaded to user space fit in the quota
+                let personal: Vec<&str> = rows
+                    .iter()
+                    .filter(|row| row.project.as_deref().or(project.as_deref()).is_none())
+                    .filter(|row| !invalid.contains_key(&row.source))
+                    .map(|row| row.source.as_str())
+                    .collect();
+                if !personal.is_empty() {
+                    let total = networking::util::get_file_sizes(&personal)?.iter().sum();
+                    networking::quota::check_upload_quota(&api, total, &id_token).await?;
+                }
+                // Logic to upload files listed in a manifest
+                let report = manifest::upload_manifest(
+                    &api,
+                    &id_token,
+                    &rows,
+                    &invalid,
+                    project.as_deref(),
+                    keep_going,
+                )
+                .await?;
+                report.print_summary();
+                let result_path = result_manifest
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| manifest::result_manifest_path(&path, format));
+                manifest::write_results(&result_path, format, &rows, &report)?;
+                println!("Results written to {}", result_path.display());
+                return Ok(report.exit_code());
+            }
             // Logic to upload files to a project
             if files.is_empty() {
                 return Err(anyhow!("No files specified for upload."));
             }
-            let upload_urls =
-                uploads::get_upload_urls(&api, project.as_deref(), &files, &id_token).await?;
-            uploads::upload_files(upload_urls, &files).await?;
+            // Files that failed validation are reported as failed and the rest are uploaded
+            let mut report = networking::report::TransferReport::default();
+            let (files, failed): (Vec<String>, Vec<String>) = files
+                .into_iter()
+                .partition(|file| !invalid.contains_key(file));
+            for file in failed {
+                let reason = invalid.remove(&file).unwrap_or_default();
+                report.push(file, networking::report::Outcome::Failed(reason));
+            }
+            let recipients = if no_encrypt {
+                Vec::new()
+            } else {
+                let published = encryption::published_recipients(
+                    &store,
+                    &api,
+                    project.as_deref(),
+                    &id_token,
+                    encrypt,
+                )
+                .await?;
+                store.upload_recipients(project.as_deref(), &published, encrypt)?
+            };
+            let encoding = uploads::StreamEncoding {
+                bgzip,
+                index: build_index.then_some(index_format),
+                recipients,
+            };
+            if encoding.encrypts() {
+                if stats {
+                    return Err(anyhow!(
+                        "--stats would upload the statistics of encrypted files unencrypted."
+                    ));
+                }
+                println!(
+                    "Encrypting uploads to {} recipient(s)",
+                    encoding.recipients.len()
+                );
+            }
+            let vcfs: Vec<String> = if stats { files.clone() } else { Vec::new() };
+            let mut encoded = Vec::new();
+            let mut others = Vec::new();
+            for file in files {
+                if bgzip && vcf::can_bgzip(&file)? {
+                    encoded.push(file);
+                } else {
+                    others.push(file);
+                }
+            }
+            let mut files = vcf::index::pair_indexes(&others, build_index, index_format)?;
+            if encoding.encrypts() {
+                // Encrypted files and their indexes are all encoded, so that nothing is sent in plain text
+                encoded.append(&mut files);
+            }
+            // Logic to compute the statistics uploaded next to the VCF files, which take up storage too
+            let stats_files = vcf::stats::compute_stats(&vcfs, &encoding).await?;
+            if project.is_none() {
+                // Logic to check that the files fit in the quota of the user space
+                let total = networking::util::get_file_sizes(&files)?
+                    .iter()
+                    .sum::<u64>()
+                    + networking::util::get_file_sizes(&encoded)?
+                        .iter()
+                        .sum::<u64>()
+                    + stats_files
+                        .iter()
+                        .filter_map(|stats| stats.json.as_ref().ok())
+                        .map(|json| json.len() as u64)
+                        .sum::<u64>();
+                networking::quota::check_upload_quota(&api, total, &id_token).await?;
+            }
+            if !files.is_empty() {
+                let upload_urls =
+                    uploads::get_upload_urls(&api, project.as_deref(), &files, &id_token).await?;
+                let uploaded = uploads::upload_files(upload_urls, &files, keep_going).await?;
+                report.results.extend(uploaded.results);
+            }
+            // Logic to compress and/or encrypt files while uploading them
+            uploads::upload_encoded_files(
+                &api,
+                project.as_deref(),
+                &encoded,
+                &id_token,
+                &encoding,
+                keep_going,
+                &mut report,
+            )
+            .await?;
+            // Logic to upload the statistics of the VCF files next to them
+            vcf::stats::upload_stats(
+                &api,
+                project.as_deref(),
+                stats_files,
+                &id_token,
+                keep_going,
+                &mut report,
+            )
+            .await;
+            report.print_summary();
+            return Ok(report.exit_code());
+        }
+        #[cfg(feature = "uploads")]
+        args::Command::Put {
+            source,
+            name,
+            project,
+            encrypt,
+            no_encrypt,
+        } => {
+            let store = encryption::KeyStore::open()?;
+            let id_token = login_or_fail().await?;
+            let recipients = if no_encrypt {
+                Vec::new()
+            } else {
+                let published = encryption::published_recipients(
+                    &store,
+                    &api,
+                    project.as_deref(),
+                    &id_token,
+                    encrypt,
+                )
+                .await?;
+                store.upload_recipients(project.as_deref(), &published, encrypt)?
+            };
+            let name = match name {
+                Some(name) => name,
+                None => networking::util::get_file_name_from_path(&source)?,
+            };
+            if !recipients.is_empty() {
+                // Logic to encrypt the upload while streaming it
+                let encoding = uploads::StreamEncoding {
+                    recipients,
+                    ..Default::default()
+                };
+                uploads::upload_encoded(
+                    &api,
+                    project.as_deref(),
+                    &source,
+                    &name,
+                    &id_token,
+                    &encoding,
+                )
+                .await?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            if Path::new(&source).is_file() {
+                // Logic to upload a regular file, whose size is known in advance
+                if project.is_none() {
+                    let size = networking::util::get_file_sizes(&[&source])?[0];
+                    networking::quota::check_upload_quota(&api, size, &id_token).await?;
+                }
+                let upload_urls = uploads::get_named_upload_urls(
+                    &api,
+                    project.as_deref(),
+                    std::slice::from_ref(&source),
+                    std::slice::from_ref(&name),
+                    &id_token,
+                )
+                .await?;
+                let report = uploads::upload_files(upload_urls, &[source], false).await?;
+                return Ok(report.exit_code());
+            }
+            // Logic to upload standard input or a named pipe through a temporary file
+            if source == "-" {
+                uploads::upload_stream(
+                    &api,
+                    project.as_deref(),
+                    &name,
+                    &id_token,
+                    tokio::io::stdin(),
+                )
+                .await?;
+            } else {
+                let file = tokio::fs::File::open(&source).await?;
+                uploads::upload_stream(&api, project.as_deref(), &name, &id_token, file).await?;
+            }
+        }
+        args::Command::Keys { command } => {
+            let store = encryption::KeyStore::open()?;
+            match command {
+                args::KeysCommand::Generate { force } => {
+                    let recipient = store.generate(force)?;
+                    println!("Public key: {}", recipient);
+                    println!("Share it with collaborators so that they can encrypt files for you.");
+                }
+                args::KeysCommand::Show {} => {
+                    println!("{}", store.public_key()?);
+                }
+                args::KeysCommand::List { project } => {
+                    let recipients = store.recipients()?;
+                    for (i, recipient) in recipients.get(project.as_deref()).iter().enumerate() {
+                        println!("Recipient {:>4}: {}", i + 1, recipient);
+                    }
+                    let pinned = project.and_then(|project| recipients.pinned.get(&project));
+                    for (i, recipient) in pinned.into_iter().flatten().enumerate() {
+                        println!("Published {:>4}: {}", i + 1, recipient);
+                    }
+                }
+                args::KeysCommand::Add { recipient, project } => {
+                    let mut recipients = store.recipients()?;
+                    if recipients.add(project.as_deref(), &recipient)? {
+                        store.save_recipients(&recipients)?;
+                    } else {
+                        println!("{} is already a recipient.", recipient);
+                    }
+                }
+                args::KeysCommand::Remove { recipient, project } => {
+                    let mut recipients = store.recipients()?;
+                    if !recipients.remove(project.as_deref(), &recipient) {
+                        return Err(anyhow!("{} is not a recipient.", recipient));
+                    }
+                    store.save_recipients(&recipients)?;
+                }
+                #[cfg(feature = "uploads")]
+                args::KeysCommand::Publish { project } => {
+                    let id_token = login_or_fail().await?;
+                    // Logic to add the local recipients and own key to those already published
+                    let mut published =
+                        encryption::project_recipients(&api, Some(&project), &id_token).await?;
+                    let local = store.recipients()?;
+                    let own = store
+                        .has_identity()
+                        .then(|| store.public_key().map(|key| key.to_string()))
+                        .transpose()?;
+                    for recipient in local.get(Some(&project)).iter().chain(own.as_ref()) {
+                        if !published.contains(recipient) {
+                            published.push(recipient.clone());
+                        }
+                    }
+                    if published.is_empty() {
+                        return Err(anyhow!(
+                            "No recipients to publish. Run `gaspifs keys generate` or `gaspifs keys add --project {}` first.",
+                            project
+                        ));
+                    }
+                    let text = format!(
+                        "# Recipients that uploads to {} are encrypted to\n{}\n",
+                        project,
+                        published.join("\n")
+                    );
+                    uploads::upload_stream(
+                        &api,
+                        Some(&project),
+                        encryption::PROJECT_RECIPIENTS_FILE,
+                        &id_token,
+                        text.as_bytes(),
+                    )
+                    .await?;
+                    store.pin_published(&project, &published, true)?;
+                    println!(
+                        "Published {} recipient(s) to project {}.",
+                        published.len(),
+                        project
+                    );
+                }
+                #[cfg(feature = "uploads")]
+                args::KeysCommand::Use { project, yes } => {
+                    let id_token = login_or_fail().await?;
+                    let published =
+                        encryption::project_recipients(&api, Some(&project), &id_token).await?;
+                    if published.is_empty() {
+                        return Err(anyhow!(
+                            "Project {} does not publish any recipients in {}.",
+                            project,
+                            encryption::PROJECT_RECIPIENTS_FILE
+                        ));
+                    }
+                    let local = store.recipients()?;
+                    let (added, removed) = local.pinned_changes(&project, &published);
+                    for recipient in &published {
+                        let marker = if added.contains(&recipient) { "+" } else { " " };
+                        println!("{} {}", marker, recipient);
+                    }
+                    for recipient in &removed {
+                        println!("- {}", recipient);
+                    }
+                    let changed = local.pinned.contains_key(&project)
+                        && !(added.is_empty() && removed.is_empty());
+                    if changed && !encryption::confirm_pin(&project, yes)? {
+                        println!("Kept the pinned recipients.");
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                    store.pin_published(&project, &published, true)?;
+                    println!(
+                        "Uploads to project {} are encrypted to these {} recipient(s).",
+                        project,
+                        published.len()
+                    );
+                }
+            }
+        }
+        args::Command::Beacon { command } => match command {
+            args::BeaconCommand::Query { query, output } => {
+                let no_descendants = query.no_descendants;
+                let mut request = query.into_request();
+                // check the request before logging in
+                request.body()?;
+                let id_token = login_or_fail().await?;
+                // Logic to replace term labels with their ids
+                beacon::terms::resolve_filters(&api, &id_token, &mut request).await?;
+                if no_descendants {
+                    request.exclude_descendants();
+                }
+                let body = request.body()?;
+                // Logic to query the Beacon
+                let response = api::post(&api, &request.endpoint(), &body, &id_token).await?;
+                print!("{}", beacon::query::format_response(&response, output)?);
+            }
+            args::BeaconCommand::Terms {
+                scope,
+                projects,
+                search,
+                refresh,
+                output,
+            } => {
+                let id_token = login_or_fail().await?;
+                // Logic to list filtering terms, from the cache when possible
+                let terms =
+                    beacon::terms::load_terms(&api, &id_token, scope, &projects, refresh).await?;
+                let terms = match search {
+                    Some(query) => beacon::terms::search(&terms, &query),
+                    None => terms.iter().collect(),
+                };
+                print!("{}", beacon::terms::format_terms(&terms, output)?);
+            }
+            args::BeaconCommand::Lookup {
+                vcf,
+                projects,
+                output,
+                format,
+                assembly,
+                batch_size,
+                concurrency,
+                rate,
+            } => {
+                let id_token = login_or_fail().await?;
+                // Logic to look up the variants of a VCF file
+                let session = api::Session::new(&api, id_token);
+                let format =
+                    format.unwrap_or_else(|| beacon::lookup::LookupFormat::for_path(&output));
+                let options = beacon::lookup::LookupOptions {
+                    projects,
+                    assembly,
+                    batch_size,
+                    concurrency,
+                    rate,
+                };
+                let summary =
+                    beacon::lookup::lookup_vcf(&session, &vcf, &output, format, &options).await?;
+                eprintln!(
+                    "Looked up {} records: {} of {} alleles found",
+                    summary.records, summary.found, summary.queried
+                );
+            }
+        },
+        args::Command::Queries { command } => {
+            let id_token = login_or_fail().await?;
+            match command {
+                args::QueriesCommand::List { output } => {
+                    let queries = beacon::saved::list_queries(&api, &id_token).await?;
+                    print!("{}", beacon::saved::format_queries(&queries, output)?);
+                }
+                args::QueriesCommand::Show { name, output } => {
+                    let query = beacon::saved::find_query(&api, &name, &id_token).await?;
+                    print!("{}", beacon::saved::format_query(&query, output)?);
+                }
+                args::QueriesCommand::Save {
+                    name,
+                    description,
+                    query,
+                } => {
+                    let no_descendants = query.no_descendants;
+                    let mut request = query.into_request();
+                    request.body()?;
+                    // store term ids, so that the portal can load the query
+                    beacon::terms::resolve_filters(&api, &id_token, &mut request).await?;
+                    if no_descendants {
+                        request.exclude_descendants();
+                    }
+                    beacon::saved::save_query(&api, &name, &description, &request, &id_token)
+                        .await?;
+                    println!("Saved query {}", name);
+                }
+                args::QueriesCommand::Run {
+                    name,
+                    granularity,
+                    skip,
+                    limit,
+                    output,
+                } => {
+                    let query = beacon::saved::find_query(&api, &name, &id_token).await?;
+                    let mut request = query.to_request()?;
+                    request.granularity = granularity.unwrap_or(request.granularity);
+                    request.skip = skip.unwrap_or(request.skip);
+                    request.limit = limit.unwrap_or(request.limit);
+                    // Logic to query the Beacon
+                    let response =
+                        api::post(&api, &request.endpoint(), &request.body()?, &id_token).await?;
+                    print!("{}", beacon::query::format_response(&response, output)?);
+                }
+                args::QueriesCommand::Delete { name } => {
+                    beacon::saved::delete_query(&api, &name, &id_token).await?;
+                    println!("Deleted query {}", name);
+                }
+            }
+        }
+        args::Command::Clinic { command } => match command {
+            args::ClinicCommand::Submit {
+                project,
+                vcf,
+                job_name,
+                workflow,
+                missing_to_ref,
+            } => {
+                if let Some(workflow) = workflow {
+                    workflow.check(&clinic::clinic_mode())?;
+                }
+                let clinic_api = clinic::clinic_api()?;
+                let bucket = clinic::submit::data_portal_bucket()?;
+                let job_name = job_name.unwrap_or_else(|| clinic::submit::default_job_name(&vcf));
+                clinic::submit::validate_job_name(&job_name)?;
+                let id_token = login_or_fail().await?;
+                // Logic to upload a local VCF file before submitting it
+                let names = clinic::submit::prepare_inputs(
+                    &api,
+                    &project,
+                    std::slice::from_ref(&vcf),
+                    &id_token,
+                )
+                .await?;
+                let user_id = auth::identity_id(&id_token).await?;
+                let location = clinic::submit::project_file_location(&bucket, &project, &names[0]);
+                let job_id = clinic::submit::submit_job(
+                    &clinic_api,
+                    &location,
+                    &project,
+                    &user_id,
+                    &job_name,
+                    missing_to_ref,
+                    &id_token,
+                )
+                .await?;
+                let job_id = match job_id {
+                    Some(job_id) => Some(job_id),
+                    None => clinic::submit::find_job_ids(
+                        &api,
+                        &project,
+                        std::slice::from_ref(&job_name),
+                        &id_token,
+                    )
+                    .await?
+                    .remove(0),
+                };
+                eprintln!("Submitted job {:?} for {}", job_name, names[0]);
+                println!("{}", job_id.as_deref().unwrap_or("-"));
+            }
+            args::ClinicCommand::BatchSubmit {
+                sample_sheet,
+                project,
+                workflow,
+                missing_to_ref,
+            } => {
+                if let Some(workflow) = workflow {
+                    workflow.check(&clinic::clinic_mode())?;
+                }
+                let clinic_api = clinic::clinic_api()?;
+                let rows = clinic::submit::read_sample_sheet(&sample_sheet)?;
+                // group the rows by project, keeping their order
+                let mut batches: Vec<(String, Vec<(String, String)>)> = Vec::new();
+                for (index, row) in rows.into_iter().enumerate() {
+                    let row_project = row.project.or(project.clone()).ok_or(anyhow!(
+                        "Sample sheet row {} has no project; pass --project.",
+                        index + 1
+                    ))?;
+                    let job_name = row
+                        .job_name
+                        .unwrap_or_else(|| clinic::submit::default_job_name(&row.vcf));
+                    clinic::submit::validate_job_name(&job_name)?;
+                    let position = match batches.iter().position(|(name, _)| *name == row_project) {
+                        Some(position) => position,
+                        None => {
+                            batches.push((row_project, Vec::new()));
+                            batches.len() - 1
+                        }
+                    };
+                    let (batch_project, jobs) = &mut batches[position];
+                    if jobs.iter().any(|(_, name)| *name == job_name) {
+                        return Err(anyhow!(
+                            "Duplicate job name {:?} in project {}.",
+                            job_name,
+                            batch_project
+                        ));
+                    }
+                    jobs.push((row.vcf, job_name));
+                }
+                let id_token = login_or_fail().await?;
+                let user_id = auth::identity_id(&id_token).await?;
+                for (project, jobs) in batches {
+                    // Logic to upload local VCF files before submitting them
+                    let vcfs: Vec<String> = jobs.iter().map(|(vcf, _)| vcf.clone()).collect();
+                    let names =
+                        clinic::submit::prepare_inputs(&api, &project, &vcfs, &id_token).await?;
+                    let jobs: Vec<(String, String)> = names
+                        .into_iter()
+                        .zip(jobs.into_iter().map(|(_, job_name)| job_name))
+                        .collect();
+                    let job_ids = clinic::submit::batch_submit(
+                        &clinic_api,
+                        &project,
+                        &jobs,
+                        &user_id,
+                        missing_to_ref,
+                        &id_token,
+                    )
+                    .await?;
+                    eprintln!("Submitted {} jobs in {}", jobs.len(), project);
+                    // Logic to look up the IDs missing from the responses with a single listing
+                    let missing: Vec<String> = jobs
+                        .iter()
+                        .zip(&job_ids)
+                        .filter(|(_, job_id)| job_id.is_none())
+                        .map(|((_, job_name), _)| job_name.clone())
+                        .collect();
+                    let mut found =
+                        clinic::submit::find_job_ids(&api, &project, &missing, &id_token)
+                            .await?
+                            .into_iter();
+                    for ((file, job_name), job_id) in jobs.iter().zip(job_ids) {
+                        let job_id = job_id.or_else(|| found.next().flatten());
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            job_id.as_deref().unwrap_or("-"),
+                            project,
+                            job_name,
+                            file
+                        );
+                    }
+                }
+            }
+            args::ClinicCommand::Jobs { command } => {
+                let id_token = login_or_fail().await?;
+                match command {
+                    args::ClinicJobsCommand::List {
+                        project,
+                        status,
+                        search,
+                        limit,
+                        output,
+                    } => {
+                        let filter = clinic::jobs::JobFilter { status, search };
+                        let jobs =
+                            clinic::jobs::list_jobs(&api, &project, &filter, limit, &id_token)
+                                .await?;
+                        print!("{}", clinic::jobs::format_jobs(&jobs, output)?);
+                    }
+                    args::ClinicJobsCommand::Show {
+                        job_id,
+                        project,
+                        output,
+                    } => {
+                        let job = clinic::jobs::get_job(&api, &project, &job_id, &id_token).await?;
+                        print!("{}", clinic::jobs::format_job(&job, output)?);
+                    }
+                }
+            }
+            args::ClinicCommand::Watch {
+                job_id,
+                project,
+                interval,
+                max_interval,
+                timeout,
+            } => {
+                let id_token = login_or_fail().await?;
+                // Logic to wait for the job, exiting with its outcome
+                let session = api::Session::new(&api, id_token);
+                let job = clinic::jobs::watch_job(
+                    &session,
+                    &project,
+                    &job_id,
+                    Duration::from_secs(interval),
+                    Duration::from_secs(max_interval.max(interval)),
+                    timeout.map(Duration::from_secs),
+                )
+                .await?;
+                if let Some(failure) = job.failure() {
+                    eprintln!("{}", failure);
+                }
+                return Ok(match job.status() {
+                    clinic::jobs::JobStatus::Completed => ExitCode::SUCCESS,
+                    clinic::jobs::JobStatus::Failed => ExitCode::from(2),
+                    clinic::jobs::JobStatus::Expired => ExitCode::from(4),
+                    clinic::jobs::JobStatus::Pending => ExitCode::from(5),
+                });
+            }
+            args::ClinicCommand::Results {
+                job_id,
+                project,
+                format,
+                output,
+                gene,
+                consequence,
+                max_af,
+                mut filters,
+                pipeline,
+            } => {
+                if format == clinic::results::ResultFormat::Parquet && output.is_none() {
+                    return Err(anyhow!("Parquet results need a file; pass --output."));
+                }
+                let clinic_api = clinic::clinic_api()?;
+                filters.extend(clinic::results::preset_filters(
+                    gene.as_deref(),
+                    consequence.as_deref(),
+                    max_af,
+                ));
+                let id_token = login_or_fail().await?;
+                // PGxFlow portals keep the allele lookup apart from the PharmCAT report
+                let pipeline = pipeline.or((clinic::clinic_mode() == "pgxflow")
+                    .then_some(clinic::results::Pipeline::Lookup));
+                // Logic to write the rows as the pages arrive, removing a partial file on failure
+                let writer = clinic::results::ResultWriter::create(format, output.as_deref())?;
+                let written = clinic::results::fetch_results(
+                    &clinic_api,
+                    &project,
+                    &job_id,
+                    pipeline,
+                    &filters,
+                    &id_token,
+                    writer,
+                )
+                .await;
+                let written = match written {
+                    Ok(written) => written,
+                    Err(err) => {
+                        if let Some(output) = &output {
+                            let _ = std::fs::remove_file(output);
+                        }
+                        return Err(err);
+                    }
+                };
+                if let Some(output) = output {
+                    eprintln!("Wrote {} rows to {}", written, output);
+                }
+            }
+            args::ClinicCommand::Annotate {
+                job_id,
+                project,
+                annotation,
+                variants,
+                columns,
+            } => {
+                let variants = clinic::curation::read_variants(&variants, &columns)?;
+                let id_token = login_or_fail().await?;
+                clinic::curation::save_annotation(
+                    &api,
+                    &project,
+                    &job_id,
+                    &annotation,
+                    &variants,
+                    &id_token,
+                )
+                .await?;
+                println!("Annotated {} variants of job {}", variants.len(), job_id);
+            }
+            args::ClinicCommand::Variants { command } => {
+                // read the selection before logging in, so that a bad file fails fast
+                let selection = match &command {
+                    args::ClinicVariantsCommand::Save {
+                        variants, columns, ..
+                    } => clinic::curation::read_variants(variants, columns)?,
+                    _ => Vec::new(),
+                };
+                let id_token = login_or_fail().await?;
+                match command {
+                    args::ClinicVariantsCommand::Save {
+                        job_id,
+                        project,
+                        comment,
+                        ..
+                    } => {
+                        clinic::curation::save_variants(
+                            &api, &project, &job_id, &comment, &selection, &id_token,
+                        )
+                        .await?;
+                        println!(
+                            "Saved {} variants of job {} for reporting",
+                            selection.len(),
+                            job_id
+                        );
+                    }
+                    args::ClinicVariantsCommand::List {
+                        job_id,
+                        project,
+                        output,
+                    } => {
+                        let collections = clinic::curation::list_saved_variants(
+                            &api, &project, &job_id, &id_token,
+                        )
+                        .await?;
+                        print!(
+                            "{}",
+                            clinic::curation::format_saved_variants(&collections, output)?
+                        );
+                    }
+                    args::ClinicVariantsCommand::Show {
+                        job_id,
+                        name,
+                        project,
+                        output,
+                    } => {
+                        let collection = clinic::curation::find_saved_variants(
+                            &api, &project, &job_id, &name, &id_token,
+                        )
+                        .await?;
+                        print!(
+                            "{}",
+                            clinic::curation::format_collection(&collection, output)?
+                        );
+                    }
+                    args::ClinicVariantsCommand::Delete {
+                        job_id,
+                        name,
+                        project,
+                    } => {
+                        clinic::curation::delete_saved_variants(
+                            &api, &project, &job_id, &name, &id_token,
+                        )
+                        .await?;
+                        println!("Deleted saved variants {}", name);
+                    }
+                }
+            }
+            args::ClinicCommand::Validate { command } => {
+                let id_token = login_or_fail().await?;
+                match command {
+                    args::ClinicValidateCommand::Pending {
+                        project,
+                        job_id,
+                        limit,
+                        output,
+                    } => {
+                        let jobs = match job_id {
+                            Some(job_id) => vec![
+                                clinic::jobs::get_job(&api, &project, &job_id, &id_token).await?,
+                            ],
+                            None => {
+                                let filter = clinic::jobs::JobFilter {
+                                    status: Some(clinic::jobs::JobStatus::Completed),
+                                    search: None,
+                                };
+                                clinic::jobs::list_jobs(&api, &project, &filter, limit, &id_token)
+                                    .await?
+                            }
+                        };
+                        let mut pending = Vec::new();
+                        for job in jobs {
+                            let collections = clinic::curation::list_saved_variants(
+                                &api,
+                                &project,
+                                &job.job_id,
+                                &id_token,
+                            )
+                            .await?;
+                            pending.extend(clinic::validation::pending_validations(
+                                &job,
+                                &collections,
+                            ));
+                        }
+                        print!("{}", clinic::validation::format_pending(&pending, output)?);
+                    }
+                    args::ClinicValidateCommand::Approve {
+                        job_id,
+                        name,
+                        project,
+                        comment,
+                        yes,
+                    } => {
+                        let signer = clinic::validation::Signer::from_token(&id_token)?;
+                        signer.check()?;
+                        let action = match &name {
+                            Some(name) => {
+                                format!("Validation of saved variants {} of job {}", name, job_id)
+                            }
+                            None => format!("Validation of the negative report of job {}", job_id),
+                        };
+                        if !clinic::validation::confirm_signature(&signer, &action, yes)? {
+                            eprintln!("Nothing was signed.");
+                            return Ok(ExitCode::FAILURE);
+                        }
+                        clinic::validation::sign_validation(
+                            &api,
+                            &project,
+                            &job_id,
+                            name.as_deref(),
+                            &comment,
+                            &id_token,
+                        )
+                        .await?;
+                        println!("{} recorded", action);
+                    }
+                    args::ClinicValidateCommand::Revoke {
+                        job_id,
+                        name,
+                        project,
+                        yes,
+                    } => {
+                        let signer = clinic::validation::Signer::from_token(&id_token)?;
+                        signer.check()?;
+                        let action = match &name {
+                            Some(name) => format!(
+                                "Revocation of the validation of saved variants {} of job {}",
+                                name, job_id
+                            ),
+                            None => format!(
+                                "Revocation of the validation of the negative report of job {}",
+                                job_id
+                            ),
+                        };
+                        if !clinic::validation::confirm_signature(&signer, &action, yes)? {
+                            eprintln!("Nothing was signed.");
+                            return Ok(ExitCode::FAILURE);
+                        }
+                        clinic::validation::revoke_validation(
+                            &api,
+                            &project,
+                            &job_id,
+                            name.as_deref(),
+                            &id_token,
+                        )
+                        .await?;
+                        println!("{} recorded", action);
+                    }
+                    args::ClinicValidateCommand::History {
+                        job_id,
+                        project,
+                        output,
+                    } => {
+                        let (job, collections) = clinic::validation::job_with_collections(
+                            &api, &project, &job_id, &id_token,
+                        )
+                        .await?;
+                        let history = clinic::validation::validation_history(&job, &collections);
+                        print!("{}", clinic::validation::format_history(&history, output)?);
+                    }
+                }
+            }
+            args::ClinicCommand::Report {
+                job_ids,
+                project,
+                jobs,
+                output,
+                destination,
+                lang,
+                template,
+                keep_going,
+            } => {
+                let lab = clinic::report::hub_name()?;
+                let jobs = clinic::jobs::select_jobs(job_ids, project.as_deref(), jobs.as_deref())?;
+                if output.is_some() && jobs.len() > 1 {
+                    return Err(anyhow!(
+                        "--output saves a single report; use --destination for several jobs."
+                    ));
+                }
+                let options = clinic::report::ReportOptions { lang, template };
+                let id_token = login_or_fail().await?;
+                let report = clinic::report::download_reports(
+                    &api,
+                    &jobs,
+                    output.as_deref().map(Path::new),
+                    Path::new(&destination),
+                    &lab,
+                    &options,
+                    &id_token,
+                    keep_going,
+                )
+                .await;
+                report.print_summary();
+                return Ok(report.exit_code());
+            }
+            args::ClinicCommand::Qc {
+                job_ids,
+                project,
+                jobs,
+                output,
+            } => {
+                let clinic_api = clinic::clinic_api()?;
+                let jobs = clinic::jobs::select_jobs(job_ids, project.as_deref(), jobs.as_deref())?;
+                let id_token = login_or_fail().await?;
+                let summaries =
+                    clinic::qc::fetch_summaries(&api, &clinic_api, &jobs, &id_token).await?;
+                print!("{}", clinic::qc::format_summaries(&summaries, output)?);
+            }
+            args::ClinicCommand::QcNote { command } => {
+                let clinic_api = clinic::clinic_api()?;
+                let id_token = login_or_fail().await?;
+                match command {
+                    args::ClinicQcNoteCommand::Add {
+                        job_id,
+                        project,
+                        title,
+                        description,
+                    } => {
+                        let description = if description == "-" {
+                            std::io::read_to_string(std::io::stdin())?
+                        } else {
+                            description
+                        };
+                        let job = clinic::jobs::get_job(&api, &project, &job_id, &id_token).await?;
+                        let note = clinic::qc::QcNote::from_token(
+                            &id_token,
+                            &title,
+                            description.trim_end(),
+                        )?;
+                        clinic::qc::add_note(
+                            &clinic_api,
+                            &project,
+                            &job.input_vcf,
+                            &note,
+                            &id_token,
+                        )
+                        .await?;
+                        println!("Added note {} to the QC of {}", note.id, job.input_vcf);
+                    }
+                    args::ClinicQcNoteCommand::List {
+                        job_id,
+                        project,
+                        output,
+                    } => {
+                        let job = clinic::jobs::get_job(&api, &project, &job_id, &id_token).await?;
+                        let mut notes =
+                            clinic::qc::get_notes(&clinic_api, &project, &job.input_vcf, &id_token)
+                                .await?;
+                        notes.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+                        print!("{}", clinic::qc::format_notes(&notes, output)?);
+                    }
+                }
+            }
+        },
+        args::Command::Notebooks { command } => {
+            let id_token = login_or_fail().await?;
+            let waiting = match command {
+                args::NotebooksCommand::List { output } => {
+                    let names = notebooks::list_notebooks(&api, &id_token).await?;
+                    let notebooks = futures::future::try_join_all(
+                        names
+                            .iter()
+                            .map(|name| notebooks::get_notebook(&api, name, &id_token)),
+                    )
+                    .await?;
+                    print!("{}", notebooks::format_notebooks(&notebooks, output)?);
+                    None
+                }
+                args::NotebooksCommand::Create {
+                    name,
+                    instance_type,
+                    volume_size,
+                    wait,
+                } => {
+                    let identity_id = auth::identity_id(&id_token).await?;
+                    notebooks::create_notebook(
+                        &api,
+                        &name,
+                        &instance_type,
+                        volume_size,
+                        &identity_id,
+                        &id_token,
+                    )
+                    .await?;
+                    println!("Creating notebook {}", name);
+                    Some((name, notebooks::IN_SERVICE, wait))
+                }
+                args::NotebooksCommand::Start {
+                    name,
+                    instance_type,
+                    volume_size,
+                    wait,
+                } => {
+                    if instance_type.is_some() || volume_size.is_some() {
+                        notebooks::update_notebook(
+                            &api,
+                            &name,
+                            instance_type.as_deref(),
+                            volume_size,
+                            &id_token,
+                        )
+                        .await?;
+                    }
+                    notebooks::start_notebook(&api, &name, &id_token).await?;
+                    println!("Starting notebook {}", name);
+                    Some((name, notebooks::IN_SERVICE, wait))
+                }
+                args::NotebooksCommand::Stop { name, wait } => {
+                    notebooks::stop_notebook(&api, &name, &id_token).await?;
+                    println!("Stopping notebook {}", name);
+                    Some((name, notebooks::STOPPED, wait))
+                }
+                args::NotebooksCommand::Delete { name } => {
+                    notebooks::delete_notebook(&api, &name, &id_token).await?;
+                    println!("Deleted notebook {}", name);
+                    None
+                }
+                args::NotebooksCommand::Url { name } => {
+                    println!("{}", notebooks::notebook_url(&api, &name, &id_token).await?);
+                    None
+                }
+            };
+            if let Some((name, status, wait)) = waiting.filter(|(_, _, wait)| wait.wait) {
+                notebooks::wait_for_status(
+                    &api,
+                    &name,
+                    status,
+                    &id_token,
+                    Duration::from_secs(wait.interval),
+                    Duration::from_secs(wait.timeout),
+                )
+                .await?;
+            }
+        }
+        args::Command::Cohort { command } => match command {
+            args::CohortCommand::Create {
+                spec,
+                destination,
+                no_wait,
+                interval,
+                timeout,
+            } => {
+                let spec = cohort::read_spec(&spec)?;
+                let mut request = spec.to_request()?;
+                let id_token = login_or_fail().await?;
+                let available: Vec<String> = projects::list_projects(&api, &id_token)
+                    .await?
+                    .into_iter()
+                    .map(|project| project.name)
+                    .collect();
+                cohort::check_projects(&request.projects, &available)?;
+                beacon::terms::resolve_filters(&api, &id_token, &mut request).await?;
+                cohort::check_cohort_quota(&api, &spec.name, &request, &id_token).await?;
+                // Logic to submit the cohort and download its files
+                let existing = files::list_project_files(&api, None, &id_token).await?;
+                cohort::check_cohort_name(&spec.name, &existing)?;
+                cohort::submit_cohort(&api, &spec.name, &request, &id_token).await?;
+                println!("Submitted cohort {}", spec.name);
+                if no_wait {
+                    return Ok(ExitCode::SUCCESS);
+                }
+                let files = cohort::wait_for_cohort(
+                    &api,
+                    &spec.name,
+                    request.scope,
+                    &id_token,
+                    Duration::from_secs(interval),
+                    Duration::from_secs(timeout),
+                )
+                .await?;
+                std::fs::create_dir_all(&destination)
+                    .map_err(|_| anyhow!("Failed to create destination directory."))?;
+                let download_urls =
+                    downloads::get_download_urls(&api, None, &files, &id_token).await?;
+                let report = downloads::download_files(&download_urls, &destination, false).await?;
+                report.print_summary();
+                return Ok(report.exit_code());
+            }
+        },
+        #[cfg(feature = "admin")]
+        args::Command::Admin { command } => {
+            let specs = match &command {
+                args::AdminCommand::Projects {
+                    command:
+                        args::AdminProjectsCommand::Create {
+                            spec: Some(spec), ..
+                        }
+                        | args::AdminProjectsCommand::Update {
+                            spec: Some(spec), ..
+                        }
+                        | args::AdminProjectsCommand::Apply { spec },
+                } => admin::read_specs(spec)?,
+                _ => Vec::new(),
+            };
+            let id_token = login_or_fail().await?;
+            let args::AdminCommand::Projects { command } = command;
+            match command {
+                args::AdminProjectsCommand::Create {
+                    name, description, ..
+                } => {
+                    if let (Some(name), Some(description)) = (name, description) {
+                        admin::create_project(&api, &name, &description, &id_token).await?;
+                        println!("Created project {}", name);
+                    } else {
+                        admin::create_from_specs(&api, &specs, &id_token).await?;
+                    }
+                }
+                args::AdminProjectsCommand::Update {
+                    name,
+                    description,
+                    files,
+                    ..
+                } => {
+                    if let Some(name) = name {
+                        // Logic to keep the current description or files when they are not given
+                        let current = admin::get_project(&api, &name, &id_token).await?;
+                        admin::update_project(
+                            &api,
+                            &name,
+                            &description.unwrap_or(current.description),
+                            &files.unwrap_or(current.files),
+                            &id_token,
+                        )
+                        .await?;
+                        println!("Updated project {}", name);
+                    } else {
+                        admin::update_from_specs(&api, &specs, &id_token).await?;
+                    }
+                }
+                args::AdminProjectsCommand::Apply { .. } => {
+                    admin::apply_specs(&api, &specs, &id_token).await?;
+                }
+                args::AdminProjectsCommand::Delete { name, yes } => {
+                    if !admin::confirm_delete(&name, yes)? {
+                        eprintln!("Project {} was not deleted.", name);
+                        return Ok(ExitCode::FAILURE);
+                    }
+                    admin::delete_project(&api, &name, &id_token).await?;
+                    println!("Deleted project {}", name);
+                }
+                args::AdminProjectsCommand::Show {
+                    name,
+                    search,
+                    output,
+                } => {
+                    if let Some(name) = name {
+                        let project = admin::get_project(&api, &name, &id_token).await?;
+                        print!("{}", admin::format_project(&project, output)?);
+                    } else {
+                        let projects =
+                            admin::list_projects(&api, search.as_deref(), &id_token).await?;
+                        print!("{}", admin::format_projects(&projects, output)?);
+                    }
+                }
+                args::AdminProjectsCommand::Errors {
+                    name,
+                    clear,
+                    output,
+                } => {
+                    let project = admin::get_project(&api, &name, &id_token).await?;
+                    print!("{}", admin::format_errors(&project.error_messages, output)?);
+                    if clear && !project.error_messages.is_empty() {
+                        admin::clear_errors(&api, &name, &id_token).await?;
+                        eprintln!("Cleared the errors of project {}", name);
+                    }
+                }
+            }
         }
         args::Command::Login {} => {
             // Logic to login
-            let Ok((_access_token, _id_token, _refresh_token)) = login().await else {
-                eprintln!("Login failed. Please check your credentials.");
-                return Ok(());
-            };
+            login_or_fail().await?;
             println!(
                 "You are now authenticated. You can now use the CLI commands on terminal or Jupyter notebooks."
             );
@@ -116,14 +1426,16 @@ async fn run() -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(err) = run().await {
-        eprintln!("{err}");
-        return ExitCode::from(1);
+    match run().await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(1)
+        }
     }
-    ExitCode::SUCCESS
 }
@@ -0,0 +1,651 @@
+use crate::encryption::{ENCRYPTED_EXTENSION, decrypted_download_name};
+use crate::networking::checksum::Checksum;
+use crate::networking::downloads::{self, DownloadTarget};
+use crate::networking::paths::{local_path, relative_path};
+use crate::networking::report::{Outcome, TransferReport};
+#[cfg(feature = "uploads")]
+use crate::networking::uploads::{self, UploadTarget};
+use anyhow::{Result, anyhow};
+#[cfg(feature = "uploads")]
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Represents the file formats accepted for manifests.
+/// The format is chosen from the manifest file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    Tsv,
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    /// Determines the manifest format from a file extension (`.tsv`/`.txt`, `.csv` or `.json`).
+    /// # Arguments
+    /// * `path` - The path to the manifest.
+    /// # Returns
+    /// A `Result` containing the manifest format.
+    /// # Example
+    /// ```
+    /// assert_eq!(ManifestFormat::from_path("samples.tsv")?, ManifestFormat::Tsv);
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("tsv") | Some("txt") => Ok(ManifestFormat::Tsv),
+            Some("csv") => Ok(ManifestFormat::Csv),
+            Some("json") => Ok(ManifestFormat::Json),
+            _ => Err(anyhow!(
+                "Unsupported manifest {}, expected a .tsv, .csv or .json file.",
+                path.as_ref().display()
+            )),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ManifestFormat::Tsv => "tsv",
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Json => "json",
+        }
+    }
+
+    pub(crate) fn delimiter(&self) -> u8 {
+        match self {
+            ManifestFormat::Csv => b',',
+            _ => b'\t',
+        }
+    }
+}
+
+/// Represents a row of a transfer manifest.
+/// For downloads `source` is the remote file name and `destination` a local file or directory
+/// (a trailing `/` marks a directory); for uploads `source` is a local path and `destination`
+/// the remote file name. Columns other than these are ignored, so sample sheets can be used directly.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ManifestRow {
+    pub source: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub destination: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub checksum: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub project: Option<String>,
+}
+
+/// Deserializes an optional column, treating an empty value like a missing one.
+pub(crate) fn empty_as_none<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value.filter(|value| !value.is_empty()))
+}
+
+/// Represents a row of the result manifest written after a transfer.
+#[derive(Debug, serde::Serialize)]
+struct ManifestResult<'a> {
+    source: &'a str,
+    destination: &'a str,
+    project: &'a str,
+    checksum: &'a str,
+    status: &'a str,
+    reason: &'a str,
+}
+
+/// Reads the rows of a manifest, or of another table in the same formats such as a sample sheet.
+/// TSV and CSV files need a header row naming the columns; lines starting with `#` are ignored.
+/// JSON files are an array of objects with the same keys.
+/// # Arguments
+/// * `path` - The path to the file.
+/// * `kind` - What the file is, used in error messages, such as `Manifest`.
+/// # Returns
+/// A `Result` containing the format of the file and its rows.
+/// # Errors
+/// Returns an error if the file cannot be read, is not valid, or has no rows.
+/// # Example
+/// ```
+/// let (format, rows): (_, Vec<ManifestRow>) = read_rows("samples.tsv", "Manifest")?;
+/// ```
+pub fn read_rows<T, P>(path: P, kind: &str) -> Result<(ManifestFormat, Vec<T>)>
+where
+    T: serde::de::DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let format = ManifestFormat::from_path(path)?;
+    let rows: Vec<T> = match format {
+        ManifestFormat::Json => serde_json::from_reader(std::fs::File::open(path)?)?,
+        _ => csv::ReaderBuilder::new()
+            .delimiter(format.delimiter())
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .collect::<std::result::Result<_, _>>()?,
+    };
+    if rows.is_empty() {
+        return Err(anyhow!("{} {} has no rows.", kind, path.display()));
+    }
+    Ok((format, rows))
+}
+
+/// Reads a transfer manifest with `read_rows`.
+/// # Arguments
+/// * `path` - The path to the manifest.
+/// # Returns
+/// A `Result` containing the manifest format and its rows.
+/// # Errors
+/// Returns an error if the manifest cannot be read, is not valid, has no rows, or has a row without a source.
+/// # Example
+/// ```
+/// let (format, rows) = read_manifest("samples.tsv")?;
+/// for row in rows {
+///     println!("{} -> {:?}", row.source, row.destination);
+/// }
+/// ```
+pub fn read_manifest<P: AsRef<Path>>(path: P) -> Result<(ManifestFormat, Vec<ManifestRow>)> {
+    let (format, rows): (_, Vec<ManifestRow>) = read_rows(path, "Manifest")?;
+    if let Some(index) = rows.iter().position(|row| row.source.is_empty()) {
+        return Err(anyhow!("Manifest row {} has no source.", index + 1));
+    }
+    Ok((format, rows))
+}
+
+/// Returns the default path of the result manifest, `<manifest>.result.<ext>`, next to the manifest.
+/// # Example
+/// ```
+/// assert_eq!(result_manifest_path("run/samples.tsv", ManifestFormat::Tsv), PathBuf::from("run/samples.result.tsv"));
+/// ```
+pub fn result_manifest_path<P: AsRef<Path>>(path: P, format: ManifestFormat) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "manifest".to_string());
+    path.with_file_name(format!("{}.result.{}", stem, format.extension()))
+}
+
+/// Writes the outcome of each manifest row, in the same format as the manifest.
+/// # Arguments
+/// * `path` - The path of the result manifest.
+/// * `format` - The format to write.
+/// * `rows` - The manifest rows.
+/// * `report` - The transfer report, with one result per row in the same order.
+/// # Returns
+/// A `Result` indicating success or failure.
+/// # Example
+/// ```
+/// write_results("samples.result.tsv", ManifestFormat::Tsv, &rows, &report)?;
+/// ```
+pub fn write_results<P: AsRef<Path>>(
+    path: P,
+    format: ManifestFormat,
+    rows: &[ManifestRow],
+    report: &TransferReport,
+) -> Result<()> {
+    let results: Vec<ManifestResult> = rows
+        .iter()
+        .zip(&report.results)
+        .map(|(row, result)| {
+            let (status, reason) = match &result.outcome {
+                Outcome::Succeeded => ("succeeded", ""),
+                Outcome::Failed(reason) => ("failed", reason.as_str()),
+                Outcome::Skipped(reason) => ("skipped", reason.as_str()),
+            };
+            ManifestResult {
+                source: &row.source,
+                destination: row.destination.as_deref().unwrap_or_default(),
+                project: row.project.as_deref().unwrap_or_default(),
+                checksum: row.checksum.as_deref().unwrap_or_default(),
+                status,
+                reason,
+            }
+        })
+        .collect();
+
+    match format {
+        ManifestFormat::Json => {
+            let file = std::fs::File::create(path)?;
+            serde_json::to_writer_pretty(file, &results)?;
+        }
+        _ => {
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(format.delimiter())
+                .from_path(path)?;
+            for result in results {
+                writer.serialize(result)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads every file listed in a manifest.
+/// Rows are grouped by project so that a single manifest can mix projects; rows without a
+/// project use `default_project`. Relative destinations are resolved against `destination`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The authentication token.
+/// * `rows` - The manifest rows.
+/// * `default_project` - The project for rows that do not name one, if None, user space.
+/// * `destination` - The directory used for rows without a destination and for relative destinations.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// # Returns
+/// A `TransferReport` with one result per row, in the same order as `rows`.
+/// # Example
+/// ```
+/// let (_, rows) = read_manifest("samples.tsv")?;
+/// let report = download_manifest(&api, &token, &rows, None, Path::new("."), true).await?;
+/// ```
+pub async fn download_manifest(
+    api: &str,
+    token: &str,
+    rows: &[ManifestRow],
+    default_project: Option<&str>,
+    destination: &Path,
+    keep_going: bool,
+) -> Result<TransferReport> {
+    let mut targets: Vec<Option<Result<DownloadTarget>>> = rows.iter().map(|_| None).collect();
+
+    for (project, indices) in group_by_project(rows, default_project) {
+        let files: Vec<String> = indices.iter().map(|&i| rows[i].source.clone()).collect();
+        match downloads::get_download_urls(api, project.as_deref(), &files, token).await {
+            Ok(urls) if urls.len() == indices.len() => {
+                for (&index, url) in indices.iter().zip(urls) {
+                    targets[index] = Some(download_target(&rows[index], url, destination));
+                }
+            }
+            Ok(urls) => {
+                let message = format!(
+                    "expected {} download URLs, got {}",
+                    indices.len(),
+                    urls.len()
+                );
+                for &index in &indices {
+                    targets[index] = Some(Err(anyhow!(message.clone())));
+                }
+            }
+            Err(err) => {
+                let message = format!("{:#}", err);
+                for &index in &indices {
+                    targets[index] = Some(Err(anyhow!(message.clone())));
+                }
+            }
+        }
+    }
+
+    let targets = rows
+        .iter()
+        .zip(targets)
+        .map(|(row, target)| {
+            let target = target.unwrap_or_else(|| Err(anyhow!("no download URL")));
+            (row.source.clone(), target)
+        })
+        .collect();
+
+    Ok(downloads::download_targets(targets, keep_going).await)
+}
+
+/// Uploads every file listed in a manifest.
+/// Rows are grouped by project; rows without a project use `default_project`.
+/// Rows whose local file is missing or failed validation are reported as failed without affecting the other rows.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The authentication token.
+/// * `rows` - The manifest rows.
+/// * `invalid` - The sources that failed validation, with the reason, which are not uploaded.
+/// * `default_project` - The project for rows that do not name one, if None, user space.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// # Returns
+/// A `TransferReport` with one result per row, in the same order as `rows`.
+/// # Example
+/// ```
+/// let (_, rows) = read_manifest("samples.tsv")?;
+/// let report = upload_manifest(&api, &token, &rows, &BTreeMap::new(), Some("my_project"), true).await?;
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn upload_manifest(
+    api: &str,
+    token: &str,
+    rows: &[ManifestRow],
+    invalid: &BTreeMap<String, String>,
+    default_project: Option<&str>,
+    keep_going: bool,
+) -> Result<TransferReport> {
+    let mut targets: Vec<Option<Result<UploadTarget>>> = rows.iter().map(|_| None).collect();
+
+    for (project, indices) in group_by_project(rows, default_project) {
+        let mut files = Vec::new();
+        let mut names = Vec::new();
+        let mut ready = Vec::new();
+        for &index in &indices {
+            let row = &rows[index];
+            if let Some(reason) = invalid.get(&row.source) {
+                targets[index] = Some(Err(anyhow!(reason.clone())));
+                continue;
+            }
+            let checksum = row.checksum.as_deref().map(str::parse).transpose();
+            let name = match &row.destination {
+                Some(name) => Ok(name.clone()),
+                None => crate::networking::util::get_file_name_from_path(&row.source),
+            };
+            match (Path::new(&row.source).is_file(), checksum, name) {
+                (false, _, _) => {
+                    targets[index] = Some(Err(anyhow!("{} is not a file", row.source)));
+                }
+                (_, Err(err), _) | (_, _, Err(err)) => targets[index] = Some(Err(err)),
+                (true, Ok(checksum), Ok(name)) => {
+                    files.push(row.source.clone());
+                    names.push(name);
+                    ready.push((index, checksum));
+                }
+            }
+        }
+        if ready.is_empty() {
+            continue;
+        }
+
+        match uploads::get_named_upload_urls(api, project.as_deref(), &files, &names, token).await {
+            Ok(urls) if urls.len() == ready.len() => {
+                for ((index, checksum), url) in ready.into_iter().zip(urls) {
+                    targets[index] = Some(Ok(UploadTarget {
+                        url,
+                        path: rows[index].source.clone(),
+                        checksum,
+                    }));
+                }
+            }
+            Ok(urls) => {
+                let message = format!("expected {} upload URLs, got {}", ready.len(), urls.len());
+                for (index, _) in ready {
+                    targets[index] = Some(Err(anyhow!(message.clone())));
+                }
+            }
+            Err(err) => {
+                let message = format!("{:#}", err);
+                for (index, _) in ready {
+                    targets[index] = Some(Err(anyhow!(message.clone())));
+                }
+            }
+        }
+    }
+
+    let targets = rows
+        .iter()
+        .zip(targets)
+        .map(|(row, target)| {
+            let target = target.unwrap_or_else(|| Err(anyhow!("no upload URL")));
+            (row.source.clone(), target)
+        })
+        .collect();
+
+    Ok(uploads::upload_targets(targets, keep_going).await)
+}
+
+/// Groups row indices by project, keeping the order in which projects first appear.
+fn group_by_project(
+    rows: &[ManifestRow],
+    default_project: Option<&str>,
+) -> Vec<(Option<String>, Vec<usize>)> {
+    let mut groups: Vec<(Option<String>, Vec<usize>)> = Vec::new();
+    for (index, row) in rows.iter().enumerate() {
+        let project = row
+            .project
+            .clone()
+            .or_else(|| default_project.map(str::to_string));
+        match groups.iter_mut().find(|(key, _)| *key == project) {
+            Some((_, indices)) => indices.push(index),
+            None => groups.push((project, vec![index])),
+        }
+    }
+    groups
+}
+
+/// Resolves where a manifest row is downloaded to.
+/// Without a destination the file goes into `base`; a destination ending with `/` or naming an
+/// existing directory receives the file under its remote name; anything else is the file path.
+/// Encrypted files are decrypted when an identity exists, unless the destination keeps the `.age` suffix.
+/// Destinations that are absolute or contain `..` are rejected, so files always stay inside `base`.
+fn download_target(row: &ManifestRow, url: String, base: &Path) -> Result<DownloadTarget> {
+    let checksum = row
+        .checksum
+        .as_deref()
+        .map(str::parse::<Checksum>)
+        .transpose()?;
+    let remote_name = row.source.rsplit('/').next().unwrap_or(&row.source);
+    let decrypted = decrypted_download_name(remote_name);
+    let local_name = decrypted.unwrap_or(remote_name);
+
+    let path = match row.destination.as_deref() {
+        None => local_path(base, local_name)?,
+        Some(destination) => {
+            let path = relative_path(base, destination)?;
+            if destination.ends_with('/') || path.is_dir() {
+                std::fs::create_dir_all(&path)?;
+                local_path(&path, local_name)?
+            } else {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                path
+            }
+        }
+    };
+
+    let decrypt = decrypted.is_some() && !path.to_string_lossy().ends_with(ENCRYPTED_EXTENSION);
+    Ok(DownloadTarget {
+        url,
+        path,
+        checksum,
+        decrypt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn manifest(suffix: &str, content: &str) -> NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        write!(file, "{}", content).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_manifest_tsv() {
+        let file = manifest(
+            ".tsv",
+            "# sample sheet\nsample\tsource\tdestination\tproject\nS1\ta.vcf.gz\tout/\tp1\nS2\tb.vcf.gz\t\t\n",
+        );
+        let (format, rows) = read_manifest(file.path()).unwrap();
+        assert_eq!(format, ManifestFormat::Tsv);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].destination.as_deref(), Some("out/"));
+        assert_eq!(rows[0].project.as_deref(), Some("p1"));
+        assert_eq!(rows[1].destination, None);
+        assert_eq!(rows[1].project, None);
+    }
+
+    #[test]
+    fn test_read_manifest_csv_and_json() {
+        let file = manifest(
+            ".csv",
+            "source,checksum\na.vcf.gz,md5:d41d8cd98f00b204e9800998ecf8427e\n",
+        );
+        let (_, rows) = read_manifest(file.path()).unwrap();
+        assert_eq!(
+            rows[0].checksum.as_deref(),
+            Some("md5:d41d8cd98f00b204e9800998ecf8427e")
+        );
+
+        let file = manifest(
+            ".json",
+            r#"[{"source": "a.vcf.gz", "project": "p1", "destination": ""}]"#,
+        );
+        let (format, rows) = read_manifest(file.path()).unwrap();
+        assert_eq!(format, ManifestFormat::Json);
+        assert_eq!(rows[0].project.as_deref(), Some("p1"));
+        assert_eq!(rows[0].destination, None);
+    }
+
+    #[test]
+    fn test_read_manifest_errors() {
+        assert!(read_manifest(manifest(".tsv", "source\n").path()).is_err());
+        assert!(read_manifest(manifest(".tsv", "destination\nout/\n").path()).is_err());
+        assert!(read_manifest(manifest(".xlsx", "source\na\n").path()).is_err());
+    }
+
+    #[test]
+    fn test_result_manifest_path() {
+        assert_eq!(
+            result_manifest_path("run/samples.tsv", ManifestFormat::Tsv),
+            PathBuf::from("run/samples.result.tsv")
+        );
+    }
+
+    #[test]
+    fn test_write_results() {
+        let rows = vec![
+            ManifestRow {
+                source: "a.vcf.gz".to_string(),
+                destination: None,
+                checksum: None,
+                project: Some("p1".to_string()),
+            },
+            ManifestRow {
+                source: "b.vcf.gz".to_string(),
+                destination: Some("out/".to_string()),
+                checksum: None,
+                project: None,
+            },
+        ];
+        let mut report = TransferReport::default();
+        report.push("a.vcf.gz", Outcome::Succeeded);
+        report.push("b.vcf.gz", Outcome::Failed("404 Not Found".to_string()));
+
+        let output = tempfile::Builder::new().suffix(".tsv").tempfile().unwrap();
+        write_results(output.path(), ManifestFormat::Tsv, &rows, &report).unwrap();
+        let content = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(
+            content,
+            "source\tdestination\tproject\tchecksum\tstatus\treason\n\
+             a.vcf.gz\t\tp1\t\tsucceeded\t\n\
+             b.vcf.gz\tout/\t\t\tfailed\t404 Not Found\n"
+        );
+    }
+
+    #[test]
+    fn test_group_by_project() {
+        let row = |project: Option<&str>| ManifestRow {
+            source: "a".to_string(),
+            destination: None,
+            checksum: None,
+            project: project.map(str::to_string),
+        };
+        let rows = vec![row(Some("p2")), row(None), row(Some("p2")), row(Some("p1"))];
+        let groups = group_by_project(&rows, Some("p1"));
+        assert_eq!(
+            groups,
+            vec![
+                (Some("p2".to_string()), vec![0, 2]),
+                (Some("p1".to_string()), vec![1, 3]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_manifest() {
+        let mut server = Server::new_async().await;
+        let project_1 = server
+            .mock("POST", "/dportal/cli")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "mode": "download",
+                "project": "p1",
+                "files": ["dir/a.txt"]
+            })))
+            .with_status(200)
+            .with_body(serde_json::json!([format!("{}/files/a.txt", server.url())]).to_string())
+            .create();
+        let project_2 = server
+            .mock("POST", "/dportal/cli")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "mode": "download",
+                "project": "p2",
+                "files": ["b.txt"]
+            })))
+            .with_status(200)
+            .with_body(serde_json::json!([format!("{}/files/b.txt", server.url())]).to_string())
+            .create();
+        let file_a = server
+            .mock("GET", "/files/a.txt")
+            .with_body("hello")
+            .create();
+        let file_b = server
+            .mock("GET", "/files/b.txt")
+            .with_body("world")
+            .create();
+
+        let destination = tempfile::tempdir().unwrap();
+        let rows = vec![
+            ManifestRow {
+                source: "dir/a.txt".to_string(),
+                destination: Some("nested/".to_string()),
+                checksum: Some("5d41402abc4b2a76b9719d911017c592".to_string()),
+                project: None,
+            },
+            ManifestRow {
+                source: "b.txt".to_string(),
+                destination: Some("renamed.txt".to_string()),
+                checksum: Some("md5:00000000000000000000000000000000".to_string()),
+                project: Some("p2".to_string()),
+            },
+        ];
+
+        let report = download_manifest(
+            &server.url(),
+            "my_token",
+            &rows,
+            Some("p1"),
+            destination.path(),
+            true,
+        )
+        .await
+        .unwrap();
+        project_1.assert();
+        project_2.assert();
+        file_a.assert();
+        file_b.assert();
+        assert_eq!(report.results[0].outcome, Outcome::Succeeded);
+        assert!(matches!(report.results[1].outcome, Outcome::Failed(_)));
+        assert!(destination.path().join("nested/a.txt").exists());
+        // a file that fails verification is not left behind
+        assert!(!destination.path().join("renamed.txt").exists());
+    }
+
+    #[test]
+    fn test_download_target_stays_in_base() {
+        let base = tempfile::tempdir().unwrap();
+        let row = |destination: &str| ManifestRow {
+            source: "a.txt".to_string(),
+            destination: Some(destination.to_string()),
+            checksum: None,
+            project: None,
+        };
+        let url = "https://example.com/a.txt".to_string();
+        let target = download_target(&row("out/b.txt"), url.clone(), base.path()).unwrap();
+        assert_eq!(target.path, base.path().join("out").join("b.txt"));
+        assert!(download_target(&row("/tmp/b.txt"), url.clone(), base.path()).is_err());
+        assert!(download_target(&row("../b.txt"), url, base.path()).is_err());
+    }
+}
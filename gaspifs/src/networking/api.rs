@@ -0,0 +1,349 @@
+use crate::auth::login;
+use anyhow::{Result, anyhow};
+use reqwest::{Client as URLClient, RequestBuilder, StatusCode, header};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Number of times a throttled or unavailable request is retried before giving up.
+const MAX_RETRIES: u32 = 5;
+
+/// How the results of a command are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(anyhow!(
+                "Unknown output format {:?}, expected json or table",
+                value
+            )),
+        }
+    }
+}
+
+/// Formats rows as a table with a header, padding each column to its widest value.
+pub fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let header: Vec<String> = header.iter().map(|name| name.to_string()).collect();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            std::iter::once(&header)
+                .chain(rows)
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        output.push_str(line.join("  ").trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Returned by `post`, `get`, `put` and `delete` when the API responds with an error status, so that callers can tell
+/// expired tokens and throttling apart from other failures.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Posts a request body to an endpoint of the API, such as `individuals` or `dportal/notebooks`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `endpoint` - The path of the endpoint, relative to the API.
+/// * `body` - The request body.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the JSON response, or `null` if it has no body.
+/// # Errors
+/// Returns an error if the request fails, if the query quota is exhausted or if the response is not JSON.
+/// Error statuses are returned as a `StatusError`.
+/// # Example
+/// ```
+/// let body = serde_json::json!({"projects": ["my_project"], "query": {"requestedGranularity": "count"}});
+/// let response = post("https://api.example.com", "individuals", &body, "my_token").await?;
+/// ```
+pub async fn post(
+    api: &str,
+    endpoint: &str,
+    body: &serde_json::Value,
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.post(format!("{}/{}", api, endpoint)).json(body);
+    send(request, endpoint, token).await
+}
+
+/// Gets an endpoint of the API with query string parameters, such as `individuals/filtering_terms`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `endpoint` - The path of the endpoint, relative to the API.
+/// * `params` - The query string parameters.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the JSON response, or `null` if it has no body.
+/// # Errors
+/// Returns an error like `post`.
+/// # Example
+/// ```
+/// let response = get("https://api.example.com", "individuals/filtering_terms", &[("projects", "my_project")], "my_token").await?;
+/// ```
+pub async fn get(
+    api: &str,
+    endpoint: &str,
+    params: &[(&str, &str)],
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.get(format!("{}/{}", api, endpoint)).query(params);
+    send(request, endpoint, token).await
+}
+
+/// Puts a request body to a resource of the API, such as `dportal/notebooks/<name>`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `endpoint` - The path of the resource, relative to the API.
+/// * `body` - The request body.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the response, or `null` if it has no body.
+/// # Errors
+/// Returns an error like `post`.
+/// # Example
+/// ```
+/// put("https://api.example.com", "dportal/notebooks/my-notebook", &body, "my_token").await?;
+/// ```
+pub async fn put(
+    api: &str,
+    endpoint: &str,
+    body: &serde_json::Value,
+    token: &str,
+) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.put(format!("{}/{}", api, endpoint)).json(body);
+    send(request, endpoint, token).await
+}
+
+/// Deletes a resource of the API, such as `dportal/queries/<name>`.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `endpoint` - The path of the resource, relative to the API.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the response, or `null` if it has no body.
+/// # Errors
+/// Returns an error like `post`.
+/// # Example
+/// ```
+/// delete("https://api.example.com", "dportal/queries/my_query", "my_token").await?;
+/// ```
+pub async fn delete(api: &str, endpoint: &str, token: &str) -> Result<serde_json::Value> {
+    let client = URLClient::new();
+    let request = client.delete(format!("{}/{}", api, endpoint));
+    send(request, endpoint, token).await
+}
+
+/// Sends a request to the API, turning error statuses into errors.
+async fn send(request: RequestBuilder, endpoint: &str, token: &str) -> Result<serde_json::Value> {
+    let response = request
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, "*/*")
+        .header(header::USER_AGENT, "gaspifs/1.0")
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await?;
+        let code = serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|error| error["code"].as_str().map(str::to_string));
+        if status == StatusCode::FORBIDDEN
+            && matches!(code.as_deref(), Some("QUOTA_EXCEEDED" | "NO_QUOTA"))
+        {
+            return Err(anyhow!(
+                "Query quota exhausted. Please contact an administrator to increase your quota."
+            ));
+        }
+        return Err(StatusError {
+            status,
+            message: format!("Request to {} failed: {}", endpoint, text),
+        }
+        .into());
+    }
+
+    let text = response.text().await?;
+    if text.trim().is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// An authenticated connection to the API for commands that send many requests.
+/// The session logs in again when its token expires and backs off when requests are throttled.
+pub struct Session {
+    api: String,
+    token: RwLock<String>,
+}
+
+impl Session {
+    /// Creates a session from the ID token returned by `login`.
+    pub fn new(api: &str, token: String) -> Self {
+        Session {
+            api: api.to_string(),
+            token: RwLock::new(token),
+        }
+    }
+
+    /// Posts a request body to an endpoint like `post`, recovering from expired tokens and throttling.
+    /// # Errors
+    /// Returns an error if logging in again fails, if the request is still throttled after
+    /// `MAX_RETRIES` attempts or if `post` fails for any other reason.
+    pub async fn post(
+        &self,
+        endpoint: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        self.retry(|token| async move { post(&self.api, endpoint, body, &token).await })
+            .await
+    }
+
+    /// Gets a resource of the API like `get`, recovering from expired tokens and throttling.
+    /// # Errors
+    /// Returns an error like `Session::post`.
+    pub async fn get(&self, endpoint: &str, params: &[(&str, &str)]) -> Result<serde_json::Value> {
+        self.retry(|token| async move { get(&self.api, endpoint, params, &token).await })
+            .await
+    }
+
+    /// Sends a request with the current token, logging in again once on `401` and backing off
+    /// exponentially on `429`, `502`, `503` and `504`.
+    async fn retry<F, Fut>(&self, send: F) -> Result<serde_json::Value>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<serde_json::Value>>,
+    {
+        let mut delay = Duration::from_millis(500);
+        let mut retries = 0;
+        let mut logged_in = false;
+        loop {
+            let token = self.token.read().await.clone();
+            let err = match send(token.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            match err.downcast_ref::<StatusError>().map(|err| err.status) {
+                Some(StatusCode::UNAUTHORIZED) if !logged_in => {
+                    logged_in = true;
+                    let mut current = self.token.write().await;
+                    // another request may have logged in already
+                    if *current == token {
+                        let (_access_token, id_token, _refresh_token) = login().await?;
+                        *current = id_token;
+                    }
+                }
+                Some(
+                    StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT,
+                ) if retries < MAX_RETRIES => {
+                    retries += 1;
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+
+    #[test]
+    fn test_format_table() {
+        let rows = vec![
+            vec!["a".to_string(), "1".to_string()],
+            vec!["longer".to_string(), "".to_string()],
+        ];
+        assert_eq!(
+            format_table(&["NAME", "VALUE"], &rows),
+            "NAME    VALUE\na       1\nlonger\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_quota_exceeded() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/individuals")
+            .with_status(403)
+            .with_body(r#"{"code": "QUOTA_EXCEEDED"}"#)
+            .create();
+
+        let err = post(
+            &server.url(),
+            "individuals",
+            &serde_json::json!({}),
+            "token",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("quota"));
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_session_retries_throttled_requests() {
+        let mut server = Server::new_async().await;
+        let throttled = server
+            .mock("POST", "/g_variants")
+            .with_status(429)
+            .expect(1)
+            .create();
+        let session = Session::new(&server.url(), "token".to_string());
+        let body = serde_json::json!({});
+        let request = session.post("g_variants", &body);
+        let ok = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            throttled.remove();
+            server
+                .mock("POST", "/g_variants")
+                .with_status(200)
+                .with_body(r#"{"responseSummary": {"exists": true}}"#)
+                .create()
+        };
+        let (response, ok) = tokio::join!(request, ok);
+        assert_eq!(response.unwrap()["responseSummary"]["exists"], true);
+        ok.assert();
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Result, anyhow};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Represents an expected file checksum, as written in a manifest.
+/// Checksums are given either with an explicit algorithm prefix (`md5:<hex>`, `sha256:<hex>`)
+/// or as a bare hex digest, in which case the algorithm is inferred from its length.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checksum {
+    Md5(String),
+    Sha256(String),
+}
+
+impl FromStr for Checksum {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (algorithm, digest) = match value.split_once(':') {
+            Some((algorithm, digest)) => (algorithm.to_lowercase(), digest.to_lowercase()),
+            None => {
+                let algorithm = match value.len() {
+                    32 => "md5",
+                    64 => "sha256",
+                    _ => return Err(anyhow!("Cannot infer checksum algorithm of {:?}", value)),
+                };
+                (algorithm.to_string(), value.to_lowercase())
+            }
+        };
+
+        if !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid checksum digest {:?}", value));
+        }
+        match (algorithm.as_str(), digest.len()) {
+            ("md5", 32) => Ok(Checksum::Md5(digest)),
+            ("sha256", 64) => Ok(Checksum::Sha256(digest)),
+            _ => Err(anyhow!("Invalid checksum {:?}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::Md5(digest) => write!(f, "md5:{}", digest),
+            Checksum::Sha256(digest) => write!(f, "sha256:{}", digest),
+        }
+    }
+}
+
+impl Checksum {
+    /// Computes the checksum of a file using the same algorithm as `self`.
+    /// # Arguments
+    /// * `path` - The path to the file.
+    /// # Returns
+    /// A `Result` containing the checksum of the file.
+    /// # Example
+    /// ```
+    /// let expected: Checksum = "md5:d41d8cd98f00b204e9800998ecf8427e".parse()?;
+    /// let actual = expected.compute("/path/to/file.txt")?;
+    /// ```
+    pub fn compute<P: AsRef<Path>>(&self, path: P) -> Result<Checksum> {
+        let file = std::fs::File::open(path)?;
+        match self {
+            Checksum::Md5(_) => Ok(Checksum::Md5(digest_reader::<Md5>(file)?)),
+            Checksum::Sha256(_) => Ok(Checksum::Sha256(digest_reader::<Sha256>(file)?)),
+        }
+    }
+
+    /// Verifies that a file matches the checksum.
+    /// Hashing runs on a blocking thread so that large files do not stall other transfers.
+    /// # Arguments
+    /// * `path` - The path to the file.
+    /// # Returns
+    /// A `Result` indicating whether the file matches.
+    /// # Errors
+    /// Returns an error if the file cannot be read or if the checksum does not match.
+    /// # Example
+    /// ```
+    /// let expected: Checksum = "md5:d41d8cd98f00b204e9800998ecf8427e".parse()?;
+    /// expected.verify("/path/to/file.txt").await?;
+    /// ```
+    pub async fn verify<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let expected = self.clone();
+        let path = path.as_ref().to_path_buf();
+        let actual = tokio::task::spawn_blocking(move || expected.compute(path)).await??;
+        if &actual != self {
+            return Err(anyhow!(
+                "Checksum mismatch: expected {}, got {}",
+                self,
+                actual
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn digest_reader<D: Digest>(mut reader: impl Read) -> Result<String> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_parse_checksum() {
+        assert_eq!(
+            "MD5:D41D8CD98F00B204E9800998ECF8427E"
+                .parse::<Checksum>()
+                .unwrap(),
+            Checksum::Md5("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        assert!(matches!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".parse(),
+            Ok(Checksum::Sha256(_))
+        ));
+        assert!("crc32:1234".parse::<Checksum>().is_err());
+        assert!("md5:zz".parse::<Checksum>().is_err());
+        assert!("1234".parse::<Checksum>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_checksum() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "hello").unwrap();
+
+        let md5: Checksum = "5d41402abc4b2a76b9719d911017c592".parse().unwrap();
+        assert!(md5.verify(file.path()).await.is_ok());
+        let sha256: Checksum =
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+                .parse()
+                .unwrap();
+        assert!(sha256.verify(file.path()).await.is_ok());
+        let wrong: Checksum = "md5:00000000000000000000000000000000".parse().unwrap();
+        assert!(wrong.verify(file.path()).await.is_err());
+    }
+}
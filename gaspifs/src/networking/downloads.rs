@@ -1,13 +1,23 @@
+use super::checksum::Checksum;
+use super::paths::local_path;
+use super::report::{TransferReport, run_transfers};
 use super::util::parse_url;
+use crate::encryption::{KeyStore, decrypt as decrypt_file, decrypted_download_name};
 use crate::networking::util::ErrorResponse;
-use anyhow::{Ok, Result, anyhow};
+use anyhow::{Context, Ok, Result, anyhow};
+use async_compression::tokio::bufread::GzipDecoder;
 use futures::stream::StreamExt;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::{Client as URLClient, header};
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_util::io::{StreamReader, SyncIoBridge};
 
 /// Fetches download URLs for specified files in a project from the API.
 /// # Arguments
@@ -57,95 +67,389 @@ pub async fn get_download_urls(
     Ok(urls)
 }
 
+/// Represents a file to download and the local path where it will be saved.
+/// This struct is used when files are not all saved into the same directory, such as manifest-driven downloads.
+/// When `decrypt` is set, the file is decrypted with the user's identity while it is downloaded.
+#[derive(Debug, Clone)]
+pub struct DownloadTarget {
+    pub url: String,
+    pub path: PathBuf,
+    pub checksum: Option<Checksum>,
+    pub decrypt: bool,
+}
+
 /// Downloads files from the provided URLs to the specified destination directory.
+/// Local file names are derived from the URLs and sanitised so that they always stay inside `destination`.
+/// Encrypted `.age` files are decrypted on the fly and saved without the suffix when an identity exists.
 /// # Arguments
 /// * `urls` - A slice of URLs to download.
 /// * `destination` - The directory where the files will be saved.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
 /// # Returns
-/// A `Result` indicating success or failure.
-/// # Errors
-/// Returns an error if any of the downloads fail or if file operations fail.
+/// A `TransferReport` with the outcome of every download. Without `keep_going`, the first failure
+/// cancels the remaining downloads and they are reported as skipped.
+/// A file name that cannot be mapped safely into the destination directory is reported as failed.
 /// # Example
 /// ```
 /// let urls = vec!["https://example.com/file1.txt".to_string(), "https://example.com/file2.txt".to_string()];
 /// let destination = "/path/to/destination";
-/// download_files(&urls, destination).await?;
+/// let report = download_files(&urls, destination, true).await?;
+/// report.print_summary();
 /// ```
-pub async fn download_files(urls: &[String], destination: &str) -> Result<()> {
+pub async fn download_files(
+    urls: &[String],
+    destination: &str,
+    keep_going: bool,
+) -> Result<TransferReport> {
+    let targets = urls
+        .iter()
+        .map(|url| {
+            let (_, file_name) = parse_url(url)?;
+            let decrypted = decrypted_download_name(&file_name);
+            let local_name = decrypted.unwrap_or(&file_name);
+            let target = local_path(destination, local_name).map(|path| DownloadTarget {
+                url: url.clone(),
+                path,
+                checksum: None,
+                decrypt: decrypted.is_some(),
+            });
+            let name = match &target {
+                Result::Ok(target) => file_name_of(&target.path).unwrap_or(file_name),
+                Err(_) => file_name,
+            };
+            Ok((name, target))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(download_targets(targets, keep_going).await)
+}
+
+/// Downloads files to their own local paths, verifying their checksums when one is given.
+/// # Arguments
+/// * `targets` - The name of each file as shown in the report, paired with its `DownloadTarget`
+///   or the error that prevented it from being resolved.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// # Returns
+/// A `TransferReport` with the outcome of every download, in the same order as `targets`.
+/// A file whose checksum does not match is reported as failed and removed; checksums of decrypted
+/// files are computed on the decrypted data.
+/// # Example
+/// ```
+/// let targets = vec![(
+///     "file1.txt".to_string(),
+///     Ok(DownloadTarget {
+///         url: "https://example.com/file1.txt".to_string(),
+///         path: PathBuf::from("/path/to/file1.txt"),
+///         checksum: Some("md5:d41d8cd98f00b204e9800998ecf8427e".parse()?),
+///         decrypt: false,
+///     }),
+/// )];
+/// let report = download_targets(targets, false).await;
+/// ```
+pub async fn download_targets(
+    targets: Vec<(String, Result<DownloadTarget>)>,
+    keep_going: bool,
+) -> TransferReport {
     let multi_progress = Arc::new(MultiProgress::new());
     let client = Arc::new(URLClient::new());
 
-    let tasks: Vec<_> = urls
-            .iter()
-            .map(|url| {
-                let (_, file_name) = parse_url(url).unwrap();
-                let pb = multi_progress.add(ProgressBar::new(0));
-                pb.set_style(
-                    ProgressStyle::default_bar()
-                        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
-                        .unwrap()
-                        .progress_chars("#>-"),
-                );
-                pb.set_message(format!("Downloading {}", file_name));
-                let client = Arc::clone(&client);
-                tokio::spawn(download_file_with_progress(client, url.clone(), file_name, destination.into(), pb))
+    let transfers = targets
+            .into_iter()
+            .map(|(name, target)| {
+                let target = target.map(|target| {
+                    let file_name = file_name_of(&target.path).unwrap_or_else(|| name.clone());
+                    let pb = multi_progress.add(ProgressBar::new(0));
+                    pb.set_style(
+                        ProgressStyle::default_bar()
+                            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
+                            .unwrap()
+                            .progress_chars("#>-"),
+                    );
+                    pb.set_message(format!("Downloading {}", file_name));
+                    let client = Arc::clone(&client);
+                    download_file_with_progress(client, target.url, file_name, target.path, target.decrypt, target.checksum, pb)
+                });
+                (name, target)
             })
             .collect();
 
-    for task in tasks {
-        task.await??;
+    run_transfers(transfers, keep_going).await
+}
+
+fn file_name_of(path: &Path) -> Option<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// A `.part` file next to a download's destination that the download is written to.
+/// Dropping it before `commit` (for example when the download fails or its task is aborted)
+/// removes the file and tells a blocking writer to stop, so no truncated file is left behind.
+struct PartFile {
+    path: PathBuf,
+    cancelled: Arc<AtomicBool>,
+    committed: bool,
+}
+
+impl PartFile {
+    fn new(destination_path: &Path) -> Self {
+        let mut path = destination_path.as_os_str().to_owned();
+        path.push(".part");
+        PartFile {
+            path: PathBuf::from(path),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            committed: false,
+        }
     }
 
-    Ok(())
+    /// Moves the finished file to its destination.
+    async fn commit(mut self, destination_path: &Path) -> io::Result<()> {
+        tokio::fs::rename(&self.path, destination_path).await?;
+        self.committed = true;
+        io::Result::Ok(())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.cancelled.store(true, Ordering::Relaxed);
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A writer that fails once its download has been cancelled, stopping a blocking decryption early.
+struct CancellableWriter<W> {
+    inner: W,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<W: io::Write> io::Write for CancellableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "download cancelled",
+            ));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 /// Downloads a file from the specified URL and saves it to the destination directory with progress tracking.
+/// The file is written to `<destination>.part` and only renamed once it is complete and its
+/// checksum matches, so a failed, mismatched or aborted download leaves nothing at the destination.
 /// # Arguments
 /// * `client` - An instance of `URLClient` for making HTTP requests.
 /// * `url` - The URL of the file to download.      
-/// * `file_name` - The name of the file to save.
-/// * `destination` - The directory where the file will be saved.
+/// * `file_name` - The name of the file, used for progress messages.
+/// * `destination_path` - The local path where the file will be saved.
+/// * `decrypt` - Whether to decrypt the file with the user's identity while downloading it.
+/// * `checksum` - The expected checksum of the saved file, if known.
 /// * `pb` - A `ProgressBar` instance for tracking download progress.
 /// # Returns
 /// A `Result` indicating success or failure.
 /// # Errors
-/// Returns an error if the download fails or if file operations fail.  
-/// /// # Example
+/// Returns an error if the download fails, if the server responds with an error status,
+/// if the checksum does not match or if file operations fail.
+/// # Example
 /// ```
 /// let client = URLClient::new();
 /// let url = "https://example.com/file.txt".to_string();
 /// let file_name = "file.txt".to_string();
-/// let destination = "/path/to/destination".to_string();
+/// let destination_path = PathBuf::from("/path/to/destination/file.txt");
 /// let pb = ProgressBar::new(0);
-/// download_file_with_progress(client, url, file_name, destination, pb).await?;
+/// download_file_with_progress(client, url, file_name, destination_path, false, None, pb).await?;
 /// ```
 async fn download_file_with_progress(
     client: Arc<URLClient>,
     url: String,
     file_name: String,
-    destination: String,
+    destination_path: PathBuf,
+    decrypt: bool,
+    checksum: Option<Checksum>,
     pb: ProgressBar,
 ) -> Result<()> {
-    let destination_path = Path::new(&destination).join(file_name.clone());
-    let response = client.get(url).send().await?;
+    let response = client.get(url).send().await?.error_for_status()?;
     let total_size = response.content_length().unwrap_or(0);
-    let mut file = File::create(&destination_path).await?;
     let mut downloaded = 0u64;
-    let mut stream = response.bytes_stream();
+    let progress = pb.clone();
+    let mut stream = response.bytes_stream().map(move |item| {
+        let chunk = item.map_err(io::Error::other)?;
+        downloaded += chunk.len() as u64;
+        progress.set_position(downloaded);
+        io::Result::Ok(chunk)
+    });
 
     pb.set_length(total_size);
 
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
-        file.write_all(&chunk).await?;
-        downloaded += chunk.len() as u64;
-        pb.set_position(downloaded);
+    let part = PartFile::new(&destination_path);
+    let written = if decrypt {
+        let identities = KeyStore::open()?.identities()?;
+        let reader = SyncIoBridge::new(StreamReader::new(stream));
+        // The file is created here rather than in the blocking task, so that it always
+        // exists before `part` can be dropped and removed.
+        let output = CancellableWriter {
+            inner: io::BufWriter::new(File::create(&part.path).await?.into_std().await),
+            cancelled: Arc::clone(&part.cancelled),
+        };
+        tokio::task::spawn_blocking(move || decrypt_file(&identities, reader, output))
+            .await?
+            .map(|_| ())
+            .with_context(|| format!("Failed to decrypt {}", file_name))
+    } else {
+        let mut file = File::create(&part.path).await?;
+        async {
+            while let Some(item) = stream.next().await {
+                file.write_all(&item?).await?;
+            }
+            file.flush().await
+        }
+        .await
+        .with_context(|| format!("Failed to download {}", file_name))
+    };
+    let verified = match checksum {
+        Some(checksum) if written.is_ok() => checksum.verify(&part.path).await,
+        _ => written,
+    };
+    if let Err(err) = verified {
+        pb.finish_with_message(format!("✗ {}", file_name));
+        return Err(err);
     }
 
+    part.commit(&destination_path).await?;
     pb.finish_with_message(format!("✓ {}", file_name));
     Ok(())
 }
 
+/// Represents a byte range of a remote file, written as `start-end` (inclusive) or `start-`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl FromStr for ByteRange {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (start, end) = value.split_once('-').ok_or(anyhow!(
+            "Invalid range {:?}, expected START-END or START-",
+            value
+        ))?;
+        let start = start
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid range start {:?}", start))?;
+        let end = match end.trim() {
+            "" => None,
+            end => Some(
+                end.parse()
+                    .map_err(|_| anyhow!("Invalid range end {:?}", end))?,
+            ),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(anyhow!("Invalid range {:?}, end is before start", value));
+        }
+        Ok(ByteRange { start, end })
+    }
+}
+
+impl ByteRange {
+    /// Formats the range as the value of an HTTP `Range` header.
+    fn header(&self) -> String {
+        match self.end {
+            Some(end) => format!("bytes={}-{}", self.start, end),
+            None => format!("bytes={}-", self.start),
+        }
+    }
+}
+
+/// Streams a remote file into a writer, such as standard output, without saving it to disk.
+/// Gzip and BGZF files can be decompressed on the fly; BGZF is read as a series of gzip members.
+/// A closed pipe on the writer side (for example `| head`) ends the stream without an error.
+/// # Arguments
+/// * `url` - The URL of the file to stream.
+/// * `range` - Only stream this byte range of the remote file, if given.
+/// * `head` - Stop after writing this many lines, if given.
+/// * `decompress` - Whether to decompress gzip/BGZF data before writing it.
+/// * `writer` - Where to write the file contents.
+/// # Returns
+/// A `Result` indicating success or failure.
+/// # Errors
+/// Returns an error if the request fails, if the server responds with an error status,
+/// if a range is requested but the server does not answer with `206 Partial Content`,
+/// or if the data cannot be decompressed.
+/// # Example
+/// ```
+/// let mut stdout = tokio::io::stdout();
+/// stream_file("https://example.com/file.vcf.gz", None, Some(10), true, &mut stdout).await?;
+/// ```
+pub async fn stream_file<W: AsyncWrite + Unpin>(
+    url: &str,
+    range: Option<&ByteRange>,
+    head: Option<usize>,
+    decompress: bool,
+    writer: &mut W,
+) -> Result<()> {
+    let client = URLClient::new();
+    let mut request = client.get(url);
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range.header());
+    }
+    let response = request.send().await?.error_for_status()?;
+    if range.is_some() && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!(
+            "The server ignored the requested range (status {}); refusing to stream the whole file.",
+            response.status()
+        ));
+    }
+    let stream = response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(io::Error::other));
+    let reader = StreamReader::new(stream);
+    let reader: Pin<Box<dyn AsyncRead + Send>> = if decompress {
+        let mut decoder = GzipDecoder::new(reader);
+        decoder.multiple_members(true);
+        Box::pin(decoder)
+    } else {
+        Box::pin(reader)
+    };
+
+    let result = match head {
+        Some(lines) => copy_lines(reader, lines, writer).await,
+        None => tokio::io::copy(&mut BufReader::new(reader), writer)
+            .await
+            .map(|_| ()),
+    };
+    match result.and(writer.flush().await) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Copies at most `lines` lines from a reader to a writer.
+async fn copy_lines<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: R,
+    lines: usize,
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    for _ in 0..lines {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        writer.write_all(&line).await?;
+    }
+    io::Result::Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,7 +463,210 @@ mod tests {
         ];
         let destination = "/tmp/";
 
-        assert!(download_files(&urls, destination).await.is_ok());
+        let report = download_files(&urls, destination, false).await.unwrap();
+        assert!(report.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_download_files_stays_in_destination() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/data/..%2F..%2Fescape.txt")
+            .with_status(200)
+            .with_body("payload")
+            .create();
+        let destination = tempfile::tempdir().unwrap();
+
+        let urls = vec![format!("{}/data/..%2F..%2Fescape.txt", server.url())];
+        let report = download_files(&urls, destination.path().to_str().unwrap(), false)
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        mock.assert();
+        assert!(destination.path().join(".._.._escape.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_files_rejects_unsafe_name() {
+        let destination = tempfile::tempdir().unwrap();
+        let urls = vec!["https://example.com/data/%2E%2E".to_string()];
+        let report = download_files(&urls, destination.path().to_str().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(report.failed(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_files_keep_going() {
+        let mut server = Server::new_async().await;
+        let found = server
+            .mock("GET", "/data/found.txt")
+            .with_status(200)
+            .with_body("payload")
+            .create();
+        let missing = server
+            .mock("GET", "/data/missing.txt")
+            .with_status(404)
+            .create();
+        let destination = tempfile::tempdir().unwrap();
+
+        let urls = vec![
+            format!("{}/data/missing.txt", server.url()),
+            format!("{}/data/found.txt", server.url()),
+        ];
+        let report = download_files(&urls, destination.path().to_str().unwrap(), true)
+            .await
+            .unwrap();
+        found.assert();
+        missing.assert();
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.succeeded(), 1);
+        assert!(destination.path().join("found.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_targets_aborted_leaves_nothing() {
+        let mut server = Server::new_async().await;
+        let slow = server
+            .mock("GET", "/data/slow.txt")
+            .with_status(200)
+            .with_chunked_body(|writer| {
+                writer.write_all(b"partial")?;
+                writer.flush()?;
+                std::thread::sleep(std::time::Duration::from_secs(5));
+                writer.write_all(b"rest")
+            })
+            .create();
+        let corrupt = server
+            .mock("GET", "/data/corrupt.txt")
+            .with_status(200)
+            .with_chunked_body(|writer| {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                writer.write_all(b"payload")
+            })
+            .create();
+        let destination = tempfile::tempdir().unwrap();
+
+        let target = |name: &str, checksum: Option<&str>| {
+            (
+                name.to_string(),
+                Ok(DownloadTarget {
+                    url: format!("{}/data/{}", server.url(), name),
+                    path: destination.path().join(name),
+                    checksum: checksum.map(|checksum| checksum.parse().unwrap()),
+                    decrypt: false,
+                }),
+            )
+        };
+        let targets = vec![
+            target("slow.txt", None),
+            target("corrupt.txt", Some("md5:d41d8cd98f00b204e9800998ecf8427e")),
+        ];
+        let report = download_targets(targets, false).await;
+        slow.assert();
+        corrupt.assert();
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.skipped(), 1);
+        let left: Vec<_> = std::fs::read_dir(destination.path()).unwrap().collect();
+        assert!(left.is_empty(), "files left behind: {:?}", left);
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(
+            "10-20".parse::<ByteRange>().unwrap(),
+            ByteRange {
+                start: 10,
+                end: Some(20)
+            }
+        );
+        assert_eq!("10-".parse::<ByteRange>().unwrap().end, None);
+        assert!("20-10".parse::<ByteRange>().is_err());
+        assert!("abc".parse::<ByteRange>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_file_range_and_head() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/file.txt")
+            .match_header("range", "bytes=2-")
+            .with_status(206)
+            .with_body("ne 1\nline 2\nline 3\n")
+            .create();
+
+        let mut output = Vec::new();
+        let range = "2-".parse::<ByteRange>().unwrap();
+        stream_file(
+            &format!("{}/file.txt", server.url()),
+            Some(&range),
+            Some(2),
+            false,
+            &mut output,
+        )
+        .await
+        .unwrap();
+        mock.assert();
+        assert_eq!(output, b"ne 1\nline 2\n");
+    }
+
+    #[tokio::test]
+    async fn test_stream_file_range_ignored() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/file.txt")
+            .with_status(200)
+            .with_body("line 1\n")
+            .create();
+
+        let mut output = Vec::new();
+        let range = "2-".parse::<ByteRange>().unwrap();
+        let result = stream_file(
+            &format!("{}/file.txt", server.url()),
+            Some(&range),
+            None,
+            false,
+            &mut output,
+        )
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("ignored the requested range")
+        );
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_file_decompress_multiple_members() {
+        use async_compression::tokio::write::GzipEncoder;
+
+        let mut compressed = Vec::new();
+        for member in [&b"##fileformat=VCFv4.2\n"[..], &b"#CHROM\tPOS\n"[..]] {
+            let mut encoder = GzipEncoder::new(Vec::new());
+            encoder.write_all(member).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            compressed.extend(encoder.into_inner());
+        }
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/file.vcf.gz")
+            .with_body(compressed)
+            .create();
+
+        let mut output = Vec::new();
+        stream_file(
+            &format!("{}/file.vcf.gz", server.url()),
+            None,
+            None,
+            true,
+            &mut output,
+        )
+        .await
+        .unwrap();
+        mock.assert();
+        assert_eq!(output, b"##fileformat=VCFv4.2\n#CHROM\tPOS\n");
     }
 
     #[tokio::test]
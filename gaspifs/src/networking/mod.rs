@@ -1,6 +1,11 @@
+pub mod api;
+pub mod checksum;
 pub mod downloads;
 pub mod files;
+pub mod paths;
 pub mod projects;
+pub mod quota;
+pub mod report;
 #[cfg(feature = "uploads")]
 pub mod uploads;
-mod util;
+pub mod util;
@@ -0,0 +1,361 @@
+use anyhow::{Result, anyhow};
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest file name, in bytes, accepted by common local filesystems (ext4, APFS, NTFS).
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+/// Longest extension that is kept intact when a file name has to be truncated.
+const MAX_EXTENSION_BYTES: usize = 32;
+
+/// Device names that cannot be used as file names on Windows, whatever the extension.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that are not allowed in file names on Windows.
+const WINDOWS_INVALID_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Turns a remote object name into a name that is safe to create inside a local directory.
+/// The name is normalised to Unicode NFC, control characters are removed and path separators
+/// are replaced with `_` so that the result is always a single path component.
+/// Names that are too long for the local filesystem are truncated, keeping the extension,
+/// and on Windows reserved device names and invalid characters are escaped.
+/// # Arguments
+/// * `name` - The remote file name, usually the percent-decoded last segment of a URL.
+/// # Returns
+/// A `Result` containing the sanitised file name.
+/// # Errors
+/// Returns an error if the name is empty or refers to the current or parent directory
+/// (`.` or `..`), since these cannot be mapped to a file safely.
+/// # Example
+/// ```
+/// assert_eq!(sanitise_file_name("reports/../../etc/passwd").unwrap(), "reports_.._.._etc_passwd");
+/// assert!(sanitise_file_name("..").is_err());
+/// ```
+pub fn sanitise_file_name(name: &str) -> Result<String> {
+    let mut sanitised: String = name
+        .nfc()
+        .filter(|c| !c.is_control())
+        .map(|c| {
+            if c == '/' || c == '\\' || (cfg!(windows) && WINDOWS_INVALID_CHARS.contains(&c)) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    if sanitised.is_empty() || sanitised == "." || sanitised == ".." {
+        return Err(anyhow!("Unsafe file name: {:?}", name));
+    }
+
+    truncate_file_name(&mut sanitised, MAX_FILE_NAME_BYTES);
+    if cfg!(windows) {
+        escape_windows_name(&mut sanitised);
+    }
+
+    if sanitised.is_empty() || sanitised == "." || sanitised == ".." {
+        return Err(anyhow!("Unsafe file name: {:?}", name));
+    }
+
+    Ok(sanitised)
+}
+
+/// Maps a remote object name to a path inside the destination directory.
+/// The name is sanitised with `sanitise_file_name` and the resulting path is checked
+/// to be a direct child of `destination`, so a download can never escape it.
+/// # Arguments
+/// * `destination` - The local directory where the file will be saved.
+/// * `name` - The remote file name.
+/// # Returns
+/// A `Result` containing the local path for the file.
+/// # Errors
+/// Returns an error if the name is unsafe or cannot be mapped into `destination`.
+/// # Example
+/// ```
+/// let path = local_path("/tmp/downloads", "../../etc/passwd").unwrap();
+/// assert!(path.starts_with("/tmp/downloads"));
+/// ```
+pub fn local_path<P: AsRef<Path>>(destination: P, name: &str) -> Result<PathBuf> {
+    let destination = destination.as_ref();
+    let file_name = sanitise_file_name(name)?;
+    let mut components = Path::new(&file_name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => return Err(anyhow!("Unsafe file name: {:?}", name)),
+    }
+
+    let path = destination.join(&file_name);
+    if path.parent() != Some(destination) {
+        return Err(anyhow!(
+            "File name {:?} does not map into {}",
+            name,
+            destination.display()
+        ));
+    }
+
+    Ok(path)
+}
+
+/// Joins a relative path, such as the destination of a manifest row, onto the destination directory.
+/// Like `local_path`, every part of the path must be a plain name, so the result stays inside
+/// `destination`; `.` parts are skipped.
+/// # Arguments
+/// * `destination` - The local directory the path is relative to.
+/// * `relative` - The relative path.
+/// # Returns
+/// A `Result` containing the joined path.
+/// # Errors
+/// Returns an error if the path is absolute, has a drive prefix or contains `..`.
+/// # Example
+/// ```
+/// assert_eq!(relative_path("/tmp/downloads", "a/b.vcf").unwrap(), PathBuf::from("/tmp/downloads/a/b.vcf"));
+/// assert!(relative_path("/tmp/downloads", "../b.vcf").is_err());
+/// ```
+pub fn relative_path<P: AsRef<Path>>(destination: P, relative: &str) -> Result<PathBuf> {
+    let mut path = destination.as_ref().to_path_buf();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(
+                    "Unsafe path {:?}: it must be relative and stay inside {}",
+                    relative,
+                    destination.as_ref().display()
+                ));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Returns the names that the index of a data file may have, in order of preference.
+/// Compressed tab-delimited files (`.gz`, `.bgz`) are indexed by `.tbi` or `.csi` files,
+/// BAM files by `.bai` and CRAM files by `.crai`; other files have no index.
+/// # Arguments
+/// * `name` - The name or path of the data file.
+/// # Returns
+/// The candidate index names, next to the data file.
+/// # Example
+/// ```
+/// assert_eq!(index_names("sample.bam"), vec!["sample.bam.bai", "sample.bai"]);
+/// ```
+pub fn index_names(name: &str) -> Vec<String> {
+    let lower = name.to_lowercase();
+    if lower.ends_with(".bam") {
+        vec![
+            format!("{}.bai", name),
+            format!("{}.bai", &name[..name.len() - ".bam".len()]),
+        ]
+    } else if lower.ends_with(".cram") {
+        vec![format!("{}.crai", name)]
+    } else if lower.ends_with(".gz") || lower.ends_with(".bgz") {
+        vec![format!("{}.tbi", name), format!("{}.csi", name)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Adds the index of each data file to a list of remote files, when one is available.
+/// # Arguments
+/// * `files` - The requested files.
+/// * `available` - The files that exist remotely.
+/// # Returns
+/// The requested files, each followed by its index if it was not requested already.
+/// # Example
+/// ```
+/// let files = with_indexes(&["a.vcf.gz".to_string()], &["a.vcf.gz".to_string(), "a.vcf.gz.tbi".to_string()]);
+/// assert_eq!(files, vec!["a.vcf.gz", "a.vcf.gz.tbi"]);
+/// ```
+pub fn with_indexes(files: &[String], available: &[String]) -> Vec<String> {
+    let mut paired: Vec<String> = files.to_vec();
+    for file in files {
+        let index = index_names(file)
+            .into_iter()
+            .find(|index| available.contains(index));
+        if let Some(index) = index.filter(|index| !paired.contains(index)) {
+            paired.push(index);
+        }
+    }
+    paired
+}
+
+/// Truncates a file name to at most `max_bytes`, keeping a short extension when there is one.
+/// Truncation always happens on a character boundary.
+fn truncate_file_name(name: &mut String, max_bytes: usize) {
+    if name.len() <= max_bytes {
+        return;
+    }
+
+    let extension = match name.rfind('.') {
+        Some(index) if index > 0 && name.len() - index <= MAX_EXTENSION_BYTES => {
+            name[index..].to_string()
+        }
+        _ => String::new(),
+    };
+    let mut stem_len = max_bytes - extension.len();
+    while !name.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    name.truncate(stem_len);
+    name.push_str(&extension);
+}
+
+/// Escapes names that Windows refuses to create: trailing dots and spaces are removed
+/// and reserved device names such as `CON` or `LPT1.txt` are prefixed with `_`.
+fn escape_windows_name(name: &mut String) {
+    name.truncate(name.trim_end_matches(['.', ' ']).len());
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+        if name.len() > MAX_FILE_NAME_BYTES {
+            name.pop();
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_sanitise_file_name() {
+        assert_eq!(
+            sanitise_file_name("sample.vcf.gz").unwrap(),
+            "sample.vcf.gz"
+        );
+        assert_eq!(
+            sanitise_file_name("../../etc/passwd").unwrap(),
+            ".._.._etc_passwd"
+        );
+        assert_eq!(sanitise_file_name("a\\b").unwrap(), "a_b");
+        assert_eq!(
+            sanitise_file_name("bad\nname\u{7}.txt").unwrap(),
+            "badname.txt"
+        );
+    }
+
+    #[test]
+    fn test_sanitise_file_name_rejects_directories() {
+        assert!(sanitise_file_name("").is_err());
+        assert!(sanitise_file_name(".").is_err());
+        assert!(sanitise_file_name("..").is_err());
+        assert!(sanitise_file_name("\u{0}..\r").is_err());
+    }
+
+    #[test]
+    fn test_sanitise_file_name_normalises_unicode() {
+        // "e" followed by a combining acute accent is composed into a single "é"
+        assert_eq!(
+            sanitise_file_name("cafe\u{301}.txt").unwrap(),
+            "caf\u{e9}.txt"
+        );
+    }
+
+    #[test]
+    fn test_sanitise_file_name_truncates_long_names() {
+        let name = format!("{}.vcf.gz", "é".repeat(300));
+        let sanitised = sanitise_file_name(&name).unwrap();
+        assert!(sanitised.len() <= MAX_FILE_NAME_BYTES);
+        assert!(sanitised.ends_with(".gz"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_sanitise_file_name_windows() {
+        assert_eq!(sanitise_file_name("CON").unwrap(), "_CON");
+        assert_eq!(sanitise_file_name("lpt1.txt").unwrap(), "_lpt1.txt");
+        assert_eq!(sanitise_file_name("a:b?.txt. ").unwrap(), "a_b_.txt");
+    }
+
+    #[test]
+    fn test_local_path() {
+        let destination = Path::new("/tmp/downloads");
+        assert_eq!(
+            local_path(destination, "file.txt").unwrap(),
+            destination.join("file.txt")
+        );
+        assert_eq!(
+            local_path(destination, "/etc/passwd").unwrap(),
+            destination.join("_etc_passwd")
+        );
+        assert!(local_path(destination, "..").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_sanitised_name_is_single_component(name in any::<String>()) {
+            if let Ok(sanitised) = sanitise_file_name(&name) {
+                prop_assert!(!sanitised.is_empty());
+                prop_assert!(sanitised.len() <= MAX_FILE_NAME_BYTES);
+                prop_assert!(!sanitised.contains(['/', '\\']));
+                prop_assert!(!sanitised.chars().any(char::is_control));
+                prop_assert_ne!(sanitised.as_str(), ".");
+                prop_assert_ne!(sanitised.as_str(), "..");
+            }
+        }
+
+        #[test]
+        fn prop_sanitise_is_idempotent(name in any::<String>()) {
+            if let Ok(sanitised) = sanitise_file_name(&name) {
+                prop_assert_eq!(sanitise_file_name(&sanitised).unwrap(), sanitised);
+            }
+        }
+
+        #[test]
+        fn prop_local_path_stays_in_destination(
+            name in prop_oneof![
+                any::<String>(),
+                "[./\\\\a-z\u{0}-\u{1f}]{0,12}",
+            ]
+        ) {
+            let destination = Path::new("/tmp/downloads");
+            if let Ok(path) = local_path(destination, &name) {
+                prop_assert_eq!(path.parent(), Some(destination));
+                prop_assert!(path.starts_with(destination));
+            }
+        }
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let destination = Path::new("/tmp/downloads");
+        assert_eq!(
+            relative_path(destination, "./a/b.vcf").unwrap(),
+            destination.join("a").join("b.vcf")
+        );
+        assert!(relative_path(destination, "/etc/passwd").is_err());
+        assert!(relative_path(destination, "a/../../b.vcf").is_err());
+    }
+
+    #[test]
+    fn test_index_names() {
+        assert_eq!(
+            index_names("a.vcf.gz"),
+            vec!["a.vcf.gz.tbi", "a.vcf.gz.csi"]
+        );
+        assert_eq!(index_names("reads.BAM"), vec!["reads.BAM.bai", "reads.bai"]);
+        assert_eq!(index_names("reads.cram"), vec!["reads.cram.crai"]);
+        assert!(index_names("a.vcf").is_empty());
+    }
+
+    #[test]
+    fn test_with_indexes() {
+        let available = ["a.vcf.gz", "a.vcf.gz.csi", "b.bam", "b.bai", "c.txt"].map(String::from);
+        let files = ["a.vcf.gz", "b.bam", "c.txt", "b.bai"].map(String::from);
+        assert_eq!(
+            with_indexes(&files, &available),
+            vec!["a.vcf.gz", "b.bam", "c.txt", "b.bai", "a.vcf.gz.csi"]
+        );
+    }
+}
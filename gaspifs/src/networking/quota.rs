@@ -0,0 +1,189 @@
+use crate::auth::token_claims;
+use crate::networking::api::{self, OutputFormat};
+use anyhow::{Result, anyhow};
+use indicatif::BinaryBytes;
+use serde::{Deserialize, Serialize};
+
+/// The storage and query limits of a user, and how much of them is used.
+/// Sizes are in bytes; storage counts the files in the user's space.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    #[serde(default)]
+    pub quota_size: u64,
+    #[serde(default)]
+    pub usage_size: u64,
+    #[serde(default)]
+    pub quota_query_count: u64,
+    #[serde(default)]
+    pub usage_count: u64,
+    #[serde(default)]
+    pub notebook_role: Option<String>,
+}
+
+impl Quota {
+    /// Returns the number of bytes that can still be stored.
+    pub fn remaining_size(&self) -> u64 {
+        self.quota_size.saturating_sub(self.usage_size)
+    }
+
+    /// Returns the number of queries that can still be run.
+    pub fn remaining_queries(&self) -> u64 {
+        self.quota_query_count.saturating_sub(self.usage_count)
+    }
+
+    /// Checks that files of a total size fit in the remaining storage.
+    /// # Errors
+    /// Returns an error with the usage and the size of the files if they do not fit.
+    #[cfg(feature = "uploads")]
+    pub fn check_upload(&self, size: u64) -> Result<()> {
+        if size <= self.remaining_size() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "Uploading {} would exceed your storage quota: {} of {} used, {} remaining. \
+            Delete files or ask an administrator to increase your quota.",
+            BinaryBytes(size),
+            BinaryBytes(self.usage_size),
+            BinaryBytes(self.quota_size),
+            BinaryBytes(self.remaining_size())
+        ))
+    }
+}
+
+/// Gets the quota of the user signed in with a token.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The ID token, which identifies the user.
+/// # Returns
+/// A `Result` containing the quota, or `None` if no quota is set for the user.
+/// # Errors
+/// Returns an error if the token does not identify the user or the request fails.
+/// # Example
+/// ```
+/// if let Some(quota) = get_quota(&api, &id_token).await? {
+///     println!("{} bytes remaining", quota.remaining_size());
+/// }
+/// ```
+pub async fn get_quota(api: &str, token: &str) -> Result<Option<Quota>> {
+    let claims = token_claims(token)?;
+    let user = claims["sub"]
+        .as_str()
+        .ok_or(anyhow!("The ID token does not identify the user"))?;
+    let response = api::get(api, &format!("dportal/quota/{}", user), &[], token).await?;
+    if response["success"] != true {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(
+        response["data"]["Usage"].clone(),
+    )?))
+}
+
+/// Checks that files of a total size fit in the storage quota of the user before uploading them.
+/// Users without a quota are not limited.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `size` - The total size of the files in bytes.
+/// * `token` - The ID token, which identifies the user.
+/// # Errors
+/// Returns an error if the quota cannot be read, or if the files would exceed it.
+/// # Example
+/// ```
+/// let total = get_file_sizes(&files)?.iter().sum();
+/// check_upload_quota(&api, total, &id_token).await?;
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn check_upload_quota(api: &str, size: u64, token: &str) -> Result<()> {
+    match get_quota(api, token).await? {
+        Some(quota) => quota.check_upload(size),
+        None => Ok(()),
+    }
+}
+
+/// Formats a quota for printing, as usage against limits or as JSON.
+pub fn format_quota(quota: Option<&Quota>, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(&quota)?));
+    }
+    let Some(quota) = quota else {
+        return Ok("No quota is set for your account.\n".to_string());
+    };
+    let percent = |used: u64, limit: u64| {
+        if limit == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.0}%", used as f64 * 100.0 / limit as f64)
+        }
+    };
+    let mut output = format!(
+        "storage: {} of {} used ({}), {} remaining\nqueries: {} of {} used ({}), {} remaining\n",
+        BinaryBytes(quota.usage_size),
+        BinaryBytes(quota.quota_size),
+        percent(quota.usage_size, quota.quota_size),
+        BinaryBytes(quota.remaining_size()),
+        quota.usage_count,
+        quota.quota_query_count,
+        percent(quota.usage_count, quota.quota_query_count),
+        quota.remaining_queries()
+    );
+    if let Some(role) = &quota.notebook_role {
+        output.push_str(&format!("notebook role: {}\n", role));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    use mockito::Server;
+
+    #[tokio::test]
+    async fn test_get_quota() {
+        let token = format!(
+            "header.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"sub": "user-1"}"#)
+        );
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/dportal/quota/user-1")
+            .with_status(200)
+            .with_body(
+                r#"{"success": true, "data": {"IdentityUser": "user-1", "Usage": {
+                    "quotaSize": 10737418240, "usageSize": 9663676416,
+                    "quotaQueryCount": 100, "usageCount": 25, "notebookRole": "basic"}}}"#,
+            )
+            .create();
+        let quota = get_quota(&server.url(), &token).await.unwrap().unwrap();
+        mock.assert();
+        assert_eq!(quota.remaining_size(), 1073741824);
+        assert_eq!(quota.remaining_queries(), 75);
+        assert_eq!(
+            format_quota(Some(&quota), OutputFormat::Table).unwrap(),
+            "storage: 9.00 GiB of 10.00 GiB used (90%), 1.00 GiB remaining\n\
+            queries: 25 of 100 used (25%), 75 remaining\n\
+            notebook role: basic\n"
+        );
+
+        server
+            .mock("GET", "/dportal/quota/user-1")
+            .with_status(200)
+            .with_body(r#"{"success": false, "message": "No quota"}"#)
+            .create();
+        mock.remove();
+        assert_eq!(get_quota(&server.url(), &token).await.unwrap(), None);
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_check_upload() {
+        let quota = Quota {
+            quota_size: 10737418240,
+            usage_size: 9663676416,
+            ..Default::default()
+        };
+        assert!(quota.check_upload(1073741824).is_ok());
+        let err = quota.check_upload(1073741825).unwrap_err().to_string();
+        assert!(err.contains("9.00 GiB of 10.00 GiB used, 1.00 GiB remaining"));
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::Result;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::process::ExitCode;
+
+/// Exit code used when some, but not all, files of a transfer failed.
+pub const PARTIAL_FAILURE_EXIT_CODE: u8 = 3;
+
+/// Represents the outcome of transferring a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Succeeded,
+    Failed(String),
+    Skipped(String),
+}
+
+/// Represents a file that was part of a transfer and what happened to it.
+#[derive(Debug, Clone)]
+pub struct TransferResult {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// Collects the outcome of every file in a batch transfer, in the order the files were requested.
+#[derive(Debug, Default)]
+pub struct TransferReport {
+    pub results: Vec<TransferResult>,
+}
+
+impl TransferReport {
+    /// Adds the outcome of a file to the report.
+    pub fn push(&mut self, name: impl Into<String>, outcome: Outcome) {
+        self.results.push(TransferResult {
+            name: name.into(),
+            outcome,
+        });
+    }
+
+    /// Number of files that were transferred successfully.
+    pub fn succeeded(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Succeeded))
+    }
+
+    /// Number of files whose transfer failed.
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    /// Number of files that were not attempted.
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Skipped(_)))
+    }
+
+    /// Returns `true` if every file in the report was transferred successfully.
+    pub fn is_success(&self) -> bool {
+        self.succeeded() == self.results.len()
+    }
+
+    /// Maps the report to the process exit code.
+    /// `0` when every file succeeded, `1` when none did and `PARTIAL_FAILURE_EXIT_CODE` otherwise.
+    pub fn exit_code(&self) -> ExitCode {
+        if self.is_success() {
+            ExitCode::SUCCESS
+        } else if self.succeeded() == 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::from(PARTIAL_FAILURE_EXIT_CODE)
+        }
+    }
+
+    /// Prints a table with the status of every file, followed by the totals.
+    pub fn print_summary(&self) {
+        let name_width = self
+            .results
+            .iter()
+            .map(|result| result.name.chars().count())
+            .chain(std::iter::once("FILE".len()))
+            .max()
+            .unwrap_or_default();
+
+        println!();
+        println!("{:<9}  {:<name_width$}  REASON", "STATUS", "FILE");
+        for result in &self.results {
+            let (status, reason) = match &result.outcome {
+                Outcome::Succeeded => ("succeeded", ""),
+                Outcome::Failed(reason) => ("failed", reason.as_str()),
+                Outcome::Skipped(reason) => ("skipped", reason.as_str()),
+            };
+            println!(
+                "{:<9}  {:<name_width$}  {}",
+                status,
+                result.name,
+                reason.replace('\n', " ")
+            );
+        }
+        println!(
+            "{} succeeded, {} failed, {} skipped",
+            self.succeeded(),
+            self.failed(),
+            self.skipped()
+        );
+    }
+
+    fn count(&self, predicate: impl Fn(&Outcome) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|result| predicate(&result.outcome))
+            .count()
+    }
+}
+
+/// Runs a batch of file transfers concurrently and reports the outcome of each one.
+/// Transfers that could not be prepared are passed in as errors and reported as failed.
+/// Unless `keep_going` is set, the first failure cancels every transfer that is still running
+/// and those are reported as skipped; with `keep_going` every transfer is attempted.
+/// # Arguments
+/// * `transfers` - The name of each file, paired with its transfer future or the error that prevented it.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// # Returns
+/// A `TransferReport` with one entry per file, in the same order as `transfers`.
+/// # Example
+/// ```
+/// let transfers = vec![("file.txt".to_string(), Ok(async { Ok(()) }))];
+/// let report = run_transfers(transfers, true).await;
+/// assert!(report.is_success());
+/// ```
+pub async fn run_transfers<F>(
+    transfers: Vec<(String, Result<F>)>,
+    keep_going: bool,
+) -> TransferReport
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    let cancelled = "cancelled after an earlier failure";
+    let mut outcomes: Vec<Option<Outcome>> = vec![None; transfers.len()];
+    let mut names = Vec::with_capacity(transfers.len());
+    let mut futures = Vec::new();
+
+    for (index, (name, transfer)) in transfers.into_iter().enumerate() {
+        match transfer {
+            Ok(future) => futures.push((index, future)),
+            Err(err) => outcomes[index] = Some(Outcome::Failed(format!("{:#}", err))),
+        }
+        names.push(name);
+    }
+
+    let prepared = outcomes.iter().all(Option::is_none);
+    if keep_going || prepared {
+        let handles: Vec<_> = futures
+            .into_iter()
+            .map(|(index, future)| (index, tokio::spawn(future)))
+            .collect();
+        let abort_handles: Vec<_> = handles
+            .iter()
+            .map(|(_, handle)| handle.abort_handle())
+            .collect();
+        let mut pending: FuturesUnordered<_> = handles
+            .into_iter()
+            .map(|(index, handle)| async move { (index, handle.await) })
+            .collect();
+
+        while let Some((index, joined)) = pending.next().await {
+            let outcome = match joined {
+                Ok(Ok(())) => Outcome::Succeeded,
+                Ok(Err(err)) => Outcome::Failed(format!("{:#}", err)),
+                Err(err) if err.is_cancelled() => Outcome::Skipped(cancelled.to_string()),
+                Err(err) => Outcome::Failed(err.to_string()),
+            };
+            if !keep_going && matches!(outcome, Outcome::Failed(_)) {
+                abort_handles.iter().for_each(|handle| handle.abort());
+            }
+            outcomes[index] = Some(outcome);
+        }
+    }
+
+    let mut report = TransferReport::default();
+    for (name, outcome) in names.into_iter().zip(outcomes) {
+        report.push(
+            name,
+            outcome.unwrap_or_else(|| Outcome::Skipped(cancelled.to_string())),
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures::future::BoxFuture;
+    use std::time::Duration;
+
+    fn transfer(delay_ms: u64, fail: bool) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            if fail { Err(anyhow!("boom")) } else { Ok(()) }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_run_transfers_keep_going() {
+        let transfers = vec![
+            ("a".to_string(), Ok(transfer(50, false))),
+            ("b".to_string(), Ok(transfer(0, true))),
+            ("c".to_string(), Err(anyhow!("unsafe file name"))),
+        ];
+        let report = run_transfers(transfers, true).await;
+
+        assert_eq!(report.results[0].outcome, Outcome::Succeeded);
+        assert_eq!(report.results[1].outcome, Outcome::Failed("boom".into()));
+        assert_eq!(
+            report.results[2].outcome,
+            Outcome::Failed("unsafe file name".into())
+        );
+        assert_eq!(
+            report.exit_code(),
+            ExitCode::from(PARTIAL_FAILURE_EXIT_CODE)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_transfers_stops_on_first_failure() {
+        let transfers = vec![
+            ("a".to_string(), Ok(transfer(0, true))),
+            ("b".to_string(), Ok(transfer(5_000, false))),
+        ];
+        let report = run_transfers(transfers, false).await;
+
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.exit_code(), ExitCode::FAILURE);
+    }
+
+    #[tokio::test]
+    async fn test_run_transfers_does_not_start_when_preparation_fails() {
+        let transfers = vec![
+            ("a".to_string(), Ok(transfer(0, false))),
+            ("b".to_string(), Err(anyhow!("unsafe file name"))),
+        ];
+        let report = run_transfers(transfers, false).await;
+
+        assert_eq!(report.succeeded(), 0);
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.skipped(), 1);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let mut report = TransferReport::default();
+        assert_eq!(report.exit_code(), ExitCode::SUCCESS);
+        report.push("a", Outcome::Succeeded);
+        assert_eq!(report.exit_code(), ExitCode::SUCCESS);
+        report.push("b", Outcome::Skipped("cancelled".into()));
+        assert_eq!(
+            report.exit_code(),
+            ExitCode::from(PARTIAL_FAILURE_EXIT_CODE)
+        );
+    }
+}
@@ -1,11 +1,23 @@
+use super::checksum::Checksum;
+use super::quota;
+use super::report::{Outcome, TransferReport, run_transfers};
 use super::util::get_file_name_from_path;
+use crate::encryption::{ENCRYPTED_EXTENSION, encrypt};
 use crate::networking::util::{ErrorResponse, get_file_sizes};
+use crate::vcf::bgzf;
+use crate::vcf::can_bgzip;
+use crate::vcf::index::{IndexFormat, Indexer, Preset};
+use age::x25519::Recipient;
 use anyhow::{Result, anyhow};
 use futures::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::{Client as URLClient, header, multipart};
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 
 /// Represents the fields required for uploading files to S3.
@@ -59,6 +71,35 @@ pub async fn get_upload_urls(
     project: Option<&str>,
     files: &[String],
     token: &str,
+) -> Result<Vec<UploadURL>> {
+    let names = files
+        .iter()
+        .map(get_file_name_from_path)
+        .collect::<Result<Vec<String>>>()?;
+    get_named_upload_urls(api, project, files, &names, token).await
+}
+
+/// Fetches upload URLs for local files that are stored under different names in a project.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, uploads to user space.
+/// * `files` - A list of local file paths to upload, used to compute the file sizes.
+/// * `names` - The name under which each file will be stored, in the same order as `files`.
+/// * `token` - The authentication token.
+/// # Returns
+/// A vector of `UploadURL` containing the URLs and fields required for uploading files.
+/// # Errors
+/// Returns an error if the API request fails or if the response cannot be parsed.
+/// # Example
+/// ```
+/// let urls = get_named_upload_urls("https://api.example.com", Some("my_project"), &["/tmp/out.vcf.gz".to_string()], &["sample1.vcf.gz".to_string()], "my_token").await?;
+/// ```
+pub async fn get_named_upload_urls(
+    api: &str,
+    project: Option<&str>,
+    files: &[String],
+    names: &[String],
+    token: &str,
 ) -> Result<Vec<UploadURL>> {
     let client = URLClient::new();
     let file_sizes = get_file_sizes(files)?;
@@ -71,7 +112,7 @@ pub async fn get_upload_urls(
         .json(&serde_json::json!({
             "mode": "upload",
             "project": project,
-            "files": files.iter().map(|file| get_file_name_from_path(file).unwrap()).collect::<Vec<String>>(),
+            "files": names,
             "sizes": file_sizes,
         }))
         .send()
@@ -94,10 +135,10 @@ pub async fn get_upload_urls(
 /// # Arguments
 /// * `urls` - A vector of `UploadURL` containing the URLs and fields for uploading files.
 /// * `file_paths` - A slice of file paths to upload.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
 /// # Returns
-/// A `Result` indicating success or failure.
-/// # Errors
-/// Returns an error if any of the uploads fail or if file operations fail.
+/// A `TransferReport` with the outcome of every upload. Without `keep_going`, the first failure
+/// cancels the remaining uploads and they are reported as skipped.
 /// # Example
 /// ```
 /// let urls = vec![
@@ -130,43 +171,87 @@ pub async fn get_upload_urls(
 ///     "/path/to/file1.txt".to_string(),
 ///     "/path/to/file2.txt".to_string(),
 /// ];
-/// upload_files(urls, &file_paths).await?;
-/// for url in urls {
-///     println!("Uploaded to: {}", url.url);
-/// }
+/// let report = upload_files(urls, &file_paths, true).await?;
+/// report.print_summary();
+/// ```
+pub async fn upload_files(
+    urls: Vec<UploadURL>,
+    file_paths: &[String],
+    keep_going: bool,
+) -> Result<TransferReport> {
+    let targets = urls
+        .into_iter()
+        .zip(file_paths.iter())
+        .map(|(url, file_path)| {
+            let name = get_file_name_from_path(file_path)?;
+            let target = UploadTarget {
+                url,
+                path: file_path.clone(),
+                checksum: None,
+            };
+            Ok((name, Ok(target)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(upload_targets(targets, keep_going).await)
+}
+
+/// Represents a local file to upload and the presigned URL it will be uploaded to.
+pub struct UploadTarget {
+    pub url: UploadURL,
+    pub path: String,
+    pub checksum: Option<Checksum>,
+}
+
+/// Uploads files to their presigned URLs, verifying their checksums before uploading when one is given.
+/// # Arguments
+/// * `targets` - The name of each file as shown in the report, paired with its `UploadTarget`
+///   or the error that prevented it from being prepared.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// # Returns
+/// A `TransferReport` with the outcome of every upload, in the same order as `targets`.
+/// A file whose checksum does not match is reported as failed and is not uploaded.
+/// # Example
+/// ```
+/// let targets = vec![(
+///     "sample1.vcf.gz".to_string(),
+///     Ok(UploadTarget {
+///         url,
+///         path: "/path/to/sample1.vcf.gz".to_string(),
+///         checksum: Some("md5:d41d8cd98f00b204e9800998ecf8427e".parse()?),
+///     }),
+/// )];
+/// let report = upload_targets(targets, false).await;
 /// ```
-pub async fn upload_files(urls: Vec<UploadURL>, file_paths: &[String]) -> Result<()> {
+pub async fn upload_targets(
+    targets: Vec<(String, Result<UploadTarget>)>,
+    keep_going: bool,
+) -> TransferReport {
     let multi_progress = Arc::new(MultiProgress::new());
-    let upload_futures = urls
-            .into_iter()
-            .zip(file_paths.iter())
-            .map(|(url, file_path)| {
-                let file_path = file_path.clone();
-                let multi_progress = multi_progress.clone();
-
-                tokio::spawn(async move {
-                    let file_name = get_file_name_from_path(&file_path).unwrap();
-                    let pb = multi_progress.add(ProgressBar::new(0));
-                    pb.set_style(
-                        ProgressStyle::default_bar()
-                            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
-                            .unwrap()
-                        .progress_chars("#>-"),
-                );
-                pb.set_message(format!("uploading {}", file_name));
-
-                upload_file_with_progress(url, &file_path, pb).await
-            })
-        });
-    futures::future::try_join_all(upload_futures).await?;
+    let transfers = targets
+        .into_iter()
+        .map(|(name, target)| {
+            let multi_progress = multi_progress.clone();
+            let target = target.map(|target| async move {
+                if let Some(checksum) = &target.checksum {
+                    checksum.verify(&target.path).await?;
+                }
+                let file_name = get_file_name_from_path(&target.path)?;
+                let pb = multi_progress.add(upload_progress_bar(&file_name));
+                upload_file_with_progress(target.url, &target.path, &file_name, pb).await
+            });
+            (name, target)
+        })
+        .collect();
 
-    Ok(())
+    run_transfers(transfers, keep_going).await
 }
 
 /// Uploads a file to the specified URL with progress tracking.
 /// # Arguments
 /// * `url` - The `UploadURL` containing the URL and fields for uploading the file.
 /// * `file_path` - The path to the file to upload.
+/// * `file_name` - The name of the file, used for progress messages.
 /// * `pb` - A `ProgressBar` instance for tracking upload progress.
 /// # Returns
 /// A `Result` indicating success or failure.
@@ -188,10 +273,15 @@ pub async fn upload_files(urls: Vec<UploadURL>, file_paths: &[String]) -> Result
 /// };
 /// let file_path = "/path/to/file.txt";
 /// let pb = ProgressBar::new(0);
-/// upload_file_with_progress(url, file_path, pb).await?;
+/// upload_file_with_progress(url, file_path, "file.txt", pb).await?;
 /// ```
-async fn upload_file_with_progress(url: UploadURL, file_path: &str, pb: ProgressBar) -> Result<()> {
-    let file_name = get_file_name_from_path(file_path)?;
+async fn upload_file_with_progress(
+    url: UploadURL,
+    file_path: &str,
+    file_name: &str,
+    pb: ProgressBar,
+) -> Result<()> {
+    let file_name = file_name.to_string();
     let client = URLClient::new();
     let file = File::open(file_path).await?;
     let total_size = file.metadata().await?.len();
@@ -242,9 +332,390 @@ async fn upload_file_with_progress(url: UploadURL, file_path: &str, pb: Progress
     }
 }
 
+/// Uploads data of unknown size, such as standard input, to user space or a project.
+/// Presigned uploads need the size of the data in advance, so the data is first copied to a
+/// temporary file (in `TMPDIR`), which is removed once the upload is done.
+/// Once the size is known, uploads to user space are checked against the user's storage quota.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, uploads to user space.
+/// * `name` - The name under which the data will be stored.
+/// * `token` - The authentication token.
+/// * `reader` - The data to upload.
+/// # Returns
+/// A `Result` containing the number of bytes uploaded.
+/// # Errors
+/// Returns an error if reading or spooling the data fails, if the data would exceed the quota,
+/// or if the upload fails.
+/// # Example
+/// ```
+/// let sent = upload_stream("https://api.example.com", Some("my_project"), "out.vcf.gz", "my_token", tokio::io::stdin()).await?;
+/// println!("Uploaded {} bytes", sent);
+/// ```
+pub async fn upload_stream<R: AsyncRead + Unpin>(
+    api: &str,
+    project: Option<&str>,
+    name: &str,
+    token: &str,
+    mut reader: R,
+) -> Result<u64> {
+    let spooled = tempfile::NamedTempFile::new()?;
+    let pb = encode_progress_bar(name);
+    let mut file = File::create(spooled.path()).await?;
+    let mut buffer = vec![0; 1 << 20];
+    let mut size = 0u64;
+    loop {
+        let read = match reader.read(&mut buffer).await {
+            Ok(read) => read,
+            Err(err) => {
+                pb.finish_with_message(format!("✗ {}", name));
+                return Err(err.into());
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read]).await?;
+        size += read as u64;
+        pb.set_message(format!("reading {} ({} read)", name, HumanBytes(size)));
+    }
+    file.flush().await?;
+    pb.finish_and_clear();
+    if project.is_none() {
+        quota::check_upload_quota(api, size, token).await?;
+    }
+    upload_named(api, project, spooled.path(), name, token).await?;
+    Ok(size)
+}
+
+/// Uploads a local file under a given name, requesting its presigned URL first.
+async fn upload_named(
+    api: &str,
+    project: Option<&str>,
+    path: &Path,
+    name: &str,
+    token: &str,
+) -> Result<()> {
+    let path = path.to_string_lossy().into_owned();
+    let url = get_named_upload_urls(
+        api,
+        project,
+        std::slice::from_ref(&path),
+        &[name.to_string()],
+        token,
+    )
+    .await?
+    .into_iter()
+    .next()
+    .ok_or(anyhow!("No upload URL returned for {}.", name))?;
+    upload_file_with_progress(url, &path, name, upload_progress_bar(name)).await
+}
+
+/// Describes how local files are transformed while they are streamed to the API.
+#[derive(Clone, Default)]
+pub struct StreamEncoding {
+    /// Compress plain-text VCF, BED and GFF files with bgzip.
+    pub bgzip: bool,
+    /// Build an index of each compressed file in this format.
+    pub index: Option<IndexFormat>,
+    /// Encrypt each file and its index to these recipients.
+    pub recipients: Vec<Recipient>,
+}
+
+impl StreamEncoding {
+    /// Returns `true` if files are encrypted.
+    pub fn encrypts(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Resolves the encoding of a single file, which is only compressed if it is plain text.
+    fn for_file(&self, path: &str) -> Result<StreamEncoding> {
+        Ok(StreamEncoding {
+            bgzip: self.bgzip && can_bgzip(path)?,
+            index: self.index,
+            recipients: self.recipients.clone(),
+        })
+    }
+
+    /// Returns the name under which a local file is stored once uploaded with this encoding,
+    /// such as `sample.vcf.gz` for `data/sample.vcf` with `bgzip`.
+    /// # Errors
+    /// Returns an error if the file name cannot be determined or the file cannot be read.
+    pub fn stored_file_name(&self, path: &str) -> Result<String> {
+        Ok(self
+            .for_file(path)?
+            .stored_name(&get_file_name_from_path(path)?))
+    }
+
+    /// Returns the name under which an encoded file is stored:
+    /// `.gz` is added when compressing and `.age` when encrypting.
+    fn stored_name(&self, name: &str) -> String {
+        let mut stored = name.to_string();
+        if self.bgzip {
+            stored.push_str(".gz");
+        }
+        if self.encrypts() {
+            stored.push_str(ENCRYPTED_EXTENSION);
+        }
+        stored
+    }
+}
+
+/// Uploads a file after compressing and/or encrypting it.
+/// Plain-text VCF, BED and GFF files are compressed with bgzip when `encoding.bgzip` is set and,
+/// when `encoding.index` is also set, a tabix or CSI index is built from the compressed data
+/// and uploaded next to it. With recipients, the file and its index are encrypted with age, since
+/// the index reveals the contigs and regions the file covers; its offsets point into the decrypted file.
+/// The encoded file is written to a temporary file (in `TMPDIR`), since presigned uploads need
+/// its size in advance, and removed once it is uploaded.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, uploads to user space.
+/// * `path` - The path to the file, or `-` for standard input.
+/// * `name` - The name of the file; `.gz` and `.age` are added as needed.
+/// * `token` - The authentication token.
+/// * `encoding` - How to transform the file.
+/// # Returns
+/// A `Result` containing the number of bytes uploaded.
+/// # Errors
+/// Returns an error if the file cannot be compressed, indexed or encrypted, or if the upload fails.
+/// Nothing is uploaded if encoding fails.
+/// # Example
+/// ```
+/// let encoding = StreamEncoding { bgzip: true, index: Some(IndexFormat::Tbi), recipients: Vec::new() };
+/// let sent = upload_encoded("https://api.example.com", Some("my_project"), "sample.vcf", "sample.vcf", "my_token", &encoding).await?;
+/// ```
+pub async fn upload_encoded(
+    api: &str,
+    project: Option<&str>,
+    path: &str,
+    name: &str,
+    token: &str,
+    encoding: &StreamEncoding,
+) -> Result<u64> {
+    let encoding = encoding.for_file(path)?;
+    let stored_name = encoding.stored_name(name);
+    let pb = encode_progress_bar(&stored_name);
+    let (encoded, original, index_data) =
+        match encode_to_file(path, &stored_name, &encoding, &pb).await {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                pb.finish_with_message(format!("✗ {}", stored_name));
+                return Err(err);
+            }
+        };
+    pb.finish_with_message(format!(
+        "✓ encoded {} (from {})",
+        stored_name,
+        HumanBytes(original)
+    ));
+    let sent = encoded.as_file().metadata()?.len();
+    upload_named(api, project, encoded.path(), &stored_name, token).await?;
+
+    if let (Some(format), Some(mut index_data)) = (encoding.index, index_data) {
+        let mut index_name = format!("{}.gz.{}", name, format.extension());
+        if encoding.encrypts() {
+            let mut encrypted = encrypt(&encoding.recipients, Vec::new())?;
+            encrypted.write_all(&index_data)?;
+            index_data = encrypted.finish()?;
+            index_name.push_str(ENCRYPTED_EXTENSION);
+        }
+        upload_stream(api, project, &index_name, token, &index_data[..]).await?;
+    }
+    Ok(sent)
+}
+
+/// Uploads files one after the other with `upload_encoded`.
+/// The outcomes are appended to `report`; unless `keep_going` is set, the files after the first
+/// failure in the report are skipped.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, uploads to user space.
+/// * `files` - The paths of the files.
+/// * `token` - The authentication token.
+/// * `encoding` - How to transform the files.
+/// * `keep_going` - Whether to attempt every file even if some of them fail.
+/// * `report` - The report to which the outcome of each file is added.
+/// # Returns
+/// A `Result` indicating whether the file names could be determined.
+/// # Example
+/// ```
+/// let mut report = TransferReport::default();
+/// let encoding = StreamEncoding { bgzip: true, ..Default::default() };
+/// upload_encoded_files("https://api.example.com", None, &["sample.vcf".to_string()], "my_token", &encoding, false, &mut report).await?;
+/// ```
+pub async fn upload_encoded_files(
+    api: &str,
+    project: Option<&str>,
+    files: &[String],
+    token: &str,
+    encoding: &StreamEncoding,
+    keep_going: bool,
+    report: &mut TransferReport,
+) -> Result<()> {
+    for file in files {
+        let name = get_file_name_from_path(file)?;
+        if !keep_going && !report.is_success() {
+            report.push(
+                name,
+                Outcome::Skipped("cancelled after an earlier failure".to_string()),
+            );
+            continue;
+        }
+        let outcome = match upload_encoded(api, project, file, &name, token, encoding).await {
+            Ok(_) => Outcome::Succeeded,
+            Err(err) => Outcome::Failed(format!("{:#}", err)),
+        };
+        report.push(name, outcome);
+    }
+    Ok(())
+}
+
+/// Encodes a file into a temporary file on a blocking thread.
+/// Returns the temporary file, the original size and, when an index is built, the index.
+async fn encode_to_file(
+    path: &str,
+    name: &str,
+    encoding: &StreamEncoding,
+    pb: &ProgressBar,
+) -> Result<(tempfile::NamedTempFile, u64, Option<Vec<u8>>)> {
+    let source = path.to_string();
+    let name = name.to_string();
+    let encoding = encoding.clone();
+    let pb = pb.clone();
+    tokio::task::spawn_blocking(move || {
+        let encoded = tempfile::NamedTempFile::new()?;
+        let (original, index) = encode_file(&source, &encoding, encoded.as_file(), &pb, &name)?;
+        Ok((encoded, original, index))
+    })
+    .await?
+}
+
+/// Encodes a file into `output`, encrypting whatever `copy_encoded` writes when there are recipients.
+fn encode_file(
+    path: &str,
+    encoding: &StreamEncoding,
+    output: impl io::Write,
+    pb: &ProgressBar,
+    name: &str,
+) -> Result<(u64, Option<Vec<u8>>)> {
+    let input: Box<dyn io::Read> = match path {
+        "-" => Box::new(io::stdin()),
+        _ => Box::new(std::fs::File::open(path)?),
+    };
+    let input = io::BufReader::new(input);
+    let output = io::BufWriter::with_capacity(1 << 20, output);
+    if !encoding.encrypts() {
+        return copy_encoded(input, path, encoding, output, pb, name);
+    }
+    let mut encrypted = encrypt(&encoding.recipients, output)?;
+    let encoded = copy_encoded(input, path, encoding, &mut encrypted, pb, name)?;
+    encrypted.finish()?.flush()?;
+    Ok(encoded)
+}
+
+/// Copies a file into `output`, compressing it line by line into BGZF blocks when `encoding.bgzip`
+/// is set and feeding each line to the indexer if any.
+/// The progress bar counts the bytes sent, so the original size is shown in its message.
+fn copy_encoded<W: io::Write>(
+    mut input: impl io::BufRead,
+    path: &str,
+    encoding: &StreamEncoding,
+    mut output: W,
+    pb: &ProgressBar,
+    name: &str,
+) -> Result<(u64, Option<Vec<u8>>)> {
+    let mut original = 0u64;
+    let mut progress = |read: usize| {
+        original += read as u64;
+        if original % (1 << 20) < read as u64 {
+            pb.set_message(format!(
+                "uploading {} ({} read)",
+                name,
+                HumanBytes(original)
+            ));
+        }
+        original
+    };
+
+    if !encoding.bgzip {
+        loop {
+            let buffer = input.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            let read = buffer.len();
+            output.write_all(buffer)?;
+            input.consume(read);
+            progress(read);
+        }
+        output.flush()?;
+        return Ok((progress(0), None));
+    }
+
+    let preset = Preset::for_path(path).ok_or(anyhow!(
+        "Cannot compress {}: not a VCF, BED or GFF file.",
+        path
+    ))?;
+    let mut writer = bgzf::Writer::new(output);
+    let mut indexer = encoding.index.map(|format| Indexer::new(preset, format));
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let start = writer.virtual_position();
+        let read = input.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&line)?;
+        if let Some(indexer) = indexer.as_mut() {
+            indexer.push(&line, start, writer.virtual_position())?;
+        }
+        progress(read);
+    }
+    writer.finish()?.flush()?;
+
+    let index = match indexer {
+        Some(indexer) => {
+            let mut data = Vec::new();
+            indexer.write(&mut data)?;
+            Some(data)
+        }
+        None => None,
+    };
+    Ok((progress(0), index))
+}
+
+/// Creates the spinner shown while a file is read or encoded before it is uploaded.
+fn encode_progress_bar(name: &str) -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    pb.set_message(format!("reading {}", name));
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// Creates the progress bar shown while a file is uploaded.
+fn upload_progress_bar(name: &str) -> ProgressBar {
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb.set_message(format!("uploading {}", name));
+    pb
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
     use mockito::{Matcher, Server};
     use std::io::Write;
     use tempfile::NamedTempFile;
@@ -296,18 +767,279 @@ mod tests {
         let mut tmp2 = NamedTempFile::new_in("/tmp").unwrap();
         tmp2.as_file_mut().set_len(x_bytes).unwrap();
 
-        assert!(
-            upload_files(
-                urls,
-                &[
-                    tmp1.path().to_str().unwrap().into(),
-                    tmp2.path().to_str().unwrap().into(),
-                ],
+        let report = upload_files(
+            urls,
+            &[
+                tmp1.path().to_str().unwrap().into(),
+                tmp2.path().to_str().unwrap().into(),
+            ],
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(report.is_success());
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_upload_files_keep_going() {
+        let mut server = Server::new_async().await;
+        let accepted = server.mock("POST", "/accepted").with_status(204).create();
+        let rejected = server.mock("POST", "/rejected").with_status(403).create();
+
+        let url = |path: &str| UploadURL {
+            url: format!("{}{}", server.url(), path),
+            fields: Fields {
+                key: "private/asdasd/uploads/asdasdasd/asdasdasd.vcf.gz".to_string(),
+                algorithm: "AWS4-HMAC-SHA256".to_string(),
+                credential: "CRED".to_string(),
+                date: "20250709T042302Z".to_string(),
+                token: "TOKEN".to_string(),
+                policy: "POL".to_string(),
+                signature: "123123123".to_string(),
+            },
+        };
+        let urls = vec![url("/rejected"), url("/accepted")];
+
+        let mut tmp1 = NamedTempFile::new_in("/tmp").unwrap();
+        write!(tmp1, "1234").unwrap();
+        let mut tmp2 = NamedTempFile::new_in("/tmp").unwrap();
+        write!(tmp2, "5678").unwrap();
+
+        let report = upload_files(
+            urls,
+            &[
+                tmp1.path().to_str().unwrap().into(),
+                tmp2.path().to_str().unwrap().into(),
+            ],
+            true,
+        )
+        .await
+        .unwrap();
+        accepted.assert();
+        rejected.assert();
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.succeeded(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_stream() {
+        let mut server = Server::new_async().await;
+        let urls = server
+            .mock("POST", "/dportal/cli")
+            .match_body(Matcher::Json(serde_json::json!({
+                "mode": "upload",
+                "project": "test_project",
+                "files": ["out.vcf.gz"],
+                "sizes": [10]
+            })))
+            .with_status(200)
+            .with_body(
+                serde_json::json!([{
+                    "url": format!("{}/bucket", server.url()),
+                    "fields": {
+                        "key": "projects/test_project/out.vcf.gz",
+                        "x-amz-algorithm": "AWS4-HMAC-SHA256",
+                        "x-amz-credential": "CRED",
+                        "x-amz-date": "20250709T042302Z",
+                        "x-amz-security-token": "TOKEN",
+                        "policy": "POL",
+                        "x-amz-signature": "123123123"
+                    }
+                }])
+                .to_string(),
             )
-            .await
-            .is_ok()
+            .create();
+        let upload = server
+            .mock("POST", "/bucket")
+            .match_body(Matcher::Regex("abcdefghij".into()))
+            .with_status(204)
+            .create();
+
+        let sent = upload_stream(
+            &server.url(),
+            Some("test_project"),
+            "out.vcf.gz",
+            "my_token",
+            &b"abcdefghij"[..],
+        )
+        .await
+        .unwrap();
+        assert_eq!(sent, 10);
+        urls.assert();
+        upload.assert();
+    }
+
+    #[tokio::test]
+    async fn test_upload_stream_over_quota() {
+        let token = format!(
+            "header.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"sub": "user-1"}"#)
         );
-        mock.assert();
+        let mut server = Server::new_async().await;
+        let quota = server
+            .mock("GET", "/dportal/quota/user-1")
+            .with_status(200)
+            .with_body(
+                r#"{"success": true, "data": {"IdentityUser": "user-1", "Usage": {
+                    "quotaSize": 100, "usageSize": 95, "quotaQueryCount": 10, "usageCount": 0}}}"#,
+            )
+            .create();
+        let urls = server.mock("POST", "/dportal/cli").expect(0).create();
+
+        let err = upload_stream(&server.url(), None, "out.txt", &token, &b"abcdefghij"[..])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("quota"), "{}", err);
+        quota.assert();
+        urls.assert();
+    }
+
+    fn plain_vcf(records: &str) -> NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".vcf").tempfile().unwrap();
+        write!(
+            file,
+            "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n{}",
+            records
+        )
+        .unwrap();
+        file
+    }
+
+    fn bgzip_encoding(index: Option<IndexFormat>) -> StreamEncoding {
+        StreamEncoding {
+            bgzip: true,
+            index,
+            recipients: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encode_to_file_bgzip() {
+        let file = plain_vcf("chr1\t100\t.\tA\tG\t.\tPASS\t.\nchr2\t50\t.\tC\tT\t.\tPASS\t.\n");
+        let path = file.path().to_str().unwrap();
+        let (encoded, original, index) = encode_to_file(
+            path,
+            "sample.vcf.gz",
+            &bgzip_encoding(Some(IndexFormat::Tbi)),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        let compressed = std::fs::read(encoded.path()).unwrap();
+
+        let mut decompressed = Vec::new();
+        io::Read::read_to_end(&mut bgzf::Reader::new(&compressed[..]), &mut decompressed).unwrap();
+        assert_eq!(decompressed, std::fs::read(path).unwrap());
+        assert_eq!(original, decompressed.len() as u64);
+        assert!(compressed.ends_with(&bgzf::EOF_BLOCK));
+
+        let mut index_data = Vec::new();
+        io::Read::read_to_end(&mut bgzf::Reader::new(&index.unwrap()[..]), &mut index_data)
+            .unwrap();
+        assert_eq!(&index_data[..4], b"TBI\x01");
+    }
+
+    #[tokio::test]
+    async fn test_encode_to_file_fails_on_indexing_errors() {
+        let file = plain_vcf("chr1\t100\t.\tA\tG\t.\tPASS\t.\nchr1\t50\t.\tC\tT\t.\tPASS\t.\n");
+        let path = file.path().to_str().unwrap();
+        let err = encode_to_file(
+            path,
+            "sample.vcf.gz",
+            &bgzip_encoding(Some(IndexFormat::Tbi)),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not sorted"));
+    }
+
+    #[tokio::test]
+    async fn test_encode_to_file_encrypts() {
+        let file = plain_vcf("chr1\t100\t.\tA\tG\t.\tPASS\t.\n");
+        let path = file.path().to_str().unwrap();
+        let identity = age::x25519::Identity::generate();
+        let encoding = StreamEncoding {
+            recipients: vec![identity.to_public()],
+            ..bgzip_encoding(None)
+        };
+        assert_eq!(encoding.stored_name("sample.vcf"), "sample.vcf.gz.age");
+        let (encoded, _, _) =
+            encode_to_file(path, "sample.vcf.gz.age", &encoding, &ProgressBar::hidden())
+                .await
+                .unwrap();
+        let encrypted = std::fs::read(encoded.path()).unwrap();
+
+        let mut compressed = Vec::new();
+        crate::encryption::decrypt(&[identity], &encrypted[..], &mut compressed).unwrap();
+        let mut decompressed = Vec::new();
+        io::Read::read_to_end(&mut bgzf::Reader::new(&compressed[..]), &mut decompressed).unwrap();
+        assert_eq!(decompressed, std::fs::read(path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_upload_encoded_encrypts_index() {
+        let file = plain_vcf("chr1\t100\t.\tA\tG\t.\tPASS\t.\n");
+        let path = file.path().to_str().unwrap();
+        let mut server = Server::new_async().await;
+        let bucket_url = format!("{}/bucket", server.url());
+        let upload_url = |name: &str| {
+            serde_json::json!([{
+                "url": bucket_url,
+                "fields": {
+                    "key": format!("projects/p1/{}", name),
+                    "x-amz-algorithm": "AWS4-HMAC-SHA256",
+                    "x-amz-credential": "CRED",
+                    "x-amz-date": "20250709T042302Z",
+                    "x-amz-security-token": "TOKEN",
+                    "policy": "POL",
+                    "x-amz-signature": "123123123"
+                }
+            }])
+            .to_string()
+        };
+        let mut mocks = Vec::new();
+        for name in ["sample.vcf.gz.age", "sample.vcf.gz.tbi.age"] {
+            mocks.push(
+                server
+                    .mock("POST", "/dportal/cli")
+                    .match_body(Matcher::PartialJson(serde_json::json!({"files": [name]})))
+                    .with_status(200)
+                    .with_body(upload_url(name))
+                    .create(),
+            );
+        }
+        let plain_index = server
+            .mock("POST", "/dportal/cli")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"files": ["sample.vcf.gz.tbi"]}),
+            ))
+            .expect(0)
+            .create();
+        let bucket = server
+            .mock("POST", "/bucket")
+            .with_status(204)
+            .expect(2)
+            .create();
+
+        let encoding = StreamEncoding {
+            recipients: vec![age::x25519::Identity::generate().to_public()],
+            ..bgzip_encoding(Some(IndexFormat::Tbi))
+        };
+        upload_encoded(
+            &server.url(),
+            Some("p1"),
+            path,
+            "sample.vcf",
+            "token",
+            &encoding,
+        )
+        .await
+        .unwrap();
+        mocks.iter().for_each(|mock| mock.assert());
+        plain_index.assert();
+        bucket.assert();
     }
 
     #[tokio::test]
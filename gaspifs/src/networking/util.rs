@@ -1,5 +1,4 @@
 use anyhow::{Result, anyhow};
-#[cfg(feature = "uploads")]
 use std::path::Path;
 
 #[derive(Debug, serde::Deserialize)]
@@ -49,7 +48,6 @@ pub fn parse_url(url: &str) -> Result<(String, String)> {
 /// let file_name = get_file_name_from_path(file_path).unwrap();
 /// assert_eq!(file_name, "file.txt");
 /// ```
-#[cfg(feature = "uploads")]
 pub fn get_file_name_from_path<P: AsRef<Path>>(file_path: P) -> Result<String> {
     file_path
         .as_ref()
@@ -102,7 +100,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "uploads")]
     fn test_get_file_name_from_path() {
         let file_path = "/path/to/file.txt";
         let file_name = get_file_name_from_path(file_path).unwrap();
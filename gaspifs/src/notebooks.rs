@@ -0,0 +1,345 @@
+use crate::networking::api::{self, OutputFormat, format_table};
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// The status of a notebook that can be opened.
+pub const IN_SERVICE: &str = "InService";
+/// The status of a notebook that has been stopped.
+pub const STOPPED: &str = "Stopped";
+
+/// A notebook instance of the user, as described by the portal.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Notebook {
+    #[serde(default)]
+    pub name: String,
+    pub status: String,
+    pub instance_type: String,
+    pub volume_size: u64,
+}
+
+/// Checks that a notebook name has 5 to 30 letters, digits or `-`, as the portal requires.
+/// # Errors
+/// Returns an error describing the expected name if it is invalid.
+pub fn validate_name(name: &str) -> Result<()> {
+    if (5..=30).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid notebook name {:?}: use 5 to 30 letters, digits or '-'.",
+            name
+        ))
+    }
+}
+
+/// Lists the names of the notebooks of the user.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the names of the notebooks.
+/// # Errors
+/// Returns an error if the request fails.
+/// # Example
+/// ```
+/// let names = list_notebooks(&api, &id_token).await?;
+/// ```
+pub async fn list_notebooks(api: &str, token: &str) -> Result<Vec<String>> {
+    let response = api::get(api, "dportal/notebooks", &[], token).await?;
+    Ok(serde_json::from_value(response)?)
+}
+
+/// Gets the status, instance type and volume size of a notebook.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the notebook.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the notebook.
+/// # Errors
+/// Returns an error if the notebook does not exist or the request fails.
+/// # Example
+/// ```
+/// let notebook = get_notebook(&api, "my-notebook", &id_token).await?;
+/// println!("{}", notebook.status);
+/// ```
+pub async fn get_notebook(api: &str, name: &str, token: &str) -> Result<Notebook> {
+    let response = api::get(api, &format!("dportal/notebooks/{}", name), &[], token).await?;
+    let mut notebook: Notebook = serde_json::from_value(response)
+        .map_err(|_| anyhow!("Notebook {} was not found.", name))?;
+    notebook.name = name.to_string();
+    Ok(notebook)
+}
+
+/// Creates a notebook instance, which the portal then starts.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the notebook.
+/// * `instance_type` - The instance type, such as `ml.t3.medium`.
+/// * `volume_size` - The size of the volume in GB.
+/// * `identity_id` - The identity ID of the user, returned by `auth::identity_id`.
+/// * `token` - The authentication token.
+/// # Errors
+/// Returns an error if the name is invalid, or with the message of the API if the notebook cannot be created.
+/// # Example
+/// ```
+/// create_notebook(&api, "my-notebook", "ml.t3.medium", 5, &identity_id, &id_token).await?;
+/// ```
+pub async fn create_notebook(
+    api: &str,
+    name: &str,
+    instance_type: &str,
+    volume_size: u64,
+    identity_id: &str,
+    token: &str,
+) -> Result<()> {
+    validate_name(name)?;
+    let body = json!({
+        "instanceName": name,
+        "instanceType": instance_type,
+        "volumeSize": volume_size,
+        "identityId": identity_id,
+    });
+    api::post(api, "dportal/notebooks", &body, token).await?;
+    Ok(())
+}
+
+/// Changes the instance type and/or volume size of a stopped notebook.
+/// Values that are not given are kept.
+/// # Errors
+/// Returns an error if the notebook cannot be read or updated.
+/// # Example
+/// ```
+/// update_notebook(&api, "my-notebook", Some("ml.t3.xlarge"), None, &id_token).await?;
+/// ```
+pub async fn update_notebook(
+    api: &str,
+    name: &str,
+    instance_type: Option<&str>,
+    volume_size: Option<u64>,
+    token: &str,
+) -> Result<()> {
+    let current = get_notebook(api, name, token).await?;
+    let body = json!({
+        "instanceType": instance_type.unwrap_or(&current.instance_type),
+        "volumeSize": volume_size.unwrap_or(current.volume_size),
+    });
+    api::put(api, &format!("dportal/notebooks/{}", name), &body, token).await?;
+    Ok(())
+}
+
+/// Starts a stopped notebook.
+/// # Errors
+/// Returns an error if the request fails.
+pub async fn start_notebook(api: &str, name: &str, token: &str) -> Result<()> {
+    notebook_action(api, name, "start", token).await
+}
+
+/// Stops a running notebook, keeping its volume.
+/// # Errors
+/// Returns an error if the request fails.
+pub async fn stop_notebook(api: &str, name: &str, token: &str) -> Result<()> {
+    notebook_action(api, name, "stop", token).await
+}
+
+/// Deletes a stopped notebook and its volume.
+/// # Errors
+/// Returns an error if the request fails.
+pub async fn delete_notebook(api: &str, name: &str, token: &str) -> Result<()> {
+    notebook_action(api, name, "delete", token).await
+}
+
+async fn notebook_action(api: &str, name: &str, action: &str, token: &str) -> Result<()> {
+    let endpoint = format!("dportal/notebooks/{}/{}", name, action);
+    api::post(api, &endpoint, &json!({}), token).await?;
+    Ok(())
+}
+
+/// Gets a signed URL that opens a running notebook in the browser.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the notebook.
+/// * `token` - The authentication token.
+/// # Returns
+/// A `Result` containing the URL.
+/// # Errors
+/// Returns an error if the request fails or no URL is returned.
+/// # Example
+/// ```
+/// println!("{}", notebook_url(&api, "my-notebook", &id_token).await?);
+/// ```
+pub async fn notebook_url(api: &str, name: &str, token: &str) -> Result<String> {
+    let endpoint = format!("dportal/notebooks/{}/url", name);
+    let response = api::get(api, &endpoint, &[], token).await?;
+    response["AuthorizedUrl"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or(anyhow!(
+            "No URL returned for notebook {}; is it in service?",
+            name
+        ))
+}
+
+/// Waits for a notebook to reach a status, such as `InService` after it is created or started.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `name` - The name of the notebook.
+/// * `status` - The status to wait for.
+/// * `token` - The authentication token.
+/// * `interval` - How long to wait between checks.
+/// * `timeout` - How long to wait in total.
+/// # Returns
+/// A `Result` containing the notebook once it has the status.
+/// # Errors
+/// Returns an error if the notebook fails, or does not reach the status before the timeout.
+/// # Example
+/// ```
+/// wait_for_status(&api, "my-notebook", IN_SERVICE, &id_token, Duration::from_secs(15), Duration::from_secs(900)).await?;
+/// ```
+pub async fn wait_for_status(
+    api: &str,
+    name: &str,
+    status: &str,
+    token: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<Notebook> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    pb.enable_steady_tick(Duration::from_millis(200));
+
+    let started = Instant::now();
+    loop {
+        let notebook = get_notebook(api, name, token).await?;
+        pb.set_message(format!("{}: {}", name, notebook.status));
+        if notebook.status == status {
+            pb.finish_with_message(format!("✓ {}: {}", name, notebook.status));
+            return Ok(notebook);
+        }
+        if notebook.status == "Failed" {
+            pb.finish_with_message(format!("✗ {}: {}", name, notebook.status));
+            return Err(anyhow!("Notebook {} failed.", name));
+        }
+        if started.elapsed() >= timeout {
+            pb.finish_with_message(format!("✗ {}: {}", name, notebook.status));
+            return Err(anyhow!(
+                "Notebook {} is still {} after {} seconds.",
+                name,
+                notebook.status,
+                timeout.as_secs()
+            ));
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Formats notebooks for printing, as a table of names, statuses, instance types and volume sizes or as JSON.
+pub fn format_notebooks(notebooks: &[Notebook], format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(notebooks)?));
+    }
+    let rows: Vec<Vec<String>> = notebooks
+        .iter()
+        .map(|notebook| {
+            vec![
+                notebook.name.clone(),
+                notebook.status.clone(),
+                notebook.instance_type.clone(),
+                format!("{} GB", notebook.volume_size),
+            ]
+        })
+        .collect();
+    Ok(format_table(
+        &["NAME", "STATUS", "INSTANCE TYPE", "VOLUME"],
+        &rows,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("my-notebook-1").is_ok());
+        assert!(validate_name("nb").is_err());
+        assert!(validate_name("my_notebook").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_notebook() {
+        let mut server = Server::new_async().await;
+        let get = server
+            .mock("GET", "/dportal/notebooks/my-notebook")
+            .with_status(200)
+            .with_body(r#"{"status": "Stopped", "instanceType": "ml.t3.medium", "volumeSize": 5}"#)
+            .create();
+        let put = server
+            .mock("PUT", "/dportal/notebooks/my-notebook")
+            .match_body(Matcher::Json(
+                json!({"instanceType": "ml.t3.medium", "volumeSize": 20}),
+            ))
+            .with_status(200)
+            .create();
+        update_notebook(&server.url(), "my-notebook", None, Some(20), "token")
+            .await
+            .unwrap();
+        get.assert();
+        put.assert();
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_status() {
+        let mut server = Server::new_async().await;
+        let pending = server
+            .mock("GET", "/dportal/notebooks/my-notebook")
+            .with_status(200)
+            .with_body(r#"{"status": "Pending", "instanceType": "ml.t3.medium", "volumeSize": 5}"#)
+            .expect(1)
+            .create();
+        let notebook = wait_for_status(
+            &server.url(),
+            "my-notebook",
+            IN_SERVICE,
+            "token",
+            Duration::from_millis(10),
+            Duration::ZERO,
+        )
+        .await;
+        assert!(notebook.unwrap_err().to_string().contains("still Pending"));
+        pending.assert();
+        pending.remove();
+
+        server
+            .mock("GET", "/dportal/notebooks/my-notebook")
+            .with_status(200)
+            .with_body(
+                r#"{"status": "InService", "instanceType": "ml.t3.medium", "volumeSize": 5}"#,
+            )
+            .create();
+        let notebook = wait_for_status(
+            &server.url(),
+            "my-notebook",
+            IN_SERVICE,
+            "token",
+            Duration::from_millis(10),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(notebook.name, "my-notebook");
+        assert_eq!(
+            format_notebooks(&[notebook], OutputFormat::Table).unwrap(),
+            "NAME         STATUS     INSTANCE TYPE  VOLUME\nmy-notebook  InService  ml.t3.medium   5 GB\n"
+        );
+    }
+}
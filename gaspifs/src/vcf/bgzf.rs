@@ -0,0 +1,331 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+#[cfg(feature = "uploads")]
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+#[cfg(feature = "uploads")]
+use std::io::{Seek, SeekFrom};
+#[cfg(feature = "uploads")]
+use std::path::Path;
+
+/// The empty block that terminates every BGZF file, as defined in the SAM/BAM specification.
+pub const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Maximum amount of uncompressed data per block, chosen so that even incompressible data fits in 64 KiB.
+const MAX_BLOCK_DATA: usize = 0xff00;
+
+/// Checks whether a file ends with the BGZF EOF block.
+/// A missing EOF block usually means that the file was truncated or compressed with plain gzip.
+/// # Arguments
+/// * `path` - The path to the file.
+/// # Returns
+/// A `Result` containing `true` if the file ends with the EOF block.
+/// # Example
+/// ```
+/// if !has_eof_block("sample.vcf.gz")? {
+///     eprintln!("sample.vcf.gz may be truncated");
+/// }
+/// ```
+#[cfg(feature = "uploads")]
+pub fn has_eof_block<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < EOF_BLOCK.len() as u64 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-(EOF_BLOCK.len() as i64)))?;
+    let mut tail = [0u8; EOF_BLOCK.len()];
+    file.read_exact(&mut tail)?;
+    Ok(tail == EOF_BLOCK)
+}
+
+/// Returns `true` if the data starts with a BGZF block header, that is a gzip header with a `BC` subfield.
+/// Plain gzip files start with the same magic bytes but have no such subfield.
+pub fn is_block_header(data: &[u8]) -> bool {
+    if data.len() < 12 || data[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+        return false;
+    }
+    let length = u16::from_le_bytes([data[10], data[11]]) as usize;
+    data.get(12..12 + length).and_then(block_size).is_some()
+}
+
+/// Reads BGZF compressed data block by block.
+/// Besides the decompressed data, the reader exposes the virtual file offset of its position,
+/// which is what tabix and CSI indexes store.
+pub struct Reader<R> {
+    inner: R,
+    block: Vec<u8>,
+    position: usize,
+    block_offset: u64,
+    next_block_offset: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Creates a reader over BGZF compressed data.
+    pub fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            block: Vec::new(),
+            position: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+        }
+    }
+
+    /// Returns the virtual file offset of the next byte to be read:
+    /// the compressed offset of its block in the upper 48 bits and its offset within the block in the lower 16.
+    #[cfg(feature = "uploads")]
+    pub fn virtual_position(&self) -> u64 {
+        if self.position >= self.block.len() {
+            self.next_block_offset << 16
+        } else {
+            (self.block_offset << 16) | self.position as u64
+        }
+    }
+
+    /// Reads and decompresses the next block, returning `false` at the end of the data.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; 12];
+        match self.inner.read(&mut header[..1])? {
+            0 => return Ok(false),
+            _ => self.inner.read_exact(&mut header[1..])?,
+        }
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+            return Err(invalid_data("not a BGZF block"));
+        }
+
+        let extra_length = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; extra_length];
+        self.inner.read_exact(&mut extra)?;
+        let block_size = block_size(&extra).ok_or(invalid_data("missing BGZF block size"))?;
+        let remaining = (block_size + 1)
+            .checked_sub(header.len() + extra_length)
+            .filter(|remaining| *remaining >= 8)
+            .ok_or(invalid_data("invalid BGZF block size"))?;
+
+        let mut data = vec![0u8; remaining];
+        self.inner.read_exact(&mut data)?;
+        let (compressed, trailer) = data.split_at(remaining - 8);
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap()) as usize;
+
+        self.block.clear();
+        self.block.reserve(size);
+        DeflateDecoder::new(compressed).read_to_end(&mut self.block)?;
+        let mut checksum = Crc::new();
+        checksum.update(&self.block);
+        if self.block.len() != size || checksum.sum() != crc {
+            return Err(invalid_data("corrupted BGZF block"));
+        }
+
+        self.position = 0;
+        self.block_offset = self.next_block_offset;
+        self.next_block_offset += (block_size + 1) as u64;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl<R: Read> BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // empty blocks, such as the EOF block, are skipped
+        while self.position >= self.block.len() {
+            if !self.read_block()? {
+                break;
+            }
+        }
+        Ok(&self.block[self.position.min(self.block.len())..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.block.len());
+    }
+}
+
+/// Writes BGZF compressed data, so that the output can be indexed and read by htslib.
+/// Call `finish` once done to write the last block and the EOF block.
+pub struct Writer<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    compressed_offset: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a writer that compresses into `inner`.
+    pub fn new(inner: W) -> Self {
+        Writer {
+            inner,
+            buffer: Vec::with_capacity(MAX_BLOCK_DATA),
+            compressed_offset: 0,
+        }
+    }
+
+    /// Returns the virtual file offset at which the next byte will be written.
+    #[cfg(feature = "uploads")]
+    pub fn virtual_position(&self) -> u64 {
+        (self.compressed_offset << 16) | self.buffer.len() as u64
+    }
+
+    /// Compresses the buffered data into a block, writing nothing if the buffer is empty.
+    pub fn flush_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let block = compress_block(&self.buffer)?;
+        self.inner.write_all(&block)?;
+        self.compressed_offset += block.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the remaining data and the EOF block, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_block()?;
+        self.inner.write_all(&EOF_BLOCK)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(MAX_BLOCK_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        if self.buffer.len() == MAX_BLOCK_DATA {
+            self.flush_block()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_block()?;
+        self.inner.flush()
+    }
+}
+
+/// Compresses data into a single BGZF block.
+fn compress_block(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    let block_size = (compressed.len() + 25) as u16;
+    let mut block = Vec::with_capacity(compressed.len() + 26);
+    block.extend([
+        0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+    ]);
+    block.extend(block_size.to_le_bytes());
+    block.extend(&compressed);
+    block.extend(crc.sum().to_le_bytes());
+    block.extend((data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+/// Finds the `BC` subfield holding the total block size minus one in a gzip extra field.
+fn block_size(extra: &[u8]) -> Option<usize> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let length = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        if rest[..2] == [b'B', b'C'] && length == 2 && rest.len() >= 6 {
+            return Some(u16::from_le_bytes([rest[4], rest[5]]) as usize);
+        }
+        rest = rest.get(4 + length..)?;
+    }
+    None
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Compresses data into BGZF blocks of at most `block_size` bytes, followed by the EOF block.
+    pub(crate) fn compress(data: &[u8], block_size: usize) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new());
+        for chunk in data.chunks(block_size) {
+            writer.write_all(chunk).unwrap();
+            writer.flush_block().unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_blocks() {
+        let data = b"line 1\nline 2\nline 3\n";
+        let compressed = compress(data, 8);
+        let mut reader = Reader::new(&compressed[..]);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_virtual_position() {
+        let compressed = compress(b"abcdefgh", 4);
+        let mut reader = Reader::new(&compressed[..]);
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.virtual_position(), 2);
+        reader.read_exact(&mut buf).unwrap();
+        let second_block = reader.virtual_position() >> 16;
+        assert!(second_block > 0);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.virtual_position(), (second_block << 16) | 2);
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_write_large_data() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let mut writer = Writer::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        assert!(writer.virtual_position() >> 16 > 0);
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.ends_with(&EOF_BLOCK));
+
+        let mut output = Vec::new();
+        Reader::new(&compressed[..])
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_rejects_plain_gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"not bgzf").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut reader = Reader::new(&compressed[..]);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "uploads")]
+    #[test]
+    fn test_has_eof_block() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&compress(b"data", 4)).unwrap();
+        assert!(has_eof_block(file.path()).unwrap());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"data").unwrap();
+        assert!(!has_eof_block(file.path()).unwrap());
+    }
+}
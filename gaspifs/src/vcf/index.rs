@@ -0,0 +1,678 @@
+use super::{bgzf, is_bgzf};
+use crate::networking::paths::index_names;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Size of the smallest bin and of the linear index windows, as a power of two (16 kbp).
+const MIN_SHIFT: u32 = 14;
+
+/// Tabix flag marking zero-based, half-open coordinates (the UCSC convention used by BED).
+const TBX_UCSC: i32 = 0x10000;
+
+/// Represents the on-disk format of an index.
+/// Tabix indexes cover positions up to 2^29, CSI indexes up to 2^32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    Tbi,
+    Csi,
+}
+
+impl FromStr for IndexFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "tbi" => Ok(IndexFormat::Tbi),
+            "csi" => Ok(IndexFormat::Csi),
+            _ => Err(anyhow!(
+                "Unknown index format {:?}, expected tbi or csi",
+                value
+            )),
+        }
+    }
+}
+
+impl IndexFormat {
+    /// The extension appended to the data file name.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            IndexFormat::Tbi => "tbi",
+            IndexFormat::Csi => "csi",
+        }
+    }
+
+    /// Number of levels of the binning scheme below the root bin.
+    fn depth(&self) -> u32 {
+        match self {
+            IndexFormat::Tbi => 5,
+            IndexFormat::Csi => 6,
+        }
+    }
+}
+
+/// Describes the columns of a tab-delimited file, as stored in the tabix header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preset {
+    format: i32,
+    sequence: usize,
+    begin: usize,
+    end: usize,
+    meta: u8,
+    skip: i32,
+}
+
+pub const VCF: Preset = Preset {
+    format: 2,
+    sequence: 1,
+    begin: 2,
+    end: 0,
+    meta: b'#',
+    skip: 0,
+};
+
+pub const BED: Preset = Preset {
+    format: TBX_UCSC,
+    sequence: 1,
+    begin: 2,
+    end: 3,
+    meta: b'#',
+    skip: 0,
+};
+
+pub const GFF: Preset = Preset {
+    format: 0,
+    sequence: 1,
+    begin: 4,
+    end: 5,
+    meta: b'#',
+    skip: 0,
+};
+
+impl Preset {
+    /// Picks the preset from the file extension, ignoring a trailing `.gz` or `.bgz`.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Option<Preset> {
+        let name = path.as_ref().to_string_lossy().to_lowercase();
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".bgz"))
+            .unwrap_or(&name);
+        match name.rsplit('.').next()? {
+            "vcf" => Some(VCF),
+            "bed" => Some(BED),
+            "gff" | "gff3" | "gtf" => Some(GFF),
+            _ => None,
+        }
+    }
+
+    /// Returns the zero-based, half-open interval covered by a record.
+    /// For VCF the end is derived from the length of REF, or from the END tag of structural variants.
+    fn interval(&self, fields: &[&str]) -> Result<(u64, u64)> {
+        let column = |index: usize| {
+            fields
+                .get(index - 1)
+                .ok_or(anyhow!("missing column {}", index))
+        };
+        let mut begin: u64 = column(self.begin)?.parse()?;
+        if self.format & TBX_UCSC == 0 {
+            begin = begin.saturating_sub(1);
+        }
+        let end = if self == &VCF {
+            let end = begin + column(4)?.len() as u64;
+            fields
+                .get(7)
+                .and_then(|info| info.split(';').find_map(|tag| tag.strip_prefix("END=")))
+                .and_then(|value| value.parse().ok())
+                .map_or(end, |tag: u64| tag.max(end))
+        } else if self.end > 0 {
+            column(self.end)?.parse()?
+        } else {
+            begin + 1
+        };
+        Ok((begin, end.max(begin + 1)))
+    }
+
+    /// Encodes the tabix header fields and the sequence names.
+    fn encode(&self, names: &[String]) -> Vec<u8> {
+        let names: Vec<u8> = names
+            .iter()
+            .flat_map(|name| name.bytes().chain(std::iter::once(0)))
+            .collect();
+        let mut buffer = Vec::new();
+        for value in [
+            self.format,
+            self.sequence as i32,
+            self.begin as i32,
+            self.end as i32,
+            self.meta as i32,
+            self.skip,
+            names.len() as i32,
+        ] {
+            buffer.extend(value.to_le_bytes());
+        }
+        buffer.extend(names);
+        buffer
+    }
+}
+
+/// Bins, chunks and linear index of a single sequence.
+#[derive(Default)]
+struct Reference {
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    linear: Vec<Option<u64>>,
+    first: Option<u64>,
+    last: u64,
+    records: u64,
+}
+
+impl Reference {
+    fn add(&mut self, interval: (u64, u64), offsets: (u64, u64), depth: u32) {
+        let (begin, end) = interval;
+        let chunks = self.bins.entry(reg2bin(begin, end, depth)).or_default();
+        match chunks.last_mut() {
+            Some(chunk) if chunk.1 == offsets.0 => chunk.1 = offsets.1,
+            _ => chunks.push(offsets),
+        }
+
+        let windows = (begin >> MIN_SHIFT) as usize..=((end - 1) >> MIN_SHIFT) as usize;
+        if self.linear.len() <= *windows.end() {
+            self.linear.resize(windows.end() + 1, None);
+        }
+        for window in windows {
+            self.linear[window].get_or_insert(offsets.0);
+        }
+
+        self.first.get_or_insert(offsets.0);
+        self.last = offsets.1;
+        self.records += 1;
+    }
+
+    /// Returns the linear index with empty windows filled with the offset of the previous window.
+    fn filled_linear(&self) -> Vec<u64> {
+        let mut previous = 0;
+        self.linear
+            .iter()
+            .map(|offset| {
+                previous = offset.unwrap_or(previous);
+                previous
+            })
+            .collect()
+    }
+
+    /// Encodes the pseudo-bin that holds the offsets and record count of the sequence.
+    fn encode_pseudo_bin(&self, buffer: &mut Vec<u8>, depth: u32, csi: bool) {
+        buffer.extend(pseudo_bin(depth).to_le_bytes());
+        if csi {
+            buffer.extend(0u64.to_le_bytes());
+        }
+        buffer.extend(2i32.to_le_bytes());
+        for value in [self.first.unwrap_or_default(), self.last, self.records, 0] {
+            buffer.extend(value.to_le_bytes());
+        }
+    }
+}
+
+/// Builds a tabix or CSI index from the lines of a BGZF compressed, coordinate sorted file.
+/// Each line is pushed with the virtual offsets of its first byte and of the byte following it.
+pub struct Indexer {
+    preset: Preset,
+    format: IndexFormat,
+    names: Vec<String>,
+    references: Vec<Reference>,
+    last_begin: u64,
+    lines: usize,
+}
+
+impl Indexer {
+    /// Creates an indexer for files with the given column layout.
+    pub fn new(preset: Preset, format: IndexFormat) -> Self {
+        Indexer {
+            preset,
+            format,
+            names: Vec::new(),
+            references: Vec::new(),
+            last_begin: 0,
+            lines: 0,
+        }
+    }
+
+    /// Adds a line to the index.
+    /// # Arguments
+    /// * `line` - The line, with or without its line terminator.
+    /// * `start` - The virtual offset of the first byte of the line.
+    /// * `end` - The virtual offset of the byte following the line.
+    /// # Errors
+    /// Returns an error if the line cannot be parsed, if the file is not sorted
+    /// or if a position is too large for the index format.
+    pub fn push(&mut self, line: &[u8], start: u64, end: u64) -> Result<()> {
+        self.lines += 1;
+        let line = std::str::from_utf8(line)?.trim_end_matches(['\n', '\r']);
+        if self.lines as i32 <= self.preset.skip
+            || line.is_empty()
+            || line.as_bytes()[0] == self.preset.meta
+        {
+            return Ok(());
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let sequence = fields[self.preset.sequence - 1];
+        let (begin, finish) = self
+            .preset
+            .interval(&fields)
+            .map_err(|err| anyhow!("line {}: {}", self.lines, err))?;
+        if finish > 1 << (MIN_SHIFT + 3 * self.format.depth()) {
+            return Err(anyhow!(
+                "line {}: position {}:{} is too large for a {} index, use CSI instead",
+                self.lines,
+                sequence,
+                finish,
+                self.format.extension()
+            ));
+        }
+
+        if self.names.last().map(String::as_str) != Some(sequence) {
+            if self.names.iter().any(|name| name == sequence) {
+                return Err(anyhow!(
+                    "line {}: records for {} are not contiguous, the file is not sorted",
+                    self.lines,
+                    sequence
+                ));
+            }
+            self.names.push(sequence.to_string());
+            self.references.push(Reference::default());
+            self.last_begin = 0;
+        }
+        if begin < self.last_begin {
+            return Err(anyhow!(
+                "line {}: {}:{} comes after {}:{}, the file is not sorted",
+                self.lines,
+                sequence,
+                begin + 1,
+                sequence,
+                self.last_begin + 1
+            ));
+        }
+        self.last_begin = begin;
+
+        let depth = self.format.depth();
+        if let Some(reference) = self.references.last_mut() {
+            reference.add((begin, finish), (start, end), depth);
+        }
+        Ok(())
+    }
+
+    /// Writes the BGZF compressed index.
+    pub fn write<W: Write>(self, writer: W) -> Result<()> {
+        let mut writer = bgzf::Writer::new(writer);
+        writer.write_all(&self.encode())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Encodes the uncompressed index.
+    fn encode(&self) -> Vec<u8> {
+        let depth = self.format.depth();
+        let header = self.preset.encode(&self.names);
+        let mut buffer = Vec::new();
+        match self.format {
+            IndexFormat::Tbi => {
+                buffer.extend(b"TBI\x01");
+                buffer.extend((self.names.len() as i32).to_le_bytes());
+                buffer.extend(header);
+            }
+            IndexFormat::Csi => {
+                buffer.extend(b"CSI\x01");
+                buffer.extend((MIN_SHIFT as i32).to_le_bytes());
+                buffer.extend((depth as i32).to_le_bytes());
+                buffer.extend((header.len() as i32).to_le_bytes());
+                buffer.extend(header);
+                buffer.extend((self.names.len() as i32).to_le_bytes());
+            }
+        }
+
+        for reference in &self.references {
+            let linear = reference.filled_linear();
+            buffer.extend((reference.bins.len() as i32 + 1).to_le_bytes());
+            for (bin, chunks) in &reference.bins {
+                buffer.extend(bin.to_le_bytes());
+                if self.format == IndexFormat::Csi {
+                    let window = (bin_first(*bin, depth) >> MIN_SHIFT) as usize;
+                    let offset = linear
+                        .get(window)
+                        .map_or(chunks[0].0, |offset| (*offset).min(chunks[0].0));
+                    buffer.extend(offset.to_le_bytes());
+                }
+                buffer.extend((chunks.len() as i32).to_le_bytes());
+                for (start, end) in chunks {
+                    buffer.extend(start.to_le_bytes());
+                    buffer.extend(end.to_le_bytes());
+                }
+            }
+            reference.encode_pseudo_bin(&mut buffer, depth, self.format == IndexFormat::Csi);
+            if self.format == IndexFormat::Tbi {
+                buffer.extend((linear.len() as i32).to_le_bytes());
+                for offset in linear {
+                    buffer.extend(offset.to_le_bytes());
+                }
+            }
+        }
+        buffer.extend(0u64.to_le_bytes());
+        buffer
+    }
+}
+
+/// Computes the smallest bin containing the zero-based, half-open interval `[begin, end)`.
+fn reg2bin(begin: u64, end: u64, depth: u32) -> u32 {
+    let end = end - 1;
+    let mut shift = MIN_SHIFT;
+    let mut offset = ((1u64 << (3 * depth)) - 1) / 7;
+    for level in (1..=depth).rev() {
+        if begin >> shift == end >> shift {
+            return (offset + (begin >> shift)) as u32;
+        }
+        shift += 3;
+        offset -= 1 << (3 * (level - 1));
+    }
+    0
+}
+
+/// Returns the first position covered by a bin.
+fn bin_first(bin: u32, depth: u32) -> u64 {
+    let mut offset = 0;
+    for level in 0..=depth {
+        let size = 1 << (3 * level);
+        if bin < offset + size {
+            return ((bin - offset) as u64) << (MIN_SHIFT + 3 * (depth - level));
+        }
+        offset += size;
+    }
+    0
+}
+
+/// Returns the number of the pseudo-bin, the first bin number past the binning scheme.
+fn pseudo_bin(depth: u32) -> u32 {
+    ((1 << (3 * (depth + 1))) - 1) / 7 + 1
+}
+
+/// Returns the path of the index of a data file, next to it.
+pub fn index_path<P: AsRef<Path>>(path: P, format: IndexFormat) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_owned();
+    name.push(".");
+    name.push(format.extension());
+    PathBuf::from(name)
+}
+
+/// Builds an index for a BGZF compressed, coordinate sorted VCF, BED or GFF file.
+/// The index is written next to the data file, as `tabix` and `bcftools index` do.
+/// # Arguments
+/// * `path` - The path to the data file.
+/// * `format` - The format of the index.
+/// # Returns
+/// A `Result` containing the path of the index.
+/// # Errors
+/// Returns an error if the file type is unknown, if it is not BGZF compressed or if it is not sorted.
+/// # Example
+/// ```
+/// let index = build_index("sample.vcf.gz", IndexFormat::Tbi)?;
+/// assert_eq!(index, PathBuf::from("sample.vcf.gz.tbi"));
+/// ```
+pub fn build_index<P: AsRef<Path>>(path: P, format: IndexFormat) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let preset = Preset::for_path(path).ok_or(anyhow!(
+        "Cannot index {}: not a VCF, BED or GFF file.",
+        path.display()
+    ))?;
+    if !is_bgzf(path)? {
+        return Err(anyhow!(
+            "Cannot index {}: the file is not BGZF compressed; compress it with bgzip.",
+            path.display()
+        ));
+    }
+
+    let mut reader = bgzf::Reader::new(BufReader::new(File::open(path)?));
+    let mut indexer = Indexer::new(preset, format);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let start = reader.virtual_position();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        indexer
+            .push(&line, start, reader.virtual_position())
+            .map_err(|err| anyhow!("Cannot index {}: {}", path.display(), err))?;
+    }
+
+    let index = index_path(path, format);
+    indexer.write(BufWriter::new(File::create(&index)?))?;
+    Ok(index)
+}
+
+/// Represents the index found next to a local data file.
+#[derive(Debug, PartialEq)]
+pub enum IndexStatus {
+    /// The index is at least as recent as the data file.
+    Fresh(PathBuf),
+    /// The index is older than the data file.
+    Stale(PathBuf),
+    Missing,
+}
+
+/// Looks for the index of a local data file and checks that it is not older than the data.
+/// # Arguments
+/// * `path` - The path to the data file.
+/// # Returns
+/// A `Result` containing the status of the index, or `None` if the file type has no index.
+/// # Example
+/// ```
+/// if let Some(IndexStatus::Fresh(index)) = find_index("sample.vcf.gz")? {
+///     println!("found {}", index.display());
+/// }
+/// ```
+pub fn find_index<P: AsRef<Path>>(path: P) -> Result<Option<IndexStatus>> {
+    let path = path.as_ref();
+    let names = index_names(&path.to_string_lossy());
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let modified = path.metadata()?.modified()?;
+    let mut status = IndexStatus::Missing;
+    for name in names {
+        let index = PathBuf::from(name);
+        let Ok(metadata) = index.metadata() else {
+            continue;
+        };
+        if metadata.modified()? >= modified {
+            return Ok(Some(IndexStatus::Fresh(index)));
+        }
+        if status == IndexStatus::Missing {
+            status = IndexStatus::Stale(index);
+        }
+    }
+    Ok(Some(status))
+}
+
+/// Pairs each data file to upload with its index, building missing or stale tabix/CSI indexes
+/// when `build` is set or, on an interactive terminal, when the user agrees.
+/// Alignment indexes (`.bai`, `.crai`) cannot be built and are only reported when missing.
+/// # Arguments
+/// * `files` - The files to upload.
+/// * `build` - Whether to build missing or stale indexes without asking.
+/// * `format` - The format of the indexes to build.
+/// # Returns
+/// A `Result` containing the files to upload, each followed by its index.
+/// # Example
+/// ```
+/// let files = pair_indexes(&["sample.vcf.gz".to_string()], true, IndexFormat::Tbi)?;
+/// assert_eq!(files, vec!["sample.vcf.gz", "sample.vcf.gz.tbi"]);
+/// ```
+pub fn pair_indexes(files: &[String], build: bool, format: IndexFormat) -> Result<Vec<String>> {
+    let mut paired = files.to_vec();
+    let mut add = |index: PathBuf| {
+        let index = index.to_string_lossy().to_string();
+        if !paired.contains(&index) {
+            paired.push(index);
+        }
+    };
+
+    for file in files {
+        let reason = match find_index(file)? {
+            None => continue,
+            Some(IndexStatus::Fresh(index)) => {
+                add(index);
+                continue;
+            }
+            Some(IndexStatus::Stale(index)) => {
+                format!("{} is older than {}", index.display(), file)
+            }
+            Some(IndexStatus::Missing) => format!("{} has no index", file),
+        };
+        if Preset::for_path(file).is_none() {
+            eprintln!("Warning: {}, it will be uploaded without one.", reason);
+        } else if build || (std::io::stdin().is_terminal() && confirm_build(&reason, format)?) {
+            println!("Building {} index for {}", format.extension(), file);
+            add(build_index(file, format)?);
+        } else {
+            eprintln!(
+                "Warning: {}, pass --build-index to build one before uploading.",
+                reason
+            );
+        }
+    }
+    Ok(paired)
+}
+
+fn confirm_build(reason: &str, format: IndexFormat) -> Result<bool> {
+    print!(
+        "{}. Build a {} index now? [y/N] ",
+        reason,
+        format.extension()
+    );
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::bgzf::tests::compress;
+    use std::io::Read;
+
+    const RECORDS: &str = "##fileformat=VCFv4.2\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        chr1\t100\t.\tA\tG\t.\tPASS\t.\n\
+        chr1\t20000\t.\tAC\tA\t.\tPASS\t.\n\
+        chr1\t20100\t.\tN\t<DEL>\t.\tPASS\tSVTYPE=DEL;END=60000\n\
+        chr2\t5\t.\tA\tT\t.\tPASS\t.\n";
+
+    fn decompressed(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        bgzf::Reader::new(File::open(path).unwrap())
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    fn read_i32(data: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_reg2bin() {
+        assert_eq!(reg2bin(0, 1, 5), 4681);
+        assert_eq!(reg2bin(16384, 16385, 5), 4682);
+        assert_eq!(reg2bin(0, 16385, 5), 585);
+        assert_eq!(reg2bin(0, 1 << 29, 5), 0);
+        assert_eq!(pseudo_bin(5), 37450);
+        assert_eq!(bin_first(4682, 5), 16384);
+        assert_eq!(bin_first(586, 5), 1 << 17);
+    }
+
+    #[test]
+    fn test_vcf_interval() {
+        assert_eq!(
+            VCF.interval(&["chr1", "100", ".", "AC", "A"]).unwrap(),
+            (99, 101)
+        );
+        assert_eq!(
+            VCF.interval(&["chr1", "100", ".", "N", "<DEL>", ".", ".", "END=500"])
+                .unwrap(),
+            (99, 500)
+        );
+        assert_eq!(BED.interval(&["chr1", "0", "10"]).unwrap(), (0, 10));
+        assert_eq!(
+            GFF.interval(&["chr1", "src", "gene", "1", "10"]).unwrap(),
+            (0, 10)
+        );
+    }
+
+    #[test]
+    fn test_build_tabix_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.vcf.gz");
+        std::fs::write(&path, compress(RECORDS.as_bytes(), 64)).unwrap();
+
+        let index = build_index(&path, IndexFormat::Tbi).unwrap();
+        assert_eq!(index, dir.path().join("sample.vcf.gz.tbi"));
+        let data = decompressed(&index);
+        assert_eq!(&data[..4], b"TBI\x01");
+        assert_eq!(read_i32(&data, 4), 2);
+        assert_eq!(read_i32(&data, 8), 2);
+        assert_eq!(read_i32(&data, 24), b'#' as i32);
+        assert_eq!(
+            &data[36..36 + read_i32(&data, 32) as usize],
+            b"chr1\0chr2\0"
+        );
+        assert_eq!(find_index(&path).unwrap(), Some(IndexStatus::Fresh(index)));
+    }
+
+    #[test]
+    fn test_build_csi_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.vcf.gz");
+        std::fs::write(&path, compress(RECORDS.as_bytes(), 64)).unwrap();
+
+        let data = decompressed(&build_index(&path, IndexFormat::Csi).unwrap());
+        assert_eq!(&data[..4], b"CSI\x01");
+        assert_eq!(read_i32(&data, 4), MIN_SHIFT as i32);
+        assert_eq!(read_i32(&data, 8), 6);
+    }
+
+    #[test]
+    fn test_rejects_unsorted_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.vcf.gz");
+        let records = "chr1\t200\t.\tA\tG\t.\t.\t.\nchr1\t100\t.\tA\tG\t.\t.\t.\n";
+        std::fs::write(&path, compress(records.as_bytes(), 64)).unwrap();
+        let err = build_index(&path, IndexFormat::Tbi).unwrap_err();
+        assert!(err.to_string().contains("not sorted"));
+
+        let path = dir.path().join("plain.vcf.gz");
+        std::fs::write(&path, records).unwrap();
+        assert!(build_index(&path, IndexFormat::Tbi).is_err());
+    }
+
+    #[test]
+    fn test_pair_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let vcf = dir.path().join("sample.vcf.gz");
+        let bam = dir.path().join("reads.bam");
+        let txt = dir.path().join("notes.txt");
+        std::fs::write(&vcf, compress(RECORDS.as_bytes(), 64)).unwrap();
+        std::fs::write(&bam, b"").unwrap();
+        std::fs::write(&txt, b"").unwrap();
+        let files: Vec<String> = [&vcf, &bam, &txt]
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        let paired = pair_indexes(&files, true, IndexFormat::Tbi).unwrap();
+        assert_eq!(paired.len(), 4);
+        assert!(paired[3].ends_with("sample.vcf.gz.tbi"));
+    }
+}
@@ -0,0 +1,69 @@
+pub mod bgzf;
+#[cfg(feature = "uploads")]
+pub mod index;
+#[cfg(feature = "uploads")]
+pub mod reference;
+pub mod stats;
+#[cfg(feature = "uploads")]
+pub mod validate;
+
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Returns `true` if the path has a VCF extension (`.vcf`, `.vcf.gz` or `.vcf.bgz`).
+#[cfg(feature = "uploads")]
+pub fn is_vcf<P: AsRef<Path>>(path: P) -> bool {
+    let name = path.as_ref().to_string_lossy().to_lowercase();
+    name.ends_with(".vcf") || name.ends_with(".vcf.gz") || name.ends_with(".vcf.bgz")
+}
+
+/// Returns `true` if the file starts with the gzip magic bytes.
+#[cfg(feature = "uploads")]
+pub fn is_compressed<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(file.fill_buf()?.starts_with(&[0x1f, 0x8b]))
+}
+
+/// Returns `true` if the file is an uncompressed VCF, BED or GFF file that can be compressed with bgzip.
+#[cfg(feature = "uploads")]
+pub fn can_bgzip<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let name = path.as_ref().to_string_lossy().to_lowercase();
+    Ok(index::Preset::for_path(&path).is_some()
+        && !name.ends_with(".gz")
+        && !name.ends_with(".bgz")
+        && !is_compressed(&path)?)
+}
+
+/// Returns `true` if the file is BGZF compressed rather than plain gzip compressed or uncompressed.
+#[cfg(feature = "uploads")]
+pub fn is_bgzf<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(bgzf::is_block_header(file.fill_buf()?))
+}
+
+/// Opens a plain, gzip or BGZF compressed VCF file for reading.
+/// # Arguments
+/// * `path` - The path to the VCF file.
+/// # Returns
+/// A `Result` containing a buffered reader over the uncompressed text.
+/// # Example
+/// ```
+/// let reader = open("sample.vcf.gz")?;
+/// for line in reader.lines() {
+///     println!("{}", line?);
+/// }
+/// ```
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn BufRead + Send>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let header = file.fill_buf()?;
+    if bgzf::is_block_header(header) {
+        Ok(Box::new(bgzf::Reader::new(file)))
+    } else if header.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
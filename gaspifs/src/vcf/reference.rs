@@ -0,0 +1,140 @@
+use std::fmt;
+
+/// Represents a human reference assembly supported by the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assembly {
+    GRCh38,
+    GRCh37,
+}
+
+impl fmt::Display for Assembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assembly::GRCh38 => write!(f, "GRCh38"),
+            Assembly::GRCh37 => write!(f, "GRCh37"),
+        }
+    }
+}
+
+/// Lengths of the primary GRCh38 contigs, in the order 1-22, X, Y, M.
+const GRCH38_LENGTHS: [u64; 25] = [
+    248956422, 242193529, 198295559, 190214555, 181538259, 170805979, 159345973, 145138636,
+    138394717, 133797422, 135086622, 133275309, 114364328, 107043718, 101991189, 90338345,
+    83257441, 80373285, 58617616, 64444167, 46709983, 50818468, 156040895, 57227415, 16569,
+];
+
+/// Lengths of the primary GRCh37 contigs, in the order 1-22, X, Y, MT.
+const GRCH37_LENGTHS: [u64; 25] = [
+    249250621, 243199373, 198022430, 191154276, 180915260, 171115067, 159138663, 146364022,
+    141213431, 135534747, 135006516, 133851895, 115169878, 107349540, 102531392, 90354753,
+    81195210, 78077248, 59128983, 63025520, 48129895, 51304566, 155270560, 59373566, 16569,
+];
+
+impl Assembly {
+    /// Guesses the assembly from a `##reference` or `##assembly` header value.
+    pub fn from_reference(value: &str) -> Option<Assembly> {
+        let value = value.to_lowercase();
+        if value.contains("grch38") || value.contains("hg38") {
+            Some(Assembly::GRCh38)
+        } else if value.contains("grch37") || value.contains("hg19") || value.contains("hs37") {
+            Some(Assembly::GRCh37)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the assembly from the declared length of a primary contig.
+    pub fn from_contig_length(name: &str, length: u64) -> Option<Assembly> {
+        [Assembly::GRCh38, Assembly::GRCh37]
+            .into_iter()
+            .find(|assembly| assembly.contig_length(name) == Some(length))
+    }
+
+    /// Returns the length of a primary contig (1-22, X, Y or the mitochondrial genome),
+    /// with or without the `chr` prefix.
+    pub fn contig_length(&self, name: &str) -> Option<u64> {
+        let index = primary_index(name)?;
+        Some(match self {
+            Assembly::GRCh38 => GRCH38_LENGTHS[index],
+            Assembly::GRCh37 => GRCH37_LENGTHS[index],
+        })
+    }
+}
+
+/// Returns `true` if the contig is a primary contig.
+pub fn is_primary_contig(name: &str) -> bool {
+    primary_index(name).is_some()
+}
+
+/// Returns `true` if the contig is a primary, unlocalised, unplaced, alternate or decoy contig
+/// of GRCh38 or GRCh37, in either the UCSC or the Ensembl naming style.
+pub fn is_known_contig(name: &str) -> bool {
+    if is_primary_contig(name) {
+        return true;
+    }
+    let bare = name.strip_prefix("chr").unwrap_or(name);
+    bare.ends_with("_random")
+        || bare.ends_with("_alt")
+        || bare.ends_with("_fix")
+        || bare.ends_with("_decoy")
+        || bare.starts_with("Un_")
+        || bare.starts_with("HLA-")
+        || bare.starts_with("GL")
+        || bare.starts_with("KI")
+        || bare.starts_with("NC_007605")
+        || bare == "EBV"
+        || bare == "hs37d5"
+}
+
+fn primary_index(name: &str) -> Option<usize> {
+    let bare = name.strip_prefix("chr").unwrap_or(name);
+    match bare {
+        "X" => Some(22),
+        "Y" => Some(23),
+        "M" | "MT" => Some(24),
+        _ => match bare.parse::<usize>() {
+            Ok(number) if (1..=22).contains(&number) && !bare.starts_with('0') => Some(number - 1),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contig_length() {
+        assert_eq!(Assembly::GRCh38.contig_length("chr1"), Some(248956422));
+        assert_eq!(Assembly::GRCh37.contig_length("X"), Some(155270560));
+        assert_eq!(Assembly::GRCh38.contig_length("chrM"), Some(16569));
+        assert_eq!(Assembly::GRCh38.contig_length("01"), None);
+        assert_eq!(Assembly::GRCh38.contig_length("23"), None);
+    }
+
+    #[test]
+    fn test_guess_assembly() {
+        assert_eq!(
+            Assembly::from_contig_length("chr1", 248956422),
+            Some(Assembly::GRCh38)
+        );
+        assert_eq!(
+            Assembly::from_contig_length("1", 249250621),
+            Some(Assembly::GRCh37)
+        );
+        assert_eq!(Assembly::from_contig_length("1", 1000), None);
+        assert_eq!(
+            Assembly::from_reference("file:///refs/hs37d5.fa"),
+            Some(Assembly::GRCh37)
+        );
+    }
+
+    #[test]
+    fn test_known_contigs() {
+        assert!(is_known_contig("chr22"));
+        assert!(is_known_contig("chr1_KI270706v1_random"));
+        assert!(is_known_contig("GL000192.1"));
+        assert!(!is_known_contig("scaffold_12"));
+        assert!(!is_known_contig("chr0"));
+    }
+}
@@ -0,0 +1,746 @@
+#[cfg(feature = "uploads")]
+use super::is_vcf;
+use super::open;
+use crate::networking::api::{OutputFormat, format_table};
+#[cfg(feature = "uploads")]
+use crate::networking::{
+    report::{Outcome, TransferReport},
+    uploads::{StreamEncoding, upload_stream},
+    util::get_file_name_from_path,
+};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Depths above this value are counted together when computing the median.
+const MAX_TRACKED_DEPTH: usize = 10_000;
+
+/// The lower bound of each depth bin, and its label.
+const DEPTH_BINS: [(u64, &str); 9] = [
+    (0, "0"),
+    (1, "1-4"),
+    (5, "5-9"),
+    (10, "10-19"),
+    (20, "20-29"),
+    (30, "30-49"),
+    (50, "50-99"),
+    (100, "100-199"),
+    (200, "200+"),
+];
+
+/// The kind of change an alternate allele makes to the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VariantType {
+    Snv,
+    Mnv,
+    Insertion,
+    Deletion,
+    Complex,
+    Symbolic,
+}
+
+impl VariantType {
+    fn classify(reference: &str, alternate: &str) -> Self {
+        if alternate.starts_with('<')
+            || alternate.contains(['[', ']'])
+            || alternate == "*"
+            || alternate == "."
+        {
+            return VariantType::Symbolic;
+        }
+        match (reference.len(), alternate.len()) {
+            (1, 1) => VariantType::Snv,
+            (r, a) if r == a => VariantType::Mnv,
+            (r, a) if r < a && alternate.starts_with(reference) => VariantType::Insertion,
+            (r, a) if r > a && reference.starts_with(alternate) => VariantType::Deletion,
+            _ => VariantType::Complex,
+        }
+    }
+}
+
+/// Counts of alternate alleles by the kind of change they make.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct VariantTypes {
+    pub snv: u64,
+    pub mnv: u64,
+    pub insertion: u64,
+    pub deletion: u64,
+    pub complex: u64,
+    pub symbolic: u64,
+}
+
+impl VariantTypes {
+    fn add(&mut self, variant_type: VariantType) {
+        *match variant_type {
+            VariantType::Snv => &mut self.snv,
+            VariantType::Mnv => &mut self.mnv,
+            VariantType::Insertion => &mut self.insertion,
+            VariantType::Deletion => &mut self.deletion,
+            VariantType::Complex => &mut self.complex,
+            VariantType::Symbolic => &mut self.symbolic,
+        } += 1;
+    }
+}
+
+/// The number of records on a chromosome.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChromosomeCount {
+    pub chromosome: String,
+    pub records: u64,
+}
+
+/// Genotype counts of a sample.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct SampleStats {
+    pub name: String,
+    pub hom_ref: u64,
+    pub het: u64,
+    pub hom_alt: u64,
+    pub missing: u64,
+    /// Heterozygous over homozygous alternate calls, if there are any of the latter.
+    pub het_hom_ratio: Option<f64>,
+    /// The fraction of records where the sample has no call.
+    pub missing_rate: f64,
+}
+
+/// The number of depth values in a range.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DepthBin {
+    pub range: String,
+    pub count: u64,
+}
+
+/// The distribution of a depth field.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DepthDistribution {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub median: u64,
+    pub bins: Vec<DepthBin>,
+}
+
+/// Accumulates depth values, counting each value up to `MAX_TRACKED_DEPTH`.
+#[derive(Default)]
+struct DepthCounter {
+    counts: Vec<u64>,
+    total: u64,
+    sum: u64,
+    min: Option<u64>,
+    max: u64,
+}
+
+impl DepthCounter {
+    fn add(&mut self, depth: u64) {
+        let index = (depth as usize).min(MAX_TRACKED_DEPTH);
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.total += 1;
+        self.sum += depth;
+        self.min = Some(self.min.map_or(depth, |min| min.min(depth)));
+        self.max = self.max.max(depth);
+    }
+
+    fn distribution(&self) -> Option<DepthDistribution> {
+        let min = self.min?;
+        let mut seen = 0;
+        let median = self
+            .counts
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen * 2 >= self.total
+            })
+            .unwrap_or_default() as u64;
+        let bins = DEPTH_BINS
+            .iter()
+            .enumerate()
+            .map(|(i, (start, label))| {
+                let end = DEPTH_BINS
+                    .get(i + 1)
+                    .map_or(self.counts.len(), |(next, _)| *next as usize);
+                let start = (*start as usize).min(self.counts.len());
+                DepthBin {
+                    range: label.to_string(),
+                    count: self.counts[start..end.min(self.counts.len())].iter().sum(),
+                }
+            })
+            .collect();
+        Some(DepthDistribution {
+            count: self.total,
+            min,
+            max: self.max,
+            mean: self.sum as f64 / self.total as f64,
+            median,
+            bins,
+        })
+    }
+}
+
+/// Summary statistics of a VCF file, like those of the `vcfstats` QC of the portal.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct VcfStats {
+    pub records: u64,
+    /// Records whose FILTER is `PASS`.
+    pub passing: u64,
+    /// Records with more than one alternate allele.
+    pub multiallelic: u64,
+    /// Alternate alleles by the kind of change they make.
+    pub variant_types: VariantTypes,
+    pub transitions: u64,
+    pub transversions: u64,
+    /// Transitions over transversions among SNVs, if there are transversions.
+    pub ts_tv: Option<f64>,
+    pub samples: Vec<SampleStats>,
+    /// Heterozygous over homozygous alternate calls of every sample.
+    pub het_hom_ratio: Option<f64>,
+    /// The fraction of genotypes without a call.
+    pub missing_rate: Option<f64>,
+    /// Records per chromosome, in the order of the file.
+    pub chromosomes: Vec<ChromosomeCount>,
+    /// The distribution of the INFO DP of the records.
+    pub site_depth: Option<DepthDistribution>,
+    /// The distribution of the FORMAT DP of the called genotypes.
+    pub sample_depth: Option<DepthDistribution>,
+}
+
+/// Accumulates the statistics of a VCF file one line at a time.
+#[derive(Default)]
+struct Collector {
+    stats: VcfStats,
+    chromosomes: HashMap<String, usize>,
+    site_depth: DepthCounter,
+    sample_depth: DepthCounter,
+}
+
+impl Collector {
+    fn add_header(&mut self, line: &str) {
+        self.stats.samples = line
+            .split('\t')
+            .skip(9)
+            .map(|name| SampleStats {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+    }
+
+    fn add_record(&mut self, number: usize, line: &str) -> Result<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 8 {
+            return Err(anyhow!(
+                "line {}: expected at least 8 columns, found {}",
+                number,
+                fields.len()
+            ));
+        }
+        let stats = &mut self.stats;
+        stats.records += 1;
+        let position = *self
+            .chromosomes
+            .entry(fields[0].to_string())
+            .or_insert_with(|| {
+                stats.chromosomes.push(ChromosomeCount {
+                    chromosome: fields[0].to_string(),
+                    records: 0,
+                });
+                stats.chromosomes.len() - 1
+            });
+        stats.chromosomes[position].records += 1;
+        if fields[6] == "PASS" {
+            stats.passing += 1;
+        }
+
+        let reference = fields[3].to_ascii_uppercase();
+        let alternates: Vec<&str> = fields[4].split(',').filter(|alt| *alt != ".").collect();
+        if alternates.len() > 1 {
+            stats.multiallelic += 1;
+        }
+        for alternate in alternates {
+            let alternate = alternate.to_ascii_uppercase();
+            let variant_type = VariantType::classify(&reference, &alternate);
+            stats.variant_types.add(variant_type);
+            if variant_type == VariantType::Snv {
+                if is_transition(&reference, &alternate) {
+                    stats.transitions += 1;
+                } else {
+                    stats.transversions += 1;
+                }
+            }
+        }
+
+        if let Some(depth) = fields[7]
+            .split(';')
+            .find_map(|entry| entry.strip_prefix("DP="))
+            .and_then(|depth| depth.parse().ok())
+        {
+            self.site_depth.add(depth);
+        }
+
+        let Some(format) = fields.get(8) else {
+            return Ok(());
+        };
+        let keys: Vec<&str> = format.split(':').collect();
+        let gt = keys.iter().position(|key| *key == "GT");
+        let dp = keys.iter().position(|key| *key == "DP");
+        for (sample, value) in self.stats.samples.iter_mut().zip(&fields[9..]) {
+            let values: Vec<&str> = value.split(':').collect();
+            let called = match gt.and_then(|i| values.get(i)) {
+                Some(genotype) => count_genotype(sample, genotype),
+                None => {
+                    sample.missing += 1;
+                    false
+                }
+            };
+            if let Some(depth) = dp
+                .filter(|_| called)
+                .and_then(|i| values.get(i))
+                .and_then(|depth| depth.parse().ok())
+            {
+                self.sample_depth.add(depth);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> VcfStats {
+        let ratio = |numerator: u64, denominator: u64| {
+            (denominator > 0).then(|| numerator as f64 / denominator as f64)
+        };
+        let stats = &mut self.stats;
+        stats.ts_tv = ratio(stats.transitions, stats.transversions);
+        let (mut het, mut hom_alt, mut missing) = (0, 0, 0);
+        for sample in &mut stats.samples {
+            sample.het_hom_ratio = ratio(sample.het, sample.hom_alt);
+            sample.missing_rate = ratio(sample.missing, stats.records).unwrap_or_default();
+            het += sample.het;
+            hom_alt += sample.hom_alt;
+            missing += sample.missing;
+        }
+        if !stats.samples.is_empty() {
+            stats.het_hom_ratio = ratio(het, hom_alt);
+            stats.missing_rate = ratio(missing, stats.records * stats.samples.len() as u64);
+        }
+        stats.site_depth = self.site_depth.distribution();
+        stats.sample_depth = self.sample_depth.distribution();
+        self.stats
+    }
+}
+
+fn is_transition(reference: &str, alternate: &str) -> bool {
+    matches!(
+        (reference, alternate),
+        ("A", "G") | ("G", "A") | ("C", "T") | ("T", "C")
+    )
+}
+
+/// Counts a genotype such as `0/1` or `1|1`, returning `false` if any of its alleles is missing.
+fn count_genotype(sample: &mut SampleStats, genotype: &str) -> bool {
+    let alleles: Vec<&str> = genotype.split(['/', '|']).collect();
+    if alleles
+        .iter()
+        .any(|allele| *allele == "." || allele.is_empty())
+    {
+        sample.missing += 1;
+        return false;
+    }
+    if alleles.iter().all(|allele| *allele == "0") {
+        sample.hom_ref += 1;
+    } else if alleles.windows(2).all(|pair| pair[0] == pair[1]) {
+        sample.hom_alt += 1;
+    } else {
+        sample.het += 1;
+    }
+    true
+}
+
+/// Computes summary statistics of a plain or BGZF compressed VCF file.
+/// # Arguments
+/// * `path` - The path to the VCF file.
+/// # Returns
+/// A `Result` containing the statistics of the file.
+/// # Errors
+/// Returns an error if the file cannot be read or a record has too few columns.
+/// # Example
+/// ```
+/// let stats = vcf_stats("sample.vcf.gz")?;
+/// println!("Ts/Tv: {:?}", stats.ts_tv);
+/// ```
+pub fn vcf_stats<P: AsRef<Path>>(path: P) -> Result<VcfStats> {
+    let mut collector = Collector::default();
+    let reader = open(&path)?;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.starts_with("##") || line.is_empty() {
+            continue;
+        }
+        if line.starts_with("#CHROM") {
+            collector.add_header(line);
+        } else {
+            collector
+                .add_record(index + 1, line)
+                .map_err(|err| anyhow!("{}: {}", path.as_ref().display(), err))?;
+        }
+    }
+    Ok(collector.finish())
+}
+
+/// The statistics of a VCF file, to upload next to it.
+#[cfg(feature = "uploads")]
+pub struct StatsFile {
+    /// The name of the VCF file in the transfer report.
+    pub source: String,
+    /// The name the statistics are stored under, `<stored VCF name>.stats.json`.
+    pub name: String,
+    /// The statistics as JSON, or the reason they could not be computed.
+    pub json: Result<Vec<u8>>,
+}
+
+/// Computes the statistics of every VCF file in a list of files to upload, serialized as the
+/// `<stored file name>.stats.json` files that `upload_stats` uploads next to them.
+/// They are computed before the upload so that their size counts towards the storage quota.
+/// Files without a VCF extension are skipped.
+/// # Arguments
+/// * `paths` - The paths of the files to upload.
+/// * `encoding` - How the files are uploaded, which determines their stored names.
+/// # Returns
+/// A `Result` containing the statistics file of each VCF file.
+/// # Errors
+/// Returns an error if the name of a file cannot be determined.
+/// # Example
+/// ```
+/// let stats = compute_stats(&["sample.vcf".to_string()], &StreamEncoding { bgzip: true, ..Default::default() }).await?;
+/// assert_eq!(stats[0].name, "sample.vcf.gz.stats.json");
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn compute_stats(paths: &[String], encoding: &StreamEncoding) -> Result<Vec<StatsFile>> {
+    let mut computed = Vec::new();
+    for path in paths.iter().filter(|path| is_vcf(path)) {
+        let source = get_file_name_from_path(path)?;
+        let name = format!("{}.stats.json", encoding.stored_file_name(path)?);
+        let file = path.clone();
+        let json = tokio::task::spawn_blocking(move || vcf_stats(file))
+            .await?
+            .and_then(|stats| Ok(serde_json::to_vec_pretty(&stats)?));
+        computed.push(StatsFile { source, name, json });
+    }
+    Ok(computed)
+}
+
+/// Uploads the statistics computed by `compute_stats`, adding the outcome of each to the transfer report.
+/// The statistics are stored as separate files rather than as metadata of the VCF files, since
+/// presigned uploads cannot carry extra metadata. Statistics of VCF files that were not uploaded
+/// are skipped, and so is everything after a failure unless `keep_going` is set.
+/// # Arguments
+/// * `api` - The base URL of the API.
+/// * `project` - The name of the project, if None, uploads to user space.
+/// * `stats` - The statistics files, as returned by `compute_stats`.
+/// * `token` - The authentication token.
+/// * `keep_going` - Whether to keep uploading after a failure.
+/// * `report` - The report of the upload, which holds the outcome of the VCF files and to which
+///   the statistics files are added.
+/// # Example
+/// ```
+/// let stats = compute_stats(&["sample.vcf.gz".to_string()], &encoding).await?;
+/// upload_stats("https://api.example.com", Some("my_project"), stats, "my_token", false, &mut report).await;
+/// ```
+#[cfg(feature = "uploads")]
+pub async fn upload_stats(
+    api: &str,
+    project: Option<&str>,
+    stats: Vec<StatsFile>,
+    token: &str,
+    keep_going: bool,
+    report: &mut TransferReport,
+) {
+    for StatsFile { source, name, json } in stats {
+        let uploaded = report
+            .results
+            .iter()
+            .any(|result| result.name == source && result.outcome == Outcome::Succeeded);
+        let outcome = if !uploaded {
+            Outcome::Skipped(format!("{} was not uploaded", source))
+        } else if !keep_going && !report.is_success() {
+            Outcome::Skipped("cancelled after an earlier failure".to_string())
+        } else {
+            match json {
+                Ok(json) => {
+                    match upload_stream(api, project, &name, token, json.as_slice()).await {
+                        Ok(_) => Outcome::Succeeded,
+                        Err(err) => Outcome::Failed(format!("{:#}", err)),
+                    }
+                }
+                Err(err) => Outcome::Failed(format!("{:#}", err)),
+            }
+        };
+        report.push(name, outcome);
+    }
+}
+
+/// Formats the statistics of a VCF file for printing, as tables or as JSON.
+pub fn format_stats(stats: &VcfStats, format: OutputFormat) -> Result<String> {
+    if format == OutputFormat::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(stats)?));
+    }
+    let ratio = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.3}", value));
+    let types = &stats.variant_types;
+    let mut output = String::new();
+    writeln!(output, "records: {}", stats.records)?;
+    writeln!(output, "passing: {}", stats.passing)?;
+    writeln!(output, "multiallelic: {}", stats.multiallelic)?;
+    writeln!(
+        output,
+        "variant types: {} SNV, {} MNV, {} insertion, {} deletion, {} complex, {} symbolic",
+        types.snv, types.mnv, types.insertion, types.deletion, types.complex, types.symbolic
+    )?;
+    writeln!(
+        output,
+        "ts/tv: {} ({} transitions, {} transversions)",
+        ratio(stats.ts_tv),
+        stats.transitions,
+        stats.transversions
+    )?;
+    writeln!(output, "het/hom: {}", ratio(stats.het_hom_ratio))?;
+    writeln!(output, "missing genotypes: {}", ratio(stats.missing_rate))?;
+
+    let chromosomes: Vec<Vec<String>> = stats
+        .chromosomes
+        .iter()
+        .map(|count| vec![count.chromosome.clone(), count.records.to_string()])
+        .collect();
+    output.push('\n');
+    output.push_str(&format_table(&["CHROMOSOME", "RECORDS"], &chromosomes));
+
+    if !stats.samples.is_empty() {
+        let samples: Vec<Vec<String>> = stats
+            .samples
+            .iter()
+            .map(|sample| {
+                vec![
+                    sample.name.clone(),
+                    sample.hom_ref.to_string(),
+                    sample.het.to_string(),
+                    sample.hom_alt.to_string(),
+                    sample.missing.to_string(),
+                    ratio(sample.het_hom_ratio),
+                    format!("{:.3}", sample.missing_rate),
+                ]
+            })
+            .collect();
+        output.push('\n');
+        output.push_str(&format_table(
+            &[
+                "SAMPLE",
+                "HOM REF",
+                "HET",
+                "HOM ALT",
+                "MISSING",
+                "HET/HOM",
+                "MISSING RATE",
+            ],
+            &samples,
+        ));
+    }
+
+    for (name, depth) in [
+        ("INFO DP", &stats.site_depth),
+        ("FORMAT DP", &stats.sample_depth),
+    ] {
+        let Some(depth) = depth else {
+            continue;
+        };
+        writeln!(
+            output,
+            "\n{}: {} values, min {}, median {}, mean {:.1}, max {}",
+            name, depth.count, depth.min, depth.median, depth.mean, depth.max
+        )?;
+        let bins: Vec<Vec<String>> = depth
+            .bins
+            .iter()
+            .map(|bin| vec![bin.range.clone(), bin.count.to_string()])
+            .collect();
+        output.push_str(&format_table(&["DEPTH", "COUNT"], &bins));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::bgzf::tests::compress;
+    use std::io::Write;
+
+    const VCF: &str = "##fileformat=VCFv4.2\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2\n\
+        chr1\t100\t.\tA\tG\t50\tPASS\tDP=30\tGT:DP\t0/1:12\t1/1:18\n\
+        chr1\t200\t.\tC\tA,CT\t50\tPASS\tDP=8\tGT:DP\t1/2:3\t./.:.\n\
+        chr2\t300\t.\tTAA\tT\t50\tLowQual\tDP=250\tGT:DP\t0|0:100\t0|1:150\n\
+        chr2\t400\t.\tAC\tGT\t.\t.\t.\tGT\t1\t.\n\
+        chr1\t500\t.\tG\t<DEL>\t.\tPASS\t.\tGT\t0/0\t0/1\n";
+
+    #[test]
+    fn test_vcf_stats() {
+        let mut file = tempfile::Builder::new()
+            .suffix(".vcf.gz")
+            .tempfile()
+            .unwrap();
+        file.write_all(&compress(VCF.as_bytes(), 64)).unwrap();
+        let stats = vcf_stats(file.path()).unwrap();
+
+        assert_eq!(
+            (stats.records, stats.passing, stats.multiallelic),
+            (5, 3, 1)
+        );
+        assert_eq!(
+            stats.variant_types,
+            VariantTypes {
+                snv: 2,
+                mnv: 1,
+                insertion: 1,
+                deletion: 1,
+                complex: 0,
+                symbolic: 1,
+            }
+        );
+        assert_eq!((stats.transitions, stats.transversions), (1, 1));
+        assert_eq!(stats.ts_tv, Some(1.0));
+        let chromosomes: Vec<(&str, u64)> = stats
+            .chromosomes
+            .iter()
+            .map(|count| (count.chromosome.as_str(), count.records))
+            .collect();
+        assert_eq!(chromosomes, [("chr1", 3), ("chr2", 2)]);
+
+        let s1 = &stats.samples[0];
+        assert_eq!((s1.hom_ref, s1.het, s1.hom_alt, s1.missing), (2, 2, 1, 0));
+        let s2 = &stats.samples[1];
+        assert_eq!((s2.hom_ref, s2.het, s2.hom_alt, s2.missing), (0, 2, 1, 2));
+        assert_eq!(s2.missing_rate, 0.4);
+        assert_eq!(stats.het_hom_ratio, Some(2.0));
+        assert_eq!(stats.missing_rate, Some(0.2));
+
+        let site_depth = stats.site_depth.as_ref().unwrap();
+        assert_eq!(
+            (
+                site_depth.count,
+                site_depth.min,
+                site_depth.median,
+                site_depth.max
+            ),
+            (3, 8, 30, 250)
+        );
+        let sample_depth = stats.sample_depth.as_ref().unwrap();
+        assert_eq!((sample_depth.count, sample_depth.median), (5, 18));
+        let bins: Vec<u64> = sample_depth.bins.iter().map(|bin| bin.count).collect();
+        assert_eq!(bins, [0, 1, 0, 2, 0, 0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_vcf_stats_plain_gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(VCF.as_bytes()).unwrap();
+        let mut file = tempfile::Builder::new()
+            .suffix(".vcf.gz")
+            .tempfile()
+            .unwrap();
+        file.write_all(&encoder.finish().unwrap()).unwrap();
+        let stats = vcf_stats(file.path()).unwrap();
+        assert_eq!((stats.records, stats.passing), (5, 3));
+    }
+
+    #[cfg(feature = "uploads")]
+    #[tokio::test]
+    async fn test_compute_stats() {
+        let mut vcf = tempfile::Builder::new().suffix(".vcf").tempfile().unwrap();
+        vcf.write_all(VCF.as_bytes()).unwrap();
+        let mut broken = tempfile::Builder::new().suffix(".vcf").tempfile().unwrap();
+        broken.write_all(b"#CHROM\tPOS\nchr1\n").unwrap();
+        let other = tempfile::Builder::new().suffix(".txt").tempfile().unwrap();
+        let paths = [vcf.path(), broken.path(), other.path()]
+            .map(|path| path.to_str().unwrap().to_string());
+
+        let stats = compute_stats(&paths, &StreamEncoding::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.len(), 2);
+        assert!(stats[0].name.ends_with(".vcf.stats.json"));
+        let json: serde_json::Value =
+            serde_json::from_slice(stats[0].json.as_ref().unwrap()).unwrap();
+        assert_eq!(json["records"], 5);
+        assert!(stats[1].json.is_err());
+
+        // With --bgzip the statistics are named after the compressed file
+        let encoding = StreamEncoding {
+            bgzip: true,
+            ..Default::default()
+        };
+        let stats = compute_stats(&paths[..1], &encoding).await.unwrap();
+        assert!(stats[0].name.ends_with(".vcf.gz.stats.json"));
+        assert_eq!(stats[0].source, get_file_name_from_path(&paths[0]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_upload_stats_skips_files_not_uploaded() {
+        let mut report = TransferReport::default();
+        report.push("a.vcf".to_string(), Outcome::Failed("boom".to_string()));
+        report.push("b.vcf".to_string(), Outcome::Succeeded);
+        let stats = ["a.vcf", "b.vcf"]
+            .map(|source| StatsFile {
+                source: source.to_string(),
+                name: format!("{}.stats.json", source),
+                json: Ok(b"{}".to_vec()),
+            })
+            .into();
+        // Without keep_going the earlier failure cancels the statistics of b.vcf too
+        upload_stats(
+            "http://localhost:1",
+            None,
+            stats,
+            "token",
+            false,
+            &mut report,
+        )
+        .await;
+        assert_eq!(
+            report.results[2].outcome,
+            Outcome::Skipped("a.vcf was not uploaded".to_string())
+        );
+        assert_eq!(
+            report.results[3].outcome,
+            Outcome::Skipped("cancelled after an earlier failure".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_stats() {
+        let mut file = tempfile::Builder::new().suffix(".vcf").tempfile().unwrap();
+        file.write_all(VCF.as_bytes()).unwrap();
+        let stats = vcf_stats(file.path()).unwrap();
+        let table = format_stats(&stats, OutputFormat::Table).unwrap();
+        assert!(table.contains("ts/tv: 1.000 (1 transitions, 1 transversions)"));
+        assert!(table.contains("S2      0        2    1        2        2.000    0.400"));
+        assert!(table.contains("FORMAT DP: 5 values, min 3, median 18, mean 56.6, max 150"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_stats(&stats, OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["variant_types"]["snv"], 2);
+        assert!(json["samples"][0]["het_hom_ratio"].is_number());
+
+        let mut file = tempfile::Builder::new().suffix(".vcf").tempfile().unwrap();
+        file.write_all(b"##fileformat=VCFv4.2\nchr1\t100\n")
+            .unwrap();
+        assert!(
+            vcf_stats(file.path())
+                .unwrap_err()
+                .to_string()
+                .contains("line 2")
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use super::reference::{self, Assembly};
+use super::{bgzf, is_bgzf, is_compressed, is_vcf, open};
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// Maximum number of problems reported per file, so that a badly broken file does not flood the terminal.
+const MAX_PROBLEMS: usize = 20;
+
+const FIXED_COLUMNS: [&str; 8] = [
+    "#CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "INFO",
+];
+
+#[derive(Default)]
+struct Problems {
+    messages: Vec<String>,
+    omitted: usize,
+}
+
+impl Problems {
+    fn push(&mut self, message: impl Into<String>) {
+        if self.messages.len() < MAX_PROBLEMS {
+            self.messages.push(message.into());
+        } else {
+            self.omitted += 1;
+        }
+    }
+
+    fn into_messages(mut self) -> Vec<String> {
+        if self.omitted > 0 {
+            self.messages
+                .push(format!("... and {} more problems", self.omitted));
+        }
+        self.messages
+    }
+}
+
+/// Checks the lines of a VCF file one at a time.
+#[derive(Default)]
+struct Checker {
+    columns: Option<usize>,
+    contigs: HashMap<String, u64>,
+    assembly: Option<Assembly>,
+    reference: Option<Assembly>,
+    current: Option<(String, u64)>,
+    finished: HashSet<String>,
+    reported: HashSet<String>,
+}
+
+impl Checker {
+    /// Checks a line, returning `false` if the rest of the file cannot be checked meaningfully.
+    fn check_line(&mut self, number: usize, line: &str, problems: &mut Problems) -> bool {
+        if number == 1 && !line.starts_with("##fileformat=VCFv4") {
+            problems.push("line 1: expected ##fileformat=VCFv4.x");
+        }
+        if self.columns.is_some() {
+            self.check_record(number, line, problems);
+        } else if let Some(meta) = line.strip_prefix("##") {
+            self.check_meta(number, meta, problems);
+        } else if line.starts_with("#CHROM") {
+            self.check_header(number, line, problems);
+        } else {
+            problems.push(format!(
+                "line {}: found a record before the #CHROM line",
+                number
+            ));
+            return false;
+        }
+        true
+    }
+
+    fn check_meta(&mut self, number: usize, meta: &str, problems: &mut Problems) {
+        let Some((key, value)) = meta.split_once('=') else {
+            problems.push(format!("line {}: malformed meta-information line", number));
+            return;
+        };
+        if value.starts_with('<') && !value.ends_with('>') {
+            problems.push(format!("line {}: unterminated {} definition", number, key));
+            return;
+        }
+        match key {
+            "contig" => self.check_contig(number, value, problems),
+            "reference" | "assembly" => self.reference = Assembly::from_reference(value),
+            _ => {}
+        }
+    }
+
+    fn check_contig(&mut self, number: usize, value: &str, problems: &mut Problems) {
+        let fields: HashMap<&str, &str> = value
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .split(',')
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let Some(id) = fields.get("ID") else {
+            problems.push(format!("line {}: contig definition without an ID", number));
+            return;
+        };
+        let Some(length) = fields.get("length") else {
+            return;
+        };
+        let Ok(length) = length.parse::<u64>() else {
+            problems.push(format!("line {}: invalid length for contig {}", number, id));
+            return;
+        };
+        self.contigs.insert(id.to_string(), length);
+
+        if !reference::is_primary_contig(id) {
+            return;
+        }
+        match Assembly::from_contig_length(id, length) {
+            None => problems.push(format!(
+                "line {}: contig {} has length {}, which matches neither GRCh38 nor GRCh37",
+                number, id, length
+            )),
+            Some(assembly) if self.assembly.is_some_and(|known| known != assembly) => problems
+                .push(format!(
+                    "line {}: contig {} has its {} length, but earlier contigs are {}",
+                    number,
+                    id,
+                    assembly,
+                    self.assembly.unwrap()
+                )),
+            Some(assembly) => self.assembly = Some(assembly),
+        }
+    }
+
+    fn check_header(&mut self, number: usize, line: &str, problems: &mut Problems) {
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < FIXED_COLUMNS.len() || columns[..FIXED_COLUMNS.len()] != FIXED_COLUMNS {
+            problems.push(format!(
+                "line {}: the header must start with the tab separated columns {}",
+                number,
+                FIXED_COLUMNS.join(" ")
+            ));
+        }
+        if columns.len() > FIXED_COLUMNS.len() {
+            if columns[8] != "FORMAT" {
+                problems.push(format!(
+                    "line {}: expected FORMAT before the sample columns, found {:?}",
+                    number, columns[8]
+                ));
+            }
+            if columns.len() == 9 {
+                problems.push(format!(
+                    "line {}: FORMAT column without sample columns",
+                    number
+                ));
+            }
+            let mut samples = HashSet::new();
+            for sample in &columns[9.min(columns.len())..] {
+                if sample.trim().is_empty() {
+                    problems.push(format!("line {}: empty sample name", number));
+                } else if !samples.insert(*sample) {
+                    problems.push(format!("line {}: duplicate sample {}", number, sample));
+                }
+            }
+        }
+        self.columns = Some(columns.len());
+        self.assembly = self.assembly.or(self.reference);
+    }
+
+    fn check_record(&mut self, number: usize, line: &str, problems: &mut Problems) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let columns = self.columns.unwrap_or_default();
+        if fields.len() != columns {
+            problems.push(format!(
+                "line {}: expected {} columns, found {}",
+                number,
+                columns,
+                fields.len()
+            ));
+            return;
+        }
+        let contig = fields[0];
+        let Ok(position) = fields[1].parse::<u64>() else {
+            problems.push(format!("line {}: invalid position {:?}", number, fields[1]));
+            return;
+        };
+
+        if !self.reported.contains(contig) {
+            if !reference::is_known_contig(contig) {
+                problems.push(format!(
+                    "line {}: contig {} is not a GRCh38 or GRCh37 contig",
+                    number, contig
+                ));
+                self.reported.insert(contig.to_string());
+            } else if !self.contigs.is_empty() && !self.contigs.contains_key(contig) {
+                problems.push(format!(
+                    "line {}: contig {} is not declared in the header",
+                    number, contig
+                ));
+                self.reported.insert(contig.to_string());
+            }
+        }
+        let length = self.contigs.get(contig).copied().or_else(|| {
+            self.assembly
+                .and_then(|assembly| assembly.contig_length(contig))
+        });
+        if let Some(length) = length.filter(|length| position > *length) {
+            problems.push(format!(
+                "line {}: position {}:{} is beyond the end of the contig ({})",
+                number, contig, position, length
+            ));
+        }
+
+        match &mut self.current {
+            Some((current, last)) if current == contig => {
+                if position < *last {
+                    problems.push(format!(
+                        "line {}: {}:{} comes after {}:{}, the file is not sorted",
+                        number, contig, position, contig, last
+                    ));
+                }
+                *last = position;
+            }
+            current => {
+                if self.finished.contains(contig) {
+                    problems.push(format!(
+                        "line {}: records for contig {} are not contiguous, the file is not sorted",
+                        number, contig
+                    ));
+                }
+                if let Some((previous, _)) = current.replace((contig.to_string(), position)) {
+                    self.finished.insert(previous);
+                }
+            }
+        }
+
+        let bases = fields[3];
+        if bases.is_empty() || !bases.chars().all(|c| "ACGTNacgtn".contains(c)) {
+            problems.push(format!("line {}: invalid REF {:?}", number, bases));
+        }
+        if columns > 9 {
+            let keys = fields[8].split(':').count();
+            if fields[9..]
+                .iter()
+                .any(|sample| sample.split(':').count() > keys)
+            {
+                problems.push(format!(
+                    "line {}: a sample has more fields than declared in FORMAT",
+                    number
+                ));
+            }
+        }
+    }
+
+    fn finish(&self, problems: &mut Problems) {
+        if self.columns.is_none() {
+            problems.push("missing #CHROM header line");
+        }
+    }
+}
+
+/// Validates a VCF file before it is uploaded.
+/// The header syntax, the BGZF EOF block of compressed files, the sort order of the records,
+/// the contig names against GRCh38 and GRCh37 and the sample columns are checked.
+/// # Arguments
+/// * `path` - The path to the VCF file.
+/// # Returns
+/// A `Result` containing the problems found, which is empty if the file is valid.
+/// # Errors
+/// Returns an error if the file cannot be read.
+/// # Example
+/// ```
+/// for problem in validate_vcf("sample.vcf.gz")? {
+///     eprintln!("{}", problem);
+/// }
+/// ```
+pub fn validate_vcf<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy().to_lowercase();
+    let compressed_name = name.ends_with(".gz") || name.ends_with(".bgz");
+    let mut problems = Problems::default();
+
+    if is_compressed(path)? {
+        if !compressed_name {
+            problems.push("the file is compressed but does not have a .gz extension");
+        }
+        if !is_bgzf(path)? {
+            problems
+                .push("the file is compressed with plain gzip, not BGZF; recompress it with bgzip");
+        } else if !bgzf::has_eof_block(path)? {
+            problems.push(
+                "missing BGZF EOF block, the file is truncated or was not compressed with bgzip",
+            );
+        }
+    } else if compressed_name {
+        problems.push("the file has a .gz extension but is not compressed");
+    }
+
+    let mut reader = open(path)?;
+    let mut checker = Checker::default();
+    let mut line = Vec::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                problems.push(format!("cannot decompress the file: {}", err));
+                break;
+            }
+        }
+        number += 1;
+        let text = String::from_utf8_lossy(&line);
+        if !checker.check_line(number, text.trim_end_matches(['\n', '\r']), &mut problems) {
+            break;
+        }
+    }
+    checker.finish(&mut problems);
+    Ok(problems.into_messages())
+}
+
+/// Validates every VCF file in a list of files before upload, printing the problems of each invalid file.
+/// Files without a VCF extension are not checked. Files are validated concurrently on blocking threads.
+/// # Arguments
+/// * `paths` - The paths of the files to upload.
+/// * `keep_going` - Whether to return the invalid files instead of failing, so that the rest can be uploaded.
+/// # Returns
+/// A `Result` containing the invalid files with the reason they failed, which is empty if every VCF file is valid.
+/// # Errors
+/// Returns an error naming the number of invalid files if any of them failed validation and `keep_going` is `false`.
+/// # Example
+/// ```
+/// let invalid = validate_files(&["sample.vcf.gz".to_string(), "notes.txt".to_string()], true).await?;
+/// for (path, reason) in &invalid {
+///     println!("{}: {}", path, reason);
+/// }
+/// ```
+pub async fn validate_files(
+    paths: &[String],
+    keep_going: bool,
+) -> Result<BTreeMap<String, String>> {
+    let handles: Vec<_> = paths
+        .iter()
+        .filter(|path| is_vcf(path))
+        .map(|path| {
+            let file = path.clone();
+            (
+                path,
+                tokio::task::spawn_blocking(move || validate_vcf(file)),
+            )
+        })
+        .collect();
+
+    let total = handles.len();
+    let mut invalid = BTreeMap::new();
+    for (path, handle) in handles {
+        let problems = match handle.await? {
+            Ok(problems) => problems,
+            Err(err) => vec![format!("{:#}", err)],
+        };
+        let Some(first) = problems.first() else {
+            continue;
+        };
+        let reason = match problems.len() {
+            1 => format!("failed validation: {}", first),
+            count => format!("failed validation: {} (and {} more)", first, count - 1),
+        };
+        invalid.insert(path.clone(), reason);
+        eprintln!("{} failed validation:", path);
+        for problem in &problems {
+            eprintln!("  - {}", problem);
+        }
+    }
+
+    if !invalid.is_empty() {
+        if keep_going {
+            eprintln!(
+                "{} of {} VCF files failed validation and will not be uploaded.",
+                invalid.len(),
+                total
+            );
+        } else {
+            return Err(anyhow!(
+                "{} of {} VCF files failed validation. Fix them, pass --keep-going to upload the rest or pass --skip-validation to upload anyway.",
+                invalid.len(),
+                total
+            ));
+        }
+    }
+    Ok(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::bgzf::tests::compress;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const HEADER: &str = "##fileformat=VCFv4.2\n\
+        ##contig=<ID=chr1,length=248956422>\n\
+        ##contig=<ID=chr2,length=242193529>\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2\n";
+
+    fn vcf_file(suffix: &str, content: &[u8]) -> NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(content).unwrap();
+        file
+    }
+
+    fn problems(records: &str) -> Vec<String> {
+        let file = vcf_file(".vcf", format!("{}{}", HEADER, records).as_bytes());
+        validate_vcf(file.path()).unwrap()
+    }
+
+    #[test]
+    fn test_valid_vcf() {
+        let records = "chr1\t100\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t1/1\n\
+            chr1\t200\t.\tC\tT\t.\tPASS\t.\tGT\t0/0\t0/1\n\
+            chr2\t50\t.\tG\tA\t.\tPASS\t.\tGT\t0/1\t./.\n";
+        assert_eq!(problems(records), Vec::<String>::new());
+
+        let file = vcf_file(
+            ".vcf.gz",
+            &compress(format!("{}{}", HEADER, records).as_bytes(), 64),
+        );
+        assert_eq!(validate_vcf(file.path()).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unsorted_vcf() {
+        let found = problems(
+            "chr1\t200\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t1/1\n\
+            chr1\t100\t.\tC\tT\t.\tPASS\t.\tGT\t0/0\t0/1\n\
+            chr2\t50\t.\tG\tA\t.\tPASS\t.\tGT\t0/1\t./.\n\
+            chr1\t300\t.\tG\tA\t.\tPASS\t.\tGT\t0/1\t./.\n",
+        );
+        assert_eq!(found.len(), 2);
+        assert!(found[0].contains("chr1:100 comes after chr1:200"));
+        assert!(found[1].contains("not contiguous"));
+    }
+
+    #[test]
+    fn test_contigs() {
+        let found = problems(
+            "scaffold_1\t10\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t1/1\n\
+            chr2\t300000000\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\t1/1\n",
+        );
+        assert!(found[0].contains("scaffold_1 is not a GRCh38 or GRCh37 contig"));
+        assert!(found[1].contains("beyond the end of the contig"));
+
+        let file = vcf_file(
+            ".vcf",
+            b"##fileformat=VCFv4.2\n##contig=<ID=1,length=1000>\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+        );
+        let found = validate_vcf(file.path()).unwrap();
+        assert!(found[0].contains("matches neither GRCh38 nor GRCh37"));
+    }
+
+    #[test]
+    fn test_header_and_samples() {
+        let file = vcf_file(
+            ".vcf",
+            b"##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS1\n\
+            chr1\t100\t.\tA\tG\t.\tPASS\t.\tGT\t0/1\n",
+        );
+        let found = validate_vcf(file.path()).unwrap();
+        assert!(found[0].contains("duplicate sample S1"));
+        assert!(found[1].contains("expected 11 columns, found 10"));
+
+        let file = vcf_file(".vcf", b"chr1\t100\t.\tA\tG\t.\tPASS\t.\n");
+        let found = validate_vcf(file.path()).unwrap();
+        assert!(found[0].contains("expected ##fileformat"));
+        assert!(found[1].contains("before the #CHROM line"));
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(HEADER.as_bytes()).unwrap();
+        let file = vcf_file(".vcf.gz", &encoder.finish().unwrap());
+        let found = validate_vcf(file.path()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("plain gzip, not BGZF"));
+
+        let mut truncated = compress(HEADER.as_bytes(), 64);
+        truncated.truncate(truncated.len() - bgzf::EOF_BLOCK.len());
+        let file = vcf_file(".vcf.gz", &truncated);
+        let found = validate_vcf(file.path()).unwrap();
+        assert!(found[0].contains("missing BGZF EOF block"));
+
+        let file = vcf_file(".vcf.gz", HEADER.as_bytes());
+        let found = validate_vcf(file.path()).unwrap();
+        assert!(found[0].contains("not compressed"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_files() {
+        let valid = vcf_file(".vcf", HEADER.as_bytes());
+        let invalid = vcf_file(".vcf", b"not a vcf\n");
+        let other = vcf_file(".txt", b"not a vcf\n");
+        let path = |file: &NamedTempFile| file.path().to_str().unwrap().to_string();
+
+        let found = validate_files(&[path(&valid), path(&other)], false).await;
+        assert!(found.unwrap().is_empty());
+        let err = validate_files(&[path(&valid), path(&invalid)], false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("1 of 2 VCF files failed"));
+
+        let found = validate_files(&[path(&valid), path(&invalid)], true)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[&path(&invalid)].starts_with("failed validation: line 1:"));
+    }
+}
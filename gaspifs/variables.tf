@@ -20,3 +20,32 @@ variable "cli_backend_api_url" {
   type        = string
   description = "API URL for the CLI backend."
 }
+
+variable "cognito_identity_pool_id" {
+  type        = string
+  description = "Cognito identity pool ID, used to identify the user to the clinic API."
+}
+
+variable "data_portal_bucket" {
+  type        = string
+  description = "Name of the data portal bucket holding project files."
+}
+
+# Clinic variables
+variable "clinic_api_url" {
+  type        = string
+  description = "API URL for clinical workflows, empty if the hub runs none."
+  default     = ""
+}
+
+variable "clinic_mode" {
+  type        = string
+  description = "Clinical workflows run by the hub: svep, pgxflow or empty."
+  default     = ""
+}
+
+variable "hub_name" {
+  type        = string
+  description = "Name of the hub, which selects the layout of clinical reports."
+  default     = ""
+}
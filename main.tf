@@ -192,4 +192,9 @@ module "gaspifs" {
   gaspifs_binary_destination  = "s3://${module.sbeacon.data-portal-bucket}/binaries/gaspifs"
   cognito_user_pool_client_id = module.cognito.cognito_client_id
   cli_backend_api_url         = module.sbeacon.api_url
+  cognito_identity_pool_id    = module.cognito.cognito_identity_pool_id
+  data_portal_bucket          = module.sbeacon.data-portal-bucket
+  clinic_api_url              = local.clinic_api_url
+  clinic_mode                 = local.clinic_mode == null ? "" : local.clinic_mode
+  hub_name                    = var.hub_name
 }